@@ -0,0 +1,149 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::rsdp::{Rsdp, RSDP_V1_LENGTH};
+use crate::sdt::{u32_at, u64_at, Sdt, SdtHeader, Signature, Table, SDT_HEADER_SIZE};
+
+/// Returns `length` bytes of physical memory at `address`, or `None` if they can not be reached.
+///
+/// The kernel reads tables through the higher half direct map, tests hand out table dumps.
+pub type PhysicalMapper = fn(address: u64, length: usize) -> Option<&'static [u8]>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The bootloader did not report an RSDP.
+    NoRsdp,
+    /// The RSDP signature or length is wrong.
+    InvalidRsdp,
+    BadRsdpChecksum,
+    /// The RSDP revision is neither 0 (ACPI 1.0) nor 2 (ACPI 2.0+).
+    UnknownRevision(u8),
+    /// The physical memory of a table could not be mapped.
+    Unmapped(u64),
+    BadChecksum(Signature),
+    /// The table is shorter than its header or than the fields we read.
+    TableTooShort(Signature),
+    SignatureMismatch {
+        expected: Signature,
+        found: Signature,
+    },
+    TableNotFound(Signature),
+}
+
+/// The root table (XSDT or RSDT) and everything reachable from it.
+pub struct AcpiTables {
+    pub rsdp: Rsdp,
+    root: Sdt,
+    /// 8 for the XSDT, 4 for the RSDT.
+    entry_size: usize,
+    mapper: PhysicalMapper,
+}
+
+impl AcpiTables {
+    /// Parses the RSDP at `rsdp_address` and the root table it points to.
+    ///
+    /// The XSDT is preferred, the RSDT is only used for ACPI 1.0.
+    pub fn new(rsdp_address: u64, mapper: PhysicalMapper) -> Result<Self, AcpiError> {
+        // The RSDP can sit at the very end of a mapping, only ask for the extended part if needed.
+        let rsdp_bytes = mapper(rsdp_address, Rsdp::max_length())
+            .or_else(|| mapper(rsdp_address, RSDP_V1_LENGTH))
+            .ok_or(AcpiError::Unmapped(rsdp_address))?;
+        let rsdp = Rsdp::parse(rsdp_bytes)?;
+
+        let (address, signature, entry_size) = match rsdp.xsdt_address {
+            Some(address) if address != 0 => (address, Signature::XSDT, 8),
+            _ => (rsdp.rsdt_address as u64, Signature::RSDT, 4),
+        };
+
+        let root = map_table(mapper, address)?;
+        root.require(signature, SDT_HEADER_SIZE)?;
+
+        Ok(Self {
+            rsdp,
+            root,
+            entry_size,
+            mapper,
+        })
+    }
+
+    /// The XSDT or RSDT.
+    #[inline]
+    pub fn root(&self) -> &Sdt {
+        &self.root
+    }
+
+    /// Physical addresses of all tables listed in the root table.
+    pub fn addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        self.root
+            .body()
+            .chunks_exact(self.entry_size)
+            .map(|entry| match self.entry_size {
+                8 => u64_at(entry, 0),
+                _ => u32_at(entry, 0) as u64,
+            })
+    }
+
+    /// Iterates over all tables listed in the root table, validating each of them.
+    pub fn iter(&self) -> impl Iterator<Item = Result<Sdt, AcpiError>> + '_ {
+        self.addresses()
+            .map(|address| map_table(self.mapper, address))
+    }
+
+    /// Returns the first valid table with `signature`.
+    ///
+    /// A table with the signature that fails validation is reported, unless a later copy is valid.
+    pub fn find(&self, signature: Signature) -> Result<Sdt, AcpiError> {
+        let mut error = AcpiError::TableNotFound(signature);
+
+        for address in self.addresses() {
+            let Some(header) = (self.mapper)(address, SDT_HEADER_SIZE).map(SdtHeader::parse) else {
+                continue;
+            };
+
+            if header.signature != signature {
+                continue;
+            }
+
+            match map_table(self.mapper, address) {
+                Ok(table) => return Ok(table),
+                Err(invalid) => error = invalid,
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Finds and parses the table of type `T`.
+    #[inline]
+    pub fn get<T: Table>(&self) -> Result<T, AcpiError> {
+        T::parse(self.find(T::SIGNATURE)?)
+    }
+
+    /// Maps and validates the table at the physical `address`, e.g. the DSDT from the FADT.
+    #[inline]
+    pub fn table_at(&self, address: u64) -> Result<Sdt, AcpiError> {
+        map_table(self.mapper, address)
+    }
+}
+
+fn map_table(mapper: PhysicalMapper, address: u64) -> Result<Sdt, AcpiError> {
+    let header = mapper(address, SDT_HEADER_SIZE).ok_or(AcpiError::Unmapped(address))?;
+    let length = (SdtHeader::parse(header).length as usize).max(SDT_HEADER_SIZE);
+    let bytes = mapper(address, length).ok_or(AcpiError::Unmapped(address))?;
+
+    Sdt::new(bytes)
+}
@@ -0,0 +1,175 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::export::AcpiError;
+use crate::sdt::{u16_at, u32_at, u64_at, GenericAddress, Sdt, SdtHeader, Signature, Table};
+
+/// Length of the ACPI 1.0 FADT, everything after it is optional.
+const V1_LENGTH: usize = 116;
+const RESET_REGISTER_END: usize = 129;
+const X_DSDT_END: usize = 148;
+const X_PM_TIMER_BLOCK_END: usize = 220;
+
+/// The reset register is supported.
+const FLAG_RESET_REGISTER: u32 = 1 << 10;
+/// The PM timer counts with 32 instead of 24 bits.
+const FLAG_TIMER_VALUE_EXTENDED: u32 = 1 << 8;
+
+/// IA-PC boot architecture flags.
+const BOOT_LEGACY_DEVICES: u16 = 1;
+const BOOT_8042: u16 = 1 << 1;
+const BOOT_VGA_NOT_PRESENT: u16 = 1 << 2;
+const BOOT_MSI_NOT_SUPPORTED: u16 = 1 << 3;
+const BOOT_CMOS_RTC_NOT_PRESENT: u16 = 1 << 5;
+
+/// The fixed ACPI description table: power management hardware and the DSDT.
+#[derive(Clone, Copy, Debug)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_control: u32,
+    pub dsdt: u32,
+    pub preferred_pm_profile: u8,
+    /// ISA IRQ of the SCI.
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: u32,
+    pub pm1a_control_block: u32,
+    /// I/O port of the ACPI PM timer, 0 if there is none.
+    pub pm_timer_block: u32,
+    pub pm_timer_length: u8,
+    /// Index of the century in the CMOS, 0 if not supported.
+    pub century: u8,
+    pub boot_architecture_flags: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+    pub x_dsdt: Option<u64>,
+    pub x_pm_timer_block: Option<GenericAddress>,
+}
+
+impl Fadt {
+    /// Physical address of the DSDT, preferring the 64-bit field.
+    #[inline]
+    pub fn dsdt_address(&self) -> u64 {
+        match self.x_dsdt {
+            Some(address) if address != 0 => address,
+            _ => self.dsdt as u64,
+        }
+    }
+
+    /// The PM timer as generic address, preferring the extended field.
+    pub fn pm_timer(&self) -> Option<GenericAddress> {
+        match self.x_pm_timer_block {
+            Some(block) if block.address != 0 => Some(block),
+            _ if self.pm_timer_block != 0 && self.pm_timer_length == 4 => Some(GenericAddress {
+                address_space: crate::sdt::AddressSpace::SystemIo,
+                bit_width: 32,
+                bit_offset: 0,
+                access_size: 3,
+                address: self.pm_timer_block as u64,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the PM timer counts with 32 bits instead of 24.
+    #[inline]
+    pub fn pm_timer_is_32bit(&self) -> bool {
+        self.flags & FLAG_TIMER_VALUE_EXTENDED != 0
+    }
+
+    /// The register and value that reset the system, if supported.
+    pub fn reset(&self) -> Option<(GenericAddress, u8)> {
+        if self.flags & FLAG_RESET_REGISTER == 0 {
+            return None;
+        }
+
+        self.reset_register
+            .map(|register| (register, self.reset_value))
+    }
+
+    #[inline]
+    pub fn has_legacy_devices(&self) -> bool {
+        self.boot_architecture_flags & BOOT_LEGACY_DEVICES != 0
+    }
+
+    #[inline]
+    pub fn has_8042(&self) -> bool {
+        self.boot_architecture_flags & BOOT_8042 != 0
+    }
+
+    #[inline]
+    pub fn has_vga(&self) -> bool {
+        self.boot_architecture_flags & BOOT_VGA_NOT_PRESENT == 0
+    }
+
+    #[inline]
+    pub fn supports_msi(&self) -> bool {
+        self.boot_architecture_flags & BOOT_MSI_NOT_SUPPORTED == 0
+    }
+
+    #[inline]
+    pub fn has_cmos_rtc(&self) -> bool {
+        self.boot_architecture_flags & BOOT_CMOS_RTC_NOT_PRESENT == 0
+    }
+}
+
+impl Table for Fadt {
+    const SIGNATURE: Signature = Signature::FADT;
+
+    fn parse(table: Sdt) -> Result<Self, AcpiError> {
+        table.require(Self::SIGNATURE, V1_LENGTH)?;
+
+        let bytes = table.bytes();
+        let length = bytes.len();
+
+        Ok(Self {
+            header: table.header,
+            firmware_control: u32_at(bytes, 36),
+            dsdt: u32_at(bytes, 40),
+            preferred_pm_profile: bytes[45],
+            sci_interrupt: u16_at(bytes, 46),
+            smi_command: u32_at(bytes, 48),
+            acpi_enable: bytes[52],
+            acpi_disable: bytes[53],
+            pm1a_event_block: u32_at(bytes, 56),
+            pm1a_control_block: u32_at(bytes, 64),
+            pm_timer_block: u32_at(bytes, 76),
+            pm_timer_length: bytes[91],
+            century: bytes[108],
+            // Reserved in ACPI 1.0.
+            boot_architecture_flags: if table.header.revision >= 2 {
+                u16_at(bytes, 109)
+            } else {
+                0
+            },
+            flags: u32_at(bytes, 112),
+            reset_register: (length >= RESET_REGISTER_END)
+                .then(|| GenericAddress::parse(bytes, 116)),
+            reset_value: if length >= RESET_REGISTER_END {
+                bytes[128]
+            } else {
+                0
+            },
+            x_dsdt: (length >= X_DSDT_END).then(|| u64_at(bytes, 140)),
+            x_pm_timer_block: (length >= X_PM_TIMER_BLOCK_END)
+                .then(|| GenericAddress::parse(bytes, 208)),
+        })
+    }
+}
@@ -0,0 +1,80 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::export::AcpiError;
+use crate::sdt::{u16_at, u32_at, GenericAddress, Sdt, SdtHeader, Signature, Table};
+
+const LENGTH: usize = 56;
+
+/// Describes one HPET block.
+#[derive(Clone, Copy, Debug)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    /// Copy of the low half of the general capabilities register.
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub number: u8,
+    /// Minimum number of ticks for periodic mode without losing interrupts.
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    #[inline]
+    pub fn hardware_revision(&self) -> u8 {
+        self.event_timer_block_id as u8
+    }
+
+    #[inline]
+    pub fn comparator_count(&self) -> u8 {
+        ((self.event_timer_block_id >> 8) & 0x1F) as u8 + 1
+    }
+
+    #[inline]
+    pub fn counter_is_64bit(&self) -> bool {
+        self.event_timer_block_id & (1 << 13) != 0
+    }
+
+    #[inline]
+    pub fn legacy_replacement_capable(&self) -> bool {
+        self.event_timer_block_id & (1 << 15) != 0
+    }
+
+    #[inline]
+    pub fn pci_vendor_id(&self) -> u16 {
+        (self.event_timer_block_id >> 16) as u16
+    }
+}
+
+impl Table for Hpet {
+    const SIGNATURE: Signature = Signature::HPET;
+
+    fn parse(table: Sdt) -> Result<Self, AcpiError> {
+        table.require(Self::SIGNATURE, LENGTH)?;
+
+        let bytes = table.bytes();
+
+        Ok(Self {
+            header: table.header,
+            event_timer_block_id: u32_at(bytes, 36),
+            base_address: GenericAddress::parse(bytes, 40),
+            number: bytes[52],
+            minimum_tick: u16_at(bytes, 53),
+            page_protection: bytes[55],
+        })
+    }
+}
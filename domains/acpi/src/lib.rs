@@ -0,0 +1,96 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_std]
+
+pub mod export;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+pub mod rsdp;
+pub mod sdt;
+
+use export::{AcpiError, AcpiTables};
+use limine::request::RsdpRequest;
+use sdt::Table;
+use spin::Once;
+use uio::{kprint, kprintln};
+
+static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+
+static TABLES: Once<AcpiTables> = Once::new();
+
+/// Locates and validates the ACPI tables handed over by the bootloader.
+///
+/// Without valid tables the kernel keeps running, drivers relying on them report
+/// [`AcpiError::NoRsdp`].
+pub fn init() {
+    let tables = RSDP_REQUEST
+        .get_response()
+        .ok_or(AcpiError::NoRsdp)
+        .and_then(|response| AcpiTables::new(rsdp_address(response.address() as u64), hhdm_mapper));
+
+    let tables = match tables {
+        Ok(tables) => TABLES.call_once(|| tables),
+        Err(error) => {
+            kprintln!("ACPI: No usable tables: {:?}", error);
+            return;
+        }
+    };
+
+    kprint!(
+        "ACPI: Revision {}, OEM {}, tables:",
+        tables.rsdp.revision,
+        core::str::from_utf8(&tables.rsdp.oem_id).unwrap_or("?")
+    );
+
+    for table in tables.iter() {
+        match table {
+            Ok(table) => kprint!(" {}", table.signature()),
+            Err(error) => kprint!(" ({:?})", error),
+        }
+    }
+
+    kprintln!();
+}
+
+/// Returns the ACPI tables, if [`init`] found valid ones.
+#[inline]
+pub fn tables() -> Option<&'static AcpiTables> {
+    TABLES.get()
+}
+
+/// Finds and parses the table of type `T`.
+pub fn get<T: Table>() -> Result<T, AcpiError> {
+    tables().ok_or(AcpiError::NoRsdp)?.get::<T>()
+}
+
+/// Depending on the protocol revision, Limine reports the RSDP as HHDM or physical address.
+fn rsdp_address(address: u64) -> u64 {
+    let offset = pmm::hhdm_offset();
+
+    address.checked_sub(offset).unwrap_or(address)
+}
+
+/// ACPI tables live in memory the bootloader includes in the higher half direct map.
+fn hhdm_mapper(address: u64, length: usize) -> Option<&'static [u8]> {
+    let start = address.checked_add(pmm::hhdm_offset())?;
+
+    // SAFETY: The HHDM covers the ACPI reclaimable and NVS regions and is never unmapped.
+    Some(unsafe { core::slice::from_raw_parts(start as *const u8, length) })
+}
@@ -0,0 +1,274 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::export::AcpiError;
+use crate::sdt::{u16_at, u32_at, u64_at, Sdt, SdtHeader, Signature, Table, SDT_HEADER_SIZE};
+
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+
+/// The system also has 8259 PICs, which have to be masked when using the APIC.
+const FLAG_PCAT_COMPAT: u32 = 1;
+
+/// The multiple APIC description table: local APICs, I/O APICs and interrupt routing.
+#[derive(Clone, Copy, Debug)]
+pub struct Madt {
+    pub header: SdtHeader,
+    pub local_apic_address: u32,
+    pub flags: u32,
+    entries: &'static [u8],
+}
+
+impl Madt {
+    #[inline]
+    pub fn has_legacy_pics(&self) -> bool {
+        self.flags & FLAG_PCAT_COMPAT != 0
+    }
+
+    pub fn entries(&self) -> MadtEntries {
+        MadtEntries {
+            bytes: self.entries,
+        }
+    }
+
+    pub fn io_apics(&self) -> impl Iterator<Item = IoApic> {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::IoApic(io_apic) => Some(io_apic),
+            _ => None,
+        })
+    }
+
+    pub fn interrupt_source_overrides(&self) -> impl Iterator<Item = InterruptSourceOverride> {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::InterruptSourceOverride(entry) => Some(entry),
+            _ => None,
+        })
+    }
+}
+
+impl Table for Madt {
+    const SIGNATURE: Signature = Signature::MADT;
+
+    fn parse(table: Sdt) -> Result<Self, AcpiError> {
+        table.require(Self::SIGNATURE, ENTRIES_OFFSET)?;
+
+        let bytes = table.bytes();
+
+        Ok(Self {
+            header: table.header,
+            local_apic_address: u32_at(bytes, SDT_HEADER_SIZE),
+            flags: u32_at(bytes, SDT_HEADER_SIZE + 4),
+            entries: &bytes[ENTRIES_OFFSET..],
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalApic {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub flags: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// Reroutes an ISA IRQ to another GSI, or changes its polarity or trigger mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    /// Always 0, ISA.
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: InterruptFlags,
+}
+
+/// A GSI wired to the NMI input of the I/O APICs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NmiSource {
+    pub flags: InterruptFlags,
+    pub gsi: u32,
+}
+
+/// The LINT pin of a local APIC the NMI is connected to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// 0xFF for all processors.
+    pub processor_id: u8,
+    pub flags: InterruptFlags,
+    pub lint: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalX2Apic {
+    pub x2apic_id: u32,
+    pub flags: u32,
+    pub processor_uid: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalX2ApicNmi {
+    /// 0xFFFF_FFFF for all processors.
+    pub processor_uid: u32,
+    pub flags: InterruptFlags,
+    pub lint: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MadtEntry {
+    LocalApic(LocalApic),
+    IoApic(IoApic),
+    InterruptSourceOverride(InterruptSourceOverride),
+    NmiSource(NmiSource),
+    LocalApicNmi(LocalApicNmi),
+    /// 64-bit address of the local APIC, replacing the one in the header.
+    LocalApicAddressOverride(u64),
+    LocalX2Apic(LocalX2Apic),
+    LocalX2ApicNmi(LocalX2ApicNmi),
+    /// An entry type we do not decode or that is too short for its type.
+    Other {
+        kind: u8,
+        data: &'static [u8],
+    },
+}
+
+/// Iterator over the variable length entries of the MADT.
+#[derive(Clone)]
+pub struct MadtEntries {
+    bytes: &'static [u8],
+}
+
+impl Iterator for MadtEntries {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<MadtEntry> {
+        let [kind, length, ..] = *self.bytes else {
+            return None;
+        };
+        let length = length as usize;
+
+        // A corrupt length would loop forever or run past the table.
+        if length < 2 || length > self.bytes.len() {
+            self.bytes = &[];
+            return None;
+        }
+
+        let data = &self.bytes[..length];
+        self.bytes = &self.bytes[length..];
+
+        Some(parse_entry(kind, data))
+    }
+}
+
+fn parse_entry(kind: u8, data: &'static [u8]) -> MadtEntry {
+    let minimum = match kind {
+        0 => 8,
+        1 => 12,
+        2 => 10,
+        3 => 8,
+        4 => 6,
+        5 => 12,
+        9 => 16,
+        10 => 12,
+        _ => usize::MAX,
+    };
+
+    if data.len() < minimum {
+        return MadtEntry::Other { kind, data };
+    }
+
+    match kind {
+        0 => MadtEntry::LocalApic(LocalApic {
+            processor_id: data[2],
+            apic_id: data[3],
+            flags: u32_at(data, 4),
+        }),
+        1 => MadtEntry::IoApic(IoApic {
+            id: data[2],
+            address: u32_at(data, 4),
+            gsi_base: u32_at(data, 8),
+        }),
+        2 => MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+            bus: data[2],
+            source: data[3],
+            gsi: u32_at(data, 4),
+            flags: InterruptFlags(u16_at(data, 8)),
+        }),
+        3 => MadtEntry::NmiSource(NmiSource {
+            flags: InterruptFlags(u16_at(data, 2)),
+            gsi: u32_at(data, 4),
+        }),
+        4 => MadtEntry::LocalApicNmi(LocalApicNmi {
+            processor_id: data[2],
+            flags: InterruptFlags(u16_at(data, 3)),
+            lint: data[5],
+        }),
+        5 => MadtEntry::LocalApicAddressOverride(u64_at(data, 4)),
+        9 => MadtEntry::LocalX2Apic(LocalX2Apic {
+            x2apic_id: u32_at(data, 4),
+            flags: u32_at(data, 8),
+            processor_uid: u32_at(data, 12),
+        }),
+        10 => MadtEntry::LocalX2ApicNmi(LocalX2ApicNmi {
+            flags: InterruptFlags(u16_at(data, 2)),
+            processor_uid: u32_at(data, 4),
+            lint: data[8],
+        }),
+        _ => MadtEntry::Other { kind, data },
+    }
+}
+
+/// The MPS INTI flags describing the electrical characteristics of an interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptFlags(pub u16);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// Whatever is standard for the bus, active high for ISA.
+    ConformsToBus,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Whatever is standard for the bus, edge for ISA.
+    ConformsToBus,
+    Edge,
+    Level,
+}
+
+impl InterruptFlags {
+    pub fn polarity(&self) -> Polarity {
+        match self.0 & 0b11 {
+            0b01 => Polarity::ActiveHigh,
+            0b11 => Polarity::ActiveLow,
+            _ => Polarity::ConformsToBus,
+        }
+    }
+
+    pub fn trigger_mode(&self) -> TriggerMode {
+        match (self.0 >> 2) & 0b11 {
+            0b01 => TriggerMode::Edge,
+            0b11 => TriggerMode::Level,
+            _ => TriggerMode::ConformsToBus,
+        }
+    }
+}
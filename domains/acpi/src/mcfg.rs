@@ -0,0 +1,65 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::export::AcpiError;
+use crate::sdt::{u16_at, u64_at, Sdt, SdtHeader, Signature, Table, SDT_HEADER_SIZE};
+
+/// The header is followed by 8 reserved bytes.
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+const ENTRY_SIZE: usize = 16;
+
+/// Locates the memory mapped PCI Express configuration space.
+#[derive(Clone, Copy, Debug)]
+pub struct Mcfg {
+    pub header: SdtHeader,
+    entries: &'static [u8],
+}
+
+/// The configuration space of the buses `start_bus..=end_bus` of a segment group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl Mcfg {
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> {
+        self.entries
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| McfgEntry {
+                base_address: u64_at(entry, 0),
+                segment_group: u16_at(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+    }
+}
+
+impl Table for Mcfg {
+    const SIGNATURE: Signature = Signature::MCFG;
+
+    fn parse(table: Sdt) -> Result<Self, AcpiError> {
+        table.require(Self::SIGNATURE, ENTRIES_OFFSET)?;
+
+        Ok(Self {
+            header: table.header,
+            entries: &table.bytes()[ENTRIES_OFFSET..],
+        })
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::export::AcpiError;
+use crate::sdt::{array, checksum_valid, u32_at, u64_at};
+
+const SIGNATURE: [u8; 8] = *b"RSD PTR ";
+
+/// Length of the ACPI 1.0 RSDP, covered by the first checksum.
+pub const RSDP_V1_LENGTH: usize = 20;
+
+/// Length of the ACPI 2.0+ RSDP, covered by the extended checksum.
+pub const RSDP_V2_LENGTH: usize = 36;
+
+/// The root system description pointer, locating the RSDT or XSDT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rsdp {
+    pub oem_id: [u8; 6],
+    /// 0 for ACPI 1.0, 2 for ACPI 2.0 and later.
+    pub revision: u8,
+    pub rsdt_address: u32,
+    /// Only present from revision 2 on.
+    pub xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    /// Validates the signature, revision and checksums of the RSDP in `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        if bytes.len() < RSDP_V1_LENGTH {
+            return Err(AcpiError::InvalidRsdp);
+        }
+
+        if bytes[..8] != SIGNATURE {
+            return Err(AcpiError::InvalidRsdp);
+        }
+
+        if !checksum_valid(&bytes[..RSDP_V1_LENGTH]) {
+            return Err(AcpiError::BadRsdpChecksum);
+        }
+
+        let revision = bytes[15];
+
+        let xsdt_address = match revision {
+            0 => None,
+            2 => {
+                if bytes.len() < RSDP_V2_LENGTH {
+                    return Err(AcpiError::InvalidRsdp);
+                }
+
+                let length = u32_at(bytes, 20) as usize;
+
+                if length < RSDP_V2_LENGTH || length > bytes.len() {
+                    return Err(AcpiError::InvalidRsdp);
+                }
+
+                if !checksum_valid(&bytes[..length]) {
+                    return Err(AcpiError::BadRsdpChecksum);
+                }
+
+                Some(u64_at(bytes, 24))
+            }
+            revision => return Err(AcpiError::UnknownRevision(revision)),
+        };
+
+        Ok(Self {
+            oem_id: array(bytes, 9),
+            revision,
+            rsdt_address: u32_at(bytes, 16),
+            xsdt_address,
+        })
+    }
+
+    /// Number of bytes to read at the RSDP address to parse it.
+    #[inline]
+    pub const fn max_length() -> usize {
+        RSDP_V2_LENGTH
+    }
+}
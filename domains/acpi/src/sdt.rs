@@ -0,0 +1,229 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::fmt;
+
+use crate::export::AcpiError;
+
+/// Size of the header every system description table starts with.
+pub const SDT_HEADER_SIZE: usize = 36;
+
+/// The four character signature identifying a table.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signature(pub [u8; 4]);
+
+impl Signature {
+    pub const RSDT: Self = Self(*b"RSDT");
+    pub const XSDT: Self = Self(*b"XSDT");
+    pub const MADT: Self = Self(*b"APIC");
+    pub const HPET: Self = Self(*b"HPET");
+    pub const FADT: Self = Self(*b"FACP");
+    pub const MCFG: Self = Self(*b"MCFG");
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap_or("????")
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SdtHeader {
+    pub signature: Signature,
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// # Panics
+    /// If `bytes` is shorter than [`SDT_HEADER_SIZE`].
+    pub fn parse(bytes: &[u8]) -> Self {
+        Self {
+            signature: Signature(array(bytes, 0)),
+            length: u32_at(bytes, 4),
+            revision: bytes[8],
+            checksum: bytes[9],
+            oem_id: array(bytes, 10),
+            oem_table_id: array(bytes, 16),
+            oem_revision: u32_at(bytes, 24),
+            creator_id: u32_at(bytes, 28),
+            creator_revision: u32_at(bytes, 32),
+        }
+    }
+}
+
+/// A system description table whose length and checksum were verified.
+#[derive(Clone, Copy, Debug)]
+pub struct Sdt {
+    pub header: SdtHeader,
+    bytes: &'static [u8],
+}
+
+impl Sdt {
+    /// Validates the table in `bytes`, which has to cover at least the header.
+    ///
+    /// `bytes` may be longer than the table, it is cut to the length in the header.
+    pub fn new(bytes: &'static [u8]) -> Result<Self, AcpiError> {
+        if bytes.len() < SDT_HEADER_SIZE {
+            return Err(AcpiError::TableTooShort(Signature(array_or_zero(bytes))));
+        }
+
+        let header = SdtHeader::parse(bytes);
+        let length = header.length as usize;
+
+        if length < SDT_HEADER_SIZE || length > bytes.len() {
+            return Err(AcpiError::TableTooShort(header.signature));
+        }
+
+        let bytes = &bytes[..length];
+
+        if !checksum_valid(bytes) {
+            return Err(AcpiError::BadChecksum(header.signature));
+        }
+
+        Ok(Self { header, bytes })
+    }
+
+    #[inline]
+    pub fn signature(&self) -> Signature {
+        self.header.signature
+    }
+
+    /// The whole table, including the header.
+    #[inline]
+    pub fn bytes(&self) -> &'static [u8] {
+        self.bytes
+    }
+
+    /// The table without the header.
+    #[inline]
+    pub fn body(&self) -> &'static [u8] {
+        &self.bytes[SDT_HEADER_SIZE..]
+    }
+
+    /// Checks that the table is at least `length` bytes long, so fixed fields can be read.
+    pub(crate) fn require(&self, signature: Signature, length: usize) -> Result<(), AcpiError> {
+        if self.signature() != signature {
+            return Err(AcpiError::SignatureMismatch {
+                expected: signature,
+                found: self.signature(),
+            });
+        }
+
+        if self.bytes.len() < length {
+            return Err(AcpiError::TableTooShort(signature));
+        }
+
+        Ok(())
+    }
+}
+
+/// A typed table, parsed from a validated [`Sdt`].
+pub trait Table: Sized {
+    const SIGNATURE: Signature;
+
+    fn parse(table: Sdt) -> Result<Self, AcpiError>;
+}
+
+/// A register location in memory, I/O or PCI configuration space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    Other(u8),
+}
+
+impl GenericAddress {
+    pub const SIZE: usize = 12;
+
+    pub(crate) fn parse(bytes: &[u8], offset: usize) -> Self {
+        Self {
+            address_space: match bytes[offset] {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfiguration,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[offset + 1],
+            bit_offset: bytes[offset + 2],
+            access_size: bytes[offset + 3],
+            address: u64_at(bytes, offset + 4),
+        }
+    }
+}
+
+/// All bytes of a table, including the checksum byte, have to add up to 0.
+#[inline]
+pub fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+#[inline]
+pub(crate) fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(array(bytes, offset))
+}
+
+#[inline]
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(array(bytes, offset))
+}
+
+#[inline]
+pub(crate) fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(array(bytes, offset))
+}
+
+#[inline]
+pub(crate) fn array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    let mut array = [0; N];
+    array.copy_from_slice(&bytes[offset..offset + N]);
+    array
+}
+
+fn array_or_zero<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut array = [0; N];
+    let length = bytes.len().min(N);
+    array[..length].copy_from_slice(&bytes[..length]);
+    array
+}
@@ -0,0 +1,299 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Parses ACPI tables laid out like those of a q35 machine with one CPU, booted through OVMF.
+//!
+//! The blobs in `q35/` are still written by `q35/generate.py`, which builds them by hand
+//! after QEMU's q35 table builder at the addresses OVMF places them. They stand in for a
+//! real dump and have to be replaced by one:
+//!
+//! 1. Boot a Linux image in `qemu-system-x86_64 -M q35 -smp 1 -m 2G -bios OVMF.fd`.
+//! 2. Run `acpidump -b` in the guest and copy the `.dat` files of the tables below into
+//!    `q35/`, `acpidump -b -n RSDP` writes `rsdp.dat`.
+//! 3. `acpidump -b` drops the addresses, take them from the `@ 0x...` headers of plain
+//!    `acpidump` output and update the constants below.
+//! 4. Adjust the expectations to the dump, OVMF adds tables like the WAET to the XSDT, and
+//!    delete `q35/generate.py`. The corruption cases mutate the blobs here, so the
+//!    generator adds nothing a real dump does not cover.
+
+use acpi::export::{AcpiError, AcpiTables};
+use acpi::fadt::Fadt;
+use acpi::hpet::Hpet;
+use acpi::madt::{
+    InterruptSourceOverride, IoApic, LocalApic, LocalApicNmi, Madt, MadtEntry, Polarity,
+    TriggerMode,
+};
+use acpi::mcfg::{Mcfg, McfgEntry};
+use acpi::rsdp::Rsdp;
+use acpi::sdt::{checksum_valid, AddressSpace, Sdt, Signature, Table};
+
+const RSDP: u64 = 0x7FB7_E014;
+const XSDT: u64 = 0x7FB7_D0E8;
+const FACP: u64 = 0x7FB7_9000;
+const APIC: u64 = 0x7FB7_8000;
+const HPET: u64 = 0x7FB7_7000;
+const MCFG: u64 = 0x7FB7_6000;
+const DSDT: u64 = 0x7FB7_A000;
+
+/// The tables at the physical addresses the firmware placed them at.
+static MEMORY: [(u64, &[u8]); 6] = [
+    (RSDP, include_bytes!("q35/rsdp.dat")),
+    (XSDT, include_bytes!("q35/xsdt.dat")),
+    (FACP, include_bytes!("q35/facp.dat")),
+    (APIC, include_bytes!("q35/apic.dat")),
+    (HPET, include_bytes!("q35/hpet.dat")),
+    (MCFG, include_bytes!("q35/mcfg.dat")),
+];
+
+/// Hands out the blobs, everything else is unmapped.
+fn mapper(address: u64, length: usize) -> Option<&'static [u8]> {
+    MEMORY.iter().find_map(|&(base, bytes)| {
+        let offset = usize::try_from(address.checked_sub(base)?).ok()?;
+
+        bytes.get(offset..offset.checked_add(length)?)
+    })
+}
+
+fn tables() -> AcpiTables {
+    AcpiTables::new(RSDP, mapper).expect("q35 tables are valid")
+}
+
+fn table(address: u64) -> &'static [u8] {
+    MEMORY
+        .iter()
+        .find(|&&(base, _)| base == address)
+        .map(|&(_, bytes)| bytes)
+        .unwrap()
+}
+
+/// A copy of the table at `address` with the byte at `offset` flipped.
+fn corrupted(address: u64, offset: usize) -> &'static [u8] {
+    let mut bytes = table(address).to_vec();
+    bytes[offset] ^= 0xFF;
+
+    Vec::leak(bytes)
+}
+
+#[test]
+fn dumps_have_valid_checksums() {
+    for &(address, bytes) in &MEMORY[1..] {
+        assert!(checksum_valid(bytes), "Table at {:#x}", address);
+    }
+
+    assert!(checksum_valid(&table(RSDP)[..20]));
+    assert!(checksum_valid(table(RSDP)));
+}
+
+#[test]
+fn rsdp_points_to_the_xsdt() {
+    let tables = tables();
+
+    assert_eq!(tables.rsdp.revision, 2);
+    assert_eq!(&tables.rsdp.oem_id, b"BOCHS ");
+    assert_eq!(tables.rsdp.xsdt_address, Some(XSDT));
+    assert_eq!(tables.root().signature(), Signature::XSDT);
+    assert_eq!(
+        tables.addresses().collect::<Vec<_>>(),
+        [FACP, APIC, HPET, MCFG]
+    );
+
+    let signatures: Vec<_> = tables
+        .iter()
+        .map(|table| table.unwrap().signature())
+        .collect();
+    assert_eq!(
+        signatures,
+        [
+            Signature::FADT,
+            Signature::MADT,
+            Signature::HPET,
+            Signature::MCFG
+        ]
+    );
+}
+
+#[test]
+fn corrupted_checksums_are_rejected() {
+    assert_eq!(
+        Rsdp::parse(corrupted(RSDP, 9)).err(),
+        Some(AcpiError::BadRsdpChecksum)
+    );
+    // Only covered by the extended checksum.
+    assert_eq!(
+        Rsdp::parse(corrupted(RSDP, 24)).err(),
+        Some(AcpiError::BadRsdpChecksum)
+    );
+
+    assert_eq!(
+        Sdt::new(corrupted(APIC, 50)).err(),
+        Some(AcpiError::BadChecksum(Signature::MADT))
+    );
+    assert_eq!(
+        Sdt::new(&table(HPET)[..40]).err(),
+        Some(AcpiError::TableTooShort(Signature::HPET))
+    );
+
+    let hpet = Sdt::new(table(HPET)).unwrap();
+    assert_eq!(
+        Madt::parse(hpet).err(),
+        Some(AcpiError::SignatureMismatch {
+            expected: Signature::MADT,
+            found: Signature::HPET
+        })
+    );
+}
+
+#[test]
+fn missing_tables_are_reported() {
+    let tables = tables();
+
+    assert_eq!(
+        tables.find(Signature(*b"SRAT")).err(),
+        Some(AcpiError::TableNotFound(Signature(*b"SRAT")))
+    );
+
+    let fadt = tables.get::<Fadt>().unwrap();
+    assert_eq!(
+        tables.table_at(fadt.dsdt_address()).err(),
+        Some(AcpiError::Unmapped(DSDT))
+    );
+}
+
+#[test]
+fn madt_entries() {
+    let madt = tables().get::<Madt>().unwrap();
+
+    assert_eq!(madt.local_apic_address, 0xFEE0_0000);
+    assert!(madt.has_legacy_pics());
+
+    let override_for = |source, gsi, flags| {
+        MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+            bus: 0,
+            source,
+            gsi,
+            flags: acpi::madt::InterruptFlags(flags),
+        })
+    };
+
+    assert_eq!(
+        madt.entries().collect::<Vec<_>>(),
+        [
+            MadtEntry::LocalApic(LocalApic {
+                processor_id: 0,
+                apic_id: 0,
+                flags: 1
+            }),
+            MadtEntry::IoApic(IoApic {
+                id: 0,
+                address: 0xFEC0_0000,
+                gsi_base: 0
+            }),
+            override_for(0, 2, 0),
+            override_for(5, 5, 0xD),
+            override_for(9, 9, 0xD),
+            override_for(10, 10, 0xD),
+            override_for(11, 11, 0xD),
+            MadtEntry::LocalApicNmi(LocalApicNmi {
+                processor_id: 0xFF,
+                flags: acpi::madt::InterruptFlags(0),
+                lint: 1
+            }),
+        ]
+    );
+
+    assert_eq!(madt.io_apics().count(), 1);
+}
+
+#[test]
+fn madt_override_flags() {
+    let madt = tables().get::<Madt>().unwrap();
+    let overrides: Vec<_> = madt.interrupt_source_overrides().collect();
+
+    // The PIT is wired to GSI 2 and keeps the ISA defaults.
+    let timer = overrides[0];
+    assert_eq!((timer.source, timer.gsi), (0, 2));
+    assert_eq!(timer.flags.polarity(), Polarity::ConformsToBus);
+    assert_eq!(timer.flags.trigger_mode(), TriggerMode::ConformsToBus);
+
+    // The PCI links are level triggered, active high.
+    for entry in &overrides[1..] {
+        assert_eq!(entry.source as u32, entry.gsi);
+        assert_eq!(entry.flags.polarity(), Polarity::ActiveHigh);
+        assert_eq!(entry.flags.trigger_mode(), TriggerMode::Level);
+    }
+}
+
+#[test]
+fn madt_iteration_stops_at_corrupt_lengths() {
+    // The length of the I/O APIC entry claims more bytes than the table has.
+    let mut bytes = table(APIC).to_vec();
+    bytes[44 + 8 + 1] = 0xFF;
+    bytes[9] = bytes[9].wrapping_sub(0xFF - 12);
+
+    let madt = Madt::parse(Sdt::new(Vec::leak(bytes)).unwrap()).unwrap();
+
+    assert_eq!(madt.entries().count(), 1);
+}
+
+#[test]
+fn hpet_block() {
+    let hpet = tables().get::<Hpet>().unwrap();
+
+    assert_eq!(hpet.hardware_revision(), 1);
+    assert_eq!(hpet.comparator_count(), 3);
+    assert!(hpet.counter_is_64bit());
+    assert!(hpet.legacy_replacement_capable());
+    assert_eq!(hpet.pci_vendor_id(), 0x8086);
+    assert_eq!(hpet.base_address.address_space, AddressSpace::SystemMemory);
+    assert_eq!(hpet.base_address.address, 0xFED0_0000);
+    assert_eq!(hpet.number, 0);
+}
+
+#[test]
+fn mcfg_entries() {
+    let mcfg = tables().get::<Mcfg>().unwrap();
+
+    assert_eq!(
+        mcfg.entries().collect::<Vec<_>>(),
+        [McfgEntry {
+            base_address: 0xB000_0000,
+            segment_group: 0,
+            start_bus: 0,
+            end_bus: 0xFF
+        }]
+    );
+}
+
+#[test]
+fn fadt_fields() {
+    let fadt = tables().get::<Fadt>().unwrap();
+
+    assert_eq!(fadt.header.revision, 3);
+    assert_eq!(fadt.sci_interrupt, 9);
+    assert_eq!(fadt.dsdt_address(), DSDT);
+    assert_eq!(fadt.century, 0x32);
+    assert!(fadt.has_8042());
+    assert!(!fadt.pm_timer_is_32bit());
+
+    let timer = fadt.pm_timer().unwrap();
+    assert_eq!(timer.address_space, AddressSpace::SystemIo);
+    assert_eq!((timer.address, timer.bit_width), (0x608, 32));
+
+    let (register, value) = fadt.reset().unwrap();
+    assert_eq!(register.address_space, AddressSpace::SystemIo);
+    assert_eq!((register.address, value), (0xCF9, 0x0F));
+}
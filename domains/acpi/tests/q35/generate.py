@@ -0,0 +1,82 @@
+#!/usr/bin/env python3
+#
+# Writes the ACPI table blobs next to this script for tests/q35.rs.
+#
+# The tables are built by hand after the layout of QEMU's q35 table builder with one CPU,
+# at the addresses OVMF places them. They are not dumped from a guest, so they only test
+# the parser against this reading of QEMU and the ACPI specification. They are a stand-in
+# until `acpidump -b` output from a q35 guest replaces them, tests/q35.rs describes how.
+# Delete this script then.
+
+import os
+import struct
+
+out = os.path.dirname(os.path.abspath(__file__))
+
+def fix(b, pos, start=0, end=None):
+    b = bytearray(b)
+    end = len(b) if end is None else end
+    b[pos] = 0
+    b[pos] = (-sum(b[start:end])) & 0xFF
+    return bytes(b)
+
+def sdt(sig, rev, body, oem_table=b'BXPC    '):
+    length = 36 + len(body)
+    hdr = struct.pack('<4sIBB6s8sI4sI', sig, length, rev, 0, b'BOCHS ', oem_table, 1, b'BXPC', 1)
+    return fix(hdr + body, 9)
+
+def gas(space, width, offset, access, address):
+    return struct.pack('<BBBBQ', space, width, offset, access, address)
+
+FACP, APIC, HPET, MCFG, DSDT, FACS = 0x7FB79000, 0x7FB78000, 0x7FB77000, 0x7FB76000, 0x7FB7A000, 0x7FBDD000
+XSDT, RSDP = 0x7FB7D0E8, 0x7FB7E014
+
+# MADT: one CPU, the I/O APIC, the ISA overrides QEMU reports and the LINT1 NMI.
+madt = struct.pack('<II', 0xFEE00000, 1)
+madt += struct.pack('<BBBBI', 0, 8, 0, 0, 1)
+madt += struct.pack('<BBBBII', 1, 12, 0, 0, 0xFEC00000, 0)
+madt += struct.pack('<BBBBIH', 2, 10, 0, 0, 2, 0)
+for irq in (5, 9, 10, 11):
+    madt += struct.pack('<BBBBIH', 2, 10, 0, irq, irq, 0x000D)
+madt += struct.pack('<BBBHB', 4, 6, 0xFF, 0, 1)
+apic = sdt(b'APIC', 1, madt)
+assert len(apic) == 0x78
+
+hpet = sdt(b'HPET', 1, struct.pack('<I', 0x8086A201) + gas(0, 0, 0, 0, 0xFED00000) + struct.pack('<BHB', 0, 0, 0))
+assert len(hpet) == 0x38
+
+mcfg = sdt(b'MCFG', 1, bytes(8) + struct.pack('<QHBBI', 0xB0000000, 0, 0, 0xFF, 0))
+assert len(mcfg) == 0x3C
+
+f = bytearray(244 - 36)
+def put(offset, fmt, *values):
+    struct.pack_into(fmt, f, offset - 36, *values)
+put(36, '<II', FACS, DSDT)
+put(44, '<BB', 1, 0)            # INT_MODEL, preferred PM profile
+put(46, '<H', 9)                # SCI
+put(48, '<IBB', 0xB2, 0x02, 0x03)
+put(56, '<I', 0x600)            # PM1a event
+put(64, '<I', 0x604)            # PM1a control
+put(76, '<I', 0x608)            # PM timer
+put(80, '<I', 0x620)            # GPE0
+put(88, '<BBBB', 4, 2, 4, 0x10)
+put(96, '<HH', 0xFFF, 0xFFF)
+put(108, '<BHB', 0x32, 0x0002, 0)
+put(112, '<I', 0x000484A5)
+put(116, '<12sB', gas(1, 8, 0, 0, 0xCF9), 0x0F)
+put(132, '<QQ', FACS, DSDT)
+put(148, '<12s', gas(1, 32, 0, 0, 0x600))
+put(172, '<12s', gas(1, 16, 0, 0, 0x604))
+put(208, '<12s', gas(1, 32, 0, 0, 0x608))
+put(220, '<12s', gas(1, 128, 0, 0, 0x620))
+facp = sdt(b'FACP', 3, bytes(f))
+assert len(facp) == 244
+
+xsdt = sdt(b'XSDT', 1, struct.pack('<4Q', FACP, APIC, HPET, MCFG))
+
+rsdp = struct.pack('<8sB6sBIIQB3s', b'RSD PTR ', 0, b'BOCHS ', 2, 0, 36, XSDT, 0, bytes(3))
+rsdp = fix(rsdp, 8, 0, 20)
+rsdp = fix(rsdp, 32, 0, 36)
+
+for name, blob in [('rsdp', rsdp), ('xsdt', xsdt), ('apic', apic), ('hpet', hpet), ('facp', facp), ('mcfg', mcfg)]:
+    open(os.path.join(out, name + '.dat'), 'wb').write(blob)
@@ -0,0 +1,268 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use idt::InterruptStackFrame;
+use kstructs::atomic_fn::AtomicFn;
+use spin::{Mutex, Once};
+use uio::kprintln;
+use x86_64::op::interrupts;
+
+use crate::timer::{self, Calibration};
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Frequency of the periodic tick of every core.
+pub const TICK_FREQUENCY: u64 = 1000;
+
+const NANOS_PER_TICK: u64 = NANOS_PER_SECOND / TICK_FREQUENCY;
+
+/// Number of timers that can be pending at once.
+const MAX_TIMERS: usize = 64;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+static SOURCE: Once<Source> = Once::new();
+
+/// Called on every core with each tick, see [`set_tick_handler`].
+static TICK_HANDLER: AtomicFn<fn()> = AtomicFn::new();
+
+static TIMERS: Mutex<[Option<Timer>; MAX_TIMERS]> = Mutex::new([None; MAX_TIMERS]);
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Called from the tick interrupt once the deadline of the timer has passed, see
+/// [`add_timer`].
+pub type TimerCallback = fn(TimerId);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockError {
+    /// All timer slots are in use.
+    NoFreeTimer,
+}
+
+/// What [`now`] reads, each variant holds the reading at the start of the clock.
+#[derive(Clone, Copy, Debug)]
+enum Source {
+    Tsc { base: u64, frequency: u64 },
+    Hpet { base: u64 },
+    Tick,
+}
+
+#[derive(Clone, Copy)]
+struct Timer {
+    id: TimerId,
+    deadline: u64,
+    callback: TimerCallback,
+}
+
+/// Calibrates the local APIC timer and starts the periodic tick on the bootstrap processor.
+pub(crate) fn init(local_apic: &crate::lapic::LocalApic) {
+    let calibration = timer::calibrate(local_apic);
+
+    log_calibration(calibration);
+
+    let source = SOURCE.call_once(|| {
+        if calibration.invariant_tsc && calibration.tsc_frequency > 0 {
+            Source::Tsc {
+                base: x86_64::op::rdtsc(),
+                frequency: calibration.tsc_frequency,
+            }
+        } else if let Some(base) = hpet::now() {
+            Source::Hpet { base }
+        } else {
+            Source::Tick
+        }
+    });
+
+    kprintln!(
+        "Clock: {} based",
+        match source {
+            Source::Tsc { .. } => "TSC",
+            Source::Hpet { .. } => "HPET",
+            Source::Tick => "tick",
+        }
+    );
+
+    idt::register(crate::TIMER_VECTOR, tick).expect("APIC timer vector already in use");
+
+    timer::start_periodic(crate::TIMER_VECTOR, TICK_FREQUENCY)
+        .expect("APIC timer can not run at the tick frequency");
+}
+
+/// Starts the periodic tick on an application processor.
+pub(crate) fn init_ap() {
+    timer::start_periodic(crate::TIMER_VECTOR, TICK_FREQUENCY)
+        .expect("APIC timer can not run at the tick frequency");
+}
+
+fn log_calibration(calibration: &Calibration) {
+    kprintln!(
+        "APIC timer: {} Hz, TSC {} Hz{}, calibrated against {}",
+        calibration.timer_frequency,
+        calibration.tsc_frequency,
+        if calibration.invariant_tsc {
+            " (invariant)"
+        } else {
+            ""
+        },
+        calibration.reference
+    );
+
+    if calibration.tsc_deadline {
+        kprintln!("APIC timer: TSC-deadline mode available");
+    }
+}
+
+/// Returns the nanoseconds since the clock was started.
+///
+/// Reads the TSC if it is invariant, otherwise the HPET. Without either the resolution
+/// is one tick.
+pub fn now() -> u64 {
+    match SOURCE.get() {
+        Some(&Source::Tsc { base, frequency }) => {
+            let elapsed = x86_64::op::rdtsc() - base;
+
+            (elapsed as u128 * NANOS_PER_SECOND as u128 / frequency as u128) as u64
+        }
+        Some(&Source::Hpet { base }) => hpet::now().unwrap_or(base) - base,
+        _ => TICKS.load(Ordering::Relaxed) * NANOS_PER_TICK,
+    }
+}
+
+/// Returns the number of ticks since the clock was started.
+#[inline]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the TSC value at the clock time `nanos`, for TSC-deadline mode.
+///
+/// Only available if the clock is TSC based.
+pub fn tsc_at(nanos: u64) -> Option<u64> {
+    match SOURCE.get() {
+        Some(&Source::Tsc { base, frequency }) => {
+            Some(base + (nanos as u128 * frequency as u128 / NANOS_PER_SECOND as u128) as u64)
+        }
+        _ => None,
+    }
+}
+
+/// Waits until [`now`] reaches `deadline`.
+///
+/// Halts between ticks if interrupts are enabled, spins otherwise.
+pub fn sleep_until(deadline: u64) {
+    while now() < deadline {
+        if interrupts::are_enabled() {
+            interrupts::hlt();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Waits for `duration` nanoseconds.
+pub fn sleep(duration: u64) {
+    sleep_until(now().saturating_add(duration));
+}
+
+/// Registers a function called on every core with each tick, after the expired timers ran.
+///
+/// The scheduler preempts threads from it, so the handler may switch away and return much
+/// later.
+pub fn set_tick_handler(handler: fn()) {
+    TICK_HANDLER.store(handler);
+}
+
+/// Calls `callback` from the tick interrupt once [`now`] reaches `deadline`.
+///
+/// The callback runs in IRQ context with interrupts disabled. It must not block, take locks
+/// threads hold with interrupts enabled or print, and should only record what happened for
+/// a thread to act on.
+pub fn add_timer(deadline: u64, callback: TimerCallback) -> Result<TimerId, ClockError> {
+    let id = TimerId(NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed));
+
+    interrupts::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let slot = timers
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ClockError::NoFreeTimer)?;
+
+        *slot = Some(Timer {
+            id,
+            deadline,
+            callback,
+        });
+
+        Ok(id)
+    })
+}
+
+/// Cancels a pending timer, returns false if it already fired or never existed.
+pub fn cancel_timer(id: TimerId) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+
+        match timers
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|timer| timer.id == id))
+        {
+            Some(slot) => {
+                *slot = None;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Removes the first timer whose deadline has passed.
+fn take_expired(now: u64) -> Option<Timer> {
+    let mut timers = TIMERS.lock();
+
+    timers
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|timer| timer.deadline <= now))
+        .and_then(Option::take)
+}
+
+fn tick(_vector: u8, _stack_frame: &InterruptStackFrame) {
+    let local_apic = crate::local_apic();
+
+    // Interrupts stay disabled until the handler returns, callbacks may switch away.
+    local_apic.end_of_interrupt();
+
+    // The clock and the timers advance with the tick of the bootstrap processor only.
+    if local_apic.is_bsp() {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+
+        let now = now();
+
+        // The lock is released before each callback, so callbacks can add timers.
+        while let Some(timer) = take_expired(now) {
+            (timer.callback)(timer.id);
+        }
+    }
+
+    if let Some(handler) = TICK_HANDLER.load() {
+        handler();
+    }
+}
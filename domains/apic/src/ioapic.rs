@@ -0,0 +1,291 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::ptr::{read_volatile, write_volatile};
+
+use x86_64::structures::memory::{PhysicalAddress, VirtualAddress};
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+const MMIO_SIZE: u64 = 0x20;
+
+const REGISTER_ID: u32 = 0x00;
+const REGISTER_VERSION: u32 = 0x01;
+/// The redirection table starts here, two registers per entry.
+const REGISTER_REDIRECTION_TABLE: u32 = 0x10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryMode {
+    Fixed = 0b000,
+    LowestPriority = 0b001,
+    Smi = 0b010,
+    Nmi = 0b100,
+    Init = 0b101,
+    ExtInt = 0b111,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// An entry of the redirection table, describing how one GSI is delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub delivery_mode: DeliveryMode,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+    /// APIC ID of the target core, physical destination mode only.
+    pub destination: u8,
+    pub masked: bool,
+}
+
+impl RedirectionEntry {
+    /// A masked entry, the state every entry is put into during initialisation.
+    pub const fn masked() -> Self {
+        Self {
+            vector: 0,
+            delivery_mode: DeliveryMode::Fixed,
+            polarity: Polarity::ActiveHigh,
+            trigger_mode: TriggerMode::Edge,
+            destination: 0,
+            masked: true,
+        }
+    }
+
+    pub const fn as_u64(&self) -> u64 {
+        (self.vector as u64)
+            | (self.delivery_mode as u64) << 8
+            | (matches!(self.polarity, Polarity::ActiveLow) as u64) << 13
+            | (matches!(self.trigger_mode, TriggerMode::Level) as u64) << 15
+            | (self.masked as u64) << 16
+            | (self.destination as u64) << 56
+    }
+
+    pub const fn from_u64(value: u64) -> Self {
+        Self {
+            vector: value as u8,
+            delivery_mode: match (value >> 8) & 0b111 {
+                0b001 => DeliveryMode::LowestPriority,
+                0b010 => DeliveryMode::Smi,
+                0b100 => DeliveryMode::Nmi,
+                0b101 => DeliveryMode::Init,
+                0b111 => DeliveryMode::ExtInt,
+                _ => DeliveryMode::Fixed,
+            },
+            polarity: if value & (1 << 13) != 0 {
+                Polarity::ActiveLow
+            } else {
+                Polarity::ActiveHigh
+            },
+            trigger_mode: if value & (1 << 15) != 0 {
+                TriggerMode::Level
+            } else {
+                TriggerMode::Edge
+            },
+            destination: (value >> 56) as u8,
+            masked: value & (1 << 16) != 0,
+        }
+    }
+}
+
+/// One I/O APIC, serving the GSIs `gsi_base..gsi_base + entries`.
+///
+/// Registers are accessed through a select/window pair, callers have to serialise access.
+#[derive(Debug)]
+pub struct IoApic {
+    base: VirtualAddress,
+    gsi_base: u32,
+    entries: u8,
+}
+
+impl IoApic {
+    /// Maps the registers of the I/O APIC at `address` and masks all of its entries.
+    ///
+    /// # Safety
+    /// `address` has to be the register base of an I/O APIC, reported by the MADT.
+    pub unsafe fn new(address: PhysicalAddress, gsi_base: u32) -> Self {
+        let base = pmm::mapping::map_mmio(address, MMIO_SIZE)
+            .expect("Failed to map the I/O APIC registers");
+
+        let mut io_apic = Self {
+            base,
+            gsi_base,
+            entries: 0,
+        };
+
+        io_apic.entries = ((io_apic.read(REGISTER_VERSION) >> 16) as u8).wrapping_add(1);
+
+        for index in 0..io_apic.entries {
+            io_apic.set_redirection(index, RedirectionEntry::masked());
+        }
+
+        io_apic
+    }
+
+    #[inline]
+    pub fn id(&self) -> u8 {
+        ((self.read(REGISTER_ID) >> 24) & 0xF) as u8
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.read(REGISTER_VERSION) as u8
+    }
+
+    #[inline]
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    /// Number of redirection entries, i.e. GSIs served.
+    #[inline]
+    pub fn entries(&self) -> u8 {
+        self.entries
+    }
+
+    #[inline]
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi - self.gsi_base < self.entries as u32
+    }
+
+    pub fn redirection(&self, index: u8) -> RedirectionEntry {
+        let register = REGISTER_REDIRECTION_TABLE + index as u32 * 2;
+        let low = self.read(register) as u64;
+        let high = self.read(register + 1) as u64;
+
+        RedirectionEntry::from_u64(high << 32 | low)
+    }
+
+    pub fn set_redirection(&mut self, index: u8, entry: RedirectionEntry) {
+        assert!(
+            index < self.entries,
+            "Redirection entry {} out of range",
+            index
+        );
+
+        let register = REGISTER_REDIRECTION_TABLE + index as u32 * 2;
+        let value = entry.as_u64();
+
+        // Mask first, so the entry never fires half written.
+        self.write(register, RedirectionEntry::masked().as_u64() as u32);
+        self.write(register + 1, (value >> 32) as u32);
+        self.write(register, value as u32);
+    }
+
+    pub fn set_masked(&mut self, index: u8, masked: bool) {
+        let mut entry = self.redirection(index);
+        entry.masked = masked;
+        self.set_redirection(index, entry);
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            write_volatile((self.base + IOREGSEL).as_mut_ptr::<u32>(), register);
+            read_volatile((self.base + IOWIN).as_ptr::<u32>())
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            write_volatile((self.base + IOREGSEL).as_mut_ptr::<u32>(), register);
+            write_volatile((self.base + IOWIN).as_mut_ptr::<u32>(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELIVERY_MODES: [DeliveryMode; 6] = [
+        DeliveryMode::Fixed,
+        DeliveryMode::LowestPriority,
+        DeliveryMode::Smi,
+        DeliveryMode::Nmi,
+        DeliveryMode::Init,
+        DeliveryMode::ExtInt,
+    ];
+
+    #[test]
+    fn entries_round_trip() {
+        for delivery_mode in DELIVERY_MODES {
+            for polarity in [Polarity::ActiveHigh, Polarity::ActiveLow] {
+                for trigger_mode in [TriggerMode::Edge, TriggerMode::Level] {
+                    for masked in [false, true] {
+                        let entry = RedirectionEntry {
+                            vector: 0x20 + delivery_mode as u8,
+                            delivery_mode,
+                            polarity,
+                            trigger_mode,
+                            destination: 0xA5,
+                            masked,
+                        };
+
+                        assert_eq!(RedirectionEntry::from_u64(entry.as_u64()), entry);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn entry_matches_the_register_layout() {
+        let entry = RedirectionEntry {
+            vector: 0x30,
+            delivery_mode: DeliveryMode::Fixed,
+            polarity: Polarity::ActiveLow,
+            trigger_mode: TriggerMode::Level,
+            destination: 3,
+            masked: true,
+        };
+
+        assert_eq!(entry.as_u64(), 0x0300_0000_0001_A030);
+        assert_eq!(RedirectionEntry::from_u64(0x0300_0000_0001_A030), entry);
+
+        let nmi = RedirectionEntry {
+            delivery_mode: DeliveryMode::Nmi,
+            masked: false,
+            ..RedirectionEntry::masked()
+        };
+        assert_eq!(nmi.as_u64(), 0x400);
+
+        assert_eq!(RedirectionEntry::masked().as_u64(), 1 << 16);
+    }
+
+    #[test]
+    fn reserved_bits_are_ignored() {
+        // Delivery status, remote IRR and destination mode are read only or unused here.
+        let entry = RedirectionEntry::from_u64(0x0100_0000_0000_5F41);
+
+        assert_eq!(entry.vector, 0x41);
+        assert_eq!(entry.delivery_mode, DeliveryMode::ExtInt);
+        assert_eq!(entry.polarity, Polarity::ActiveHigh);
+        assert_eq!(entry.trigger_mode, TriggerMode::Edge);
+        assert_eq!(entry.destination, 1);
+        assert!(!entry.masked);
+        assert_eq!(entry.as_u64(), 0x0100_0000_0000_0741);
+    }
+}
@@ -0,0 +1,345 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::ptr::{read_volatile, write_volatile};
+
+use raw_cpuid::CpuId;
+
+use crate::ioapic::DeliveryMode;
+use x86_64::op::interrupts;
+use x86_64::registers::Msr;
+use x86_64::structures::memory::{PhysicalAddress, VirtualAddress};
+
+/// IA32_APIC_BASE, holds the MMIO base and the enable bits.
+const APIC_BASE_MSR: Msr = Msr::new(0x1B);
+const APIC_BASE_BSP: u64 = 1 << 8;
+const APIC_BASE_X2APIC_ENABLE: u64 = 1 << 10;
+const APIC_BASE_GLOBAL_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// First MSR of the x2APIC register block. Register `offset` of the xAPIC is MSR `0x800 + offset / 16`.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Size of the xAPIC register page.
+const XAPIC_MMIO_SIZE: u64 = 0x1000;
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+
+const LVT_TIMER_ONE_SHOT: u32 = 0b00 << 17;
+const LVT_TIMER_PERIODIC: u32 = 0b01 << 17;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+
+/// Divide configuration for a divider of 16.
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// The local APIC timer counts at the bus frequency divided by this.
+pub const TIMER_DIVIDER: u64 = 16;
+
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_SHORTHAND_SHIFT: u32 = 18;
+
+/// IA32_TSC_DEADLINE, the timer fires once the TSC reaches the written value.
+const TSC_DEADLINE_MSR: Msr = Msr::new(0x6E0);
+
+/// Registers of the local APIC, as offsets into the xAPIC page.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    Id = 0x020,
+    Version = 0x030,
+    TaskPriority = 0x080,
+    EndOfInterrupt = 0x0B0,
+    LogicalDestination = 0x0D0,
+    DestinationFormat = 0x0E0,
+    SpuriousInterruptVector = 0x0F0,
+    ErrorStatus = 0x280,
+    InterruptCommandLow = 0x300,
+    InterruptCommandHigh = 0x310,
+    LvtTimer = 0x320,
+    LvtThermalSensor = 0x330,
+    LvtPerformanceCounter = 0x340,
+    LvtLint0 = 0x350,
+    LvtLint1 = 0x360,
+    LvtError = 0x370,
+    TimerInitialCount = 0x380,
+    TimerCurrentCount = 0x390,
+    TimerDivideConfiguration = 0x3E0,
+}
+
+/// Operating mode of the local APIC timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerMode {
+    /// Counts down the initial count once.
+    OneShot,
+    /// Counts down the initial count, then reloads it.
+    Periodic,
+    /// Fires when the TSC reaches the value in IA32_TSC_DEADLINE.
+    TscDeadline,
+}
+
+/// Cores an inter-processor interrupt is sent to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpiDestination {
+    /// The core with this APIC ID.
+    Apic(u32),
+    /// The executing core.
+    This,
+    All,
+    AllExcludingSelf,
+}
+
+/// How the registers of the local APIC are reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocalApicMode {
+    /// Memory mapped registers.
+    XApic { base: VirtualAddress },
+    /// Registers accessed through MSRs.
+    X2Apic,
+}
+
+/// The local APIC of the executing core.
+///
+/// Every core sees its own local APIC under the same address or MSRs, so a single instance
+/// serves all cores once each of them enabled its APIC.
+#[derive(Debug)]
+pub struct LocalApic {
+    mode: LocalApicMode,
+}
+
+impl LocalApic {
+    /// Detects the local APIC, switches it to x2APIC mode if supported and otherwise maps the
+    /// xAPIC register page.
+    ///
+    /// # Safety
+    /// Must only be called once, after the PMM is initialised.
+    ///
+    /// # Panics
+    /// If the processor has no local APIC.
+    pub unsafe fn new() -> Self {
+        let features = CpuId::new().get_feature_info();
+
+        assert!(
+            features
+                .as_ref()
+                .is_some_and(|features| features.has_apic()),
+            "No local APIC present."
+        );
+
+        let base = APIC_BASE_MSR.read();
+
+        let mode = if features.is_some_and(|features| features.has_x2apic()) {
+            APIC_BASE_MSR.write(base | APIC_BASE_GLOBAL_ENABLE | APIC_BASE_X2APIC_ENABLE);
+
+            LocalApicMode::X2Apic
+        } else {
+            APIC_BASE_MSR.write(base | APIC_BASE_GLOBAL_ENABLE);
+
+            let address = PhysicalAddress::new(base & APIC_BASE_ADDRESS_MASK);
+            let base = pmm::mapping::map_mmio(address, XAPIC_MMIO_SIZE)
+                .expect("Failed to map the local APIC registers");
+
+            LocalApicMode::XApic { base }
+        };
+
+        Self { mode }
+    }
+
+    #[inline]
+    pub fn mode(&self) -> LocalApicMode {
+        self.mode
+    }
+
+    /// Enables the local APIC of the executing core.
+    ///
+    /// All local interrupt sources are masked, except errors which are delivered on
+    /// `error_vector`. Interrupts the APIC can not attribute to a source arrive on
+    /// `spurious_vector`.
+    ///
+    /// # Safety
+    /// Handlers for both vectors have to be installed.
+    pub unsafe fn enable(&self, spurious_vector: u8, error_vector: u8) {
+        if let LocalApicMode::X2Apic = self.mode {
+            // Application processors start in xAPIC mode.
+            let base = APIC_BASE_MSR.read();
+            APIC_BASE_MSR.write(base | APIC_BASE_GLOBAL_ENABLE | APIC_BASE_X2APIC_ENABLE);
+        }
+
+        for register in [
+            Register::LvtTimer,
+            Register::LvtThermalSensor,
+            Register::LvtPerformanceCounter,
+            Register::LvtLint0,
+            Register::LvtLint1,
+        ] {
+            self.write(register, LVT_MASKED);
+        }
+
+        self.write(Register::LvtError, error_vector as u32);
+        self.clear_errors();
+
+        self.write(Register::TaskPriority, 0);
+        self.write(
+            Register::SpuriousInterruptVector,
+            SPURIOUS_APIC_ENABLE | spurious_vector as u32,
+        );
+
+        self.end_of_interrupt();
+    }
+
+    /// Returns the APIC ID of the executing core.
+    #[inline]
+    pub fn id(&self) -> u32 {
+        let id = self.read(Register::Id);
+
+        match self.mode {
+            LocalApicMode::XApic { .. } => id >> 24,
+            LocalApicMode::X2Apic => id,
+        }
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.read(Register::Version) as u8
+    }
+
+    /// Number of local vector table entries.
+    #[inline]
+    pub fn lvt_entries(&self) -> u8 {
+        (self.read(Register::Version) >> 16) as u8 + 1
+    }
+
+    /// Returns whether the executing core is the bootstrap processor.
+    #[inline]
+    pub fn is_bsp(&self) -> bool {
+        unsafe { APIC_BASE_MSR.read() & APIC_BASE_BSP != 0 }
+    }
+
+    /// Signals the end of the interrupt currently being serviced.
+    #[inline]
+    pub fn end_of_interrupt(&self) {
+        self.write(Register::EndOfInterrupt, 0);
+    }
+
+    /// Programs the timer entry of the local vector table and stops the timer.
+    ///
+    /// The timer is started by [`Self::set_timer_initial_count`] or, in TSC-deadline mode,
+    /// by [`Self::set_tsc_deadline`].
+    pub fn configure_timer(&self, vector: u8, mode: TimerMode) {
+        let mode = match mode {
+            TimerMode::OneShot => LVT_TIMER_ONE_SHOT,
+            TimerMode::Periodic => LVT_TIMER_PERIODIC,
+            TimerMode::TscDeadline => LVT_TIMER_TSC_DEADLINE,
+        };
+
+        self.write(Register::TimerInitialCount, 0);
+        self.write(Register::TimerDivideConfiguration, TIMER_DIVIDE_BY_16);
+        self.write(Register::LvtTimer, mode | vector as u32);
+    }
+
+    /// Starts the timer in one-shot or periodic mode, 0 stops it.
+    #[inline]
+    pub fn set_timer_initial_count(&self, count: u32) {
+        self.write(Register::TimerInitialCount, count);
+    }
+
+    #[inline]
+    pub fn timer_current_count(&self) -> u32 {
+        self.read(Register::TimerCurrentCount)
+    }
+
+    /// Arms the timer in TSC-deadline mode, 0 disarms it.
+    ///
+    /// # Safety
+    /// The processor has to support TSC-deadline mode, the MSR does not exist otherwise.
+    pub unsafe fn set_tsc_deadline(&self, deadline: u64) {
+        // Make sure the LVT write is not ordered after the MSR write.
+        core::arch::asm!("mfence", options(nostack, preserves_flags));
+        TSC_DEADLINE_MSR.write(deadline);
+    }
+
+    /// Stops and masks the timer.
+    pub fn stop_timer(&self) {
+        self.write(Register::TimerInitialCount, 0);
+        self.write(Register::LvtTimer, LVT_MASKED);
+    }
+
+    /// Sends an inter-processor interrupt and waits until the APIC accepted it.
+    pub fn send_ipi(&self, vector: u8, delivery_mode: DeliveryMode, destination: IpiDestination) {
+        let (shorthand, apic_id) = match destination {
+            IpiDestination::Apic(apic_id) => (0b00, apic_id),
+            IpiDestination::This => (0b01, 0),
+            IpiDestination::All => (0b10, 0),
+            IpiDestination::AllExcludingSelf => (0b11, 0),
+        };
+
+        let low = vector as u32
+            | (delivery_mode as u32) << 8
+            | ICR_LEVEL_ASSERT
+            | shorthand << ICR_SHORTHAND_SHIFT;
+
+        match self.mode {
+            LocalApicMode::XApic { .. } => {
+                // Writing the low half sends the interrupt. A handler sending an IPI between
+                // the two writes would replace the destination.
+                interrupts::without_interrupts(|| {
+                    self.write(Register::InterruptCommandHigh, apic_id << 24);
+                    self.write(Register::InterruptCommandLow, low);
+
+                    while self.read(Register::InterruptCommandLow) & ICR_DELIVERY_PENDING != 0 {
+                        core::hint::spin_loop();
+                    }
+                });
+            }
+            // The x2APIC has a single 64-bit register and no delivery status.
+            LocalApicMode::X2Apic => unsafe {
+                Self::msr(Register::InterruptCommandLow).write((apic_id as u64) << 32 | low as u64)
+            },
+        }
+    }
+
+    /// Returns the errors recorded since the last call and clears them.
+    pub fn clear_errors(&self) -> u32 {
+        // The register is only updated by a write.
+        self.write(Register::ErrorStatus, 0);
+        self.read(Register::ErrorStatus)
+    }
+
+    pub fn read(&self, register: Register) -> u32 {
+        match self.mode {
+            LocalApicMode::XApic { base } => unsafe {
+                read_volatile((base + register as u64).as_ptr::<u32>())
+            },
+            LocalApicMode::X2Apic => unsafe { Self::msr(register).read() as u32 },
+        }
+    }
+
+    pub fn write(&self, register: Register, value: u32) {
+        match self.mode {
+            LocalApicMode::XApic { base } => unsafe {
+                write_volatile((base + register as u64).as_mut_ptr::<u32>(), value)
+            },
+            LocalApicMode::X2Apic => unsafe { Self::msr(register).write(value as u64) },
+        }
+    }
+
+    #[inline]
+    fn msr(register: Register) -> Msr {
+        Msr::new(X2APIC_MSR_BASE + register as u32 / 16)
+    }
+}
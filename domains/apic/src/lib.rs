@@ -14,4 +14,268 @@
  * You should have received a copy of the GNU General Public License
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod clock;
+pub mod ioapic;
+pub mod lapic;
+mod reference;
+pub mod timer;
+
+use acpi::madt::{InterruptSourceOverride, Madt};
+use alloc::vec::Vec;
+use idt::InterruptStackFrame;
+use ioapic::{DeliveryMode, IoApic, Polarity, RedirectionEntry, TriggerMode};
+use lapic::{LocalApic, LocalApicMode};
+use spin::{Mutex, Once};
+use uio::kprintln;
+use x86_64::op::interrupts;
+use x86_64::structures::memory::PhysicalAddress;
+
+/// Vector of interrupts the local APIC could not attribute to a source.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Vector of the local APIC error interrupt.
+pub const ERROR_VECTOR: u8 = 0xFE;
+
+/// Vector of the local APIC timer, it drives the [`clock`].
+pub const TIMER_VECTOR: u8 = 0xF0;
+
+/// Vector of the inter-processor interrupt asking a core to pick its next thread again.
+pub const RESCHEDULE_VECTOR: u8 = 0xFC;
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+static IO_APICS: Once<Mutex<IoApics>> = Once::new();
+
+/// The I/O APICs of the system and the ISA IRQ overrides from the MADT.
+struct IoApics {
+    controllers: Vec<IoApic>,
+    overrides: Vec<InterruptSourceOverride>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoutingError {
+    /// No I/O APIC serves the GSI.
+    NoIoApic(u32),
+    /// The APIC ID can not be addressed by an I/O APIC in physical destination mode.
+    InvalidDestination(u32),
+}
+
+/// Masks the 8259 PICs and stops the PIT tick, enables the local APIC of the bootstrap
+/// processor, brings up the I/O APICs and starts the clock.
+pub fn init() {
+    legacy::disable();
+
+    // SAFETY: `call_once` runs this only once, the kernel initialises the PMM before.
+    let local_apic = LOCAL_APIC.call_once(|| unsafe { LocalApic::new() });
+
+    idt::register(SPURIOUS_VECTOR, spurious_interrupt)
+        .expect("Spurious interrupt vector already in use");
+    idt::register(ERROR_VECTOR, error_interrupt).expect("APIC error vector already in use");
+
+    // SAFETY: Both handlers were just installed.
+    unsafe { local_apic.enable(SPURIOUS_VECTOR, ERROR_VECTOR) };
+
+    idt::set_end_of_interrupt_handler(end_of_interrupt);
+
+    kprintln!(
+        "LAPIC: {} mode, ID {}, version {:#x}, {} LVT entries",
+        match local_apic.mode() {
+            LocalApicMode::XApic { .. } => "xAPIC",
+            LocalApicMode::X2Apic => "x2APIC",
+        },
+        local_apic.id(),
+        local_apic.version(),
+        local_apic.lvt_entries()
+    );
+
+    IO_APICS.call_once(|| Mutex::new(init_io_apics()));
+
+    clock::init(local_apic);
+}
+
+/// Enables the local APIC of an application processor and starts its timer.
+///
+/// # Panics
+/// If called before [`init`] ran on the bootstrap processor.
+pub fn init_ap() {
+    let local_apic = local_apic();
+
+    // SAFETY: The IDT is shared, [`init`] installed both handlers.
+    unsafe { local_apic.enable(SPURIOUS_VECTOR, ERROR_VECTOR) };
+
+    clock::init_ap();
+}
+
+fn init_io_apics() -> IoApics {
+    let madt = match acpi::get::<Madt>() {
+        Ok(madt) => madt,
+        Err(error) => {
+            kprintln!(
+                "IOAPIC: No MADT ({:?}), external interrupts are unavailable.",
+                error
+            );
+
+            return IoApics {
+                controllers: Vec::new(),
+                overrides: Vec::new(),
+            };
+        }
+    };
+
+    let controllers: Vec<IoApic> = madt
+        .io_apics()
+        .map(|entry| {
+            // SAFETY: The address comes from the MADT.
+            let io_apic =
+                unsafe { IoApic::new(PhysicalAddress::new(entry.address as u64), entry.gsi_base) };
+
+            kprintln!(
+                "IOAPIC: ID {}, version {:#x}, GSIs {}-{}",
+                entry.id,
+                io_apic.version(),
+                io_apic.gsi_base(),
+                io_apic.gsi_base() + io_apic.entries() as u32 - 1
+            );
+
+            io_apic
+        })
+        .collect();
+
+    let overrides: Vec<InterruptSourceOverride> = madt.interrupt_source_overrides().collect();
+
+    for entry in &overrides {
+        kprintln!("IOAPIC: IRQ {} -> GSI {}", entry.source, entry.gsi);
+    }
+
+    IoApics {
+        controllers,
+        overrides,
+    }
+}
+
+/// Returns the GSI, polarity and trigger mode of the ISA IRQ `irq`.
+///
+/// Without an override, ISA IRQs are identity mapped, active high and edge triggered.
+pub fn isa_irq_to_gsi(irq: u8) -> (u32, Polarity, TriggerMode) {
+    let overridden = IO_APICS.get().and_then(|io_apics| {
+        interrupts::without_interrupts(|| {
+            io_apics
+                .lock()
+                .overrides
+                .iter()
+                .find(|entry| entry.bus == 0 && entry.source == irq)
+                .copied()
+        })
+    });
+
+    let Some(entry) = overridden else {
+        return (irq as u32, Polarity::ActiveHigh, TriggerMode::Edge);
+    };
+
+    // Conforming to the bus means active high and edge triggered for ISA.
+    let polarity = match entry.flags.polarity() {
+        acpi::madt::Polarity::ActiveLow => Polarity::ActiveLow,
+        _ => Polarity::ActiveHigh,
+    };
+
+    let trigger_mode = match entry.flags.trigger_mode() {
+        acpi::madt::TriggerMode::Level => TriggerMode::Level,
+        _ => TriggerMode::Edge,
+    };
+
+    (entry.gsi, polarity, trigger_mode)
+}
+
+/// Delivers the ISA IRQ `irq` as `vector` to the core with the APIC ID `destination`.
+///
+/// Returns the GSI the IRQ is connected to.
+pub fn route_irq(irq: u8, vector: u8, destination: u32) -> Result<u32, RoutingError> {
+    let (gsi, polarity, trigger_mode) = isa_irq_to_gsi(irq);
+
+    route_gsi(gsi, vector, destination, polarity, trigger_mode)?;
+
+    Ok(gsi)
+}
+
+/// Delivers `gsi` as `vector` to the core with the APIC ID `destination` and unmasks it.
+pub fn route_gsi(
+    gsi: u32,
+    vector: u8,
+    destination: u32,
+    polarity: Polarity,
+    trigger_mode: TriggerMode,
+) -> Result<(), RoutingError> {
+    let destination =
+        u8::try_from(destination).map_err(|_| RoutingError::InvalidDestination(destination))?;
+
+    with_io_apic(gsi, |io_apic, index| {
+        io_apic.set_redirection(
+            index,
+            RedirectionEntry {
+                vector,
+                delivery_mode: DeliveryMode::Fixed,
+                polarity,
+                trigger_mode,
+                destination,
+                masked: false,
+            },
+        )
+    })
+}
+
+pub fn mask_gsi(gsi: u32) -> Result<(), RoutingError> {
+    with_io_apic(gsi, |io_apic, index| io_apic.set_masked(index, true))
+}
+
+pub fn unmask_gsi(gsi: u32) -> Result<(), RoutingError> {
+    with_io_apic(gsi, |io_apic, index| io_apic.set_masked(index, false))
+}
+
+/// Runs `f` on the I/O APIC handling `gsi`. Interrupt handlers mask their lines, so the
+/// lock is only taken with interrupts disabled.
+fn with_io_apic(gsi: u32, f: impl FnOnce(&mut IoApic, u8)) -> Result<(), RoutingError> {
+    let io_apics = IO_APICS.get().ok_or(RoutingError::NoIoApic(gsi))?;
+
+    interrupts::without_interrupts(|| {
+        let mut io_apics = io_apics.lock();
+
+        let io_apic = io_apics
+            .controllers
+            .iter_mut()
+            .find(|io_apic| io_apic.handles(gsi))
+            .ok_or(RoutingError::NoIoApic(gsi))?;
+
+        let index = (gsi - io_apic.gsi_base()) as u8;
+        f(io_apic, index);
+
+        Ok(())
+    })
+}
+
+/// Returns the local APIC driver.
+///
+/// # Panics
+/// If called before [`init`].
+pub fn local_apic() -> &'static LocalApic {
+    LOCAL_APIC.get().expect("Local APIC is not initialised")
+}
+
+/// Signals the end of the current interrupt to the local APIC.
+#[inline]
+pub fn end_of_interrupt() {
+    local_apic().end_of_interrupt()
+}
+
+/// Spurious interrupts are not in service, they must not be acknowledged.
+fn spurious_interrupt(_vector: u8, _stack_frame: &InterruptStackFrame) {}
+
+fn error_interrupt(_vector: u8, _stack_frame: &InterruptStackFrame) {
+    let local_apic = local_apic();
+
+    kprintln!("LAPIC: error {:#x}", local_apic.clear_errors());
+    local_apic.end_of_interrupt();
+}
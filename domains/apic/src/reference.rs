@@ -0,0 +1,53 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use hpet::device::Hpet;
+use uio::kprintln;
+
+/// A clock of known frequency, used to calibrate the local APIC timer and the TSC.
+pub(crate) enum ReferenceClock {
+    Hpet(&'static Hpet),
+    Pit,
+}
+
+impl ReferenceClock {
+    /// Prefers the HPET, the PIT is always there on PCs.
+    pub fn detect() -> Self {
+        match hpet::hpet() {
+            Some(hpet) => Self::Hpet(hpet),
+            None => {
+                kprintln!("APIC timer: No HPET, calibrating against the PIT.");
+                Self::Pit
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Hpet(_) => "HPET",
+            Self::Pit => "PIT",
+        }
+    }
+
+    /// Busy waits for `duration` nanoseconds.
+    pub fn wait(&self, duration: u64) {
+        match *self {
+            Self::Hpet(hpet) => hpet.wait(duration),
+            Self::Pit => legacy::pit::wait(duration),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use raw_cpuid::CpuId;
+use spin::Once;
+
+use crate::clock::NANOS_PER_SECOND;
+use crate::lapic::{LocalApic, TimerMode};
+use crate::reference::ReferenceClock;
+
+/// Length of a single calibration run.
+const CALIBRATION_WINDOW_NS: u64 = 50_000_000;
+const CALIBRATION_RUNS: usize = 3;
+
+static CALIBRATION: Once<Calibration> = Once::new();
+
+/// Frequencies of the local APIC timer and the TSC, measured against a reference clock.
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    /// Decrements of the timer counter per second, with the divider applied.
+    pub timer_frequency: u64,
+    pub tsc_frequency: u64,
+    /// The TSC runs at a constant rate in all power states.
+    pub invariant_tsc: bool,
+    /// The timer supports TSC-deadline mode.
+    pub tsc_deadline: bool,
+    /// Name of the clock the frequencies were measured against.
+    pub reference: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerError {
+    /// [`calibrate`] has not run yet.
+    NotCalibrated,
+    TscDeadlineUnsupported,
+    /// The requested period does not fit into the 32-bit counter.
+    OutOfRange,
+}
+
+/// Measures the frequencies of the local APIC timer and the TSC.
+///
+/// Runs only once, later calls return the first result.
+pub(crate) fn calibrate(local_apic: &LocalApic) -> &'static Calibration {
+    CALIBRATION.call_once(|| {
+        let reference = ReferenceClock::detect();
+        let mut timer_frequencies = [0; CALIBRATION_RUNS];
+        let mut tsc_frequencies = [0; CALIBRATION_RUNS];
+
+        for run in 0..CALIBRATION_RUNS {
+            let (timer_frequency, tsc_frequency) = measure(local_apic, &reference);
+            timer_frequencies[run] = timer_frequency;
+            tsc_frequencies[run] = tsc_frequency;
+        }
+
+        timer_frequencies.sort_unstable();
+        tsc_frequencies.sort_unstable();
+
+        let cpuid = CpuId::new();
+
+        Calibration {
+            timer_frequency: timer_frequencies[CALIBRATION_RUNS / 2],
+            tsc_frequency: tsc_frequencies[CALIBRATION_RUNS / 2],
+            invariant_tsc: cpuid
+                .get_advanced_power_mgmt_info()
+                .is_some_and(|info| info.has_invariant_tsc()),
+            tsc_deadline: cpuid
+                .get_feature_info()
+                .is_some_and(|info| info.has_tsc_deadline()),
+            reference: reference.name(),
+        }
+    })
+}
+
+/// Lets the timer and the TSC run for one calibration window.
+fn measure(local_apic: &LocalApic, reference: &ReferenceClock) -> (u64, u64) {
+    // The timer is stopped again long before it could reach 0 and raise the vector.
+    local_apic.configure_timer(crate::TIMER_VECTOR, TimerMode::OneShot);
+
+    local_apic.set_timer_initial_count(u32::MAX);
+    let tsc_start = x86_64::op::rdtsc();
+
+    reference.wait(CALIBRATION_WINDOW_NS);
+
+    let remaining = local_apic.timer_current_count();
+    let tsc_end = x86_64::op::rdtsc();
+
+    local_apic.stop_timer();
+
+    let elapsed = (u32::MAX - remaining) as u64;
+
+    (
+        elapsed * NANOS_PER_SECOND / CALIBRATION_WINDOW_NS,
+        (tsc_end - tsc_start) * NANOS_PER_SECOND / CALIBRATION_WINDOW_NS,
+    )
+}
+
+/// Returns the calibration, if the timer has been calibrated.
+pub fn calibration() -> Option<&'static Calibration> {
+    CALIBRATION.get()
+}
+
+/// Raises `vector` `frequency` times per second on the current core.
+pub fn start_periodic(vector: u8, frequency: u64) -> Result<(), TimerError> {
+    let calibration = calibration().ok_or(TimerError::NotCalibrated)?;
+    let count = calibration
+        .timer_frequency
+        .checked_div(frequency)
+        .and_then(|count| u32::try_from(count).ok())
+        .filter(|&count| count > 0)
+        .ok_or(TimerError::OutOfRange)?;
+
+    let local_apic = crate::local_apic();
+    local_apic.configure_timer(vector, TimerMode::Periodic);
+    local_apic.set_timer_initial_count(count);
+
+    Ok(())
+}
+
+/// Raises `vector` once on the current core after `duration` nanoseconds.
+pub fn start_one_shot(vector: u8, duration: u64) -> Result<(), TimerError> {
+    let calibration = calibration().ok_or(TimerError::NotCalibrated)?;
+    let count = duration as u128 * calibration.timer_frequency as u128 / NANOS_PER_SECOND as u128;
+    let count = u32::try_from(count.max(1)).map_err(|_| TimerError::OutOfRange)?;
+
+    let local_apic = crate::local_apic();
+    local_apic.configure_timer(vector, TimerMode::OneShot);
+    local_apic.set_timer_initial_count(count);
+
+    Ok(())
+}
+
+/// Raises `vector` once on the current core when its TSC reaches `deadline`.
+pub fn start_tsc_deadline(vector: u8, deadline: u64) -> Result<(), TimerError> {
+    let calibration = calibration().ok_or(TimerError::NotCalibrated)?;
+
+    if !calibration.tsc_deadline {
+        return Err(TimerError::TscDeadlineUnsupported);
+    }
+
+    let local_apic = crate::local_apic();
+    local_apic.configure_timer(vector, TimerMode::TscDeadline);
+
+    // SAFETY: The processor supports TSC-deadline mode.
+    unsafe { local_apic.set_tsc_deadline(deadline) };
+
+    Ok(())
+}
+
+/// Stops the timer of the current core, whatever its mode.
+pub fn stop() {
+    crate::local_apic().stop_timer();
+}
@@ -0,0 +1,314 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use bitflags::bitflags;
+use raw_cpuid::CpuId;
+use spin::Once;
+use uio::{kprint, kprintln};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags, Efer, EferFlags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+/// Size of the FXSAVE area, used when XSAVE is not available.
+const FXSAVE_AREA_SIZE: u32 = 512;
+
+bitflags! {
+    /// Processor features the kernel knows about.
+    #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+    pub struct Features: u64 {
+        const FPU = 1;
+        const FXSR = 1 << 1;
+        const SSE = 1 << 2;
+        const SSE2 = 1 << 3;
+        const SSE3 = 1 << 4;
+        const SSSE3 = 1 << 5;
+        const SSE4_1 = 1 << 6;
+        const SSE4_2 = 1 << 7;
+        const XSAVE = 1 << 8;
+        const XSAVEOPT = 1 << 9;
+        const XSAVEC = 1 << 10;
+        const XSAVES = 1 << 11;
+        const AVX = 1 << 12;
+        const AVX2 = 1 << 13;
+        const FMA = 1 << 14;
+        const AVX512F = 1 << 15;
+        const NX = 1 << 16;
+        const SMEP = 1 << 17;
+        const SMAP = 1 << 18;
+        const UMIP = 1 << 19;
+        const PGE = 1 << 20;
+        const PCID = 1 << 21;
+        const INVPCID = 1 << 22;
+        const FSGSBASE = 1 << 23;
+        const PAGES_1GIB = 1 << 24;
+        const RDRAND = 1 << 25;
+        const RDSEED = 1 << 26;
+        const RDTSCP = 1 << 27;
+        const X2APIC = 1 << 28;
+        const TSC_DEADLINE = 1 << 29;
+        const INVARIANT_TSC = 1 << 30;
+        const HYPERVISOR = 1 << 31;
+    }
+}
+
+/// Features of one core.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuFeatures {
+    /// Reported by CPUID.
+    pub detected: Features,
+    /// Usable, either always on or switched on by the kernel in CR0, CR4, EFER or XCR0.
+    pub enabled: Features,
+    /// State components saved by XSAVE.
+    pub xcr0: XCr0Flags,
+    /// Bytes needed to save the FPU and vector state of a thread.
+    pub save_area_size: u32,
+}
+
+impl CpuFeatures {
+    #[inline]
+    pub fn has(&self, features: Features) -> bool {
+        self.detected.contains(features)
+    }
+
+    #[inline]
+    pub fn is_enabled(&self, features: Features) -> bool {
+        self.enabled.contains(features)
+    }
+}
+
+crate::percpu! {
+    static FEATURES: Once<CpuFeatures> = Once::new();
+}
+
+/// Enumerates the features of the executing core through CPUID.
+pub fn detect() -> Features {
+    let cpuid = CpuId::new();
+    let mut features = Features::empty();
+
+    if let Some(info) = cpuid.get_feature_info() {
+        features.set(Features::FPU, info.has_fpu());
+        features.set(Features::FXSR, info.has_fxsave_fxstor());
+        features.set(Features::SSE, info.has_sse());
+        features.set(Features::SSE2, info.has_sse2());
+        features.set(Features::SSE3, info.has_sse3());
+        features.set(Features::SSSE3, info.has_ssse3());
+        features.set(Features::SSE4_1, info.has_sse41());
+        features.set(Features::SSE4_2, info.has_sse42());
+        features.set(Features::XSAVE, info.has_xsave());
+        features.set(Features::AVX, info.has_avx());
+        features.set(Features::FMA, info.has_fma());
+        features.set(Features::PGE, info.has_pge());
+        features.set(Features::PCID, info.has_pcid());
+        features.set(Features::RDRAND, info.has_rdrand());
+        features.set(Features::X2APIC, info.has_x2apic());
+        features.set(Features::TSC_DEADLINE, info.has_tsc_deadline());
+        features.set(Features::HYPERVISOR, info.has_hypervisor());
+    }
+
+    if let Some(info) = cpuid.get_extended_feature_info() {
+        features.set(Features::AVX2, info.has_avx2());
+        features.set(Features::AVX512F, info.has_avx512f());
+        features.set(Features::SMEP, info.has_smep());
+        features.set(Features::SMAP, info.has_smap());
+        features.set(Features::UMIP, info.has_umip());
+        features.set(Features::INVPCID, info.has_invpcid());
+        features.set(Features::FSGSBASE, info.has_fsgsbase());
+        features.set(Features::RDSEED, info.has_rdseed());
+    }
+
+    if let Some(info) = cpuid.get_extended_processor_and_feature_identifiers() {
+        features.set(Features::NX, info.has_execute_disable());
+        features.set(Features::PAGES_1GIB, info.has_1gib_pages());
+        features.set(Features::RDTSCP, info.has_rdtscp());
+    }
+
+    if let Some(info) = cpuid.get_advanced_power_mgmt_info() {
+        features.set(Features::INVARIANT_TSC, info.has_invariant_tsc());
+    }
+
+    if features.contains(Features::XSAVE) {
+        if let Some(info) = cpuid.get_extended_state_info() {
+            features.set(Features::XSAVEOPT, info.has_xsaveopt());
+            features.set(Features::XSAVEC, info.has_xsavec());
+            features.set(Features::XSAVES, info.has_xsaves_xrstors());
+        }
+    }
+
+    features
+}
+
+/// Enables the supported features on the executing core and records them.
+///
+/// Runs once per core, from [`crate::init`] and the entry of the application processors.
+pub(crate) fn init() -> &'static CpuFeatures {
+    FEATURES.get().call_once(|| {
+        let detected = detect();
+        // SAFETY: Only features CPUID reported are enabled.
+        let (enabled, xcr0) = unsafe { enable(detected) };
+
+        let save_area_size = if xcr0.is_empty() {
+            FXSAVE_AREA_SIZE
+        } else {
+            // Reported for the components currently enabled in XCR0.
+            CpuId::new()
+                .get_extended_state_info()
+                .map_or(FXSAVE_AREA_SIZE, |info| {
+                    info.xsave_area_size_enabled_features()
+                })
+        };
+
+        CpuFeatures {
+            detected,
+            enabled,
+            xcr0,
+            save_area_size,
+        }
+    })
+}
+
+/// # Safety
+/// `detected` must only contain features of the executing core.
+unsafe fn enable(detected: Features) -> (Features, XCr0Flags) {
+    // Features every long mode processor has, nothing to switch on. PCID is left to the
+    // address space switch, CR4.PCIDE changes what the low bits of CR3 mean.
+    let mut enabled = detected
+        & (Features::FPU
+            | Features::INVPCID
+            | Features::PAGES_1GIB
+            | Features::RDRAND
+            | Features::RDSEED
+            | Features::RDTSCP
+            | Features::X2APIC
+            | Features::TSC_DEADLINE
+            | Features::INVARIANT_TSC
+            | Features::HYPERVISOR);
+    let mut cr4 = Cr4Flags::empty();
+
+    if detected.contains(Features::NX) {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        enabled |= Features::NX;
+    }
+
+    if detected.contains(Features::FXSR) {
+        // Run SSE instructions natively and report their exceptions through #XM.
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        cr4 |= Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE;
+        enabled |= detected
+            & (Features::FXSR
+                | Features::SSE
+                | Features::SSE2
+                | Features::SSE3
+                | Features::SSSE3
+                | Features::SSE4_1
+                | Features::SSE4_2);
+    }
+
+    for (feature, flag) in [
+        (
+            Features::SMEP,
+            Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION,
+        ),
+        (Features::SMAP, Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+        (Features::UMIP, Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION),
+        (Features::PGE, Cr4Flags::PAGE_GLOBAL),
+        (Features::XSAVE, Cr4Flags::OSXSAVE),
+    ] {
+        if detected.contains(feature) {
+            cr4 |= flag;
+            enabled |= feature;
+        }
+    }
+
+    Cr4::update(|flags| flags.insert(cr4));
+
+    if !enabled.contains(Features::XSAVE) {
+        return (enabled, XCr0Flags::empty());
+    }
+
+    enabled |= detected & (Features::XSAVEOPT | Features::XSAVEC | Features::XSAVES);
+
+    let supported =
+        CpuId::new()
+            .get_extended_state_info()
+            .map_or(XCr0Flags::X87 | XCr0Flags::SSE, |info| {
+                let mut supported = XCr0Flags::X87 | XCr0Flags::SSE;
+                supported.set(XCr0Flags::AVX, info.xcr0_supports_avx_256());
+                supported.set(
+                    XCr0Flags::OPMASK | XCr0Flags::ZMM_HI256 | XCr0Flags::HI16_ZMM,
+                    info.xcr0_supports_avx512_opmask()
+                        && info.xcr0_supports_avx512_zmm_hi256()
+                        && info.xcr0_supports_avx512_zmm_hi16(),
+                );
+                supported
+            });
+
+    let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+
+    if detected.contains(Features::AVX) && supported.contains(XCr0Flags::AVX) {
+        xcr0 |= XCr0Flags::AVX;
+        enabled |= detected & (Features::AVX | Features::AVX2 | Features::FMA);
+
+        let avx512 = XCr0Flags::OPMASK | XCr0Flags::ZMM_HI256 | XCr0Flags::HI16_ZMM;
+
+        if detected.contains(Features::AVX512F) && supported.contains(avx512) {
+            xcr0 |= avx512;
+            enabled |= Features::AVX512F;
+        }
+    }
+
+    XCr0::write(xcr0);
+
+    (enabled, xcr0)
+}
+
+/// Returns the features of the executing core.
+///
+/// # Panics
+/// If the core has not been initialised yet.
+pub fn current() -> &'static CpuFeatures {
+    FEATURES
+        .get()
+        .get()
+        .expect("CPU features of this core are not initialised")
+}
+
+/// Returns the features of the core with the index `index`, once it is initialised.
+pub fn of(index: usize) -> Option<&'static CpuFeatures> {
+    FEATURES.get_for(index).and_then(Once::get)
+}
+
+pub(crate) fn log(features: &CpuFeatures) {
+    kprint!("CPU: Detected");
+    for (name, _) in features.detected.iter_names() {
+        kprint!(" {}", name);
+    }
+    kprintln!();
+
+    kprint!("CPU: Enabled");
+    for (name, _) in features.enabled.iter_names() {
+        kprint!(" {}", name);
+    }
+    kprintln!();
+
+    kprintln!(
+        "CPU: XCR0 {:#x}, {} byte save area",
+        features.xcr0.bits(),
+        features.save_area_size
+    );
+}
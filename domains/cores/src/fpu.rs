@@ -0,0 +1,344 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
+use spin::Once;
+use uio::kprintln;
+use x86_64::op::{fpu, interrupts};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+use crate::features::{self, Features};
+
+/// XSAVE areas have to be 64 byte aligned, FXSAVE areas 16 byte.
+const SAVE_AREA_ALIGN: usize = 64;
+
+/// Offsets into the legacy region shared by FXSAVE and XSAVE.
+const FCW_OFFSET: usize = 0;
+const MXCSR_OFFSET: usize = 24;
+/// XCOMP_BV in the XSAVE header, bit 63 marks the compacted format XRSTORS expects.
+const XCOMP_BV_OFFSET: usize = 520;
+const XCOMP_BV_COMPACTED: u64 = 1 << 63;
+
+/// Initial control words: all exceptions masked, round to nearest.
+const DEFAULT_FCW: u16 = 0x037F;
+const DEFAULT_MXCSR: u32 = 0x1F80;
+
+/// Selects every component enabled in XCR0.
+const ALL_COMPONENTS: u64 = u64::MAX;
+
+static MECHANISM: Once<Mechanism> = Once::new();
+
+/// State restored for threads without a state of their own and for kernel use.
+static INITIAL: Once<FpuState> = Once::new();
+
+static LAZY: AtomicBool = AtomicBool::new(true);
+
+crate::percpu! {
+    /// State of the thread running on the core, null while none is attached.
+    static CURRENT: AtomicPtr<FpuState> = AtomicPtr::new(ptr::null_mut());
+    /// The registers hold the state of `CURRENT`.
+    static LOADED: AtomicBool = AtomicBool::new(false);
+    /// A [`KernelFpuGuard`] is alive on the core.
+    static KERNEL_USE: AtomicBool = AtomicBool::new(false);
+}
+
+/// When the state of the next thread is loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwitchMode {
+    /// On every switch.
+    Eager,
+    /// On the first FPU or SIMD instruction after a switch, trapped through CR0.TS and #NM.
+    Lazy,
+}
+
+/// Instructions saving and restoring the state, the best one the processor supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mechanism {
+    Fxsave,
+    Xsave,
+    Xsaveopt,
+    Xsaves,
+}
+
+/// FPU, SSE and AVX state of one thread.
+pub struct FpuState {
+    area: NonNull<u8>,
+    layout: Layout,
+}
+
+// SAFETY: The buffer is owned by the state, shared references only read it.
+unsafe impl Send for FpuState {}
+unsafe impl Sync for FpuState {}
+
+impl FpuState {
+    /// Allocates a buffer sized from CPUID leaf 0xD, holding the initial state.
+    ///
+    /// # Panics
+    /// If called before the FPU of the executing core is initialised.
+    pub fn new() -> Self {
+        let size = features::current().save_area_size as usize;
+        let layout = Layout::from_size_align(size, SAVE_AREA_ALIGN).expect("Invalid save area");
+
+        // SAFETY: The save area is never empty.
+        let area = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+
+        // A zeroed XSAVE header puts every component into its initial state, the legacy
+        // region still needs valid control words.
+        unsafe {
+            let base = area.as_ptr();
+            base.add(FCW_OFFSET).cast::<u16>().write(DEFAULT_FCW);
+            base.add(MXCSR_OFFSET).cast::<u32>().write(DEFAULT_MXCSR);
+
+            if mechanism() == Mechanism::Xsaves {
+                let xcr0 = features::current().xcr0.bits();
+                base.add(XCOMP_BV_OFFSET)
+                    .cast::<u64>()
+                    .write(XCOMP_BV_COMPACTED | xcr0);
+            }
+        }
+
+        Self { area, layout }
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Saves the registers into the buffer. CR0.TS must be clear.
+    unsafe fn save(&mut self) {
+        let area = self.area.as_ptr();
+
+        match mechanism() {
+            Mechanism::Fxsave => fpu::fxsave(area),
+            Mechanism::Xsave => fpu::xsave(area, ALL_COMPONENTS),
+            Mechanism::Xsaveopt => fpu::xsaveopt(area, ALL_COMPONENTS),
+            Mechanism::Xsaves => fpu::xsaves(area, ALL_COMPONENTS),
+        }
+    }
+
+    /// Loads the buffer into the registers. CR0.TS must be clear.
+    unsafe fn restore(&self) {
+        let area = self.area.as_ptr();
+
+        match mechanism() {
+            Mechanism::Fxsave => fpu::fxrstor(area),
+            Mechanism::Xsave | Mechanism::Xsaveopt => fpu::xrstor(area, ALL_COMPONENTS),
+            Mechanism::Xsaves => fpu::xrstors(area, ALL_COMPONENTS),
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        // SAFETY: Allocated in `new` with the same layout.
+        unsafe { dealloc(self.area.as_ptr(), self.layout) };
+    }
+}
+
+/// Prepares FPU state switching on the executing core.
+///
+/// Runs once per core, after its features are enabled.
+pub(crate) fn init() {
+    let features = features::current();
+
+    let mechanism = *MECHANISM.call_once(|| {
+        if features.is_enabled(Features::XSAVES) {
+            Mechanism::Xsaves
+        } else if features.is_enabled(Features::XSAVEOPT) {
+            Mechanism::Xsaveopt
+        } else if features.is_enabled(Features::XSAVE) {
+            Mechanism::Xsave
+        } else {
+            Mechanism::Fxsave
+        }
+    });
+
+    if INITIAL.get().is_none() {
+        INITIAL.call_once(FpuState::new);
+        idt::set_device_not_available_handler(device_not_available);
+
+        kprintln!(
+            "FPU: {:?}, {} byte state, {} switching",
+            mechanism,
+            features.save_area_size,
+            if is_lazy() { "lazy" } else { "eager" }
+        );
+    }
+
+    if is_lazy() {
+        set_task_switched();
+    }
+}
+
+/// Selects how states are switched. Has to be called before any thread state is attached.
+pub fn set_mode(mode: SwitchMode) {
+    LAZY.store(mode == SwitchMode::Lazy, Ordering::Relaxed);
+}
+
+#[inline]
+fn is_lazy() -> bool {
+    LAZY.load(Ordering::Relaxed)
+}
+
+/// Returns how states are saved and restored.
+///
+/// # Panics
+/// If no core initialised its FPU yet.
+#[inline]
+pub fn mechanism() -> Mechanism {
+    *MECHANISM.get().expect("FPU is not initialised")
+}
+
+/// Attaches `next` to the executing core, called by the scheduler on every thread switch.
+///
+/// The state of the previous thread is saved if the registers hold it. Null attaches no
+/// state, FPU instructions then run on the initial state.
+///
+/// # Safety
+/// Interrupts must be disabled and `next` must stay valid until the next switch on this
+/// core.
+pub unsafe fn switch_to(next: *mut FpuState) {
+    let current = CURRENT.get();
+    let loaded = LOADED.get();
+    let previous = current.load(Ordering::Relaxed);
+
+    if previous == next && loaded.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if loaded.swap(false, Ordering::Relaxed) && !previous.is_null() {
+        // Loaded implies CR0.TS is clear.
+        (*previous).save();
+    }
+
+    current.store(next, Ordering::Relaxed);
+
+    if is_lazy() {
+        set_task_switched();
+    } else {
+        fpu::clts();
+        load(next);
+        loaded.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Loads `state`, or the initial state for null. CR0.TS must be clear.
+unsafe fn load(state: *const FpuState) {
+    match state.as_ref().or(INITIAL.get()) {
+        Some(state) => state.restore(),
+        None => fpu::fninit(),
+    }
+}
+
+fn set_task_switched() {
+    // SAFETY: Only makes FPU instructions trap into `device_not_available`.
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+}
+
+/// Loads the state of the current thread on its first FPU instruction after a switch.
+fn device_not_available() -> bool {
+    if !Cr0::read().contains(Cr0Flags::TASK_SWITCHED) {
+        return false;
+    }
+
+    fpu::clts();
+
+    // SAFETY: `switch_to` requires the state to stay valid while attached.
+    unsafe { load(CURRENT.get().load(Ordering::Relaxed)) };
+    LOADED.get().store(true, Ordering::Relaxed);
+
+    true
+}
+
+/// Lets kernel code use FPU and SIMD instructions until dropped.
+///
+/// The state of the current thread is saved first and kernel code starts from the initial
+/// state, so neither can clobber the other. Interrupts stay disabled while the guard lives
+/// and guards can not be nested.
+pub struct KernelFpuGuard {
+    interrupts_enabled: bool,
+    /// The guard belongs to the core it was created on.
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl KernelFpuGuard {
+    /// # Panics
+    /// If another guard is alive on the executing core.
+    pub fn new() -> Self {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        assert!(
+            !KERNEL_USE.get().swap(true, Ordering::Relaxed),
+            "Nested kernel FPU use"
+        );
+
+        fpu::clts();
+
+        unsafe {
+            let current = CURRENT.get().load(Ordering::Relaxed);
+
+            if LOADED.get().swap(false, Ordering::Relaxed) && !current.is_null() {
+                (*current).save();
+            }
+
+            load(ptr::null());
+        }
+
+        Self {
+            interrupts_enabled,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Default for KernelFpuGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for KernelFpuGuard {
+    fn drop(&mut self) {
+        if is_lazy() {
+            set_task_switched();
+        } else {
+            // SAFETY: The attached state is valid until the next switch.
+            unsafe { load(CURRENT.get().load(Ordering::Relaxed)) };
+            LOADED.get().store(true, Ordering::Relaxed);
+        }
+
+        KERNEL_USE.get().store(false, Ordering::Relaxed);
+
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -16,20 +16,94 @@
  */
 #![no_std]
 
-// To be implemented. We will provide a placeholder struct here.
+extern crate alloc;
+
+pub mod features;
+pub mod fpu;
+pub mod percpu;
+mod smp;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec::Vec;
+use spin::Once;
+
+pub use smp::set_ap_main;
+
+static CORES: Once<Vec<Core>> = Once::new();
+
+/// A processor core, as reported by the bootloader.
+#[derive(Debug)]
 pub struct Core {
-    id: u32,
+    index: u32,
+    apic_id: u32,
+    bsp: bool,
+    online: AtomicBool,
 }
 
-// For now we will use this
-impl Default for Core {
-    fn default() -> Self {
-        Self { id: 0 }
+impl Core {
+    fn new(index: u32, apic_id: u32, bsp: bool) -> Self {
+        Self {
+            index,
+            apic_id,
+            bsp,
+            online: AtomicBool::new(false),
+        }
+    }
+
+    /// Position of the core in [`cores`].
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
     }
-}
 
-impl Core {
-    pub fn id(&self) -> u32 {
-        self.id
+    #[inline]
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id
     }
+
+    /// Returns whether this is the bootstrap processor.
+    #[inline]
+    pub fn is_bsp(&self) -> bool {
+        self.bsp
+    }
+
+    /// Returns whether the core finished its initialisation and runs kernel code.
+    #[inline]
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    fn set_online(&self, online: bool) {
+        self.online.store(online, Ordering::Release);
+    }
+}
+
+/// Starts the application processors and waits for them to check in.
+///
+/// The GDT, IDT, heap and APIC of the bootstrap processor have to be initialised before.
+pub fn init() {
+    smp::init();
+}
+
+/// Returns all cores, empty before [`init`].
+#[inline]
+pub fn cores() -> &'static [Core] {
+    CORES.get().map_or(&[], Vec::as_slice)
+}
+
+pub fn online_count() -> usize {
+    cores().iter().filter(|core| core.is_online()).count()
+}
+
+/// Returns the executing core.
+///
+/// Must not be called before the core finished its initialisation in [`init`].
+#[inline]
+pub fn current() -> &'static Core {
+    percpu::local().core()
+}
+
+fn by_apic_id(apic_id: u32) -> Option<&'static Core> {
+    cores().iter().find(|core| core.apic_id == apic_id)
 }
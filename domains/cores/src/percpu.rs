@@ -0,0 +1,145 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::arch::asm;
+use core::mem::offset_of;
+
+use alloc::boxed::Box;
+use security::core::x86_64::segmentation::{GSegment, Segment64, TaskStateSegment};
+use x86_64::structures::memory::VirtualAddress;
+
+use crate::Core;
+
+/// Highest number of cores the kernel brings up, every [`PerCpu`] has this many instances.
+pub const MAX_CORES: usize = 64;
+
+/// Data of one core. The GS base of each core points to its own instance.
+#[repr(C)]
+pub struct CoreLocal {
+    /// Address of this structure, `gs:[0]` turns the GS base into a reference.
+    this: *const CoreLocal,
+    /// Read by [`PerCpu`] through `gs:[8]`.
+    index: usize,
+    core: &'static Core,
+    tss: &'static TaskStateSegment,
+}
+
+const _: () = assert!(offset_of!(CoreLocal, this) == 0);
+const _: () = assert!(offset_of!(CoreLocal, index) == 8);
+
+// SAFETY: `this` only ever points to the structure itself, which is never freed.
+unsafe impl Sync for CoreLocal {}
+
+impl CoreLocal {
+    #[inline]
+    pub fn core(&self) -> &'static Core {
+        self.core
+    }
+
+    /// The TSS loaded on this core.
+    #[inline]
+    pub fn tss(&self) -> &'static TaskStateSegment {
+        self.tss
+    }
+}
+
+/// Allocates the data of `core` and points the GS base of the executing core to it.
+pub(crate) fn init(core: &'static Core, tss: &'static TaskStateSegment) {
+    let local = Box::leak(Box::new(CoreLocal {
+        this: core::ptr::null(),
+        index: core.index() as usize,
+        core,
+        tss,
+    }));
+    local.this = local;
+
+    // There is no user space yet, `swapgs` would find nothing to swap in.
+    GSegment::write_base(VirtualAddress::from_ptr(local));
+    GSegment::write_kernel_base(VirtualAddress::zero());
+}
+
+/// Returns the data of the executing core.
+///
+/// Must not be called before the core finished its initialisation in [`crate::init`].
+#[inline]
+pub fn local() -> &'static CoreLocal {
+    let this: *const CoreLocal;
+
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) this, options(nostack, preserves_flags, readonly));
+
+        &*this
+    }
+}
+
+/// Returns the index of the executing core, see [`local`].
+#[inline]
+pub fn index() -> usize {
+    let index: usize;
+
+    unsafe {
+        asm!("mov {}, gs:[8]", out(reg) index, options(nostack, preserves_flags, readonly));
+    }
+
+    index
+}
+
+/// A variable with one instance per core, declared with [`percpu!`](crate::percpu!).
+pub struct PerCpu<T> {
+    values: [T; MAX_CORES],
+}
+
+// SAFETY: A thread may move to another core while it holds the instance of its previous
+// core, which that core keeps using. Every instance can be shared between cores, so it has
+// to be `Sync` itself.
+unsafe impl<T: Sync> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(values: [T; MAX_CORES]) -> Self {
+        Self { values }
+    }
+
+    /// Returns the instance of the executing core.
+    ///
+    /// Once the thread moves to another core the reference still points to the instance of
+    /// the previous core, and interrupt handlers on the same core see the same instance.
+    #[inline]
+    pub fn get(&self) -> &T {
+        &self.values[index()]
+    }
+
+    /// Returns the instance of the core with the index `index`.
+    #[inline]
+    pub fn get_for(&self, index: usize) -> Option<&T> {
+        self.values.get(index)
+    }
+}
+
+/// Declares statics with one instance per core, like `static NAME: Type = init;`.
+///
+/// The initialiser has to be a constant expression, it is evaluated once per instance.
+#[macro_export]
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::percpu::PerCpu<$ty> =
+                $crate::percpu::PerCpu::new([const { $init }; $crate::percpu::MAX_CORES]);
+        )*
+    };
+}
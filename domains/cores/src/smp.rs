@@ -0,0 +1,175 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec;
+use apic::ioapic::DeliveryMode;
+use apic::lapic::IpiDestination;
+use kstructs::atomic_fn::AtomicFn;
+use limine::request::SmpRequest;
+use limine::smp::Cpu;
+use uio::kprintln;
+use x86_64::op::interrupts;
+
+use crate::percpu::{self, MAX_CORES};
+use crate::{by_apic_id, features, fpu, online_count, Core, CORES};
+
+static SMP_REQUEST: SmpRequest = SmpRequest::new();
+
+/// Size of the kernel stack of an application processor.
+const AP_STACK_SIZE: usize = 64 * 1024;
+
+/// How long the bootstrap processor waits for the application processors to check in.
+const STARTUP_TIMEOUT_NS: u64 = 1_000_000_000;
+
+static AP_MAIN: AtomicFn<fn() -> !> = AtomicFn::new();
+
+/// Set before the halt NMI is sent, other NMIs are reported as usual.
+static HALTING: AtomicBool = AtomicBool::new(false);
+
+/// Registers the function the application processors continue in once they are online.
+///
+/// Cores waiting already pick it up on their next interrupt, at the latest with the next
+/// tick. It is called with interrupts disabled.
+pub fn set_ap_main(main: fn() -> !) {
+    AP_MAIN.store(main);
+}
+
+pub(crate) fn init() {
+    let bsp_apic_id = apic::local_apic().id();
+
+    let Some(response) = SMP_REQUEST.get_response() else {
+        kprintln!("SMP: No response from the bootloader, running on the BSP only.");
+
+        let bsp = &CORES.call_once(|| vec![Core::new(0, bsp_apic_id, true)])[0];
+        percpu::init(bsp, gdt::bsp_task_state_segment());
+        features::log(features::init());
+        fpu::init();
+        bsp.set_online(true);
+
+        return;
+    };
+
+    if response.cpus().len() > MAX_CORES {
+        kprintln!(
+            "SMP: Only bringing up {} of {} cores",
+            MAX_CORES,
+            response.cpus().len()
+        );
+    }
+
+    let cores = CORES.call_once(|| {
+        response
+            .cpus()
+            .iter()
+            .take(MAX_CORES)
+            .enumerate()
+            .map(|(index, cpu)| Core::new(index as u32, cpu.lapic_id, cpu.lapic_id == bsp_apic_id))
+            .collect()
+    });
+
+    let bsp = by_apic_id(bsp_apic_id).expect("The bootstrap processor is not in the SMP response");
+    percpu::init(bsp, gdt::bsp_task_state_segment());
+    features::log(features::init());
+    fpu::init();
+    bsp.set_online(true);
+
+    idt::set_non_maskable_interrupt_handler(halt);
+    exception::set_halt_handler(halt_other_cores);
+
+    for cpu in response.cpus() {
+        if cpu.lapic_id != bsp_apic_id && by_apic_id(cpu.lapic_id).is_some() {
+            cpu.goto_address.write(ap_entry);
+        }
+    }
+
+    let deadline = apic::clock::now() + STARTUP_TIMEOUT_NS;
+
+    while online_count() < cores.len() && apic::clock::now() < deadline {
+        core::hint::spin_loop();
+    }
+
+    kprintln!("SMP: {} of {} cores online", online_count(), cores.len());
+}
+
+/// Entry point of the application processors, still on the stack of the bootloader.
+unsafe extern "C" fn ap_entry(cpu: &Cpu) -> ! {
+    let stack = pmm::mapping::map_stack(AP_STACK_SIZE as u64).expect("Could not map an AP stack");
+
+    // The bootloader reclaimable stack is left behind, a zero frame pointer ends backtraces.
+    asm!(
+        "mov rsp, {stack}",
+        "xor ebp, ebp",
+        "call {main}",
+        "ud2",
+        stack = in(reg) stack.as_u64(),
+        main = sym ap_main,
+        in("edi") cpu.lapic_id,
+        options(noreturn)
+    );
+}
+
+extern "C" fn ap_main(apic_id: u32) -> ! {
+    let tss = gdt::init_ap();
+    idt::init();
+
+    let core = by_apic_id(apic_id).expect("Unknown core checked in");
+    percpu::init(core, tss);
+    features::init();
+    fpu::init();
+
+    apic::init_ap();
+    core.set_online(true);
+
+    kprintln!(
+        "SMP: Core {} (APIC ID {}) online",
+        core.index(),
+        core.apic_id()
+    );
+
+    loop {
+        interrupts::disable();
+
+        if let Some(main) = AP_MAIN.load() {
+            main();
+        }
+
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Registered as halt handler of the panic handler. An NMI also reaches cores running with
+/// interrupts disabled, for example spinning on a lock the panicking core holds.
+fn halt_other_cores() {
+    HALTING.store(true, Ordering::Release);
+
+    // The vector is ignored for NMIs.
+    apic::local_apic().send_ipi(0, DeliveryMode::Nmi, IpiDestination::AllExcludingSelf);
+}
+
+/// Handles the NMI sent by [`halt_other_cores`].
+fn halt() -> bool {
+    if !HALTING.load(Ordering::Acquire) {
+        return false;
+    }
+
+    crate::current().set_online(false);
+
+    exception::hcf()
+}
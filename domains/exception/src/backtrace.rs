@@ -0,0 +1,57 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+/// Frames deeper than this are not reported, in case the chain loops.
+const MAX_FRAMES: usize = 32;
+
+/// Start of the higher half, every kernel stack lives above it.
+const KERNEL_HALF: u64 = 0xffff_8000_0000_0000;
+
+/// Follows the saved frame pointers starting at `rbp` and calls `visit` with every return address.
+///
+/// The walk stops at the first frame pointer that does not look like a kernel stack address,
+/// so a missing or corrupted chain ends the backtrace instead of faulting in most cases.
+///
+/// # Safety
+/// `rbp` has to be a frame pointer of the current stack. The kernel has to be built with frame
+/// pointers, otherwise the chain is meaningless.
+pub unsafe fn walk(mut rbp: u64, mut visit: impl FnMut(u64)) {
+    for _ in 0..MAX_FRAMES {
+        if rbp < KERNEL_HALF || !rbp.is_multiple_of(8) {
+            return;
+        }
+
+        // A frame starts with the caller's frame pointer, followed by the return address.
+        let frame = rbp as *const u64;
+        let return_address = *frame.add(1);
+
+        if return_address == 0 {
+            return;
+        }
+
+        visit(return_address);
+
+        // Stacks grow down, the caller's frame has to be above ours.
+        let next = *frame;
+
+        if next <= rbp {
+            return;
+        }
+
+        rbp = next;
+    }
+}
@@ -15,13 +15,73 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+// Host tests link std, which brings its own panic handler, so everything behind ours is unused.
+#![cfg_attr(test, allow(dead_code, unused_imports))]
+
+mod backtrace;
+mod symbols;
+
+pub use symbols::{Demangle, SymbolTable};
 
 use core::arch::asm;
+use core::fmt;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use kstructs::atomic_fn::AtomicFn;
+use limine::request::KernelFileRequest;
+use uio::kprintln_emergency;
+use x86_64::op::interrupts;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4, Efer};
+use x86_64::registers::rflags;
+
+static KERNEL_FILE_REQUEST: KernelFileRequest = KernelFileRequest::new();
+
+/// Set by the first panic, a panic inside the panic handler must not recurse.
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
+/// The function stopping all other cores, if one is registered.
+static HALT_HANDLER: AtomicFn<fn()> = AtomicFn::new();
+
+/// Registers the function the panic handler uses to stop all other cores.
+///
+/// Until the other cores are started there is nothing to stop, so this is set up together
+/// with SMP. The handler must not block and must not panic.
+pub fn set_halt_handler(handler: fn()) {
+    HALT_HANDLER.store(handler);
+}
+
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+fn panic(info: &PanicInfo) -> ! {
+    let registers = Registers::capture();
+
+    interrupts::disable();
+
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        kprintln_emergency!("Nested panic: {}", info.message());
+        hcf()
+    }
+
+    halt_other_cores();
+
+    kprintln_emergency!("\n:: KERNEL PANIC ::");
+
+    match info.location() {
+        Some(location) => kprintln_emergency!(
+            "Panicked at {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        ),
+        None => kprintln_emergency!("Panicked at an unknown location"),
+    }
+
+    kprintln_emergency!("{}", info.message());
+
+    dump_registers(&registers);
+    print_backtrace();
+
     hcf()
 }
 
@@ -33,3 +93,143 @@ pub fn hcf() -> ! {
         }
     }
 }
+
+fn halt_other_cores() {
+    if let Some(handler) = HALT_HANDLER.load() {
+        handler();
+    }
+}
+
+/// The general purpose registers, in the order of [`Registers::NAMES`].
+struct Registers([u64; 16]);
+
+impl Registers {
+    const NAMES: [&'static str; 16] = [
+        "RAX:", "RBX:", "RCX:", "RDX:", "RSI:", "RDI:", "RBP:", "RSP:", "R8:", "R9:", "R10:",
+        "R11:", "R12:", "R13:", "R14:", "R15:",
+    ];
+
+    /// Saves the registers of the calling function, inlined so nothing runs in between.
+    ///
+    /// The panic handler is entered through `core::panicking`, so the callee-saved registers
+    /// and RSP/RBP are what reaches it, the others only hold what that code left in them. The
+    /// register holding the address of the buffer is saved as that address.
+    #[inline(always)]
+    fn capture() -> Self {
+        let mut registers = [0; 16];
+
+        // SAFETY: Only writes the 16 words of `registers`.
+        unsafe {
+            asm!(
+                "mov [{0}], rax",
+                "mov [{0} + 0x08], rbx",
+                "mov [{0} + 0x10], rcx",
+                "mov [{0} + 0x18], rdx",
+                "mov [{0} + 0x20], rsi",
+                "mov [{0} + 0x28], rdi",
+                "mov [{0} + 0x30], rbp",
+                "mov [{0} + 0x38], rsp",
+                "mov [{0} + 0x40], r8",
+                "mov [{0} + 0x48], r9",
+                "mov [{0} + 0x50], r10",
+                "mov [{0} + 0x58], r11",
+                "mov [{0} + 0x60], r12",
+                "mov [{0} + 0x68], r13",
+                "mov [{0} + 0x70], r14",
+                "mov [{0} + 0x78], r15",
+                in(reg) registers.as_mut_ptr(),
+                options(nostack, preserves_flags)
+            );
+        }
+
+        Self(registers)
+    }
+}
+
+/// Up to three registers on one line of the dump.
+struct Row<'a>(&'a [&'static str], &'a [u64]);
+
+impl fmt::Display for Row<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (name, value)) in self.0.iter().zip(self.1).enumerate() {
+            if index > 0 {
+                f.write_str("  ")?;
+            }
+
+            write!(f, "{:<6}{:#018x}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints the general purpose registers captured at panic entry and the control registers.
+fn dump_registers(registers: &Registers) {
+    for (names, values) in Registers::NAMES.chunks(3).zip(registers.0.chunks(3)) {
+        kprintln_emergency!("{}", Row(names, values));
+    }
+
+    let (frame, flags) = Cr3::read();
+
+    kprintln_emergency!(
+        "CR0:  {:#018x}  CR2:  {:#018x}  CR3:    {:#018x}",
+        Cr0::read_raw(),
+        Cr2::read().as_u64(),
+        frame.start_address().as_u64() | flags.bits()
+    );
+    kprintln_emergency!(
+        "CR4:  {:#018x}  EFER: {:#018x}  RFLAGS: {:#018x}",
+        Cr4::read_raw(),
+        Efer::read_raw(),
+        rflags::read().bits()
+    );
+}
+
+fn print_backtrace() {
+    let symbols = KERNEL_FILE_REQUEST.get_response().and_then(|response| {
+        let file = response.file();
+
+        // SAFETY: The bootloader loaded the kernel file there and the memory is never reclaimed.
+        let elf = unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) };
+
+        SymbolTable::from_elf(elf)
+    });
+
+    if symbols.is_none() {
+        kprintln_emergency!("No kernel symbol table, backtrace is not symbolized.");
+    }
+
+    let rbp: u64;
+
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+
+    kprintln_emergency!("Backtrace:");
+
+    let mut index = 0;
+
+    // SAFETY: RBP holds the frame pointer of this function, the target enables frame pointers.
+    unsafe {
+        backtrace::walk(rbp, |return_address| {
+            // The return address already belongs to the next instruction, which might be part of
+            // another function when the call was the last instruction.
+            let symbol = symbols
+                .as_ref()
+                .and_then(|symbols| symbols.lookup(return_address - 1));
+
+            match symbol {
+                Some((name, offset)) => kprintln_emergency!(
+                    "  #{:<2} {:#018x} {}+{:#x}",
+                    index,
+                    return_address,
+                    Demangle(name),
+                    offset + 1
+                ),
+                None => kprintln_emergency!("  #{:<2} {:#018x} <unknown>", index, return_address),
+            }
+
+            index += 1;
+        });
+    }
+}
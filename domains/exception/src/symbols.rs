@@ -0,0 +1,385 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::fmt;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SYMBOL_SIZE: usize = 24;
+const SECTION_HEADER_SIZE: usize = 64;
+
+/// The function symbols of an ELF64 image.
+pub struct SymbolTable {
+    symbols: &'static [u8],
+    strings: &'static [u8],
+}
+
+impl SymbolTable {
+    /// Locates `.symtab` and its string table in `elf`.
+    ///
+    /// Returns `None` if the image is not an ELF64 file or was stripped.
+    pub fn from_elf(elf: &'static [u8]) -> Option<Self> {
+        if elf.get(0..5)? != b"\x7fELF\x02" {
+            return None;
+        }
+
+        let section_offset = read_u64(elf, 0x28)? as usize;
+        let section_count = read_u16(elf, 0x3C)? as usize;
+
+        let section = |index: usize| {
+            let base = section_offset.checked_add(index.checked_mul(SECTION_HEADER_SIZE)?)?;
+            elf.get(base..base.checked_add(SECTION_HEADER_SIZE)?)
+        };
+
+        let contents = |header: &[u8]| {
+            let offset = read_u64(header, 24)? as usize;
+            let size = read_u64(header, 32)? as usize;
+            elf.get(offset..offset.checked_add(size)?)
+        };
+
+        for index in 0..section_count {
+            let header = section(index)?;
+
+            if read_u32(header, 4)? != SHT_SYMTAB {
+                continue;
+            }
+
+            let strings = section(read_u32(header, 40)? as usize)?;
+
+            return Some(Self {
+                symbols: contents(header)?,
+                strings: contents(strings)?,
+            });
+        }
+
+        None
+    }
+
+    /// Returns the name of the function containing `address` and the offset into it.
+    pub fn lookup(&self, address: u64) -> Option<(&'static str, u64)> {
+        let symbols: &'static [u8] = self.symbols;
+
+        symbols.chunks_exact(SYMBOL_SIZE).find_map(|symbol| {
+            let value = read_u64(symbol, 8)?;
+            let size = read_u64(symbol, 16)?;
+
+            if symbol[4] & 0xF != STT_FUNC || address < value || address - value >= size.max(1) {
+                return None;
+            }
+
+            Some((self.name(read_u32(symbol, 0)? as usize)?, address - value))
+        })
+    }
+
+    fn name(&self, offset: usize) -> Option<&'static str> {
+        let strings: &'static [u8] = self.strings;
+        let bytes = strings.get(offset..)?;
+        let length = bytes.iter().position(|&byte| byte == 0)?;
+
+        core::str::from_utf8(&bytes[..length]).ok()
+    }
+}
+
+/// Displays a symbol name with the legacy Rust mangling undone.
+///
+/// Names that are not mangled that way are printed as they are.
+pub struct Demangle<'a>(pub &'a str);
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.0.strip_prefix("_ZN").and_then(|s| s.strip_suffix('E'));
+
+        // Check the whole path first, nothing may be written for names that turn out malformed.
+        let Some(path) = path.filter(|path| Components(path).all(|component| component.is_some()))
+        else {
+            return f.write_str(self.0);
+        };
+
+        let mut components = Components(path).flatten().peekable();
+        let mut first = true;
+
+        while let Some(component) = components.next() {
+            // The trailing hash only disambiguates crate versions.
+            if components.peek().is_none() && is_hash(component) {
+                break;
+            }
+
+            if !first {
+                f.write_str("::")?;
+            }
+
+            write_component(f, component)?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// The length-prefixed components of a mangled path, `None` if a length is invalid.
+struct Components<'a>(&'a str);
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Option<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let digits = self.0.bytes().take_while(u8::is_ascii_digit).count();
+
+        let component = match self.0[..digits].parse::<usize>() {
+            Ok(length) if digits + length <= self.0.len() => {
+                let component = &self.0[digits..digits + length];
+                self.0 = &self.0[digits + length..];
+                Some(component)
+            }
+            _ => {
+                self.0 = "";
+                None
+            }
+        };
+
+        Some(component)
+    }
+}
+
+fn is_hash(component: &str) -> bool {
+    component.len() == 17
+        && component.starts_with('h')
+        && component[1..].bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn write_component(f: &mut fmt::Formatter<'_>, component: &str) -> fmt::Result {
+    const ESCAPES: [(&str, &str); 15] = [
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$RF$", "&"),
+        ("$BP$", "*"),
+        ("$C$", ","),
+        ("$SP$", "@"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+    ];
+
+    // Components that would start with an escape get an underscore prepended.
+    let mut rest = if component.starts_with("_$") {
+        &component[1..]
+    } else {
+        component
+    };
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = after;
+            continue;
+        }
+
+        if let Some((escape, replacement)) =
+            ESCAPES.iter().find(|(escape, _)| rest.starts_with(escape))
+        {
+            f.write_str(replacement)?;
+            rest = &rest[escape.len()..];
+            continue;
+        }
+
+        let next = rest[1..]
+            .find(['$', '.'])
+            .map_or(rest.len(), |index| index + 1);
+        f.write_str(&rest[..next])?;
+        rest = &rest[next..];
+    }
+
+    Ok(())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STT_OBJECT: u8 = 1;
+    const SHT_STRTAB: u32 = 3;
+
+    const MAIN: &str = "_ZN6kernel4main17h0123456789abcdefE";
+    const PANIC_FMT: &str = "_ZN4core9panicking9panic_fmt17hfedcba9876543210E";
+
+    fn section(kind: u32, offset: usize, size: usize, link: u32) -> [u8; SECTION_HEADER_SIZE] {
+        let mut header = [0; SECTION_HEADER_SIZE];
+        header[4..8].copy_from_slice(&kind.to_le_bytes());
+        header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+        header[40..44].copy_from_slice(&link.to_le_bytes());
+        header
+    }
+
+    /// An ELF64 image with a null section, `.symtab` and `.strtab`, holding `symbols` given
+    /// as name, type, address and size.
+    fn elf(symbols: &[(&str, u8, u64, u64)]) -> &'static [u8] {
+        let mut strings = Vec::from([0]);
+        let mut table = Vec::from([0; SYMBOL_SIZE]);
+
+        for &(name, kind, value, size) in symbols {
+            let mut symbol = [0; SYMBOL_SIZE];
+            symbol[0..4].copy_from_slice(&(strings.len() as u32).to_le_bytes());
+            symbol[4] = 0x10 | kind;
+            symbol[8..16].copy_from_slice(&value.to_le_bytes());
+            symbol[16..24].copy_from_slice(&size.to_le_bytes());
+            table.extend_from_slice(&symbol);
+
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+        }
+
+        let symbols_offset = 64;
+        let strings_offset = symbols_offset + table.len();
+        let sections_offset = strings_offset + strings.len();
+
+        let mut elf = Vec::from([0; 64]);
+        elf[0..5].copy_from_slice(b"\x7fELF\x02");
+        elf[0x28..0x30].copy_from_slice(&(sections_offset as u64).to_le_bytes());
+        elf[0x3C..0x3E].copy_from_slice(&3u16.to_le_bytes());
+        elf.extend_from_slice(&table);
+        elf.extend_from_slice(&strings);
+        elf.extend_from_slice(&[0; SECTION_HEADER_SIZE]);
+        elf.extend_from_slice(&section(SHT_SYMTAB, symbols_offset, table.len(), 2));
+        elf.extend_from_slice(&section(SHT_STRTAB, strings_offset, strings.len(), 0));
+
+        Vec::leak(elf)
+    }
+
+    fn fixture() -> SymbolTable {
+        SymbolTable::from_elf(elf(&[
+            (MAIN, STT_FUNC, 0x1000, 0x40),
+            (PANIC_FMT, STT_FUNC, 0x1040, 0x20),
+            ("KERNEL_FILE_REQUEST", STT_OBJECT, 0x2000, 0x100),
+            ("_start", STT_FUNC, 0x3000, 0),
+        ]))
+        .expect("fixture has a symbol table")
+    }
+
+    fn demangle(name: &str) -> String {
+        Demangle(name).to_string()
+    }
+
+    #[test]
+    fn lookup_finds_the_containing_function() {
+        let symbols = fixture();
+
+        assert_eq!(symbols.lookup(0x1000), Some((MAIN, 0)));
+        assert_eq!(symbols.lookup(0x103F), Some((MAIN, 0x3F)));
+        assert_eq!(symbols.lookup(0x1040), Some((PANIC_FMT, 0)));
+        assert_eq!(symbols.lookup(0x1060), None);
+        assert_eq!(symbols.lookup(0xFFF), None);
+    }
+
+    #[test]
+    fn lookup_skips_data_and_sizes_unknown_functions_to_one_byte() {
+        let symbols = fixture();
+
+        assert_eq!(symbols.lookup(0x2000), None);
+        assert_eq!(symbols.lookup(0x3000), Some(("_start", 0)));
+        assert_eq!(symbols.lookup(0x3001), None);
+    }
+
+    #[test]
+    fn from_elf_rejects_other_images() {
+        let image = elf(&[(MAIN, STT_FUNC, 0x1000, 0x40)]);
+
+        assert!(SymbolTable::from_elf(b"\x7fELF").is_none());
+
+        // ELF32
+        let mut other = image.to_vec();
+        other[4] = 1;
+        assert!(SymbolTable::from_elf(Vec::leak(other)).is_none());
+
+        // Stripped, the symbol table turned into a string table.
+        let mut stripped = image.to_vec();
+        let header = stripped.len() - 2 * SECTION_HEADER_SIZE;
+        stripped[header + 4] = SHT_STRTAB as u8;
+        assert!(SymbolTable::from_elf(Vec::leak(stripped)).is_none());
+
+        // The section headers run past the end.
+        let truncated = &image[..image.len() - 1];
+        assert!(SymbolTable::from_elf(truncated).is_none());
+    }
+
+    #[test]
+    fn demangles_paths_and_drops_the_hash() {
+        assert_eq!(demangle(MAIN), "kernel::main");
+        assert_eq!(demangle(PANIC_FMT), "core::panicking::panic_fmt");
+        // Only a trailing hash is dropped.
+        assert_eq!(
+            demangle("_ZN6kernel17h0123456789abcdef4mainE"),
+            "kernel::h0123456789abcdef::main"
+        );
+    }
+
+    #[test]
+    fn demangles_escapes() {
+        assert_eq!(
+            demangle(
+                "_ZN59_$LT$hadron..thread..Thread$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE"
+            ),
+            "<hadron::thread::Thread as core::fmt::Debug>::fmt"
+        );
+        assert_eq!(
+            demangle("_ZN6kernel4main28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE"),
+            "kernel::main::{{closure}}"
+        );
+        assert_eq!(demangle("_ZN4core3ptr8drop$RF$E"), "core::ptr::drop&");
+    }
+
+    #[test]
+    fn leaves_other_names_alone() {
+        assert_eq!(demangle("_start"), "_start");
+        assert_eq!(demangle("_ZN6kernelE"), "kernel");
+        // A length past the end of the name.
+        assert_eq!(demangle("_ZN6kernel9mainE"), "_ZN6kernel9mainE");
+        // v0 mangling is not supported.
+        assert_eq!(
+            demangle("_RNvCs1234_6kernel4main"),
+            "_RNvCs1234_6kernel4main"
+        );
+    }
+}
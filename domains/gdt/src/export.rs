@@ -105,7 +105,7 @@ impl GlobalDescriptorTable {
     #[inline]
     fn as_descriptor_table_pointer(&self) -> DescriptorTablePointer {
         DescriptorTablePointer {
-            base: VirtualAddress::new(self.table.as_ptr() as u64),
+            base: VirtualAddress::from_ptr(self.table.as_ptr()),
             limit: (self.len * size_of::<u64>() - 1) as u16,
         }
     }
@@ -19,5 +19,6 @@ use security::core::x86_64::segmentation::SegmentSelector;
 
 pub struct SegmentSelectors {
     pub code_segment_selector: SegmentSelector,
+    pub data_segment_selector: SegmentSelector,
     pub tss_segment_selector: SegmentSelector,
 }
@@ -15,9 +15,13 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 #![no_std]
+
+extern crate alloc;
+
 pub mod export;
 mod internal;
 
+use alloc::boxed::Box;
 use lazy_static::lazy_static;
 use security::core::x86_64::segmentation::{Descriptor, Segment32, TaskStateSegment};
 use uio::kprintln;
@@ -44,41 +48,81 @@ lazy_static! {
 }
 
 lazy_static! {
-    static ref GLOBAL_DESCRIPTOR_TABLE: (GlobalDescriptorTable, SegmentSelectors) = {
-        let mut global_descriptor_table = GlobalDescriptorTable::new();
-        let code_segment_selector = global_descriptor_table.add(Descriptor::kernel_code_segment());
-        let tss_segment_selector =
-            global_descriptor_table.add(Descriptor::tss_segment(&TASK_STATE_SEGMENT));
-
-        (
-            global_descriptor_table,
-            SegmentSelectors {
-                code_segment_selector,
-                tss_segment_selector,
-            },
-        )
-    };
+    static ref GLOBAL_DESCRIPTOR_TABLE: (GlobalDescriptorTable, SegmentSelectors) =
+        build(&TASK_STATE_SEGMENT);
 }
 
-pub fn init() {
-    use core::arch::asm;
-    use security::core::x86_64::segmentation::CodeSegment;
+fn build(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, SegmentSelectors) {
+    let mut global_descriptor_table = GlobalDescriptorTable::new();
+    let code_segment_selector = global_descriptor_table.add(Descriptor::kernel_code_segment());
+    let data_segment_selector = global_descriptor_table.add(Descriptor::kernel_data_segment());
+    let tss_segment_selector = global_descriptor_table.add(Descriptor::tss_segment(tss));
 
-    GLOBAL_DESCRIPTOR_TABLE.0.init();
+    (
+        global_descriptor_table,
+        SegmentSelectors {
+            code_segment_selector,
+            data_segment_selector,
+            tss_segment_selector,
+        },
+    )
+}
+
+pub fn init() {
+    load(&GLOBAL_DESCRIPTOR_TABLE);
 
     #[cfg(debug_assertions)]
     kprintln!(
-        "GDT intialized.\nCS: {}\nTSS: {}",
+        "GDT intialized.\nCS: {}\nDS: {}\nTSS: {}",
         GLOBAL_DESCRIPTOR_TABLE.1.code_segment_selector.0,
+        GLOBAL_DESCRIPTOR_TABLE.1.data_segment_selector.0,
         GLOBAL_DESCRIPTOR_TABLE.1.tss_segment_selector.0
     );
+}
+
+/// Returns the TSS loaded by [`init`] on the bootstrap processor.
+pub fn bsp_task_state_segment() -> &'static TaskStateSegment {
+    &TASK_STATE_SEGMENT
+}
+
+/// Loads a GDT and TSS of its own on an application processor and returns the TSS.
+///
+/// The TSS is per core, so every core has its own double fault stack, with a guard page
+/// below it. The stack and both tables are never freed.
+pub fn init_ap() -> &'static TaskStateSegment {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        pmm::mapping::map_stack(STACK_SIZE as u64).expect("Could not map a double fault stack");
+
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+    let tables: &'static (GlobalDescriptorTable, SegmentSelectors) =
+        Box::leak(Box::new(build(tss)));
+
+    load(tables);
+
+    tss
+}
+
+/// Loads the GDT and reloads every segment register that still holds a selector of the
+/// bootloader's GDT. SS in particular must not keep Limine's selector, which is beyond the
+/// end of our table and faults on the next `iretq` that pops it.
+fn load((global_descriptor_table, selectors): &'static (GlobalDescriptorTable, SegmentSelectors)) {
+    use core::arch::asm;
+    use security::core::x86_64::segmentation::{
+        CodeSegment, DataSegment, ExtraSegment, StackSegment,
+    };
+
+    global_descriptor_table.init();
 
     unsafe {
-        CodeSegment::set_reg(GLOBAL_DESCRIPTOR_TABLE.1.code_segment_selector);
+        CodeSegment::set_reg(selectors.code_segment_selector);
+        StackSegment::set_reg(selectors.data_segment_selector);
+        DataSegment::set_reg(selectors.data_segment_selector);
+        ExtraSegment::set_reg(selectors.data_segment_selector);
 
         asm!(
             "ltr {0:x}",
-            in(reg) GLOBAL_DESCRIPTOR_TABLE.1.tss_segment_selector.0,
+            in(reg) selectors.tss_segment_selector.0,
             options(nostack, preserves_flags)
         );
     }
@@ -0,0 +1,147 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::linked_list::LinkedListAllocator;
+use crate::slab::{Slab, SLAB_SIZE};
+
+/// Object sizes served by the slab caches. Larger requests go to the fallback allocator.
+const SLAB_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// The kernel heap: slab caches for small objects on top of a linked list allocator.
+///
+/// It only manages memory it is given through [`Self::add_region`], mapping that memory is
+/// up to the caller.
+pub struct KernelHeap {
+    slabs: [Slab; SLAB_SIZES.len()],
+    fallback: LinkedListAllocator,
+    size: usize,
+    used: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStatistics {
+    /// Bytes handed to the heap.
+    pub size: usize,
+    /// Bytes currently allocated, rounded up to the size class.
+    pub used: usize,
+    /// Bytes held by the fallback allocator.
+    pub free: usize,
+    /// Bytes of unused objects in the slab caches.
+    pub cached: usize,
+}
+
+impl KernelHeap {
+    pub const fn new() -> Self {
+        Self {
+            slabs: [
+                Slab::new(SLAB_SIZES[0]),
+                Slab::new(SLAB_SIZES[1]),
+                Slab::new(SLAB_SIZES[2]),
+                Slab::new(SLAB_SIZES[3]),
+                Slab::new(SLAB_SIZES[4]),
+                Slab::new(SLAB_SIZES[5]),
+                Slab::new(SLAB_SIZES[6]),
+                Slab::new(SLAB_SIZES[7]),
+                Slab::new(SLAB_SIZES[8]),
+            ],
+            fallback: LinkedListAllocator::new(),
+            size: 0,
+            used: 0,
+        }
+    }
+
+    /// Hands the memory `start..start + size` to the heap.
+    ///
+    /// # Safety
+    /// The memory must be mapped, writable, unused and must not be handed out twice.
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        self.fallback.add_region(start, size);
+        self.size += size;
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let ptr = match Self::slab_index(layout) {
+            Some(index) => {
+                if self.slabs[index].free_objects() == 0 {
+                    let chunk_layout = Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).ok()?;
+                    let chunk = self.fallback.allocate(chunk_layout)?;
+
+                    // SAFETY: The chunk was just taken from the fallback allocator.
+                    unsafe { self.slabs[index].grow(chunk) };
+                }
+
+                self.slabs[index].allocate()?
+            }
+            None => self.fallback.allocate(layout)?,
+        };
+
+        self.used += Self::class_size(layout);
+
+        Some(ptr)
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by [`Self::allocate`] with the same layout.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match Self::slab_index(layout) {
+            Some(index) => self.slabs[index].deallocate(ptr),
+            None => self.fallback.deallocate(ptr, layout),
+        }
+
+        self.used -= Self::class_size(layout);
+    }
+
+    pub fn statistics(&self) -> HeapStatistics {
+        HeapStatistics {
+            size: self.size,
+            used: self.used,
+            free: self.fallback.free_bytes(),
+            cached: self
+                .slabs
+                .iter()
+                .map(|slab| slab.free_objects() * slab.object_size())
+                .sum(),
+        }
+    }
+
+    /// Index of the smallest slab cache whose objects fit size and alignment of `layout`.
+    #[inline]
+    fn slab_index(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+
+        SLAB_SIZES
+            .iter()
+            .position(|&object_size| size <= object_size)
+    }
+
+    #[inline]
+    fn class_size(layout: Layout) -> usize {
+        match Self::slab_index(layout) {
+            Some(index) => SLAB_SIZES[index],
+            None => layout.size(),
+        }
+    }
+}
+
+impl Default for KernelHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod export;
+pub mod linked_list;
+pub mod slab;
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+
+use export::{HeapStatistics, KernelHeap};
+use slab::SLAB_SIZE;
+use spin::Mutex;
+use uio::kprintln;
+use x86_64::op::interrupts;
+use x86_64::structures::memory::VirtualAddress;
+use x86_64::types::paging::page::{Page, PageSize, Size4KiB};
+use x86_64::types::paging::table::PageTableFlags;
+
+/// Start of the kernel heap, above 64 TiB of higher half direct map.
+pub const HEAP_START: u64 = 0xffff_c000_0000_0000;
+/// Bytes mapped by [`init`].
+pub const HEAP_INITIAL_SIZE: u64 = 1024 * 1024;
+/// The heap never grows beyond this size.
+pub const HEAP_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+/// The heap grows by at least this many bytes at once.
+const HEAP_GROWTH: u64 = 64 * 1024;
+
+static ALLOCATOR: LockedHeap = LockedHeap::new();
+
+struct HeapState {
+    heap: KernelHeap,
+    /// Bytes mapped from `HEAP_START` on.
+    mapped: u64,
+}
+
+/// The kernel heap behind a lock, growing on demand by mapping fresh frames.
+pub struct LockedHeap {
+    state: Mutex<HeapState>,
+}
+
+impl HeapState {
+    /// Maps at least `bytes` more memory at the end of the heap.
+    fn grow(&mut self, bytes: u64) -> Result<(), ()> {
+        let bytes = bytes.max(HEAP_GROWTH).next_multiple_of(Size4KiB::SIZE);
+
+        if self.mapped + bytes > HEAP_MAX_SIZE {
+            return Err(());
+        }
+
+        let start = VirtualAddress::new(HEAP_START + self.mapped);
+        let mut page = Page::<Size4KiB>::containing_address(start);
+        let mut mapped = 0;
+
+        while mapped < bytes {
+            if pmm::mapping::map_page(page, PageTableFlags::PRESENT | PageTableFlags::WRITABLE)
+                .is_err()
+            {
+                break;
+            }
+
+            mapped += Size4KiB::SIZE;
+            page = page.next();
+        }
+
+        if mapped == 0 {
+            return Err(());
+        }
+
+        // SAFETY: The pages were just mapped and are not part of the heap yet.
+        unsafe {
+            self.heap
+                .add_region(start.as_u64() as usize, mapped as usize);
+        }
+
+        self.mapped += mapped;
+
+        Ok(())
+    }
+}
+
+impl LockedHeap {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(HeapState {
+                heap: KernelHeap::new(),
+                mapped: 0,
+            }),
+        }
+    }
+
+    /// Runs `f` on the heap with interrupts disabled. A thread preempted while holding the
+    /// lock would stall every allocation made with interrupts disabled on its core.
+    fn with_state<R>(&self, f: impl FnOnce(&mut HeapState) -> R) -> R {
+        interrupts::without_interrupts(|| f(&mut self.state.lock()))
+    }
+}
+
+impl Default for LockedHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_state(|state| {
+            if let Some(ptr) = state.heap.allocate(layout) {
+                return ptr.as_ptr();
+            }
+
+            // Make sure a fresh region can hold the request even after alignment and slab
+            // refill.
+            let needed = (layout.size() + layout.align()).max(SLAB_SIZE) as u64;
+
+            if state.mapped > 0 && state.grow(needed).is_ok() {
+                if let Some(ptr) = state.heap.allocate(layout) {
+                    return ptr.as_ptr();
+                }
+            }
+
+            out_of_memory(layout, &state.heap.statistics());
+
+            ptr::null_mut()
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.with_state(|state| state.heap.deallocate(ptr, layout));
+        }
+    }
+}
+
+/// Serves allocations from the kernel heap, for `#[global_allocator]`.
+///
+/// The kernel registers it, so host tests of this crate and the crates using it keep the
+/// allocator of the host.
+pub struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATOR.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATOR.dealloc(ptr, layout)
+    }
+}
+
+/// Reports an allocation the heap can not serve. The caller returns null afterwards, so
+/// infallible allocations end up in the panic handler through `handle_alloc_error`.
+#[cold]
+fn out_of_memory(layout: Layout, statistics: &HeapStatistics) {
+    kprintln!(
+        "HEAP: Out of memory allocating {} bytes (align {}). {} of {} bytes in use, {} free.",
+        layout.size(),
+        layout.align(),
+        statistics.used,
+        statistics.size,
+        statistics.free + statistics.cached
+    );
+}
+
+pub fn init() {
+    let mapped = ALLOCATOR.with_state(|state| {
+        if state.grow(HEAP_INITIAL_SIZE).is_err() {
+            panic!("Could not map the initial kernel heap.");
+        }
+
+        state.mapped
+    });
+
+    kprintln!("HEAP: {} KiB mapped at {:#x}", mapped / 1024, HEAP_START);
+}
+
+/// Current usage of the kernel heap.
+pub fn statistics() -> HeapStatistics {
+    ALLOCATOR.with_state(|state| state.heap.statistics())
+}
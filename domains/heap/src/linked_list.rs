@@ -0,0 +1,259 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+/// Header written into every free block. Blocks are kept sorted by address.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Granularity of all blocks. Every block start and size is a multiple of it, so splitting a
+/// block never leaves a remainder too small to hold a [`FreeBlock`].
+const GRANULE: usize = size_of::<FreeBlock>();
+
+/// First-fit allocator over a sorted list of free blocks, merging neighbours on free.
+///
+/// Used as fallback for allocations the slab caches can not serve and as their page source.
+pub struct LinkedListAllocator {
+    head: FreeBlock,
+    free: usize,
+}
+
+// SAFETY: The allocator owns the memory of its blocks; it is only reached through a lock.
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        Self {
+            head: FreeBlock {
+                size: 0,
+                next: ptr::null_mut(),
+            },
+            free: 0,
+        }
+    }
+
+    /// Hands the memory `start..start + size` to the allocator.
+    ///
+    /// # Safety
+    /// The memory must be valid, unused and must not be handed out twice.
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        let aligned = start.next_multiple_of(GRANULE);
+        let size = size.saturating_sub(aligned - start) & !(GRANULE - 1);
+
+        if size > 0 {
+            self.insert(aligned, size);
+        }
+    }
+
+    /// Number of bytes currently held in free blocks.
+    #[inline]
+    pub fn free_bytes(&self) -> usize {
+        self.free
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let (size, align) = Self::adjust(layout);
+        let mut previous: *mut FreeBlock = &mut self.head;
+
+        // SAFETY: Every block in the list was handed to us through `add_region` or `deallocate`.
+        unsafe {
+            while !(*previous).next.is_null() {
+                let block = (*previous).next;
+                let block_start = block as usize;
+                let block_end = block_start + (*block).size;
+
+                let mut start = block_start.next_multiple_of(align);
+
+                if start != block_start && start - block_start < GRANULE {
+                    start = (block_start + GRANULE).next_multiple_of(align);
+                }
+
+                match start.checked_add(size) {
+                    Some(end) if end <= block_end => {
+                        (*previous).next = (*block).next;
+                        self.free -= block_end - block_start;
+
+                        if start > block_start {
+                            self.insert(block_start, start - block_start);
+                        }
+
+                        if block_end > end {
+                            self.insert(end, block_end - end);
+                        }
+
+                        return NonNull::new(start as *mut u8);
+                    }
+                    _ => previous = block,
+                }
+            }
+        }
+
+        None
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by [`Self::allocate`] with the same layout.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let (size, _) = Self::adjust(layout);
+
+        self.insert(ptr.as_ptr() as usize, size);
+    }
+
+    /// Rounds a layout up to the granularity of the allocator.
+    #[inline]
+    fn adjust(layout: Layout) -> (usize, usize) {
+        let size = layout.size().max(GRANULE).next_multiple_of(GRANULE);
+        let align = layout.align().max(align_of::<FreeBlock>()).max(GRANULE);
+
+        (size, align)
+    }
+
+    /// Inserts a block at its sorted position and merges it with adjacent blocks.
+    unsafe fn insert(&mut self, start: usize, size: usize) {
+        let mut previous: *mut FreeBlock = &mut self.head;
+
+        while !(*previous).next.is_null() && ((*previous).next as usize) < start {
+            previous = (*previous).next;
+        }
+
+        let next = (*previous).next;
+        let block = start as *mut FreeBlock;
+
+        block.write(FreeBlock { size, next });
+        self.free += size;
+
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if !ptr::eq(previous, &self.head) && previous as usize + (*previous).size == start {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        } else {
+            (*previous).next = block;
+        }
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGION_SIZE: usize = 4096;
+
+    #[repr(C, align(4096))]
+    struct Region([u8; REGION_SIZE]);
+
+    fn region() -> usize {
+        Box::leak(Box::new(Region([0; REGION_SIZE]))) as *mut Region as usize
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn add_region_trims_to_granules() {
+        let start = region();
+        let mut allocator = LinkedListAllocator::new();
+
+        unsafe { allocator.add_region(start + 1, REGION_SIZE - 1) };
+
+        assert_eq!(allocator.free_bytes(), REGION_SIZE - GRANULE);
+
+        let ptr = allocator.allocate(layout(8, 1)).unwrap();
+        assert_eq!(ptr.as_ptr() as usize, start + GRANULE);
+    }
+
+    #[test]
+    fn allocate_respects_alignment() {
+        let start = region();
+        let mut allocator = LinkedListAllocator::new();
+
+        unsafe { allocator.add_region(start, REGION_SIZE) };
+
+        let small = allocator.allocate(layout(24, 8)).unwrap();
+        let aligned = allocator.allocate(layout(64, 256)).unwrap();
+
+        assert_eq!(small.as_ptr() as usize, start);
+        assert_eq!(aligned.as_ptr() as usize, start + 256);
+
+        // The gap in front of the aligned block stays usable.
+        let gap = allocator.allocate(layout(128, 16)).unwrap();
+        assert_eq!(gap.as_ptr() as usize, start + 32);
+    }
+
+    #[test]
+    fn too_small_gap_is_skipped() {
+        let start = region();
+        let mut allocator = LinkedListAllocator::new();
+
+        unsafe { allocator.add_region(start + GRANULE, REGION_SIZE - GRANULE) };
+
+        // Aligning to 32 would leave a gap of one granule, enough for a free block.
+        let ptr = allocator.allocate(layout(16, 32)).unwrap();
+        assert_eq!(ptr.as_ptr() as usize, start + 2 * GRANULE);
+        assert_eq!(allocator.free_bytes(), REGION_SIZE - 2 * GRANULE);
+    }
+
+    #[test]
+    fn deallocate_merges_neighbours() {
+        let start = region();
+        let mut allocator = LinkedListAllocator::new();
+
+        unsafe { allocator.add_region(start, REGION_SIZE) };
+
+        let blocks: [_; 3] = core::array::from_fn(|_| allocator.allocate(layout(1024, 8)).unwrap());
+
+        unsafe {
+            allocator.deallocate(blocks[1], layout(1024, 8));
+            allocator.deallocate(blocks[0], layout(1024, 8));
+            allocator.deallocate(blocks[2], layout(1024, 8));
+        }
+
+        assert_eq!(allocator.free_bytes(), REGION_SIZE);
+
+        // Only a single merged block can serve the whole region.
+        let all = allocator.allocate(layout(REGION_SIZE, 8)).unwrap();
+        assert_eq!(all.as_ptr() as usize, start);
+    }
+
+    #[test]
+    fn exhaustion() {
+        let start = region();
+        let mut allocator = LinkedListAllocator::new();
+
+        unsafe { allocator.add_region(start, REGION_SIZE) };
+
+        assert!(allocator.allocate(layout(REGION_SIZE + 1, 8)).is_none());
+        assert!(allocator.allocate(layout(REGION_SIZE, 8)).is_some());
+        assert!(allocator.allocate(layout(1, 1)).is_none());
+        assert_eq!(allocator.free_bytes(), 0);
+    }
+}
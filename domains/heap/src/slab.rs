@@ -0,0 +1,167 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+
+/// Size of the chunks a slab cache takes from the fallback allocator.
+pub const SLAB_SIZE: usize = 4096;
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Cache of equally sized objects, carved from [`SLAB_SIZE`] chunks.
+///
+/// Objects are aligned to their size, as chunks are aligned to [`SLAB_SIZE`] and all object
+/// sizes are powers of two.
+pub struct Slab {
+    object_size: usize,
+    free_list: *mut FreeObject,
+    free: usize,
+}
+
+// SAFETY: The cache owns the memory of its free objects; it is only reached through a lock.
+unsafe impl Send for Slab {}
+
+impl Slab {
+    pub const fn new(object_size: usize) -> Self {
+        assert!(object_size.is_power_of_two() && object_size >= size_of::<FreeObject>());
+
+        Self {
+            object_size,
+            free_list: ptr::null_mut(),
+            free: 0,
+        }
+    }
+
+    #[inline]
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    /// Number of objects currently held in the cache.
+    #[inline]
+    pub fn free_objects(&self) -> usize {
+        self.free
+    }
+
+    /// Splits a [`SLAB_SIZE`] chunk into objects.
+    ///
+    /// # Safety
+    /// `chunk` must be valid, unused and aligned to [`SLAB_SIZE`].
+    pub unsafe fn grow(&mut self, chunk: NonNull<u8>) {
+        for offset in (0..SLAB_SIZE).step_by(self.object_size).rev() {
+            self.push(chunk.as_ptr().add(offset));
+        }
+    }
+
+    pub fn allocate(&mut self) -> Option<NonNull<u8>> {
+        let object = NonNull::new(self.free_list)?;
+
+        // SAFETY: Objects in the free list are valid and unused.
+        self.free_list = unsafe { object.as_ref().next };
+        self.free -= 1;
+
+        Some(object.cast())
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by [`Self::allocate`] of this cache.
+    #[inline]
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>) {
+        self.push(ptr.as_ptr());
+    }
+
+    #[inline]
+    unsafe fn push(&mut self, object: *mut u8) {
+        let object = object as *mut FreeObject;
+
+        object.write(FreeObject {
+            next: self.free_list,
+        });
+
+        self.free_list = object;
+        self.free += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(4096))]
+    struct Chunk([u8; SLAB_SIZE]);
+
+    fn chunk() -> NonNull<u8> {
+        let chunk = Box::leak(Box::new(Chunk([0; SLAB_SIZE])));
+
+        NonNull::from(chunk).cast()
+    }
+
+    #[test]
+    fn grow_splits_chunk() {
+        let mut slab = Slab::new(64);
+        let chunk = chunk();
+
+        unsafe { slab.grow(chunk) };
+
+        assert_eq!(slab.free_objects(), SLAB_SIZE / 64);
+
+        for index in 0..SLAB_SIZE / 64 {
+            let object = slab.allocate().unwrap();
+
+            assert_eq!(
+                object.as_ptr() as usize,
+                chunk.as_ptr() as usize + index * 64
+            );
+        }
+
+        assert_eq!(slab.free_objects(), 0);
+        assert!(slab.allocate().is_none());
+    }
+
+    #[test]
+    fn objects_are_aligned_to_their_size() {
+        let mut slab = Slab::new(256);
+
+        unsafe { slab.grow(chunk()) };
+
+        while let Some(object) = slab.allocate() {
+            assert_eq!(object.as_ptr() as usize % 256, 0);
+        }
+    }
+
+    #[test]
+    fn deallocate_reuses_last_object() {
+        let mut slab = Slab::new(8);
+
+        unsafe { slab.grow(chunk()) };
+
+        let first = slab.allocate().unwrap();
+        let second = slab.allocate().unwrap();
+
+        unsafe {
+            slab.deallocate(first);
+            slab.deallocate(second);
+        }
+
+        assert_eq!(slab.free_objects(), SLAB_SIZE / 8);
+        assert_eq!(slab.allocate(), Some(second));
+        assert_eq!(slab.allocate(), Some(first));
+    }
+}
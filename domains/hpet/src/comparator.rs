@@ -0,0 +1,190 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use crate::device::Hpet;
+
+const CONFIGURATION_LEVEL_TRIGGERED: u64 = 1 << 1;
+const CONFIGURATION_INTERRUPT_ENABLE: u64 = 1 << 2;
+const CONFIGURATION_PERIODIC: u64 = 1 << 3;
+const CAPABILITY_PERIODIC: u64 = 1 << 4;
+const CAPABILITY_64BIT: u64 = 1 << 5;
+/// Lets the next write to the comparator set the accumulator instead, in periodic mode.
+const CONFIGURATION_VALUE_SET: u64 = 1 << 6;
+const CONFIGURATION_32BIT_MODE: u64 = 1 << 8;
+const CONFIGURATION_ROUTE_SHIFT: u64 = 9;
+const CONFIGURATION_ROUTE_MASK: u64 = 0x1F << CONFIGURATION_ROUTE_SHIFT;
+const CONFIGURATION_FSB_ENABLE: u64 = 1 << 14;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparatorError {
+    /// The comparator can not be connected to the I/O APIC input.
+    RouteUnavailable(u8),
+    PeriodicUnsupported,
+    /// The period is shorter than the minimum tick of the HPET table.
+    BelowMinimumTick,
+    /// The comparator is 32 bits wide and the value does not fit.
+    OutOfRange,
+}
+
+/// How the comparator signals its I/O APIC input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    /// Stays asserted until [`Hpet::acknowledge`].
+    Level,
+}
+
+/// One timer of an HPET block, comparing the main counter against a value.
+#[derive(Debug)]
+pub struct Comparator<'a> {
+    hpet: &'a Hpet,
+    index: u8,
+}
+
+impl<'a> Comparator<'a> {
+    pub(crate) fn new(hpet: &'a Hpet, index: u8) -> Self {
+        Self { hpet, index }
+    }
+
+    #[inline]
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Bit `n` is set if the comparator can be connected to I/O APIC input `n`.
+    #[inline]
+    pub fn routes(&self) -> u32 {
+        (self.configuration() >> 32) as u32
+    }
+
+    #[inline]
+    pub fn supports_periodic(&self) -> bool {
+        self.configuration() & CAPABILITY_PERIODIC != 0
+    }
+
+    #[inline]
+    pub fn is_64bit(&self) -> bool {
+        self.configuration() & CAPABILITY_64BIT != 0
+    }
+
+    /// Raises I/O APIC input `route` every `period` counter ticks.
+    ///
+    /// `route` is the GSI on systems whose first I/O APIC starts at GSI 0.
+    pub fn set_periodic(
+        &self,
+        route: u8,
+        period: u64,
+        trigger: Trigger,
+    ) -> Result<(), ComparatorError> {
+        if !self.supports_periodic() {
+            return Err(ComparatorError::PeriodicUnsupported);
+        }
+
+        if period < self.hpet.minimum_tick().max(1) {
+            return Err(ComparatorError::BelowMinimumTick);
+        }
+
+        self.check_range(period)?;
+        let first = self.hpet.counter().wrapping_add(period);
+        self.configure(
+            route,
+            trigger,
+            CONFIGURATION_PERIODIC | CONFIGURATION_VALUE_SET,
+        )?;
+
+        // With the value set bit, the first write sets the comparator, the second the period.
+        self.hpet.write(self.comparator_register(), first);
+        self.hpet.write(self.comparator_register(), period);
+
+        Ok(())
+    }
+
+    /// Raises I/O APIC input `route` once, when the main counter reaches `deadline`.
+    ///
+    /// 32-bit comparators only match the low half of the counter, they can not wait for
+    /// more than 2^32 ticks.
+    pub fn set_one_shot(
+        &self,
+        route: u8,
+        deadline: u64,
+        trigger: Trigger,
+    ) -> Result<(), ComparatorError> {
+        self.check_range(deadline.saturating_sub(self.hpet.counter()))?;
+        self.configure(route, trigger, 0)?;
+
+        self.hpet.write(self.comparator_register(), deadline);
+
+        Ok(())
+    }
+
+    /// Disables the interrupt of the comparator.
+    pub fn disable(&self) {
+        let configuration = self.configuration()
+            & !(CONFIGURATION_INTERRUPT_ENABLE | CONFIGURATION_PERIODIC | CONFIGURATION_FSB_ENABLE);
+
+        self.hpet
+            .write(self.configuration_register(), configuration);
+    }
+
+    fn configure(&self, route: u8, trigger: Trigger, mode: u64) -> Result<(), ComparatorError> {
+        if route >= 32 || self.routes() & (1 << route) == 0 {
+            return Err(ComparatorError::RouteUnavailable(route));
+        }
+
+        let mut configuration = self.configuration()
+            & !(CONFIGURATION_ROUTE_MASK
+                | CONFIGURATION_LEVEL_TRIGGERED
+                | CONFIGURATION_PERIODIC
+                | CONFIGURATION_32BIT_MODE
+                | CONFIGURATION_FSB_ENABLE);
+
+        configuration |=
+            (route as u64) << CONFIGURATION_ROUTE_SHIFT | CONFIGURATION_INTERRUPT_ENABLE | mode;
+
+        if trigger == Trigger::Level {
+            configuration |= CONFIGURATION_LEVEL_TRIGGERED;
+        }
+
+        self.hpet
+            .write(self.configuration_register(), configuration);
+
+        Ok(())
+    }
+
+    fn check_range(&self, value: u64) -> Result<(), ComparatorError> {
+        if self.is_64bit() || value <= u32::MAX as u64 {
+            Ok(())
+        } else {
+            Err(ComparatorError::OutOfRange)
+        }
+    }
+
+    #[inline]
+    fn configuration(&self) -> u64 {
+        self.hpet.read(self.configuration_register())
+    }
+
+    #[inline]
+    fn configuration_register(&self) -> u64 {
+        0x100 + 0x20 * self.index as u64
+    }
+
+    #[inline]
+    fn comparator_register(&self) -> u64 {
+        self.configuration_register() + 0x08
+    }
+}
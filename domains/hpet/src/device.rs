@@ -0,0 +1,207 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::structures::memory::{PhysicalAddress, VirtualAddress};
+use x86_64::types::paging::mapper::MapToError;
+use x86_64::types::paging::page::Size4KiB;
+
+use crate::comparator::Comparator;
+
+const MMIO_SIZE: u64 = 0x400;
+
+const GENERAL_CAPABILITIES: u64 = 0x000;
+const GENERAL_CONFIGURATION: u64 = 0x010;
+const GENERAL_INTERRUPT_STATUS: u64 = 0x020;
+const MAIN_COUNTER: u64 = 0x0F0;
+
+const CAPABILITY_COUNTER_64BIT: u64 = 1 << 13;
+
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+const CONFIGURATION_LEGACY_ROUTING: u64 = 1 << 1;
+
+/// The specification caps the counter period at 100 ns.
+const MAXIMUM_PERIOD_FS: u64 = 100_000_000;
+
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HpetError {
+    /// The registers could not be mapped.
+    Unmapped(MapToError<Size4KiB>),
+    /// The counter period is 0 or above 100 ns.
+    InvalidPeriod(u64),
+}
+
+/// An HPET block: a free running main counter and up to 32 comparators.
+#[derive(Debug)]
+pub struct Hpet {
+    base: VirtualAddress,
+    /// Length of one counter tick in femtoseconds.
+    period: u64,
+    comparators: u8,
+    counter_64bit: bool,
+    minimum_tick: u64,
+    /// Last value returned by [`Self::counter`], extends 32-bit counters to 64 bits.
+    last_counter: AtomicU64,
+}
+
+impl Hpet {
+    /// Maps the registers, resets the main counter to 0 and starts it with all comparator
+    /// interrupts disabled.
+    ///
+    /// # Safety
+    /// `address` has to be the base address of an HPET block, from the ACPI HPET table.
+    pub unsafe fn new(address: PhysicalAddress, minimum_tick: u16) -> Result<Self, HpetError> {
+        let base = pmm::mapping::map_mmio(address, MMIO_SIZE).map_err(HpetError::Unmapped)?;
+
+        let capabilities = read_volatile((base + GENERAL_CAPABILITIES).as_ptr::<u64>());
+        let period = capabilities >> 32;
+
+        if period == 0 || period > MAXIMUM_PERIOD_FS {
+            return Err(HpetError::InvalidPeriod(period));
+        }
+
+        let hpet = Self {
+            base,
+            period,
+            comparators: ((capabilities >> 8) & 0x1F) as u8 + 1,
+            counter_64bit: capabilities & CAPABILITY_COUNTER_64BIT != 0,
+            minimum_tick: minimum_tick as u64,
+            last_counter: AtomicU64::new(0),
+        };
+
+        // The main counter is only writable while halted.
+        let configuration = hpet.read(GENERAL_CONFIGURATION);
+        hpet.write(
+            GENERAL_CONFIGURATION,
+            configuration & !(CONFIGURATION_ENABLE | CONFIGURATION_LEGACY_ROUTING),
+        );
+        hpet.write(MAIN_COUNTER, 0);
+
+        for index in 0..hpet.comparators {
+            hpet.comparator(index)
+                .expect("Comparator index out of range")
+                .disable();
+        }
+
+        hpet.write(
+            GENERAL_CONFIGURATION,
+            hpet.read(GENERAL_CONFIGURATION) | CONFIGURATION_ENABLE,
+        );
+
+        Ok(hpet)
+    }
+
+    /// Length of one counter tick in femtoseconds.
+    #[inline]
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Counter ticks per second.
+    #[inline]
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period
+    }
+
+    #[inline]
+    pub fn comparator_count(&self) -> u8 {
+        self.comparators
+    }
+
+    #[inline]
+    pub fn counter_is_64bit(&self) -> bool {
+        self.counter_64bit
+    }
+
+    /// Minimum number of ticks between interrupts in periodic mode, from the ACPI table.
+    #[inline]
+    pub fn minimum_tick(&self) -> u64 {
+        self.minimum_tick
+    }
+
+    /// Returns the comparator `index`, if the block has it.
+    pub fn comparator(&self, index: u8) -> Option<Comparator<'_>> {
+        (index < self.comparators).then(|| Comparator::new(self, index))
+    }
+
+    /// Reads the main counter.
+    ///
+    /// 32-bit counters are extended to 64 bits, which requires a read at least once per
+    /// wrap around, about every 5 minutes at the usual 14.318 MHz.
+    pub fn counter(&self) -> u64 {
+        if self.counter_64bit {
+            return self.read(MAIN_COUNTER);
+        }
+
+        // Loaded before the counter, a lower counter value then means it wrapped.
+        let last = self.last_counter.load(Ordering::Acquire);
+        let low = unsafe { read_volatile((self.base + MAIN_COUNTER).as_ptr::<u32>()) } as u64;
+
+        let mut value = (last & !(u32::MAX as u64)) | low;
+        if value < last {
+            value += 1 << 32;
+        }
+
+        value.max(self.last_counter.fetch_max(value, Ordering::AcqRel))
+    }
+
+    /// Nanoseconds since [`Self::new`] started the counter.
+    #[inline]
+    pub fn nanos(&self) -> u64 {
+        self.ticks_to_nanos(self.counter())
+    }
+
+    #[inline]
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        (ticks as u128 * self.period as u128 / FEMTOS_PER_NANO as u128) as u64
+    }
+
+    #[inline]
+    pub fn nanos_to_ticks(&self, nanos: u64) -> u64 {
+        (nanos as u128 * FEMTOS_PER_NANO as u128 / self.period as u128) as u64
+    }
+
+    /// Busy waits for `duration` nanoseconds.
+    pub fn wait(&self, duration: u64) {
+        let start = self.counter();
+        let ticks = self.nanos_to_ticks(duration);
+
+        while self.counter() - start < ticks {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Clears the interrupt status of a level triggered comparator.
+    #[inline]
+    pub fn acknowledge(&self, index: u8) {
+        self.write(GENERAL_INTERRUPT_STATUS, 1 << index);
+    }
+
+    #[inline]
+    pub(crate) fn read(&self, register: u64) -> u64 {
+        unsafe { read_volatile((self.base + register).as_ptr::<u64>()) }
+    }
+
+    #[inline]
+    pub(crate) fn write(&self, register: u64, value: u64) {
+        unsafe { write_volatile((self.base + register).as_mut_ptr::<u64>(), value) }
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_std]
+
+pub mod comparator;
+pub mod device;
+
+use acpi::sdt::AddressSpace;
+use device::Hpet;
+use spin::Once;
+use uio::kprintln;
+use x86_64::structures::memory::PhysicalAddress;
+
+static HPET: Once<Hpet> = Once::new();
+
+/// Brings up the HPET described by the ACPI HPET table.
+///
+/// Without an HPET the kernel keeps running, [`hpet`] and [`now`] then return `None` and
+/// timekeeping falls back to the PIT.
+pub fn init() {
+    let table = match acpi::get::<acpi::hpet::Hpet>() {
+        Ok(table) => table,
+        Err(error) => {
+            kprintln!(
+                "HPET: Not available ({:?}), falling back to the PIT.",
+                error
+            );
+            return;
+        }
+    };
+
+    if table.base_address.address_space != AddressSpace::SystemMemory {
+        kprintln!(
+            "HPET: Registers in {:?}, falling back to the PIT.",
+            table.base_address.address_space
+        );
+        return;
+    }
+
+    // SAFETY: The address comes from the HPET table.
+    let hpet = match unsafe {
+        Hpet::new(
+            PhysicalAddress::new(table.base_address.address),
+            table.minimum_tick,
+        )
+    } {
+        Ok(hpet) => HPET.call_once(|| hpet),
+        Err(error) => {
+            kprintln!("HPET: Unusable ({:?}), falling back to the PIT.", error);
+            return;
+        }
+    };
+
+    kprintln!(
+        "HPET: {} Hz, {}-bit counter, {} comparators",
+        hpet.frequency(),
+        if hpet.counter_is_64bit() { 64 } else { 32 },
+        hpet.comparator_count()
+    );
+}
+
+/// Returns the HPET, if [`init`] found a usable one.
+#[inline]
+pub fn hpet() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+/// Nanoseconds since [`init`], a monotonic clock available before any timer interrupt.
+#[inline]
+pub fn now() -> Option<u64> {
+    hpet().map(Hpet::nanos)
+}
@@ -0,0 +1,175 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kstructs::atomic_fn::AtomicFn;
+use uio::kprintln;
+
+use crate::export::{InterruptDescriptorTable, FIRST_USER_VECTOR, USER_VECTOR_COUNT};
+use crate::internal::{InterruptHandlerFunction, InterruptStackFrame};
+
+/// Handler for a vector claimed at runtime. Receives the vector it was invoked for.
+pub type DynamicHandler = fn(vector: u8, stack_frame: &InterruptStackFrame);
+
+/// Vectors handed out by [`allocate`]. The vectors above are kept for fixed system vectors
+/// like IPIs, the timer and the spurious interrupt.
+pub const DYNAMIC_VECTORS: core::ops::RangeInclusive<u8> = 0x30..=0xEF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationError {
+    /// The vector is one of the 32 exception vectors.
+    Reserved(u8),
+    /// Another handler already claimed the vector.
+    InUse(u8),
+    /// All vectors in [`DYNAMIC_VECTORS`] are claimed.
+    Exhausted,
+}
+
+/// Registered handlers of the vectors 32 - 255, stored as function pointers. 0 marks a free vector.
+static HANDLERS: [AtomicUsize; USER_VECTOR_COUNT] =
+    [const { AtomicUsize::new(0) }; USER_VECTOR_COUNT];
+
+/// The function acknowledging an interrupt at the interrupt controller, if one is registered.
+static END_OF_INTERRUPT_HANDLER: AtomicFn<fn()> = AtomicFn::new();
+
+/// Set once an interrupt without a handler was reported, later ones are not.
+static UNHANDLED_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Registers the function signalling the end of an interrupt to the interrupt controller.
+///
+/// Vectors without a handler are acknowledged through it, a stray interrupt would otherwise
+/// stay in service and block every vector of equal or lower priority on its core.
+pub fn set_end_of_interrupt_handler(handler: fn()) {
+    END_OF_INTERRUPT_HANDLER.store(handler);
+}
+
+/// Installs `handler` for `vector`.
+pub fn register(vector: u8, handler: DynamicHandler) -> Result<(), RegistrationError> {
+    slot(vector)?
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .map(|_| ())
+        .map_err(|_| RegistrationError::InUse(vector))
+}
+
+/// Installs `handler` for the first free vector in [`DYNAMIC_VECTORS`] and returns it.
+pub fn allocate(handler: DynamicHandler) -> Result<u8, RegistrationError> {
+    DYNAMIC_VECTORS
+        .into_iter()
+        .find(|&vector| register(vector, handler).is_ok())
+        .ok_or(RegistrationError::Exhausted)
+}
+
+/// Removes the handler of `vector`, making the vector available again.
+pub fn unregister(vector: u8) -> Result<(), RegistrationError> {
+    slot(vector)?.store(0, Ordering::Release);
+
+    Ok(())
+}
+
+/// Returns whether a handler is installed for `vector`.
+pub fn is_registered(vector: u8) -> bool {
+    slot(vector).is_ok_and(|slot| slot.load(Ordering::Acquire) != 0)
+}
+
+#[inline]
+fn slot(vector: u8) -> Result<&'static AtomicUsize, RegistrationError> {
+    match vector.checked_sub(FIRST_USER_VECTOR) {
+        Some(index) => Ok(&HANDLERS[index as usize]),
+        None => Err(RegistrationError::Reserved(vector)),
+    }
+}
+
+fn dispatch_vector(vector: u8, stack_frame: &InterruptStackFrame) {
+    let handler = HANDLERS[(vector - FIRST_USER_VECTOR) as usize].load(Ordering::Acquire);
+
+    if handler == 0 {
+        if let Some(end_of_interrupt) = END_OF_INTERRUPT_HANDLER.load() {
+            end_of_interrupt();
+        }
+
+        if !UNHANDLED_REPORTED.swap(true, Ordering::Relaxed) {
+            kprintln!(
+                "Unhandled interrupt on vector {:#x}, further ones are not reported.",
+                vector
+            );
+        }
+
+        return;
+    }
+
+    // SAFETY: Non-zero slots are only ever written with a `DynamicHandler` by `register`.
+    let handler: DynamicHandler = unsafe { core::mem::transmute::<usize, DynamicHandler>(handler) };
+
+    handler(vector, stack_frame)
+}
+
+extern "x86-interrupt" fn dispatch<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
+    dispatch_vector(VECTOR, &stack_frame)
+}
+
+macro_rules! row {
+    ($row:literal) => {
+        [
+            dispatch::<{ $row * 16 }>,
+            dispatch::<{ $row * 16 + 0x1 }>,
+            dispatch::<{ $row * 16 + 0x2 }>,
+            dispatch::<{ $row * 16 + 0x3 }>,
+            dispatch::<{ $row * 16 + 0x4 }>,
+            dispatch::<{ $row * 16 + 0x5 }>,
+            dispatch::<{ $row * 16 + 0x6 }>,
+            dispatch::<{ $row * 16 + 0x7 }>,
+            dispatch::<{ $row * 16 + 0x8 }>,
+            dispatch::<{ $row * 16 + 0x9 }>,
+            dispatch::<{ $row * 16 + 0xA }>,
+            dispatch::<{ $row * 16 + 0xB }>,
+            dispatch::<{ $row * 16 + 0xC }>,
+            dispatch::<{ $row * 16 + 0xD }>,
+            dispatch::<{ $row * 16 + 0xE }>,
+            dispatch::<{ $row * 16 + 0xF }>,
+        ]
+    };
+}
+
+/// One entry stub per user vector, each forwarding to the registered [`DynamicHandler`].
+static STUBS: [[InterruptHandlerFunction; 16]; USER_VECTOR_COUNT / 16] = [
+    row!(0x2),
+    row!(0x3),
+    row!(0x4),
+    row!(0x5),
+    row!(0x6),
+    row!(0x7),
+    row!(0x8),
+    row!(0x9),
+    row!(0xA),
+    row!(0xB),
+    row!(0xC),
+    row!(0xD),
+    row!(0xE),
+    row!(0xF),
+];
+
+/// Points every user vector that has no fixed handler yet to its dispatch stub.
+pub(crate) fn install_stubs(idt: &mut InterruptDescriptorTable) {
+    for (index, stub) in STUBS.iter().flatten().enumerate() {
+        let vector = FIRST_USER_VECTOR + index as u8;
+
+        if idt[vector].handler_address().is_none() {
+            idt[vector].set_handler_fn(*stub);
+        }
+    }
+}
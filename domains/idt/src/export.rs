@@ -15,7 +15,11 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use core::mem::size_of;
+use core::ops::{Index, IndexMut};
+
 use crate::internal::{
+    DivergingInterruptHandlerFunction, DivergingInterruptHandlerFunctionWithErrorCode,
     InterruptDescriptorTableEntry, InterruptHandlerFunction, InterruptHandlerFunctionWithErrorCode,
     PageFaultInterruptHandlerFunction,
 };
@@ -98,10 +102,12 @@ pub struct InterruptDescriptorTable {
     /// exception.
     ///
     /// The error code 0x0 is pushed on the stack of the double-fault handler.
-    pub double_fault: InterruptDescriptorTableEntry<InterruptHandlerFunctionWithErrorCode>,
+    pub double_fault: InterruptDescriptorTableEntry<DivergingInterruptHandlerFunctionWithErrorCode>,
+
+    /// Interrupt 0x9: Reserved
+    /// Coprocessor segment overrun, only raised by processors before the Intel486.
+    pub(crate) coprocessor_segment_overrun: InterruptDescriptorTableEntry<InterruptHandlerFunction>,
 
-    // Interrupt 0x9: Reserved
-    //
     /// Interrupt 0xA: Fault
     /// Indicates that there was an error related to a TSS. Such an error might be detected during a task switch or during
     /// the execution of instructions that use information from a TSS. Table 6-6 shows the conditions that cause an invalid
@@ -163,8 +169,9 @@ pub struct InterruptDescriptorTable {
     /// The processor provides the page-fault handler with two items of information to aid in diagosing the exception and recovering from it.
     pub page_fault: InterruptDescriptorTableEntry<PageFaultInterruptHandlerFunction>,
 
-    // Interrupt 0xF: Reserved
-    //
+    /// Interrupt 0xF: Reserved
+    pub(crate) reserved_1: InterruptDescriptorTableEntry<InterruptHandlerFunction>,
+
     /// Interrupt 0x10: Fault
     /// Indicates that the x87 FPU has detected a floating-point error. The NE flag in the register CR0 must be set for an
     /// interrupt 16 (floating-point error exception) to be generated. (See Section 2.5, “Control Registers,” for a detailed
@@ -190,7 +197,7 @@ pub struct InterruptDescriptorTable {
     /// a bus error. See page 3248 for more information.
     ///
     /// No error code is pushed on the stack.
-    pub machine_check: InterruptDescriptorTableEntry<InterruptHandlerFunction>,
+    pub machine_check: InterruptDescriptorTableEntry<DivergingInterruptHandlerFunction>,
 
     /// Interrupt 0x13: Fault
     /// Indicates the processor has detected an SSE/SSE2/SSE3 SIMD floating-point exception. The appropriate status
@@ -215,4 +222,71 @@ pub struct InterruptDescriptorTable {
     /// The processor provides the control protection exception handler with following information
     /// through the error code on the stack.
     pub control_protection: InterruptDescriptorTableEntry<InterruptHandlerFunctionWithErrorCode>,
+
+    /// Interrupt 0x16 - 0x1B: Reserved
+    pub(crate) reserved_2: [InterruptDescriptorTableEntry<InterruptHandlerFunction>; 6],
+
+    /// Interrupt 0x1C: Fault (AMD only)
+    /// Injected by a hypervisor to notify a SEV-SNP guest with restricted injection about pending events.
+    ///
+    /// No error code is pushed on the stack.
+    pub hypervisor_injection: InterruptDescriptorTableEntry<InterruptHandlerFunction>,
+
+    /// Interrupt 0x1D: Fault (AMD only)
+    /// Raised in SEV-ES guests on intercepted events, so the guest can communicate with the hypervisor.
+    ///
+    /// The error code holds the exit code of the intercepted event.
+    pub vmm_communication: InterruptDescriptorTableEntry<InterruptHandlerFunctionWithErrorCode>,
+
+    /// Interrupt 0x1E: Fault (AMD only)
+    /// Indicates a security-sensitive event, e.g. an INIT redirected by SVM.
+    ///
+    /// An error code describing the event is pushed onto the stack of the exception handler.
+    pub security: InterruptDescriptorTableEntry<InterruptHandlerFunctionWithErrorCode>,
+
+    /// Interrupt 0x1F: Reserved
+    pub(crate) reserved_3: InterruptDescriptorTableEntry<InterruptHandlerFunction>,
+
+    /// Interrupt 0x20 - 0xFF: User defined
+    /// External interrupts (IRQs), inter-processor interrupts and software interrupts. Reached through
+    /// [`Index<u8>`] and [`IndexMut<u8>`] with the vector number.
+    ///
+    /// No error code is pushed on the stack.
+    pub(crate) interrupts:
+        [InterruptDescriptorTableEntry<InterruptHandlerFunction>; USER_VECTOR_COUNT],
+}
+
+/// The first vector that is not reserved for exceptions.
+pub const FIRST_USER_VECTOR: u8 = 32;
+
+/// Number of vectors available for IRQs, IPIs and software interrupts.
+pub const USER_VECTOR_COUNT: usize = 256 - FIRST_USER_VECTOR as usize;
+
+// The limit loaded by `lidt` is derived from the size, so the table has to cover all 256 vectors.
+const _: () = assert!(size_of::<InterruptDescriptorTable>() == 256 * 16);
+
+impl Index<u8> for InterruptDescriptorTable {
+    type Output = InterruptDescriptorTableEntry<InterruptHandlerFunction>;
+
+    /// # Panics
+    /// If `vector` is one of the 32 exception vectors. Those are reached through the named fields.
+    #[inline]
+    fn index(&self, vector: u8) -> &Self::Output {
+        match vector.checked_sub(FIRST_USER_VECTOR) {
+            Some(index) => &self.interrupts[index as usize],
+            None => panic!("Vector {:#x} is reserved for exceptions.", vector),
+        }
+    }
+}
+
+impl IndexMut<u8> for InterruptDescriptorTable {
+    /// # Panics
+    /// If `vector` is one of the 32 exception vectors. Those are reached through the named fields.
+    #[inline]
+    fn index_mut(&mut self, vector: u8) -> &mut Self::Output {
+        match vector.checked_sub(FIRST_USER_VECTOR) {
+            Some(index) => &mut self.interrupts[index as usize],
+            None => panic!("Vector {:#x} is reserved for exceptions.", vector),
+        }
+    }
 }
@@ -15,13 +15,570 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use uio::kprintln;
+use core::fmt::{self, Arguments, Write};
+
+use bit_field::BitField;
+use kstructs::atomic_fn::AtomicFn;
+use x86_64::registers::control::{Cr2, Cr3, Cr4, Cr4Flags, Efer, EferFlags};
+use x86_64::structures::memory::VirtualAddress;
+use x86_64::types::paging::mapper;
+use x86_64::types::paging::table::{PageTable, PageTableFlags};
+use x86_64::types::paging::PageFaultErrorCode;
 
 use crate::internal::InterruptStackFrame;
 
-extern "x86-interrupt" fn default_handler(stack_frame: InterruptStackFrame) {
-    kprintln!(
-        ":: KERNEL PANIC ::\nCalled default_handler. Stacktrace:\n{:#?}",
-        stack_frame
+/// Mnemonic and name of the exception vectors 0x0 - 0x1F.
+const EXCEPTIONS: [(&str, &str); 32] = [
+    ("#DE", "Divide Error"),
+    ("#DB", "Debug"),
+    ("NMI", "Non-Maskable Interrupt"),
+    ("#BP", "Breakpoint"),
+    ("#OF", "Overflow"),
+    ("#BR", "BOUND Range Exceeded"),
+    ("#UD", "Invalid Opcode"),
+    ("#NM", "Device Not Available"),
+    ("#DF", "Double Fault"),
+    ("", "Coprocessor Segment Overrun"),
+    ("#TS", "Invalid TSS"),
+    ("#NP", "Segment Not Present"),
+    ("#SS", "Stack-Segment Fault"),
+    ("#GP", "General Protection"),
+    ("#PF", "Page Fault"),
+    ("", "Reserved"),
+    ("#MF", "x87 FPU Floating-Point Error"),
+    ("#AC", "Alignment Check"),
+    ("#MC", "Machine Check"),
+    ("#XM", "SIMD Floating-Point Exception"),
+    ("#VE", "Virtualization Exception"),
+    ("#CP", "Control Protection Exception"),
+    ("", "Reserved"),
+    ("", "Reserved"),
+    ("", "Reserved"),
+    ("", "Reserved"),
+    ("", "Reserved"),
+    ("", "Reserved"),
+    ("#HV", "Hypervisor Injection Exception"),
+    ("#VC", "VMM Communication Exception"),
+    ("#SX", "Security Exception"),
+    ("", "Reserved"),
+];
+
+/// How the error code pushed by the CPU has to be read.
+enum ErrorCode {
+    None,
+    /// The error code references a segment selector or an IDT vector.
+    Selector(u64),
+    /// The error code describes the branch that violated CET.
+    ControlProtection(u64),
+    Raw(u64),
+}
+
+/// Prints through `kprint!`, for reports after which the kernel keeps running.
+struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_fmt(format_args!("{}", s))
+    }
+
+    fn write_fmt(&mut self, args: Arguments<'_>) -> fmt::Result {
+        uio::_kprint(args);
+        Ok(())
+    }
+}
+
+/// Prints without waiting for the console locks, for reports before a panic and from NMIs.
+///
+/// The exception might have interrupted this core while it held the serial or framebuffer
+/// lock, `kprint!` would spin on it forever.
+struct Emergency;
+
+impl Write for Emergency {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_fmt(format_args!("{}", s))
+    }
+
+    fn write_fmt(&mut self, args: Arguments<'_>) -> fmt::Result {
+        uio::_kprint_emergency(args);
+        Ok(())
+    }
+}
+
+fn report(
+    out: &mut dyn Write,
+    vector: u8,
+    error_code: ErrorCode,
+    stack_frame: &InterruptStackFrame,
+) -> fmt::Result {
+    let (mnemonic, name) = EXCEPTIONS[vector as usize];
+
+    writeln!(
+        out,
+        ":: EXCEPTION ::\n{} {} (vector {:#x})",
+        mnemonic, name, vector
+    )?;
+
+    match error_code {
+        ErrorCode::None => {}
+        ErrorCode::Selector(code) => {
+            let table = match (code.get_bit(1), code.get_bit(2)) {
+                (true, _) => "IDT",
+                (false, false) => "GDT",
+                (false, true) => "LDT",
+            };
+
+            writeln!(
+                out,
+                "Error code: {:#x} ({} index {}{})",
+                code,
+                table,
+                code.get_bits(3..16),
+                if code.get_bit(0) {
+                    ", external event"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        ErrorCode::ControlProtection(code) => {
+            let cause = match code.get_bits(0..15) {
+                1 => "near RET",
+                2 => "far RET or IRET",
+                3 => "missing ENDBRANCH",
+                4 => "RSTORSSP",
+                5 => "SETSSBSY",
+                _ => "unknown",
+            };
+
+            writeln!(
+                out,
+                "Error code: {:#x} ({}{})",
+                code,
+                cause,
+                if code.get_bit(15) { ", in enclave" } else { "" }
+            )?;
+        }
+        ErrorCode::Raw(code) => writeln!(out, "Error code: {:#x}", code)?,
+    }
+
+    writeln!(out, "{:#?}", stack_frame)
+}
+
+/// Reports an exception the kernel can not recover from and panics.
+fn fatal(vector: u8, error_code: ErrorCode, stack_frame: &InterruptStackFrame) -> ! {
+    let _ = report(&mut Emergency, vector, error_code, stack_frame);
+
+    panic!("Unhandled exception {}", EXCEPTIONS[vector as usize].1);
+}
+
+pub extern "x86-interrupt" fn divide_error(stack_frame: InterruptStackFrame) {
+    fatal(0x0, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn debug(stack_frame: InterruptStackFrame) {
+    let _ = report(&mut Console, 0x1, ErrorCode::None, &stack_frame);
+}
+
+/// The function handling NMIs, if one is registered.
+static NON_MASKABLE_INTERRUPT_HANDLER: AtomicFn<fn() -> bool> = AtomicFn::new();
+
+/// Registers the function handling NMIs, for example those sent to halt the core. It
+/// returns false if it did not expect the NMI, which is then reported.
+pub fn set_non_maskable_interrupt_handler(handler: fn() -> bool) {
+    NON_MASKABLE_INTERRUPT_HANDLER.store(handler);
+}
+
+pub extern "x86-interrupt" fn non_maskable_interrupt(stack_frame: InterruptStackFrame) {
+    if NON_MASKABLE_INTERRUPT_HANDLER
+        .load()
+        .is_some_and(|handler| handler())
+    {
+        return;
+    }
+
+    // The NMI can arrive while this core holds a console lock.
+    let _ = report(&mut Emergency, 0x2, ErrorCode::None, &stack_frame);
+}
+
+pub extern "x86-interrupt" fn breakpoint(stack_frame: InterruptStackFrame) {
+    let _ = report(&mut Console, 0x3, ErrorCode::None, &stack_frame);
+}
+
+pub extern "x86-interrupt" fn overflow(stack_frame: InterruptStackFrame) {
+    let _ = report(&mut Console, 0x4, ErrorCode::None, &stack_frame);
+}
+
+pub extern "x86-interrupt" fn bound_range_exceeded(stack_frame: InterruptStackFrame) {
+    fatal(0x5, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn invalid_opcode(stack_frame: InterruptStackFrame) {
+    fatal(0x6, ErrorCode::None, &stack_frame)
+}
+
+/// The function resolving #NM, if one is registered.
+static DEVICE_NOT_AVAILABLE_HANDLER: AtomicFn<fn() -> bool> = AtomicFn::new();
+
+/// Registers the function handling #NM, which is raised by FPU and SIMD instructions while
+/// CR0.TS is set. It returns false if the fault was not caused by lazy state switching.
+pub fn set_device_not_available_handler(handler: fn() -> bool) {
+    DEVICE_NOT_AVAILABLE_HANDLER.store(handler);
+}
+
+pub extern "x86-interrupt" fn device_not_available(stack_frame: InterruptStackFrame) {
+    if DEVICE_NOT_AVAILABLE_HANDLER
+        .load()
+        .is_some_and(|handler| handler())
+    {
+        return;
+    }
+
+    fatal(0x7, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn double_fault(stack_frame: InterruptStackFrame, error_code: u64) -> ! {
+    fatal(0x8, ErrorCode::Raw(error_code), &stack_frame)
+}
+
+pub extern "x86-interrupt" fn invalid_tss(stack_frame: InterruptStackFrame, error_code: u64) {
+    fatal(0xA, ErrorCode::Selector(error_code), &stack_frame)
+}
+
+pub extern "x86-interrupt" fn segment_not_present(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal(0xB, ErrorCode::Selector(error_code), &stack_frame)
+}
+
+pub extern "x86-interrupt" fn stack_fault(stack_frame: InterruptStackFrame, error_code: u64) {
+    fatal(0xC, ErrorCode::Selector(error_code), &stack_frame)
+}
+
+pub extern "x86-interrupt" fn general_protection(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal(0xD, ErrorCode::Selector(error_code), &stack_frame)
+}
+
+/// The function resolving #PF, if one is registered.
+static PAGE_FAULT_HANDLER: AtomicFn<fn(VirtualAddress, PageFaultErrorCode) -> bool> =
+    AtomicFn::new();
+
+/// Registers the function resolving page faults, for example by mapping the faulting page.
+/// It returns false if it can not resolve the fault, which is then reported as fatal.
+pub fn set_page_fault_handler(handler: fn(VirtualAddress, PageFaultErrorCode) -> bool) {
+    PAGE_FAULT_HANDLER.store(handler);
+}
+
+/// Removes the function resolving page faults, every page fault is fatal again.
+pub fn clear_page_fault_handler() {
+    PAGE_FAULT_HANDLER.clear();
+}
+
+pub extern "x86-interrupt" fn page_fault(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let address = Cr2::read();
+
+    if PAGE_FAULT_HANDLER
+        .load()
+        .is_some_and(|handler| handler(address, error_code))
+    {
+        return;
+    }
+
+    let _ = report(
+        &mut Emergency,
+        0xE,
+        ErrorCode::Raw(error_code.bits()),
+        &stack_frame,
     );
+    let _ = describe_page_fault(&mut Emergency, address, error_code);
+
+    panic!(
+        "Unhandled exception {} at {:#x}",
+        EXCEPTIONS[0xE].1,
+        address.as_u64()
+    );
+}
+
+/// Prints the faulting address, the cause encoded in the error code and the page table
+/// entries translating the address.
+pub fn describe_page_fault(
+    out: &mut dyn Write,
+    address: VirtualAddress,
+    error_code: PageFaultErrorCode,
+) -> fmt::Result {
+    writeln!(out, "Faulting address: {:#x}", address.as_u64())?;
+
+    let (access, mode, reason) = page_fault_cause(error_code);
+    writeln!(
+        out,
+        "Cause: {} in {} mode caused by {}",
+        access, mode, reason
+    )?;
+
+    let no_execute = Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE);
+    let smep = Cr4::read().contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION);
+
+    if let Some(description) = fetch_violation(error_code, no_execute, smep) {
+        writeln!(out, "  - {}", description)?;
+    }
+
+    for description in page_fault_details(error_code) {
+        writeln!(out, "  - {}", description)?;
+    }
+
+    walk_page_tables(out, address)
+}
+
+/// Access, mode and reason of a page fault.
+fn page_fault_cause(error_code: PageFaultErrorCode) -> (&'static str, &'static str, &'static str) {
+    let access = if error_code.contains(PageFaultErrorCode::CAUSED_BY_INSTRUCTION_FETCH) {
+        "Instruction fetch"
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "Write"
+    } else {
+        "Read"
+    };
+
+    let mode = if error_code.contains(PageFaultErrorCode::CAUSED_BY_USER_MODE) {
+        "user"
+    } else {
+        "kernel"
+    };
+
+    let reason = if error_code.contains(PageFaultErrorCode::CAUSED_BY_PROTECTION_VIOLATION) {
+        "a protection violation"
+    } else {
+        "a non-present page"
+    };
+
+    (access, mode, reason)
+}
+
+/// What forbade an instruction fetch from a present page, given whether EFER.NXE and
+/// CR4.SMEP are set. `None` for any other fault.
+fn fetch_violation(
+    error_code: PageFaultErrorCode,
+    no_execute: bool,
+    smep: bool,
+) -> Option<&'static str> {
+    if !error_code.contains(
+        PageFaultErrorCode::CAUSED_BY_INSTRUCTION_FETCH
+            | PageFaultErrorCode::CAUSED_BY_PROTECTION_VIOLATION,
+    ) {
+        return None;
+    }
+
+    // SMEP only restricts fetches in kernel mode.
+    let smep = smep && !error_code.contains(PageFaultErrorCode::CAUSED_BY_USER_MODE);
+
+    Some(match (no_execute, smep) {
+        (true, true) => "No-execute page or SMEP violation",
+        (true, false) => "No-execute page",
+        (false, true) => "SMEP violation",
+        (false, false) => "Instruction fetch from a protected page",
+    })
+}
+
+/// Descriptions of the less common causes set in a page fault error code.
+fn page_fault_details(error_code: PageFaultErrorCode) -> impl Iterator<Item = &'static str> {
+    let details = [
+        (
+            PageFaultErrorCode::CAUSED_BY_MALFORMED_TABLE,
+            "Reserved bit set in a paging-structure entry",
+        ),
+        (
+            PageFaultErrorCode::CAUSED_BY_PROTECTION_KEY,
+            "Access denied by protection key",
+        ),
+        (
+            PageFaultErrorCode::CAUSED_BY_SHADOW_STACK,
+            "Shadow-stack access",
+        ),
+        (
+            PageFaultErrorCode::CAUSED_BY_HLAT_PAGING,
+            "No translation during HLAT paging",
+        ),
+        (
+            PageFaultErrorCode::CAUSED_BY_SGX,
+            "SGX access-control violation",
+        ),
+    ];
+
+    details
+        .into_iter()
+        .filter(move |(flag, _)| error_code.contains(*flag))
+        .map(|(_, description)| description)
+}
+
+/// Prints the entries of the active page tables used to translate `address`.
+fn walk_page_tables(out: &mut dyn Write, address: VirtualAddress) -> fmt::Result {
+    let (frame, _) = Cr3::read();
+    let offset = pmm::hhdm_offset();
+    let names = ["P1", "P2", "P3", "P4"];
+    let mut result = Ok(());
+
+    writeln!(
+        out,
+        "Page table walk (CR3 = {:#x}):",
+        frame.start_address().as_u64()
+    )?;
+
+    // SAFETY: The bootloader maps all page tables in the higher half direct map. The tables
+    // are only read, so a mapper holding them concurrently is fine.
+    unsafe {
+        let table: &PageTable =
+            &*VirtualAddress::new(frame.start_address().as_u64() + offset).as_ptr();
+
+        mapper::walk(table, offset, address, |level, index, entry| {
+            let name = names[level as usize - 1];
+            let flags = entry.flags();
+
+            result = result.and_then(|_| {
+                if flags.contains(PageTableFlags::PRESENT) {
+                    writeln!(
+                        out,
+                        "  {}[{}]: {:#x} {:?}",
+                        name,
+                        index,
+                        entry.address().as_u64(),
+                        flags
+                    )
+                } else {
+                    writeln!(out, "  {}[{}]: not present {:?}", name, index, entry)
+                }
+            });
+        });
+    }
+
+    result
+}
+
+pub extern "x86-interrupt" fn x87_fpu_floating_point(stack_frame: InterruptStackFrame) {
+    fatal(0x10, ErrorCode::None, &stack_frame)
+}
+
+// The error code of #AC is always 0.
+pub extern "x86-interrupt" fn alignment_check(stack_frame: InterruptStackFrame, _error_code: u64) {
+    fatal(0x11, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn machine_check(stack_frame: InterruptStackFrame) -> ! {
+    fatal(0x12, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn simd_floating_point(stack_frame: InterruptStackFrame) {
+    fatal(0x13, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn virtualization(stack_frame: InterruptStackFrame) {
+    fatal(0x14, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn control_protection(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    fatal(0x15, ErrorCode::ControlProtection(error_code), &stack_frame)
+}
+
+pub extern "x86-interrupt" fn hypervisor_injection(stack_frame: InterruptStackFrame) {
+    fatal(0x1C, ErrorCode::None, &stack_frame)
+}
+
+pub extern "x86-interrupt" fn vmm_communication(stack_frame: InterruptStackFrame, error_code: u64) {
+    fatal(0x1D, ErrorCode::Raw(error_code), &stack_frame)
+}
+
+pub extern "x86-interrupt" fn security(stack_frame: InterruptStackFrame, error_code: u64) {
+    fatal(0x1E, ErrorCode::Raw(error_code), &stack_frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cause_names_access_mode_and_reason() {
+        assert_eq!(
+            page_fault_cause(PageFaultErrorCode::empty()),
+            ("Read", "kernel", "a non-present page")
+        );
+        assert_eq!(
+            page_fault_cause(
+                PageFaultErrorCode::CAUSED_BY_WRITE
+                    | PageFaultErrorCode::CAUSED_BY_USER_MODE
+                    | PageFaultErrorCode::CAUSED_BY_PROTECTION_VIOLATION
+            ),
+            ("Write", "user", "a protection violation")
+        );
+        // A fetch is never a write, even if the bit is set.
+        assert_eq!(
+            page_fault_cause(
+                PageFaultErrorCode::CAUSED_BY_INSTRUCTION_FETCH
+                    | PageFaultErrorCode::CAUSED_BY_WRITE
+            ),
+            ("Instruction fetch", "kernel", "a non-present page")
+        );
+    }
+
+    #[test]
+    fn fetch_violation_depends_on_nxe_and_smep() {
+        let fetch = PageFaultErrorCode::CAUSED_BY_INSTRUCTION_FETCH
+            | PageFaultErrorCode::CAUSED_BY_PROTECTION_VIOLATION;
+
+        assert_eq!(
+            fetch_violation(PageFaultErrorCode::CAUSED_BY_INSTRUCTION_FETCH, true, true),
+            None
+        );
+        assert_eq!(
+            fetch_violation(
+                PageFaultErrorCode::CAUSED_BY_PROTECTION_VIOLATION,
+                true,
+                true
+            ),
+            None
+        );
+        assert_eq!(
+            fetch_violation(fetch, true, true),
+            Some("No-execute page or SMEP violation")
+        );
+        assert_eq!(fetch_violation(fetch, true, false), Some("No-execute page"));
+        assert_eq!(fetch_violation(fetch, false, true), Some("SMEP violation"));
+        assert_eq!(
+            fetch_violation(fetch, false, false),
+            Some("Instruction fetch from a protected page")
+        );
+        // SMEP does not apply to user mode fetches.
+        assert_eq!(
+            fetch_violation(fetch | PageFaultErrorCode::CAUSED_BY_USER_MODE, false, true),
+            Some("Instruction fetch from a protected page")
+        );
+    }
+
+    #[test]
+    fn details_list_every_set_flag() {
+        assert_eq!(
+            page_fault_details(PageFaultErrorCode::CAUSED_BY_WRITE).count(),
+            0
+        );
+
+        let details: Vec<_> = page_fault_details(
+            PageFaultErrorCode::CAUSED_BY_MALFORMED_TABLE | PageFaultErrorCode::CAUSED_BY_SGX,
+        )
+        .collect();
+
+        assert_eq!(
+            details,
+            [
+                "Reserved bit set in a paging-structure entry",
+                "SGX access-control violation"
+            ]
+        );
+    }
 }
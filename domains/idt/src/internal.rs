@@ -18,18 +18,51 @@
 use core::arch::asm;
 use core::marker::PhantomData;
 
+use bit_field::BitField;
+use security::core::x86_64::privileges::PLevel;
+use security::core::x86_64::segmentation::{CodeSegment, Segment32};
 use x86_64::structures::memory::VirtualAddress;
 use x86_64::structures::table::DescriptorTablePointer;
 use x86_64::types::paging::PageFaultErrorCode;
 
-use crate::export::InterruptDescriptorTable;
+use crate::export::{InterruptDescriptorTable, USER_VECTOR_COUNT};
 
-pub type InterruptHandlerFunction = extern "x86-interrupt" fn(&mut InterruptStackFrame);
+pub type InterruptHandlerFunction = extern "x86-interrupt" fn(InterruptStackFrame);
 pub type InterruptHandlerFunctionWithErrorCode =
     extern "x86-interrupt" fn(InterruptStackFrame, error_code: u64);
 pub type PageFaultInterruptHandlerFunction =
     extern "x86-interrupt" fn(InterruptStackFrame, error_code: PageFaultErrorCode);
+pub type DivergingInterruptHandlerFunction = extern "x86-interrupt" fn(InterruptStackFrame) -> !;
+pub type DivergingInterruptHandlerFunctionWithErrorCode =
+    extern "x86-interrupt" fn(InterruptStackFrame, error_code: u64) -> !;
 
+/// A function that can be installed as handler of an [`InterruptDescriptorTableEntry`].
+pub trait HandlerFunction {
+    fn address(self) -> VirtualAddress;
+}
+
+macro_rules! impl_handler_function {
+    ($($t:ty),*) => {
+        $(
+            impl HandlerFunction for $t {
+                #[inline]
+                fn address(self) -> VirtualAddress {
+                    VirtualAddress::new(self as usize as u64)
+                }
+            }
+        )*
+    };
+}
+
+impl_handler_function!(
+    InterruptHandlerFunction,
+    InterruptHandlerFunctionWithErrorCode,
+    PageFaultInterruptHandlerFunction,
+    DivergingInterruptHandlerFunction,
+    DivergingInterruptHandlerFunctionWithErrorCode
+);
+
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct InterruptDescriptorTableEntry<T> {
     low_ptr: u16,
@@ -41,14 +74,47 @@ pub struct InterruptDescriptorTableEntry<T> {
     phantom: PhantomData<T>,
 }
 
+/// Bits 32 - 47 of a gate descriptor.
+///
+/// Bits 0 - 2 hold the IST index plus one (0 disables the stack switch), bits 8 - 11 the gate
+/// type, bits 13 - 14 the DPL and bit 15 the present flag.
+#[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct InterruptDescriptorTableEntryOptions(u16);
+pub struct InterruptDescriptorTableEntryOptions(u16);
 
 #[repr(transparent)]
 pub struct InterruptStackFrame {
     value: InterruptStackFrameValue,
 }
 
+impl InterruptStackFrame {
+    /// Address of the instruction the handler returns to.
+    #[inline]
+    pub fn instruction_pointer(&self) -> VirtualAddress {
+        self.value.instruction_pointer
+    }
+
+    #[inline]
+    pub fn code_segment(&self) -> u64 {
+        self.value.code_segment
+    }
+
+    #[inline]
+    pub fn cpu_flags(&self) -> u64 {
+        self.value.cpu_flags
+    }
+
+    #[inline]
+    pub fn stack_pointer(&self) -> VirtualAddress {
+        self.value.stack_pointer
+    }
+
+    #[inline]
+    pub fn stack_segment(&self) -> u64 {
+        self.value.stack_segment
+    }
+}
+
 impl core::fmt::Debug for InterruptStackFrame {
     #[inline]
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
@@ -97,17 +163,25 @@ impl InterruptDescriptorTable {
             invalid_opcode: InterruptDescriptorTableEntry::null(),
             device_not_available: InterruptDescriptorTableEntry::null(),
             double_fault: InterruptDescriptorTableEntry::null(),
+            coprocessor_segment_overrun: InterruptDescriptorTableEntry::null(),
             invalid_tss: InterruptDescriptorTableEntry::null(),
             segment_not_present: InterruptDescriptorTableEntry::null(),
             stack_fault: InterruptDescriptorTableEntry::null(),
             general_protection: InterruptDescriptorTableEntry::null(),
             page_fault: InterruptDescriptorTableEntry::null(),
+            reserved_1: InterruptDescriptorTableEntry::null(),
             x87_fpu_floating_point: InterruptDescriptorTableEntry::null(),
             alignment_check: InterruptDescriptorTableEntry::null(),
             machine_check: InterruptDescriptorTableEntry::null(),
             simd_floating_point: InterruptDescriptorTableEntry::null(),
             virtualization: InterruptDescriptorTableEntry::null(),
             control_protection: InterruptDescriptorTableEntry::null(),
+            reserved_2: [InterruptDescriptorTableEntry::null(); 6],
+            hypervisor_injection: InterruptDescriptorTableEntry::null(),
+            vmm_communication: InterruptDescriptorTableEntry::null(),
+            security: InterruptDescriptorTableEntry::null(),
+            reserved_3: InterruptDescriptorTableEntry::null(),
+            interrupts: [InterruptDescriptorTableEntry::null(); USER_VECTOR_COUNT],
         }
     }
 
@@ -120,7 +194,7 @@ impl InterruptDescriptorTable {
         use core::mem::size_of;
 
         DescriptorTablePointer {
-            base: VirtualAddress::new(self as *const _ as u64),
+            base: VirtualAddress::from_ptr(self),
             limit: (size_of::<Self>() - 1) as u16,
         }
     }
@@ -139,11 +213,11 @@ impl InterruptDescriptorTable {
 
 impl<T> InterruptDescriptorTableEntry<T> {
     #[inline]
-    pub fn null() -> Self {
+    pub const fn null() -> Self {
         Self {
             low_ptr: 0,
             gdt_selector: 0,
-            options: InterruptDescriptorTableEntryOptions::default(),
+            options: InterruptDescriptorTableEntryOptions::minimal(),
             middle_ptr: 0,
             high_ptr: 0,
             reserved: 0,
@@ -152,8 +226,111 @@ impl<T> InterruptDescriptorTableEntry<T> {
     }
 }
 
+impl<F: HandlerFunction> InterruptDescriptorTableEntry<F> {
+    /// Installs `handler` as present interrupt gate using the current code segment.
+    ///
+    /// The returned options can be used to switch stacks, allow software interrupts from
+    /// user mode or to turn the gate into a trap gate.
+    #[inline]
+    pub fn set_handler_fn(&mut self, handler: F) -> &mut InterruptDescriptorTableEntryOptions {
+        // SAFETY: `handler` is a function with the ABI the entry expects.
+        unsafe { self.set_handler_address(handler.address()) }
+    }
+}
+
+impl<T> InterruptDescriptorTableEntry<T> {
+    /// # Safety
+    /// `address` must point to a handler which matches the calling convention of the vector.
+    #[inline]
+    pub unsafe fn set_handler_address(
+        &mut self,
+        address: VirtualAddress,
+    ) -> &mut InterruptDescriptorTableEntryOptions {
+        let address = address.as_u64();
+
+        self.low_ptr = address as u16;
+        self.middle_ptr = (address >> 16) as u16;
+        self.high_ptr = (address >> 32) as u32;
+        self.gdt_selector = CodeSegment::get_reg().0;
+        self.options.set_present(true);
+
+        &mut self.options
+    }
+
+    /// Address of the installed handler, `None` if the entry is not present.
+    #[inline]
+    pub fn handler_address(&self) -> Option<VirtualAddress> {
+        if !self.options.is_present() {
+            return None;
+        }
+
+        let address =
+            self.low_ptr as u64 | (self.middle_ptr as u64) << 16 | (self.high_ptr as u64) << 32;
+
+        Some(VirtualAddress::new(address))
+    }
+
+    #[inline]
+    pub fn options(&self) -> InterruptDescriptorTableEntryOptions {
+        self.options
+    }
+}
+
+impl InterruptDescriptorTableEntryOptions {
+    #[inline]
+    pub fn is_present(&self) -> bool {
+        self.0.get_bit(15)
+    }
+
+    #[inline]
+    pub fn set_present(&mut self, present: bool) -> &mut Self {
+        self.0.set_bit(15, present);
+        self
+    }
+
+    /// Interrupt gates (the default) clear IF on entry, trap gates keep it.
+    #[inline]
+    pub fn disable_interrupts(&mut self, disable: bool) -> &mut Self {
+        self.0.set_bit(8, !disable);
+        self
+    }
+
+    /// Sets the lowest privilege level that may trigger the vector with `int n`.
+    #[inline]
+    pub fn set_privilege_level(&mut self, privilege_level: PLevel) -> &mut Self {
+        self.0.set_bits(13..15, privilege_level as u16);
+        self
+    }
+
+    /// Switches to the given interrupt stack table entry of the TSS on entry.
+    ///
+    /// # Safety
+    /// The index must be valid (0 - 6) and the TSS entry must point to a usable stack.
+    #[inline]
+    pub unsafe fn set_stack_index(&mut self, index: u16) -> &mut Self {
+        assert!(index < 7, "The interrupt stack table only has 7 entries.");
+
+        self.0.set_bits(0..3, index + 1);
+        self
+    }
+
+    /// The IST index used by the gate, if any.
+    #[inline]
+    pub fn stack_index(&self) -> Option<u16> {
+        self.0.get_bits(0..3).checked_sub(1)
+    }
+}
+
+impl InterruptDescriptorTableEntryOptions {
+    /// A not present 64-bit interrupt gate.
+    #[inline]
+    const fn minimal() -> Self {
+        Self(0b1110_0000_0000)
+    }
+}
+
 impl Default for InterruptDescriptorTableEntryOptions {
     fn default() -> Self {
-        Self(0b1110_0000_0000)
+        Self::minimal()
     }
 }
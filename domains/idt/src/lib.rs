@@ -14,23 +14,69 @@
  * You should have received a copy of the GNU General Public License
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(abi_x86_interrupt)]
 
+mod dispatch;
 pub mod export;
 mod handler;
 mod internal;
 
+pub use dispatch::{
+    allocate, is_registered, register, set_end_of_interrupt_handler, unregister, DynamicHandler,
+    RegistrationError, DYNAMIC_VECTORS,
+};
+pub use handler::{
+    clear_page_fault_handler, describe_page_fault, set_device_not_available_handler,
+    set_non_maskable_interrupt_handler, set_page_fault_handler,
+};
+pub use internal::InterruptStackFrame;
+
 use export::InterruptDescriptorTable;
 use lazy_static::lazy_static;
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
+
+        idt.divide_error.set_handler_fn(handler::divide_error);
+        idt.debug.set_handler_fn(handler::debug);
+        idt.non_maskable_interrupt.set_handler_fn(handler::non_maskable_interrupt);
+        idt.breakpoint.set_handler_fn(handler::breakpoint);
+        idt.overflow.set_handler_fn(handler::overflow);
+        idt.bound_range_exceeded.set_handler_fn(handler::bound_range_exceeded);
+        idt.invalid_opcode.set_handler_fn(handler::invalid_opcode);
+        idt.device_not_available.set_handler_fn(handler::device_not_available);
+        idt.invalid_tss.set_handler_fn(handler::invalid_tss);
+        idt.segment_not_present.set_handler_fn(handler::segment_not_present);
+        idt.stack_fault.set_handler_fn(handler::stack_fault);
+        idt.general_protection.set_handler_fn(handler::general_protection);
+        idt.page_fault.set_handler_fn(handler::page_fault);
+        idt.x87_fpu_floating_point.set_handler_fn(handler::x87_fpu_floating_point);
+        idt.alignment_check.set_handler_fn(handler::alignment_check);
+        idt.machine_check.set_handler_fn(handler::machine_check);
+        idt.simd_floating_point.set_handler_fn(handler::simd_floating_point);
+        idt.virtualization.set_handler_fn(handler::virtualization);
+        idt.control_protection.set_handler_fn(handler::control_protection);
+        idt.hypervisor_injection.set_handler_fn(handler::hypervisor_injection);
+        idt.vmm_communication.set_handler_fn(handler::vmm_communication);
+        idt.security.set_handler_fn(handler::security);
+
+        // SAFETY: The GDT domain points this IST entry to a dedicated stack, so a double
+        // fault caused by a stack overflow does not escalate into a triple fault.
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(handler::double_fault)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+
+        dispatch::install_stubs(&mut idt);
+
         idt
     };
 }
 
+/// Loads the IDT on the executing core. All cores share the same table.
 pub fn init() {
     IDT.init()
 }
@@ -0,0 +1,364 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use scheduler::{ThreadState, Transfer};
+use security::capability::{KernelObject, ObjectType};
+use spin::Mutex;
+use x86_64::op::interrupts;
+
+use crate::message::Message;
+use crate::notification;
+use crate::timeout::{self, Timeout, Timer};
+use crate::waiter::{Outcome, Waiter};
+use crate::{IpcError, Reply};
+
+/// Rendezvous point of synchronous IPC. Senders and receivers wait in FIFO order until a
+/// partner arrives.
+pub struct Endpoint {
+    queues: Mutex<Queues>,
+}
+
+/// At most one of the queues holds live waiters. A waiter whose wait ended without a partner
+/// takes itself out, partners skip it until then.
+struct Queues {
+    senders: VecDeque<Arc<Waiter>>,
+    receivers: VecDeque<Arc<Waiter>>,
+}
+
+/// What ended a wait of a thread bound to a notification, see [`Endpoint::wait`].
+pub enum Event {
+    /// A sender came, with the handle to reply with if it is a caller.
+    Message(Message, Option<Reply>),
+    /// The bound notification was signaled, carrying its signal word.
+    Signal(u64),
+}
+
+impl Event {
+    /// The message of an event that can not be a signal, only bound waits are signaled.
+    fn into_message(self) -> (Message, Option<Reply>) {
+        match self {
+            Self::Message(message, reply) => (message, reply),
+            Self::Signal(_) => unreachable!("Signal delivered to an unbound wait"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Sender,
+    Receiver,
+}
+
+/// What a thread found at the endpoint.
+enum Meeting {
+    /// A claimed partner.
+    Partner(Arc<Waiter>),
+    /// No partner, the thread is queued.
+    Queued(Arc<Waiter>),
+    /// No partner and the timeout is zero.
+    Nobody,
+}
+
+impl Endpoint {
+    pub const fn new() -> Self {
+        Self {
+            queues: Mutex::new(Queues {
+                senders: VecDeque::new(),
+                receivers: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Sends `message` to the next receiver and switches to it right away.
+    pub fn send(&self, message: Message, timeout: Timeout) -> Result<(), IpcError> {
+        self.transfer(message, false, timeout).map(|_| ())
+    }
+
+    /// Sends `message` and waits for the reply of the receiver, which runs right away on
+    /// the rest of the current timeslice.
+    ///
+    /// `timeout` only limits the wait for a receiver, the reply is awaited without one.
+    pub fn call(&self, message: Message, timeout: Timeout) -> Result<Message, IpcError> {
+        self.transfer(message, true, timeout)
+    }
+
+    /// Waits for the next sender. Returns its message and, if it is a caller, the handle
+    /// to reply with.
+    pub fn receive(&self, timeout: Timeout) -> Result<(Message, Option<Reply>), IpcError> {
+        match self.meet(Side::Receiver, timeout, || {
+            Waiter::new(Message::default(), false)
+        }) {
+            Meeting::Partner(sender) => Ok(accept(sender)),
+            Meeting::Queued(waiter) => self
+                .wait_queued(Side::Receiver, &waiter, timeout)
+                .map(Event::into_message),
+            Meeting::Nobody => Err(IpcError::Timeout),
+        }
+    }
+
+    /// Waits for the next sender or a signal of the notification bound to the current
+    /// thread, whichever comes first. Without a bound notification this is
+    /// [`Endpoint::receive`].
+    pub fn wait(&self, timeout: Timeout) -> Result<Event, IpcError> {
+        let Some(notification) = notification::bound(scheduler::current().id()) else {
+            return self
+                .receive(timeout)
+                .map(|(message, reply)| Event::Message(message, reply));
+        };
+
+        if let Some(word) = notification.poll() {
+            return Ok(Event::Signal(word));
+        }
+
+        match self.meet(Side::Receiver, timeout, || {
+            Waiter::new(Message::default(), false)
+        }) {
+            Meeting::Partner(sender) => {
+                let (message, reply) = accept(sender);
+
+                Ok(Event::Message(message, reply))
+            }
+            Meeting::Queued(waiter) => {
+                notification.attach(&waiter);
+                let event = self.wait_queued(Side::Receiver, &waiter, timeout);
+                notification.detach(&waiter);
+
+                event
+            }
+            Meeting::Nobody => Err(IpcError::Timeout),
+        }
+    }
+
+    /// Replies through `reply` and waits for the next sender, the fast path of servers.
+    ///
+    /// The current thread queues as receiver before the reply is delivered, so the caller
+    /// runs right away and can call again without finding the server busy.
+    pub fn reply_wait(
+        &self,
+        reply: Reply,
+        message: Message,
+        timeout: Timeout,
+    ) -> Result<(Message, Option<Reply>), IpcError> {
+        let meeting = self.meet(Side::Receiver, timeout, || {
+            let waiter = Waiter::new(Message::default(), false);
+
+            // Blocked before a sender can find the waiter, so its wake is not lost.
+            waiter.thread().set_state(ThreadState::Blocked);
+
+            waiter
+        });
+
+        let waiter = match meeting {
+            Meeting::Partner(sender) => {
+                // A failed reply only means the caller gave up, the next message is there.
+                let _ = reply.send(message);
+
+                return Ok(accept(sender));
+            }
+            Meeting::Queued(waiter) => waiter,
+            Meeting::Nobody => {
+                reply.send(message)?;
+
+                return Err(IpcError::Timeout);
+            }
+        };
+
+        let timer = match timeout::arm(&waiter, timeout) {
+            Ok(timer) => timer,
+            Err(error) if waiter.expire() => {
+                waiter.thread().set_state(ThreadState::Running);
+                self.withdraw(Side::Receiver, &waiter);
+                reply.send(message)?;
+
+                return Err(error);
+            }
+            Err(_) => Timer::none(),
+        };
+
+        // A sender on the fast path resumes this thread with its message in registers.
+        let resumed = reply.donate(message);
+
+        let event = wait(&waiter, timer, resumed);
+
+        if !matches!(event, Ok(Event::Message(..))) {
+            self.withdraw(Side::Receiver, &waiter);
+        }
+
+        event.map(Event::into_message)
+    }
+
+    /// Delivers `message` to a waiting receiver or queues the current thread as sender.
+    fn transfer(
+        &self,
+        message: Message,
+        call: bool,
+        timeout: Timeout,
+    ) -> Result<Message, IpcError> {
+        let waiter = Waiter::new(message, call);
+
+        let receiver = match self.meet(Side::Sender, timeout, || waiter.clone()) {
+            Meeting::Partner(receiver) => receiver,
+            Meeting::Queued(waiter) => {
+                return self
+                    .wait_queued(Side::Sender, &waiter, timeout)
+                    .map(|event| event.into_message().0)
+            }
+            Meeting::Nobody => return Err(IpcError::Timeout),
+        };
+
+        let reply = call.then(|| {
+            // Blocked before the receiver can reply, so the wake is not lost.
+            waiter.thread().set_state(ThreadState::Blocked);
+            waiter.await_reply();
+
+            Reply::new(waiter.clone())
+        });
+
+        // Direct process switch, the receiver continues on the current timeslice with the
+        // message in registers. A replying `reply_wait` switches back the same way.
+        let resumed = receiver.hand_over(message, reply);
+
+        if call {
+            wait(&waiter, Timer::none(), resumed).map(|event| event.into_message().0)
+        } else {
+            Ok(message)
+        }
+    }
+
+    /// Claims the first live partner of a thread on `side`. Without one the thread queues
+    /// the waiter created by `waiter`, unless `timeout` is zero.
+    fn meet(&self, side: Side, timeout: Timeout, waiter: impl FnOnce() -> Arc<Waiter>) -> Meeting {
+        // A thread preempted while holding the lock would stall all partners.
+        interrupts::without_interrupts(|| {
+            let mut queues = self.queues.lock();
+            let queues = &mut *queues;
+
+            let (partners, own) = match side {
+                Side::Sender => (&mut queues.receivers, &mut queues.senders),
+                Side::Receiver => (&mut queues.senders, &mut queues.receivers),
+            };
+
+            while let Some(partner) = partners.pop_front() {
+                if partner.claim() {
+                    return Meeting::Partner(partner);
+                }
+            }
+
+            if timeout == Timeout::Zero {
+                return Meeting::Nobody;
+            }
+
+            let waiter = waiter();
+            own.push_back(waiter.clone());
+
+            Meeting::Queued(waiter)
+        })
+    }
+
+    /// Waits for a partner to take the queued `waiter`, taking it out of the queue if none
+    /// came.
+    fn wait_queued(
+        &self,
+        side: Side,
+        waiter: &Arc<Waiter>,
+        timeout: Timeout,
+    ) -> Result<Event, IpcError> {
+        let event = wait_for(waiter, timeout);
+
+        if !matches!(event, Ok(Event::Message(..))) {
+            self.withdraw(side, waiter);
+        }
+
+        event
+    }
+
+    /// Removes `waiter` from the queue of `side`, so a timed out or signaled waiter does not
+    /// keep its thread alive until a partner comes along.
+    fn withdraw(&self, side: Side, waiter: &Arc<Waiter>) {
+        interrupts::without_interrupts(|| {
+            let mut queues = self.queues.lock();
+            let own = match side {
+                Side::Sender => &mut queues.senders,
+                Side::Receiver => &mut queues.receivers,
+            };
+
+            own.retain(|queued| !Arc::ptr_eq(queued, waiter));
+        })
+    }
+}
+
+impl KernelObject for Endpoint {
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Endpoint
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Takes the message of a claimed sender. A plain sender is done and woken, a caller
+/// waits for its reply.
+fn accept(sender: Arc<Waiter>) -> (Message, Option<Reply>) {
+    let message = sender.message();
+
+    if sender.is_call() {
+        sender.await_reply();
+
+        (message, Some(Reply::new(sender)))
+    } else {
+        sender.release();
+        scheduler::wake(sender.thread());
+
+        (message, None)
+    }
+}
+
+/// Arms `timeout` for the queued `waiter` and blocks until a partner or the timeout came.
+pub(crate) fn wait_for(waiter: &Arc<Waiter>, timeout: Timeout) -> Result<Event, IpcError> {
+    let timer = match timeout::arm(waiter, timeout) {
+        Ok(timer) => timer,
+        // Still queued, unless a partner claimed it in the meantime.
+        Err(error) if waiter.expire() => return Err(error),
+        Err(_) => Timer::none(),
+    };
+
+    wait(waiter, timer, None)
+}
+
+/// Blocks until the wait of `waiter` ended, `timer` stays armed until then. `resumed` is
+/// the transfer the current thread was resumed with, if it blocked for the wait already.
+fn wait(waiter: &Waiter, timer: Timer, resumed: Option<Transfer>) -> Result<Event, IpcError> {
+    let outcome = waiter.wait(resumed);
+    drop(timer);
+
+    match outcome {
+        Outcome::Done => {
+            let (message, reply) = waiter.take();
+
+            Ok(Event::Message(message, reply))
+        }
+        Outcome::Transferred(message) => Ok(Event::Message(message, waiter.take_reply())),
+        Outcome::Signaled => Ok(Event::Signal(waiter.take().0.label())),
+        Outcome::TimedOut => Err(IpcError::Timeout),
+        Outcome::Canceled => Err(IpcError::Canceled),
+    }
+}
@@ -0,0 +1,165 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use apic::RoutingError;
+use idt::{InterruptStackFrame, RegistrationError};
+use security::capability::{KernelObject, ObjectType};
+use spin::Mutex;
+use x86_64::op::interrupts;
+
+use crate::notification::Notification;
+
+/// Vectors of all IRQ handlers, looked up by the interrupt dispatcher.
+static HANDLERS: Mutex<Vec<(u8, Weak<IrqHandler>)>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqError {
+    /// No vector could be claimed for the IRQ.
+    Vector(RegistrationError),
+    /// The IRQ could not be routed through the I/O APIC.
+    Routing(RoutingError),
+}
+
+/// Hands an ISA IRQ to a driver by signalling a notification.
+///
+/// The IRQ is masked from the interrupt until the driver calls [`IrqHandler::ack`], so a
+/// level triggered device can not storm the core before its driver ran.
+pub struct IrqHandler {
+    irq: u8,
+    gsi: u32,
+    vector: u8,
+    target: Mutex<Option<(Arc<Notification>, u64)>>,
+}
+
+impl IrqHandler {
+    /// Routes `irq` to the current core, masked until a notification is set.
+    pub fn new(irq: u8) -> Result<Arc<Self>, IrqError> {
+        let vector = idt::allocate(dispatch).map_err(IrqError::Vector)?;
+        let destination = apic::local_apic().id();
+
+        let gsi = match apic::route_irq(irq, vector, destination) {
+            Ok(gsi) => gsi,
+            Err(error) => {
+                let _ = idt::unregister(vector);
+                return Err(IrqError::Routing(error));
+            }
+        };
+
+        // Interrupts raised before the handler is listed are only acknowledged.
+        let _ = apic::mask_gsi(gsi);
+
+        let handler = Arc::new(Self {
+            irq,
+            gsi,
+            vector,
+            target: Mutex::new(None),
+        });
+
+        interrupts::without_interrupts(|| {
+            HANDLERS.lock().push((vector, Arc::downgrade(&handler)));
+        });
+
+        Ok(handler)
+    }
+
+    #[inline]
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    #[inline]
+    pub fn gsi(&self) -> u32 {
+        self.gsi
+    }
+
+    #[inline]
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// Signals `notification` with `badge` on every interrupt and unmasks the IRQ.
+    ///
+    /// # Panics
+    /// If `badge` is 0, the signal would be lost.
+    pub fn set_notification(
+        &self,
+        notification: Arc<Notification>,
+        badge: u64,
+    ) -> Result<(), IrqError> {
+        assert_ne!(badge, 0, "IRQ handlers signal a non-zero badge");
+
+        interrupts::without_interrupts(|| *self.target.lock() = Some((notification, badge)));
+
+        apic::unmask_gsi(self.gsi).map_err(IrqError::Routing)
+    }
+
+    /// Masks the IRQ and stops signalling the notification.
+    pub fn clear_notification(&self) {
+        let _ = apic::mask_gsi(self.gsi);
+
+        let target = interrupts::without_interrupts(|| self.target.lock().take());
+        drop(target);
+    }
+
+    /// Unmasks the IRQ once the driver handled the last one.
+    pub fn ack(&self) -> Result<(), IrqError> {
+        apic::unmask_gsi(self.gsi).map_err(IrqError::Routing)
+    }
+}
+
+impl KernelObject for IrqHandler {
+    fn object_type(&self) -> ObjectType {
+        ObjectType::IrqHandler
+    }
+}
+
+impl Drop for IrqHandler {
+    fn drop(&mut self) {
+        let _ = apic::mask_gsi(self.gsi);
+
+        interrupts::without_interrupts(|| {
+            HANDLERS.lock().retain(|(vector, _)| *vector != self.vector);
+        });
+
+        let _ = idt::unregister(self.vector);
+    }
+}
+
+/// Masks the IRQ and signals the notification of the handler owning `vector`.
+fn dispatch(vector: u8, _stack_frame: &InterruptStackFrame) {
+    let handler = HANDLERS
+        .lock()
+        .iter()
+        .find(|(handled, _)| *handled == vector)
+        .and_then(|(_, handler)| handler.upgrade());
+
+    if let Some(handler) = &handler {
+        let _ = apic::mask_gsi(handler.gsi);
+
+        let target = handler.target.lock().clone();
+        if let Some((notification, badge)) = target {
+            notification.signal(badge);
+        }
+    }
+
+    apic::end_of_interrupt();
+
+    // Dropping the last reference takes the list lock again.
+    drop(handler);
+}
@@ -0,0 +1,47 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod endpoint;
+pub mod irq;
+pub mod message;
+pub mod notification;
+mod reply;
+pub mod timeout;
+mod waiter;
+
+pub use endpoint::{Endpoint, Event};
+pub use irq::{IrqError, IrqHandler};
+pub use message::{Message, MESSAGE_REGISTERS};
+pub use notification::Notification;
+pub use reply::Reply;
+pub use timeout::Timeout;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpcError {
+    /// No partner arrived before the timeout expired.
+    Timeout,
+    /// The receiver of a call dropped its [`Reply`] without answering.
+    Canceled,
+    /// All clock timers are in use, the timeout could not be armed.
+    NoTimer,
+    /// The thread or the notification is bound already.
+    Bound,
+}
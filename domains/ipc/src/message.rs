@@ -0,0 +1,102 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use scheduler::Transfer;
+
+/// Number of untyped words a message carries besides its label.
+///
+/// Together with the label they fit into the general purpose registers a system call can
+/// pass. On the `call` and `reply_wait` fast path the message travels in registers through
+/// the switch to the partner, see [`scheduler::donate_transfer`]. It is only copied into
+/// the `Waiter` of the blocked side if the partner can not run right away.
+pub const MESSAGE_REGISTERS: usize = 6;
+
+// Label and length travel next to the words.
+const _: () = assert!(MESSAGE_REGISTERS + 2 == scheduler::TRANSFER_REGISTERS);
+
+/// The label and the message registers of one IPC transfer. The kernel does not interpret
+/// either.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Message {
+    label: u64,
+    length: u8,
+    registers: [u64; MESSAGE_REGISTERS],
+}
+
+impl Message {
+    /// # Panics
+    /// If `words` holds more than [`MESSAGE_REGISTERS`] words.
+    pub fn new(label: u64, words: &[u64]) -> Self {
+        assert!(
+            words.len() <= MESSAGE_REGISTERS,
+            "A message carries at most {} words",
+            MESSAGE_REGISTERS
+        );
+
+        let mut registers = [0; MESSAGE_REGISTERS];
+        registers[..words.len()].copy_from_slice(words);
+
+        Self {
+            label,
+            length: words.len() as u8,
+            registers,
+        }
+    }
+
+    /// A message without words.
+    #[inline]
+    pub const fn empty(label: u64) -> Self {
+        Self {
+            label,
+            length: 0,
+            registers: [0; MESSAGE_REGISTERS],
+        }
+    }
+
+    #[inline]
+    pub fn label(&self) -> u64 {
+        self.label
+    }
+
+    /// The message registers in use.
+    #[inline]
+    pub fn words(&self) -> &[u64] {
+        &self.registers[..self.length as usize]
+    }
+
+    /// Lays the message out for the registers of a switch: label, length, words.
+    pub(crate) fn into_transfer(self) -> Transfer {
+        let mut transfer = [0; scheduler::TRANSFER_REGISTERS];
+        transfer[0] = self.label;
+        transfer[1] = self.length as u64;
+        transfer[2..].copy_from_slice(&self.registers);
+
+        transfer
+    }
+
+    /// Reads a message laid out by [`Message::into_transfer`].
+    pub(crate) fn from_transfer(transfer: Transfer) -> Self {
+        let mut registers = [0; MESSAGE_REGISTERS];
+        registers.copy_from_slice(&transfer[2..]);
+
+        Self {
+            label: transfer[0],
+            length: (transfer[1] as usize).min(MESSAGE_REGISTERS) as u8,
+            registers,
+        }
+    }
+}
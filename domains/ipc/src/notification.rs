@@ -0,0 +1,244 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use scheduler::{Thread, ThreadId};
+use security::capability::{KernelObject, ObjectType};
+use spin::Mutex;
+use x86_64::op::interrupts;
+
+use crate::endpoint::{self, Event};
+use crate::message::Message;
+use crate::timeout::Timeout;
+use crate::waiter::Waiter;
+use crate::IpcError;
+
+/// Notifications bound to threads, looked up when a thread waits on an endpoint.
+static BINDINGS: Mutex<BTreeMap<ThreadId, Weak<Notification>>> = Mutex::new(BTreeMap::new());
+
+/// A word of signal bits for asynchronous events like interrupts.
+///
+/// Signalling ORs a badge into the word and never blocks, so interrupt handlers can signal.
+/// Waiting takes and clears the whole word.
+pub struct Notification {
+    state: Mutex<State>,
+}
+
+struct State {
+    word: u64,
+    /// Threads blocked in [`Notification::wait`]. One that times out takes itself out,
+    /// signals skip it until then.
+    waiters: VecDeque<Arc<Waiter>>,
+    bound: Option<ThreadId>,
+    /// Waiter of the bound thread while it waits on an endpoint, see [`Endpoint::wait`].
+    ///
+    /// [`Endpoint::wait`]: crate::Endpoint::wait
+    receiver: Option<Arc<Waiter>>,
+}
+
+/// What a thread found at the notification.
+enum Pending {
+    Word(u64),
+    Queued(Arc<Waiter>),
+    Nothing,
+}
+
+impl Notification {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                word: 0,
+                waiters: VecDeque::new(),
+                bound: None,
+                receiver: None,
+            }),
+        }
+    }
+
+    /// ORs `badge` into the signal word, or hands it to a waiting thread right away.
+    /// Signalling with 0 does nothing.
+    pub fn signal(&self, badge: u64) {
+        if badge == 0 {
+            return;
+        }
+
+        let woken = self.with_state(|state| {
+            state.word |= badge;
+
+            let waiter = core::iter::from_fn(|| state.waiters.pop_front())
+                .find(|waiter| waiter.claim())
+                .or_else(|| state.receiver.take().filter(|receiver| receiver.claim()))?;
+
+            // Threads only wait while the word is empty, so they get the badge alone.
+            waiter.signal(core::mem::take(&mut state.word));
+
+            Some(waiter)
+        });
+
+        if let Some(waiter) = woken {
+            scheduler::wake(waiter.thread());
+        }
+    }
+
+    /// Waits until a signal arrives and takes the signal word.
+    pub fn wait(&self, timeout: Timeout) -> Result<u64, IpcError> {
+        let pending = self.with_state(|state| {
+            if state.word != 0 {
+                return Pending::Word(core::mem::take(&mut state.word));
+            }
+
+            if timeout == Timeout::Zero {
+                return Pending::Nothing;
+            }
+
+            let waiter = Waiter::new(Message::default(), false);
+            state.waiters.push_back(waiter.clone());
+
+            Pending::Queued(waiter)
+        });
+
+        match pending {
+            Pending::Word(word) => Ok(word),
+            Pending::Nothing => Err(IpcError::Timeout),
+            Pending::Queued(waiter) => match endpoint::wait_for(&waiter, timeout) {
+                Ok(Event::Signal(word)) => Ok(word),
+                Ok(Event::Message(..)) => unreachable!("Notification waiters are only signaled"),
+                Err(error) => {
+                    // Signals pop the waiters they complete, a timed out one is still queued.
+                    self.with_state(|state| {
+                        state.waiters.retain(|queued| !Arc::ptr_eq(queued, &waiter))
+                    });
+
+                    Err(error)
+                }
+            },
+        }
+    }
+
+    /// Takes the signal word without blocking, `None` if no signal arrived.
+    pub fn poll(&self) -> Option<u64> {
+        self.with_state(|state| Some(core::mem::take(&mut state.word)).filter(|&word| word != 0))
+    }
+
+    /// Binds the notification to `thread`, which then also receives its signals while it
+    /// waits on an endpoint with [`Endpoint::wait`].
+    ///
+    /// A thread is bound to at most one notification and the other way around.
+    ///
+    /// [`Endpoint::wait`]: crate::Endpoint::wait
+    pub fn bind(self: &Arc<Self>, thread: &Thread) -> Result<(), IpcError> {
+        interrupts::without_interrupts(|| {
+            let mut bindings = BINDINGS.lock();
+            let mut state = self.state.lock();
+
+            let taken = bindings
+                .get(&thread.id())
+                .is_some_and(|bound| bound.strong_count() > 0);
+
+            if state.bound.is_some() || taken {
+                return Err(IpcError::Bound);
+            }
+
+            bindings.insert(thread.id(), Arc::downgrade(self));
+            state.bound = Some(thread.id());
+
+            Ok(())
+        })
+    }
+
+    /// Ends the binding to a thread, if any.
+    pub fn unbind(&self) {
+        interrupts::without_interrupts(|| {
+            let mut bindings = BINDINGS.lock();
+            let mut state = self.state.lock();
+
+            if let Some(thread) = state.bound.take() {
+                bindings.remove(&thread);
+            }
+
+            state.receiver = None;
+        })
+    }
+
+    /// Lets signals complete `waiter` of the bound thread, queued on an endpoint. A signal
+    /// that arrived before completes it right away.
+    pub(crate) fn attach(&self, waiter: &Arc<Waiter>) {
+        self.with_state(|state| {
+            if state.word == 0 {
+                state.receiver = Some(waiter.clone());
+            } else if waiter.claim() {
+                waiter.signal(core::mem::take(&mut state.word));
+            }
+        })
+    }
+
+    /// Stops signals from completing `waiter`, once its wait ended.
+    pub(crate) fn detach(&self, waiter: &Arc<Waiter>) {
+        self.with_state(|state| {
+            if state
+                .receiver
+                .as_ref()
+                .is_some_and(|receiver| Arc::ptr_eq(receiver, waiter))
+            {
+                state.receiver = None;
+            }
+        })
+    }
+
+    /// Runs `f` on the state with interrupts disabled, interrupt handlers signal too.
+    fn with_state<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        interrupts::without_interrupts(|| f(&mut self.state.lock()))
+    }
+}
+
+impl KernelObject for Notification {
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Notification
+    }
+}
+
+impl Default for Notification {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Notification {
+    fn drop(&mut self) {
+        let Some(thread) = self.state.get_mut().bound else {
+            return;
+        };
+
+        interrupts::without_interrupts(|| {
+            let mut bindings = BINDINGS.lock();
+
+            // The thread may be bound to a new notification already.
+            if bindings
+                .get(&thread)
+                .is_some_and(|bound| core::ptr::eq(bound.as_ptr(), self))
+            {
+                bindings.remove(&thread);
+            }
+        })
+    }
+}
+
+/// The notification `thread` is bound to.
+pub(crate) fn bound(thread: ThreadId) -> Option<Arc<Notification>> {
+    interrupts::without_interrupts(|| BINDINGS.lock().get(&thread).and_then(Weak::upgrade))
+}
@@ -0,0 +1,72 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::sync::Arc;
+use scheduler::Transfer;
+
+use crate::message::Message;
+use crate::waiter::Waiter;
+use crate::IpcError;
+
+/// The right to answer one call, handed to the receiver of the call.
+///
+/// Dropping it without replying fails the call with [`IpcError::Canceled`].
+pub struct Reply {
+    caller: Arc<Waiter>,
+}
+
+impl Reply {
+    pub(crate) fn new(caller: Arc<Waiter>) -> Self {
+        Self { caller }
+    }
+
+    /// Delivers `message` to the caller and makes it ready.
+    pub fn send(self, message: Message) -> Result<(), IpcError> {
+        self.deliver(message)?;
+        scheduler::wake(self.caller.thread());
+
+        Ok(())
+    }
+
+    /// Hands `message` to the caller in registers and switches to it right away. Used once
+    /// the current thread blocked itself, returns the transfer it is resumed with.
+    pub(crate) fn donate(self, message: Message) -> Option<Transfer> {
+        if self.caller.claim_reply() {
+            self.caller.hand_over(message, None)
+        } else {
+            scheduler::block()
+        }
+    }
+
+    fn deliver(&self, message: Message) -> Result<(), IpcError> {
+        if !self.caller.claim_reply() {
+            return Err(IpcError::Canceled);
+        }
+
+        self.caller.complete(message, None);
+
+        Ok(())
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if self.caller.cancel() {
+            scheduler::wake(self.caller.thread());
+        }
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use apic::clock::{self, TimerId};
+use spin::Mutex;
+use x86_64::op::interrupts;
+
+use crate::waiter::Waiter;
+use crate::IpcError;
+
+/// Waiters whose timeout is armed, looked up by the timer callback.
+static ARMED: Mutex<Vec<(TimerId, Arc<Waiter>)>> = Mutex::new(Vec::new());
+
+/// How long an operation waits for its partner.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timeout {
+    /// Fails at once unless a partner is already waiting.
+    Zero,
+    /// Waits at most this many nanoseconds.
+    Relative(u64),
+    /// Waits until the clock reaches this time, see [`apic::clock::now`].
+    Absolute(u64),
+    /// Waits until a partner arrives.
+    #[default]
+    Infinite,
+}
+
+impl Timeout {
+    /// Clock time the wait ends at, `None` for waits without end.
+    fn deadline(&self) -> Option<u64> {
+        match *self {
+            Self::Zero => Some(0),
+            Self::Relative(nanos) => Some(clock::now().saturating_add(nanos)),
+            Self::Absolute(deadline) => Some(deadline),
+            Self::Infinite => None,
+        }
+    }
+}
+
+/// An armed timeout, disarmed when dropped.
+pub(crate) struct Timer(Option<TimerId>);
+
+impl Timer {
+    /// A timer that never fires.
+    #[inline]
+    pub fn none() -> Self {
+        Self(None)
+    }
+}
+
+/// Expires `waiter` once `timeout` passed.
+pub(crate) fn arm(waiter: &Arc<Waiter>, timeout: Timeout) -> Result<Timer, IpcError> {
+    let Some(deadline) = timeout.deadline() else {
+        return Ok(Timer::none());
+    };
+
+    // Holding the list keeps the callback from looking up the timer before it is in there.
+    interrupts::without_interrupts(|| {
+        let mut armed = ARMED.lock();
+        let id = clock::add_timer(deadline, expire).map_err(|_| IpcError::NoTimer)?;
+        armed.push((id, waiter.clone()));
+
+        Ok(Timer(Some(id)))
+    })
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(id) = self.0 {
+            clock::cancel_timer(id);
+
+            interrupts::without_interrupts(|| ARMED.lock().retain(|(armed, _)| *armed != id));
+        }
+    }
+}
+
+/// Wakes a waiter whose partner did not come in time. The woken thread takes its waiter
+/// out of the queue it waited in.
+fn expire(id: TimerId) {
+    let waiter = {
+        let mut armed = ARMED.lock();
+
+        armed
+            .iter()
+            .position(|(armed, _)| *armed == id)
+            .map(|index| armed.swap_remove(index).1)
+    };
+
+    if let Some(waiter) = waiter.filter(|waiter| waiter.expire()) {
+        scheduler::wake(waiter.thread());
+    }
+}
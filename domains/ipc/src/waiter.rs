@@ -0,0 +1,239 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use alloc::sync::Arc;
+use scheduler::{Thread, ThreadState, Transfer};
+
+use crate::message::Message;
+use crate::Reply;
+
+/// Waits for a partner, may still time out.
+const WAITING: u8 = 0;
+/// A partner took the waiter and is transferring the message.
+const CLAIMED: u8 = 1;
+/// The message of a call was received, the caller waits for the reply without timeout.
+const REPLY_PENDING: u8 = 2;
+/// The transfer completed.
+const DONE: u8 = 3;
+/// No partner came in time.
+const TIMED_OUT: u8 = 4;
+/// The reply will never come.
+const CANCELED: u8 = 5;
+/// A notification delivered its signal word instead of a message.
+const SIGNALED: u8 = 6;
+
+/// How a wait ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// The partner completed the waiter, the message is read with [`Waiter::take`].
+    Done,
+    /// The partner switched here directly and handed the message over in registers.
+    Transferred(Message),
+    Signaled,
+    TimedOut,
+    Canceled,
+}
+
+/// A thread blocked in IPC, queued on an endpoint or notification or waiting for a reply.
+///
+/// The status decides who owns the waiter: only whoever moves it out of [`WAITING`] or
+/// [`REPLY_PENDING`] may touch the message and wake the thread.
+pub(crate) struct Waiter {
+    thread: Arc<Thread>,
+    status: AtomicU8,
+    /// Sent by a queued sender, or delivered to a receiver or a caller.
+    message: UnsafeCell<Message>,
+    /// Delivered to a receiver whose sender is a caller.
+    reply: UnsafeCell<Option<Reply>>,
+    /// The sender waits for a reply once its message is received.
+    call: bool,
+}
+
+// SAFETY: The cells are only accessed by the owner of the waiter, see `status`.
+unsafe impl Send for Waiter {}
+unsafe impl Sync for Waiter {}
+
+impl Waiter {
+    /// A waiter for the current thread, carrying `message` if it sends.
+    pub fn new(message: Message, call: bool) -> Arc<Self> {
+        Arc::new(Self {
+            thread: scheduler::current(),
+            status: AtomicU8::new(WAITING),
+            message: UnsafeCell::new(message),
+            reply: UnsafeCell::new(None),
+            call,
+        })
+    }
+
+    #[inline]
+    pub fn thread(&self) -> &Arc<Thread> {
+        &self.thread
+    }
+
+    #[inline]
+    pub fn is_call(&self) -> bool {
+        self.call
+    }
+
+    /// Takes the waiter from [`WAITING`], fails if it timed out already.
+    #[inline]
+    pub fn claim(&self) -> bool {
+        self.status
+            .compare_exchange(WAITING, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Takes a caller waiting for its reply.
+    #[inline]
+    pub fn claim_reply(&self) -> bool {
+        self.status
+            .compare_exchange(REPLY_PENDING, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Gives up waiting once the timeout expired, fails if a partner came first.
+    #[inline]
+    pub fn expire(&self) -> bool {
+        self.status
+            .compare_exchange(WAITING, TIMED_OUT, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Reads the message of a claimed sender.
+    #[inline]
+    pub fn message(&self) -> Message {
+        // SAFETY: Senders write their message before they are queued, the claim is ordered
+        // after that.
+        unsafe { *self.message.get() }
+    }
+
+    /// Completes a claimed waiter with `message`, the waiting thread has to be woken
+    /// afterwards.
+    pub fn complete(&self, message: Message, reply: Option<Reply>) {
+        // SAFETY: Only the owner of the claim writes, the waiting thread reads after it saw
+        // DONE.
+        unsafe {
+            *self.message.get() = message;
+            *self.reply.get() = reply;
+        }
+
+        self.status.store(DONE, Ordering::Release);
+    }
+
+    /// Completes a claimed waiter with the signal word of a notification, the waiting
+    /// thread has to be woken afterwards.
+    pub fn signal(&self, word: u64) {
+        // SAFETY: Like `complete`, the waiting thread reads after it saw SIGNALED.
+        unsafe { *self.message.get() = Message::empty(word) };
+
+        self.status.store(SIGNALED, Ordering::Release);
+    }
+
+    /// Completes a claimed waiter with `message` and switches to its thread right away,
+    /// handing the message over in registers. Falls back to completing through the waiter
+    /// and [`scheduler::donate`] if the thread can not run here right now.
+    ///
+    /// Returns the transfer the current thread is resumed with, see [`Waiter::wait`].
+    pub fn hand_over(&self, message: Message, reply: Option<Reply>) -> Option<Transfer> {
+        // SAFETY: Only the owner of the claim writes, the thread reads the reply after the
+        // switch or after it saw DONE.
+        unsafe { *self.reply.get() = reply };
+
+        match scheduler::donate_transfer(&self.thread, message.into_transfer()) {
+            Ok(resumed) => resumed,
+            Err(_) => {
+                // SAFETY: As above.
+                unsafe { *self.message.get() = message };
+                self.status.store(DONE, Ordering::Release);
+
+                scheduler::donate(&self.thread)
+            }
+        }
+    }
+
+    /// Lets a claimed sender complete without a new message.
+    #[inline]
+    pub fn release(&self) {
+        self.status.store(DONE, Ordering::Release);
+    }
+
+    /// Turns a claimed caller into one waiting for its reply.
+    #[inline]
+    pub fn await_reply(&self) {
+        self.status.store(REPLY_PENDING, Ordering::Release);
+    }
+
+    /// Fails a caller waiting for its reply, returns false if it already got one.
+    pub fn cancel(&self) -> bool {
+        self.status
+            .compare_exchange(REPLY_PENDING, CANCELED, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Blocks the current thread, which owns the waiter, until the wait ended.
+    ///
+    /// `resumed` is the transfer of the switch that resumed the thread, if it blocked for
+    /// this wait before. Only the partner holding the claim switches to a blocked waiter with
+    /// a transfer, see [`Waiter::hand_over`].
+    pub fn wait(&self, mut resumed: Option<Transfer>) -> Outcome {
+        loop {
+            if let Some(transfer) = resumed {
+                return Outcome::Transferred(Message::from_transfer(transfer));
+            }
+
+            if let Some(outcome) = self.outcome() {
+                return outcome;
+            }
+
+            // A wake between here and the switch makes `block` return at once.
+            self.thread.set_state(ThreadState::Blocked);
+
+            if let Some(outcome) = self.outcome() {
+                self.thread.set_state(ThreadState::Running);
+                return outcome;
+            }
+
+            resumed = scheduler::block();
+        }
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        match self.status.load(Ordering::Acquire) {
+            DONE => Some(Outcome::Done),
+            SIGNALED => Some(Outcome::Signaled),
+            TIMED_OUT => Some(Outcome::TimedOut),
+            CANCELED => Some(Outcome::Canceled),
+            _ => None,
+        }
+    }
+
+    /// Takes the message and reply delivered to the owner once [`Waiter::wait`] returned
+    /// [`Outcome::Done`], or the signal word as label after [`Outcome::Signaled`].
+    pub fn take(&self) -> (Message, Option<Reply>) {
+        // SAFETY: DONE was observed, the partner no longer touches the cells.
+        unsafe { (*self.message.get(), (*self.reply.get()).take()) }
+    }
+
+    /// Takes the reply handed over next to a message after [`Outcome::Transferred`].
+    pub fn take_reply(&self) -> Option<Reply> {
+        // SAFETY: The partner wrote the reply before it switched here and is done with it.
+        unsafe { (*self.reply.get()).take() }
+    }
+}
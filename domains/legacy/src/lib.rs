@@ -0,0 +1,43 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_std]
+
+pub mod pic;
+pub mod pit;
+
+use uio::kprintln;
+
+/// Remaps the 8259 PICs with all lines masked, so the legacy devices are usable until the
+/// APIC takes over.
+pub fn init() {
+    pic::init();
+
+    kprintln!(
+        "PIC: Remapped to {:#x} and {:#x}, all lines masked",
+        pic::PIC1_OFFSET,
+        pic::PIC2_OFFSET
+    );
+}
+
+/// Stops the PIT tick and masks the PICs, once the APIC handles interrupts.
+///
+/// The PIT stays usable as reference through [`pit::wait`].
+pub fn disable() {
+    pit::disable_tick();
+    pic::disable();
+}
@@ -0,0 +1,232 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use idt::InterruptStackFrame;
+use spin::{Mutex, Once};
+use x86_64::op::interrupts;
+use x86_64::structures::port::Port;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+/// Starts the initialisation sequence, an ICW4 follows.
+const ICW1_INIT: u8 = 0x11;
+const ICW4_8086: u8 = 0x01;
+/// OCW2, non-specific end of interrupt.
+const OCW2_EOI: u8 = 0x20;
+/// OCW3, the next read of the command port returns the in-service register.
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// Vectors the 8259s are moved to, out of the way of the exceptions.
+pub const PIC1_OFFSET: u8 = 0x20;
+pub const PIC2_OFFSET: u8 = 0x28;
+
+/// The slave is attached to this line of the master.
+pub const CASCADE_IRQ: u8 = 2;
+
+/// The lowest priority line of each PIC, where spurious IRQs show up.
+const PIC1_SPURIOUS_IRQ: u8 = 7;
+const PIC2_SPURIOUS_IRQ: u8 = 15;
+
+static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new());
+
+static INITIALISED: Once = Once::new();
+
+/// One 8259.
+struct Pic {
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+/// The master and the slave PIC, connected through [`CASCADE_IRQ`].
+struct ChainedPics {
+    master: Pic,
+    slave: Pic,
+}
+
+impl Pic {
+    const fn new(command: u16, data: u16) -> Self {
+        Self {
+            command: Port::new(command),
+            data: Port::new(data),
+        }
+    }
+
+    fn in_service(&mut self) -> u8 {
+        unsafe {
+            self.command.write(OCW3_READ_ISR);
+            self.command.read()
+        }
+    }
+
+    fn end_of_interrupt(&mut self) {
+        unsafe { self.command.write(OCW2_EOI) }
+    }
+}
+
+impl ChainedPics {
+    const fn new() -> Self {
+        Self {
+            master: Pic::new(PIC1_COMMAND, PIC1_DATA),
+            slave: Pic::new(PIC2_COMMAND, PIC2_DATA),
+        }
+    }
+
+    /// Returns the PIC serving `irq` and the line on it.
+    fn line(&mut self, irq: u8) -> (&mut Pic, u8) {
+        assert!(irq < 16, "The PICs only have 16 lines.");
+
+        if irq < 8 {
+            (&mut self.master, irq)
+        } else {
+            (&mut self.slave, irq - 8)
+        }
+    }
+
+    /// Acknowledges `irq`, the slave and the master for IRQs of the slave.
+    fn end_of_interrupt(&mut self, irq: u8) {
+        if irq >= 8 {
+            self.slave.end_of_interrupt();
+        }
+
+        self.master.end_of_interrupt();
+    }
+
+    fn set_masked(&mut self, irq: u8, masked: bool) {
+        let (pic, line) = self.line(irq);
+
+        unsafe {
+            let mask = pic.data.read();
+            pic.data.write(if masked {
+                mask | 1 << line
+            } else {
+                mask & !(1 << line)
+            });
+        }
+    }
+}
+
+/// Remaps both PICs away from the exception vectors and masks all lines but the cascade.
+///
+/// Only runs once, later calls do nothing.
+pub fn init() {
+    INITIALISED.call_once(|| {
+        with_pics(|ChainedPics { master, slave }| unsafe {
+            master.command.write(ICW1_INIT);
+            io_wait();
+            slave.command.write(ICW1_INIT);
+            io_wait();
+
+            master.data.write(PIC1_OFFSET);
+            io_wait();
+            slave.data.write(PIC2_OFFSET);
+            io_wait();
+
+            master.data.write(1 << CASCADE_IRQ);
+            io_wait();
+            slave.data.write(CASCADE_IRQ);
+            io_wait();
+
+            master.data.write(ICW4_8086);
+            io_wait();
+            slave.data.write(ICW4_8086);
+            io_wait();
+
+            master.data.write(!(1 << CASCADE_IRQ));
+            slave.data.write(0xFF);
+        });
+
+        // Spurious IRQs arrive even on masked lines.
+        idt::register(vector(PIC1_SPURIOUS_IRQ), spurious_interrupt)
+            .expect("PIC spurious vector already in use");
+        idt::register(vector(PIC2_SPURIOUS_IRQ), spurious_interrupt)
+            .expect("PIC spurious vector already in use");
+    });
+}
+
+/// Masks every line, after remapping the PICs if [`init`] did not run yet.
+///
+/// The PICs stay programmed when the APIC takes over, a spurious IRQ from them would
+/// otherwise arrive as an exception.
+pub fn disable() {
+    init();
+
+    with_pics(|pics| unsafe {
+        pics.master.data.write(0xFF);
+        pics.slave.data.write(0xFF);
+    });
+}
+
+/// Returns the vector `irq` arrives on.
+#[inline]
+pub const fn vector(irq: u8) -> u8 {
+    if irq < 8 {
+        PIC1_OFFSET + irq
+    } else {
+        PIC2_OFFSET + irq - 8
+    }
+}
+
+pub fn mask(irq: u8) {
+    with_pics(|pics| pics.set_masked(irq, true));
+}
+
+/// Unmasks `irq`, and the cascade line for IRQs of the slave.
+pub fn unmask(irq: u8) {
+    with_pics(|pics| {
+        pics.set_masked(irq, false);
+
+        if irq >= 8 {
+            pics.set_masked(CASCADE_IRQ, false);
+        }
+    });
+}
+
+/// Acknowledges `irq`, the slave and the master for IRQs of the slave.
+pub fn end_of_interrupt(irq: u8) {
+    with_pics(|pics| pics.end_of_interrupt(irq));
+}
+
+/// A spurious IRQ is not in service and must not be acknowledged, except for the cascade
+/// line when the slave raised it.
+fn spurious_interrupt(vector: u8, _stack_frame: &InterruptStackFrame) {
+    let irq = vector - PIC1_OFFSET;
+
+    with_pics(|pics| {
+        let (pic, line) = pics.line(irq);
+
+        if pic.in_service() & 1 << line != 0 {
+            pics.end_of_interrupt(irq);
+        } else if irq == PIC2_SPURIOUS_IRQ {
+            pics.master.end_of_interrupt();
+        }
+    });
+}
+
+/// Runs `f` on the PICs with interrupts disabled, interrupt handlers acknowledge through
+/// them and would spin forever on a lock held by the code they interrupted.
+fn with_pics<R>(f: impl FnOnce(&mut ChainedPics) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut PICS.lock()))
+}
+
+/// Gives the PICs time to process a command by writing to an unused port.
+#[inline]
+fn io_wait() {
+    unsafe { Port::<u8>::new(0x80).write(0) }
+}
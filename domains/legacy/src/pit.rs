@@ -0,0 +1,193 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use idt::{InterruptStackFrame, RegistrationError};
+use spin::Mutex;
+use x86_64::structures::port::Port;
+
+use crate::pic;
+
+/// Input frequency of all three channels.
+pub const FREQUENCY: u64 = 1_193_182;
+
+/// The PIT raises IRQ 0 through channel 0.
+pub const IRQ: u8 = 0;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+
+/// Channel 0, low then high byte, mode 2 (rate generator), binary.
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+/// Channel 0, low then high byte, mode 0. Counting stops until a count is written.
+const CHANNEL_0_STOP: u8 = 0b0011_0000;
+/// Channel 2, low then high byte, mode 0 (interrupt on terminal count), binary.
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Bit 0 gates channel 2, bit 1 connects it to the speaker, bit 5 reads its output.
+const GATE_PORT: u16 = 0x61;
+const GATE: u8 = 1 << 0;
+const SPEAKER: u8 = 1 << 1;
+const OUTPUT: u8 = 1 << 5;
+
+/// Longest wait a 16-bit count allows, about 54 ms.
+const MAX_WAIT_NS: u64 = 50_000_000;
+
+/// Serialises the programming sequences, they span several port writes.
+static PIT: Mutex<()> = Mutex::new(());
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static TICK_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PitError {
+    /// The frequency needs a divisor outside 1 - 65536.
+    OutOfRange(u64),
+    /// The vector of IRQ 0 could not be claimed.
+    Vector(RegistrationError),
+}
+
+/// Busy waits for `duration` nanoseconds by counting down channel 2.
+///
+/// Channel 2 is not connected to an IRQ, so this works with interrupts disabled and
+/// without touching the tick of channel 0.
+pub fn wait(duration: u64) {
+    let mut remaining = duration;
+
+    while remaining > 0 {
+        let chunk = remaining.min(MAX_WAIT_NS);
+        wait_once(chunk);
+        remaining -= chunk;
+    }
+}
+
+/// Counts down channel 2 once, polling its output instead of using an interrupt.
+fn wait_once(duration: u64) {
+    let count = (FREQUENCY * duration / NANOS_PER_SECOND).clamp(1, u16::MAX as u64) as u16;
+
+    let _pit = PIT.lock();
+    let mut gate: Port<u8> = Port::new(GATE_PORT);
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel: Port<u8> = Port::new(CHANNEL_2);
+
+    unsafe {
+        let value = (gate.read() & !(SPEAKER | GATE)) & 0x0F;
+        gate.write(value);
+
+        command.write(CHANNEL_2_ONE_SHOT);
+        channel.write(count as u8);
+        channel.write((count >> 8) as u8);
+
+        // The count starts on the rising edge of the gate.
+        gate.write(value | GATE);
+
+        while gate.read() & OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+
+        gate.write(value);
+    }
+}
+
+/// Runs channel 0 as rate generator, raising IRQ 0 `frequency` times per second.
+///
+/// Returns the frequency actually programmed, the divisor is rounded.
+pub fn start_periodic(frequency: u64) -> Result<u64, PitError> {
+    let divisor = FREQUENCY
+        .checked_div(frequency)
+        .filter(|divisor| (1..=u16::MAX as u64 + 1).contains(divisor))
+        .ok_or(PitError::OutOfRange(frequency))?;
+
+    let _pit = PIT.lock();
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel: Port<u8> = Port::new(CHANNEL_0);
+
+    // A count of 0 stands for 65536.
+    let count = divisor as u16;
+
+    unsafe {
+        command.write(CHANNEL_0_RATE_GENERATOR);
+        channel.write(count as u8);
+        channel.write((count >> 8) as u8);
+    }
+
+    Ok(FREQUENCY / divisor)
+}
+
+/// Stops channel 0, IRQ 0 is no longer raised.
+pub fn stop() {
+    let _pit = PIT.lock();
+
+    unsafe { Port::<u8>::new(COMMAND).write(CHANNEL_0_STOP) };
+}
+
+/// Counts IRQ 0 through the PIC, a tick for early boot and machines without an APIC.
+///
+/// Remaps the PICs if that did not happen yet.
+pub fn enable_tick(frequency: u64) -> Result<(), PitError> {
+    pic::init();
+
+    idt::register(pic::vector(IRQ), tick).map_err(PitError::Vector)?;
+
+    match start_periodic(frequency) {
+        Ok(frequency) => TICK_FREQUENCY.store(frequency, Ordering::Relaxed),
+        Err(error) => {
+            let _ = idt::unregister(pic::vector(IRQ));
+            return Err(error);
+        }
+    }
+
+    pic::unmask(IRQ);
+
+    Ok(())
+}
+
+/// Stops the tick started by [`enable_tick`], once the APIC timer takes over.
+pub fn disable_tick() {
+    if TICK_FREQUENCY.swap(0, Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    pic::mask(IRQ);
+    stop();
+
+    let _ = idt::unregister(pic::vector(IRQ));
+}
+
+/// Number of ticks counted since [`enable_tick`].
+#[inline]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Nanoseconds since [`enable_tick`], with the resolution of one tick. 0 without a tick.
+pub fn now() -> u64 {
+    match TICK_FREQUENCY.load(Ordering::Relaxed) {
+        0 => 0,
+        frequency => (ticks() as u128 * NANOS_PER_SECOND as u128 / frequency as u128) as u64,
+    }
+}
+
+fn tick(_vector: u8, _stack_frame: &InterruptStackFrame) {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+
+    pic::end_of_interrupt(IRQ);
+}
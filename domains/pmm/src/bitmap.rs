@@ -0,0 +1,408 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use x86_64::structures::memory::PhysicalAddress;
+use x86_64::types::paging::frame::PhysFrame;
+
+use crate::export::{MemoryRegion, RegionKind, FRAME_SIZE};
+use crate::MAX_REGIONS;
+
+const BITS: u64 = u64::BITS as u64;
+
+/// Frame allocator keeping one bit per 4 KiB frame. A set bit marks the frame as used.
+///
+/// The allocator does not know where its bitmap lives, which keeps it usable on the host
+/// against synthetic memory maps.
+pub struct BitmapFrameAllocator<'a> {
+    bitmap: &'a mut [u64],
+    frames: u64,
+    /// Frame ranges of the usable regions, nothing outside of them may be freed.
+    regions: [(u64, u64); MAX_REGIONS],
+    region_count: usize,
+    usable: u64,
+    free: u64,
+    next: u64,
+}
+
+impl<'a> BitmapFrameAllocator<'a> {
+    /// Number of frames the bitmap has to cover, i.e. up to the end of the highest usable region.
+    pub fn required_frames(regions: &[MemoryRegion]) -> u64 {
+        regions
+            .iter()
+            .filter(|region| region.kind == RegionKind::Usable)
+            .map(MemoryRegion::last_frame)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Number of words the bitmap for the given memory map needs.
+    #[inline]
+    pub fn required_words(regions: &[MemoryRegion]) -> usize {
+        Self::required_frames(regions).div_ceil(BITS) as usize
+    }
+
+    /// Creates an allocator which hands out every usable frame of `regions`.
+    ///
+    /// Frame 0 is never handed out, so a null physical address stays invalid.
+    pub fn new(regions: &[MemoryRegion], bitmap: &'a mut [u64]) -> Self {
+        let frames = Self::required_frames(regions);
+
+        assert!(
+            bitmap.len() >= frames.div_ceil(BITS) as usize,
+            "Bitmap is too small for the memory map."
+        );
+
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = Self {
+            bitmap,
+            frames,
+            regions: [(0, 0); MAX_REGIONS],
+            region_count: 0,
+            usable: 0,
+            free: 0,
+            next: 0,
+        };
+
+        for region in regions.iter().filter(|r| r.kind == RegionKind::Usable) {
+            let (first, last) = (region.first_frame().max(1), region.last_frame());
+
+            assert!(
+                allocator.region_count < MAX_REGIONS,
+                "Too many usable memory regions."
+            );
+
+            allocator.regions[allocator.region_count] = (first, last);
+            allocator.region_count += 1;
+
+            for frame in first..last {
+                if allocator.is_used(frame) {
+                    allocator.clear(frame);
+                    allocator.usable += 1;
+                    allocator.free += 1;
+                }
+            }
+        }
+
+        allocator
+    }
+
+    /// Marks every frame overlapping `base..base + length` as used.
+    pub fn reserve(&mut self, base: u64, length: u64) {
+        let first = base / FRAME_SIZE;
+        let last = (base + length).div_ceil(FRAME_SIZE).min(self.frames);
+
+        for frame in first..last {
+            if !self.is_used(frame) {
+                self.set(frame);
+                self.free -= 1;
+            }
+        }
+    }
+
+    /// Allocates a single frame.
+    pub fn allocate(&mut self) -> Option<PhysFrame> {
+        if self.free == 0 {
+            return None;
+        }
+
+        let words = self.bitmap.len();
+        let start = (self.next / BITS) as usize;
+
+        for i in 0..words {
+            let word = (start + i) % words;
+            let value = self.bitmap[word];
+
+            if value != u64::MAX {
+                let frame = word as u64 * BITS + value.trailing_ones() as u64;
+
+                self.set(frame);
+                self.free -= 1;
+                self.next = frame + 1;
+
+                return Some(frame_from_number(frame));
+            }
+        }
+
+        None
+    }
+
+    /// Allocates `count` physically contiguous frames and returns the first one.
+    ///
+    /// The run starts at a frame number that is a multiple of `align` (in frames).
+    pub fn allocate_contiguous(&mut self, count: u64, align: u64) -> Option<PhysFrame> {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two.");
+
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let mut start = 0;
+
+        while start + count <= self.frames {
+            match (start..start + count)
+                .rev()
+                .find(|&frame| self.is_used(frame))
+            {
+                Some(used) => start = (used + 1).next_multiple_of(align),
+                None => {
+                    for frame in start..start + count {
+                        self.set(frame);
+                    }
+
+                    self.free -= count;
+
+                    return Some(frame_from_number(start));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns a frame obtained from [`Self::allocate`].
+    #[inline]
+    pub fn free(&mut self, frame: PhysFrame) {
+        self.free_contiguous(frame, 1)
+    }
+
+    /// Returns a run of frames obtained from [`Self::allocate_contiguous`].
+    pub fn free_contiguous(&mut self, frame: PhysFrame, count: u64) {
+        let first = frame.start_address().as_u64() / FRAME_SIZE;
+
+        for number in first..first + count {
+            assert!(
+                self.is_usable(number),
+                "Freed {:?} is not managed by the frame allocator.",
+                frame_from_number(number)
+            );
+            assert!(
+                self.is_used(number),
+                "Double free of {:?}.",
+                frame_from_number(number)
+            );
+
+            self.clear(number);
+        }
+
+        self.free += count;
+        self.next = self.next.min(first);
+    }
+
+    /// Number of frames that are currently free.
+    #[inline]
+    pub fn free_frames(&self) -> u64 {
+        self.free
+    }
+
+    /// Number of frames the allocator was created with.
+    #[inline]
+    pub fn usable_frames(&self) -> u64 {
+        self.usable
+    }
+
+    #[inline]
+    fn is_usable(&self, frame: u64) -> bool {
+        self.regions[..self.region_count]
+            .iter()
+            .any(|&(first, last)| (first..last).contains(&frame))
+    }
+
+    #[inline]
+    fn is_used(&self, frame: u64) -> bool {
+        self.bitmap[(frame / BITS) as usize] & (1 << (frame % BITS)) != 0
+    }
+
+    #[inline]
+    fn set(&mut self, frame: u64) {
+        self.bitmap[(frame / BITS) as usize] |= 1 << (frame % BITS);
+    }
+
+    #[inline]
+    fn clear(&mut self, frame: u64) {
+        self.bitmap[(frame / BITS) as usize] &= !(1 << (frame % BITS));
+    }
+}
+
+#[inline]
+fn frame_from_number(number: u64) -> PhysFrame {
+    PhysFrame::containing_address(PhysicalAddress::new(number * FRAME_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// 1 MiB of usable memory above a reserved first page, a hole, then 2 MiB more.
+    fn regions() -> [MemoryRegion; 4] {
+        [
+            MemoryRegion::new(0, FRAME_SIZE, RegionKind::Reserved),
+            MemoryRegion::new(FRAME_SIZE, MIB - FRAME_SIZE, RegionKind::Usable),
+            MemoryRegion::new(MIB, MIB, RegionKind::Reserved),
+            MemoryRegion::new(2 * MIB, 2 * MIB, RegionKind::Usable),
+        ]
+    }
+
+    fn number(frame: PhysFrame) -> u64 {
+        frame.start_address().as_u64() / FRAME_SIZE
+    }
+
+    #[test]
+    fn required_words_cover_the_highest_usable_frame() {
+        let regions = regions();
+
+        assert_eq!(BitmapFrameAllocator::required_frames(&regions), 1024);
+        assert_eq!(BitmapFrameAllocator::required_words(&regions), 16);
+
+        let partial = [MemoryRegion::new(0, 65 * FRAME_SIZE, RegionKind::Usable)];
+        assert_eq!(BitmapFrameAllocator::required_words(&partial), 2);
+
+        let reserved = [MemoryRegion::new(0, MIB, RegionKind::Reserved)];
+        assert_eq!(BitmapFrameAllocator::required_words(&reserved), 0);
+    }
+
+    #[test]
+    fn new_only_frees_usable_frames_above_frame_zero() {
+        let regions = [MemoryRegion::new(0, 8 * FRAME_SIZE, RegionKind::Usable)];
+        let mut bitmap = [0; 1];
+        let allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+
+        assert_eq!(allocator.usable_frames(), 7);
+        assert_eq!(allocator.free_frames(), 7);
+        assert_eq!(bitmap[0], !0b1111_1110);
+    }
+
+    #[test]
+    fn reserve_removes_frames_once() {
+        let regions = regions();
+        let mut bitmap = [0; 16];
+        let mut allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+        let free = allocator.free_frames();
+
+        // Partially covers frames 1 and 3.
+        allocator.reserve(FRAME_SIZE + 1, 2 * FRAME_SIZE);
+        assert_eq!(allocator.free_frames(), free - 3);
+
+        allocator.reserve(FRAME_SIZE, FRAME_SIZE);
+        allocator.reserve(MIB, MIB);
+        assert_eq!(allocator.free_frames(), free - 3);
+
+        assert_eq!(allocator.allocate().map(number), Some(4));
+    }
+
+    #[test]
+    fn allocate_and_free_round_trip() {
+        let regions = regions();
+        let mut bitmap = [0; 16];
+        let mut allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+        let free = allocator.free_frames();
+
+        let first = allocator.allocate().unwrap();
+        let second = allocator.allocate().unwrap();
+        assert_eq!((number(first), number(second)), (1, 2));
+        assert_eq!(allocator.free_frames(), free - 2);
+
+        allocator.free(first);
+        assert_eq!(allocator.free_frames(), free - 1);
+        assert_eq!(allocator.allocate(), Some(first));
+
+        allocator.free(first);
+        allocator.free(second);
+        assert_eq!(allocator.free_frames(), free);
+    }
+
+    #[test]
+    #[should_panic(expected = "Double free")]
+    fn double_free_panics() {
+        let regions = regions();
+        let mut bitmap = [0; 16];
+        let mut allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+
+        let frame = allocator.allocate().unwrap();
+        allocator.free(frame);
+        allocator.free(frame);
+    }
+
+    #[test]
+    #[should_panic(expected = "not managed")]
+    fn free_outside_usable_regions_panics() {
+        let regions = regions();
+        let mut bitmap = [0; 16];
+        let mut allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+
+        // The frames of the reserved hole are marked used, but were never handed out.
+        allocator.free(frame_from_number(MIB / FRAME_SIZE + 4));
+    }
+
+    #[test]
+    #[should_panic(expected = "not managed")]
+    fn free_contiguous_into_a_reserved_region_panics() {
+        let regions = regions();
+        let mut bitmap = [0; 16];
+        let mut allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+
+        // The run covers the last usable frame below the hole and the first frame of the hole.
+        let last = MIB / FRAME_SIZE - 1;
+        allocator.reserve(last * FRAME_SIZE, FRAME_SIZE);
+        allocator.free_contiguous(frame_from_number(last), 2);
+    }
+
+    #[test]
+    fn allocate_contiguous_respects_alignment_and_holes() {
+        let regions = regions();
+        let mut bitmap = [0; 16];
+        let mut allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+        let free = allocator.free_frames();
+
+        // Frame 0 is never free, the first aligned run starts at 16.
+        let run = allocator.allocate_contiguous(16, 16).unwrap();
+        assert_eq!(number(run), 16);
+        assert_eq!(allocator.free_frames(), free - 16);
+
+        // Runs never span the reserved hole between 1 MiB and 2 MiB.
+        let large = allocator.allocate_contiguous(300, 1).unwrap();
+        assert_eq!(number(large), 512);
+
+        allocator.free_contiguous(run, 16);
+        allocator.free_contiguous(large, 300);
+        assert_eq!(allocator.free_frames(), free);
+
+        let aligned = allocator.allocate_contiguous(1, 512).unwrap();
+        assert_eq!(number(aligned), 512);
+    }
+
+    #[test]
+    fn exhaustion_returns_none() {
+        let regions = [MemoryRegion::new(0, 4 * FRAME_SIZE, RegionKind::Usable)];
+        let mut bitmap = [0; 1];
+        let mut allocator = BitmapFrameAllocator::new(&regions, &mut bitmap);
+
+        assert_eq!(allocator.allocate_contiguous(4, 1), None);
+
+        let frames = [(); 3].map(|_| allocator.allocate().unwrap());
+        assert_eq!(frames.map(number), [1, 2, 3]);
+
+        assert_eq!(allocator.free_frames(), 0);
+        assert_eq!(allocator.allocate(), None);
+        assert_eq!(allocator.allocate_contiguous(1, 1), None);
+
+        allocator.free(frames[1]);
+        assert_eq!(allocator.allocate(), Some(frames[1]));
+    }
+}
@@ -0,0 +1,99 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use x86_64::types::paging::page::{PageSize, Size4KiB};
+
+/// Size of a physical frame handed out by the allocator.
+pub const FRAME_SIZE: u64 = Size4KiB::SIZE;
+
+/// How a region of the memory map may be used by the kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Free RAM which can be handed out by the frame allocator.
+    Usable,
+    /// Memory that holds ACPI tables or bootloader data. It becomes usable once the kernel
+    /// no longer needs the information stored in it.
+    Reclaimable,
+    /// The kernel image and the modules loaded by the bootloader.
+    Kernel,
+    /// Memory backing the framebuffer.
+    Framebuffer,
+    /// Reserved, ACPI NVS or defective memory. Must never be touched.
+    Reserved,
+}
+
+/// A physically contiguous region of the memory map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: RegionKind,
+}
+
+impl MemoryRegion {
+    #[inline]
+    pub const fn new(base: u64, length: u64, kind: RegionKind) -> Self {
+        Self { base, length, kind }
+    }
+
+    #[inline]
+    pub const fn end(&self) -> u64 {
+        self.base + self.length
+    }
+
+    /// The first frame that lies completely inside the region.
+    #[inline]
+    pub const fn first_frame(&self) -> u64 {
+        self.base.div_ceil(FRAME_SIZE)
+    }
+
+    /// One past the last frame that lies completely inside the region.
+    #[inline]
+    pub const fn last_frame(&self) -> u64 {
+        self.end() / FRAME_SIZE
+    }
+}
+
+/// Byte totals per region kind, as reported by the memory map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStatistics {
+    pub usable: u64,
+    pub reclaimable: u64,
+    pub kernel: u64,
+    pub framebuffer: u64,
+    pub reserved: u64,
+}
+
+impl MemoryStatistics {
+    pub fn from_regions(regions: &[MemoryRegion]) -> Self {
+        let mut statistics = Self::default();
+
+        for region in regions {
+            let total = match region.kind {
+                RegionKind::Usable => &mut statistics.usable,
+                RegionKind::Reclaimable => &mut statistics.reclaimable,
+                RegionKind::Kernel => &mut statistics.kernel,
+                RegionKind::Framebuffer => &mut statistics.framebuffer,
+                RegionKind::Reserved => &mut statistics.reserved,
+            };
+
+            *total += region.length;
+        }
+
+        statistics
+    }
+}
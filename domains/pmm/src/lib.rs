@@ -0,0 +1,201 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod bitmap;
+pub mod export;
+pub mod mapping;
+
+use core::mem::size_of;
+
+use bitmap::BitmapFrameAllocator;
+use export::{MemoryRegion, MemoryStatistics, RegionKind};
+use limine::memory_map::EntryType;
+use limine::request::{HhdmRequest, MemoryMapRequest};
+use spin::{Mutex, Once};
+use uio::kprintln;
+use x86_64::op::interrupts;
+use x86_64::structures::memory::VirtualAddress;
+use x86_64::types::paging::frame::{FrameAllocator, FrameDeallocator, PhysFrame};
+
+/// Upper bound for the number of memory map entries we keep track of.
+const MAX_REGIONS: usize = 256;
+
+static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
+static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
+
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator<'static>>> = Mutex::new(None);
+static STATISTICS: Once<MemoryStatistics> = Once::new();
+
+/// Handle to the global frame allocator, for use with the page table mapper.
+pub struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator for GlobalFrameAllocator {
+    #[inline]
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        allocate_frame()
+    }
+}
+
+impl FrameDeallocator for GlobalFrameAllocator {
+    #[inline]
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        free_frame(frame)
+    }
+}
+
+/// Offset of the higher half direct map, which maps all physical memory.
+pub fn hhdm_offset() -> u64 {
+    match HHDM_REQUEST.get_response() {
+        Some(response) => response.offset(),
+        None => panic!("Limine did not provide a higher half direct map."),
+    }
+}
+
+pub fn init() {
+    let entries = match MEMORY_MAP_REQUEST.get_response() {
+        Some(response) => response.entries(),
+        None => panic!("Limine did not provide a memory map."),
+    };
+
+    let mut regions = [MemoryRegion::new(0, 0, RegionKind::Reserved); MAX_REGIONS];
+    let count = entries.len().min(MAX_REGIONS);
+
+    for (region, entry) in regions.iter_mut().zip(entries.iter()) {
+        *region = MemoryRegion::new(entry.base, entry.length, classify(entry.entry_type));
+    }
+
+    if entries.len() > MAX_REGIONS {
+        // Frames outside of the tracked regions are never handed out.
+        let lost: u64 = entries[MAX_REGIONS..]
+            .iter()
+            .filter(|entry| classify(entry.entry_type) == RegionKind::Usable)
+            .map(|entry| entry.length)
+            .sum();
+
+        kprintln!(
+            "PMM: Ignoring {} of {} memory map entries, {} KiB usable memory is lost",
+            entries.len() - MAX_REGIONS,
+            entries.len(),
+            lost / 1024
+        );
+    }
+
+    let regions = &regions[..count];
+    let words = BitmapFrameAllocator::required_words(regions);
+    let bytes = (words * size_of::<u64>()) as u64;
+
+    let storage = match regions
+        .iter()
+        .find(|region| region.kind == RegionKind::Usable && region.length >= bytes)
+    {
+        Some(region) => region.base,
+        None => panic!("No usable memory region can hold the frame bitmap."),
+    };
+
+    // SAFETY: The region is usable RAM, mapped by the HHDM and reserved right below, so
+    // nothing else will ever alias the bitmap.
+    let bitmap = unsafe {
+        core::slice::from_raw_parts_mut(
+            VirtualAddress::new(hhdm_offset() + storage).as_mut_ptr(),
+            words,
+        )
+    };
+
+    let mut allocator = BitmapFrameAllocator::new(regions, bitmap);
+    allocator.reserve(storage, bytes);
+
+    let statistics = STATISTICS.call_once(|| MemoryStatistics::from_regions(regions));
+
+    kprintln!(
+        "PMM: {} KiB usable, {} KiB reclaimable, {} KiB kernel, {} KiB framebuffer, {} KiB reserved",
+        statistics.usable / 1024,
+        statistics.reclaimable / 1024,
+        statistics.kernel / 1024,
+        statistics.framebuffer / 1024,
+        statistics.reserved / 1024
+    );
+    kprintln!(
+        "PMM: {} of {} frames free",
+        allocator.free_frames(),
+        allocator.usable_frames()
+    );
+
+    *FRAME_ALLOCATOR.lock() = Some(allocator);
+}
+
+/// Allocates a single 4 KiB frame.
+#[inline]
+pub fn allocate_frame() -> Option<PhysFrame> {
+    with_allocator(|allocator| allocator.allocate())
+}
+
+/// Allocates `count` physically contiguous frames.
+#[inline]
+pub fn allocate_frames(count: u64) -> Option<PhysFrame> {
+    allocate_frames_aligned(count, 1)
+}
+
+/// Allocates `count` physically contiguous frames starting at a multiple of `align` frames.
+#[inline]
+pub fn allocate_frames_aligned(count: u64, align: u64) -> Option<PhysFrame> {
+    with_allocator(|allocator| allocator.allocate_contiguous(count, align))
+}
+
+#[inline]
+pub fn free_frame(frame: PhysFrame) {
+    with_allocator(|allocator| allocator.free(frame))
+}
+
+#[inline]
+pub fn free_frames(frame: PhysFrame, count: u64) {
+    with_allocator(|allocator| allocator.free_contiguous(frame, count))
+}
+
+/// Number of frames which are currently free.
+#[inline]
+pub fn free_frame_count() -> u64 {
+    with_allocator(|allocator| allocator.free_frames())
+}
+
+/// Totals of the memory map as reported at boot.
+#[inline]
+pub fn statistics() -> Option<&'static MemoryStatistics> {
+    STATISTICS.get()
+}
+
+/// Runs `f` with the frame allocator locked.
+///
+/// Interrupts stay disabled while the lock is held, the heap grows and freed threads give
+/// back their stacks with interrupts disabled.
+fn with_allocator<R>(f: impl FnOnce(&mut BitmapFrameAllocator<'static>) -> R) -> R {
+    interrupts::without_interrupts(|| match FRAME_ALLOCATOR.lock().as_mut() {
+        Some(allocator) => f(allocator),
+        None => panic!("The PMM is used before pmm::init()."),
+    })
+}
+
+fn classify(entry_type: EntryType) -> RegionKind {
+    match entry_type {
+        EntryType::USABLE => RegionKind::Usable,
+        EntryType::ACPI_RECLAIMABLE | EntryType::BOOTLOADER_RECLAIMABLE => RegionKind::Reclaimable,
+        EntryType::KERNEL_AND_MODULES => RegionKind::Kernel,
+        EntryType::FRAMEBUFFER => RegionKind::Framebuffer,
+        _ => RegionKind::Reserved,
+    }
+}
@@ -0,0 +1,199 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::op::interrupts;
+use x86_64::structures::memory::{PhysicalAddress, VirtualAddress};
+use x86_64::types::paging::frame::PhysFrame;
+use x86_64::types::paging::mapper::{MapToError, Mapper, UnmapError};
+use x86_64::types::paging::page::{Page, PageSize, Size4KiB};
+use x86_64::types::paging::table::PageTableFlags;
+
+use crate::export::FRAME_SIZE;
+use crate::{allocate_frame, free_frame, hhdm_offset, GlobalFrameAllocator};
+
+/// Start of the virtual window device memory gets mapped into.
+pub const MMIO_START: u64 = 0xffff_d000_0000_0000;
+
+/// Size of the MMIO window.
+pub const MMIO_SIZE: u64 = 64 * 1024 * 1024 * 1024;
+
+/// Start of the virtual window kernel stacks get mapped into.
+pub const STACK_START: u64 = 0xffff_e000_0000_0000;
+
+/// Size of the stack window.
+pub const STACK_SIZE: u64 = 64 * 1024 * 1024 * 1024;
+
+/// Serialises all changes to the kernel page tables.
+static MAPPER_LOCK: Mutex<()> = Mutex::new(());
+
+/// Next free address in the MMIO window. Device mappings are never taken down.
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
+/// Next free address in the stack window. Stacks are unmapped when freed, but their
+/// addresses are not handed out again.
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACK_START);
+
+/// Takes `length` bytes from the window `next` points into, which ends at `end`. Leaves
+/// `next` untouched if they don't fit.
+fn reserve(next: &AtomicU64, end: u64, length: u64) -> Option<u64> {
+    next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |base| {
+        base.checked_add(length).filter(|&limit| limit <= end)
+    })
+    .ok()
+}
+
+/// Runs `f` with a mapper for the active page tables.
+///
+/// Interrupts stay disabled while the lock is held, as stacks are unmapped when a thread is
+/// freed during a switch.
+pub fn with_kernel_mapper<R>(f: impl FnOnce(&mut Mapper<'_>, &mut GlobalFrameAllocator) -> R) -> R {
+    interrupts::without_interrupts(|| {
+        let _guard = MAPPER_LOCK.lock();
+
+        // SAFETY: The HHDM maps all physical memory and the lock guarantees a single mapper.
+        let mut mapper = unsafe { Mapper::active(hhdm_offset()) };
+
+        f(&mut mapper, &mut GlobalFrameAllocator)
+    })
+}
+
+/// Maps `page` to a newly allocated frame and returns that frame.
+pub fn map_page(page: Page, flags: PageTableFlags) -> Result<PhysFrame, MapToError<Size4KiB>> {
+    let frame = allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+
+    // SAFETY: The frame was just allocated, so nothing else uses it.
+    match unsafe { map_to(page, frame, flags) } {
+        Ok(()) => Ok(frame),
+        Err(error) => {
+            free_frame(frame);
+            Err(error)
+        }
+    }
+}
+
+/// Maps `page` to `frame` in the active page tables and flushes the TLB entry.
+///
+/// # Safety
+/// See [`Mapper::map_to`].
+pub unsafe fn map_to<S: PageSize>(
+    page: Page<S>,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<S>> {
+    with_kernel_mapper(|mapper, allocator| mapper.map_to(page, frame, flags, allocator))
+        .map(|flush| flush.flush())
+}
+
+/// Removes the mapping of `page` from the active page tables and returns its frame.
+pub fn unmap<S: PageSize>(page: Page<S>) -> Result<PhysFrame<S>, UnmapError> {
+    with_kernel_mapper(|mapper, _| mapper.unmap(page)).map(|(frame, flush)| {
+        flush.flush();
+        frame
+    })
+}
+
+/// Maps a kernel stack of `size` bytes, rounded up to whole pages, to fresh frames and
+/// returns its top. The page below the stack stays unmapped, so an overflow faults instead
+/// of overwriting whatever lies below.
+///
+/// # Panics
+/// If the stack window is exhausted.
+pub fn map_stack(size: u64) -> Result<VirtualAddress, MapToError<Size4KiB>> {
+    let length = size.max(1).next_multiple_of(FRAME_SIZE);
+    let Some(guard) = reserve(&NEXT_STACK, STACK_START + STACK_SIZE, length + FRAME_SIZE) else {
+        panic!(
+            "Not enough space in the stack window for {:#x} bytes",
+            length
+        );
+    };
+    let bottom = guard + FRAME_SIZE;
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    for offset in (0..length).step_by(FRAME_SIZE as usize) {
+        let page = Page::containing_address(VirtualAddress::new(bottom + offset));
+
+        if let Err(error) = map_page(page, flags) {
+            unmap_frames(bottom, offset);
+            return Err(error);
+        }
+    }
+
+    Ok(VirtualAddress::new(bottom + length))
+}
+
+/// Unmaps a stack mapped by [`map_stack`] and frees its frames.
+///
+/// # Safety
+/// `top` and `size` have to be the top and size of a stack returned by [`map_stack`], which
+/// is no longer in use.
+pub unsafe fn unmap_stack(top: VirtualAddress, size: u64) {
+    let length = size.max(1).next_multiple_of(FRAME_SIZE);
+
+    unmap_frames(top.as_u64() - length, length);
+}
+
+/// Unmaps the `length` bytes from `bottom` on and frees their frames.
+fn unmap_frames(bottom: u64, length: u64) {
+    for offset in (0..length).step_by(FRAME_SIZE as usize) {
+        let page = Page::<Size4KiB>::containing_address(VirtualAddress::new(bottom + offset));
+
+        match unmap(page) {
+            Ok(frame) => free_frame(frame),
+            Err(error) => panic!("Could not unmap {:#x}: {:?}", bottom + offset, error),
+        }
+    }
+}
+
+/// Maps `size` bytes of device memory starting at `address` uncached into the MMIO window
+/// and returns the virtual address `address` ended up at.
+///
+/// # Safety
+/// `address` has to refer to device memory, not to RAM owned by the frame allocator.
+///
+/// # Panics
+/// If the MMIO window is exhausted.
+pub unsafe fn map_mmio(
+    address: PhysicalAddress,
+    size: u64,
+) -> Result<VirtualAddress, MapToError<Size4KiB>> {
+    let start = address.align_down(FRAME_SIZE);
+    let length = (address + size.max(1)).align_up(FRAME_SIZE) - start;
+    let Some(base) = reserve(&NEXT_MMIO, MMIO_START + MMIO_SIZE, length) else {
+        panic!(
+            "Not enough space in the MMIO window for {:#x} bytes",
+            length
+        );
+    };
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_CACHE;
+
+    for offset in (0..length).step_by(FRAME_SIZE as usize) {
+        let page = Page::containing_address(VirtualAddress::new(base + offset));
+        let frame = PhysFrame::containing_address(start + offset);
+
+        map_to(page, frame, flags)?;
+    }
+
+    Ok(VirtualAddress::new(base + address.frame_offset()))
+}
@@ -0,0 +1,203 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_std]
+
+extern crate alloc;
+
+mod queue;
+mod stats;
+mod switch;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use uio::kprintln;
+use x86_64::op::interrupts;
+
+use switch::Reason;
+
+pub use queue::PRIORITY_LEVELS;
+pub use stats::{log_statistics, statistics, Statistics};
+pub use switch::TIMESLICE_TICKS;
+pub use threads::{
+    Affinity, Priority, Thread, ThreadId, ThreadState, Transfer, TRANSFER_REGISTERS,
+};
+
+/// Work a spawned thread runs, boxed twice so it fits into one register.
+type Entry = Box<dyn FnOnce() + Send + 'static>;
+
+/// Starts scheduling on the bootstrap processor, the code running on it becomes the first
+/// thread. The application processors join on their next tick.
+///
+/// Has to run after [`cores::init`], every thread gets its own FPU state.
+pub fn init() {
+    threads::init();
+    switch::init();
+
+    let main = switch::init_core("main");
+
+    kprintln!(
+        "Scheduler: {} priorities, {} tick timeslices, running as thread {} ({}) on core {}",
+        PRIORITY_LEVELS,
+        TIMESLICE_TICKS,
+        main.id(),
+        main.name(),
+        cores::percpu::index()
+    );
+}
+
+/// Creates a kernel thread running `f` at the default priority on any core.
+///
+/// The thread exits when `f` returns.
+pub fn spawn<F>(name: &'static str, f: F) -> Arc<Thread>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with(name, Priority::DEFAULT, Affinity::ALL, f)
+}
+
+/// Creates a kernel thread running `f` and queues it on the least loaded core in
+/// `affinity`.
+///
+/// [`Priority::IDLE`] is reserved for the idle threads, it is raised to [`Priority::MIN`].
+pub fn spawn_with<F>(
+    name: &'static str,
+    priority: Priority,
+    affinity: Affinity,
+    f: F,
+) -> Arc<Thread>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let entry: Box<Entry> = Box::new(Box::new(f));
+
+    let thread = Arc::new(Thread::new(
+        name,
+        priority.max(Priority::MIN),
+        threads::address_space::kernel(),
+        start,
+        Box::into_raw(entry) as usize,
+    ));
+    thread.set_affinity(affinity);
+
+    switch::enqueue(thread.clone());
+
+    thread
+}
+
+/// Returns the thread running on the executing core.
+///
+/// # Panics
+/// If called before [`init`].
+#[inline]
+pub fn current() -> Arc<Thread> {
+    switch::current()
+}
+
+/// Lets the ready threads of the same or a higher priority run before the current one
+/// continues with a new timeslice.
+#[inline]
+pub fn yield_now() {
+    switch::schedule(Reason::Yield);
+}
+
+/// Switches away from the current thread until [`wake`] makes it ready again.
+///
+/// The caller marks the thread as [`ThreadState::Blocked`] first, usually while holding the
+/// lock of whatever it waits for, so a wake in between is not lost. Returns at once if the
+/// thread was woken before it could switch away.
+///
+/// Returns the transfer of a thread that switched here with [`donate_transfer`].
+#[inline]
+pub fn block() -> Option<Transfer> {
+    switch::schedule(Reason::Yield)
+}
+
+/// Makes a blocked thread ready, a thread that is not blocked is left alone.
+///
+/// Preempts the core the thread is queued on if it has a higher priority than the thread
+/// running there.
+#[inline]
+pub fn wake(thread: &Arc<Thread>) {
+    interrupts::without_interrupts(|| switch::wake(thread));
+}
+
+/// Switches to the blocked thread `to` right away and lets it run on the rest of the
+/// current timeslice, for IPC. The current thread stays blocked if it marked itself so,
+/// otherwise it is queued again.
+///
+/// Behaves like [`wake`] followed by [`block`] if `to` can not run on this core right now.
+/// Returns what [`block`] would return once the current thread is resumed.
+#[inline]
+pub fn donate(to: &Arc<Thread>) -> Option<Transfer> {
+    switch::donate(to)
+}
+
+/// Like [`donate`], but hands `transfer` to `to` in registers, for the IPC fast path. `to`
+/// receives it from the [`block`] or [`donate`] it is suspended in.
+///
+/// Does nothing and returns the transfer if `to` can not run on this core right now, the
+/// caller delivers it some other way and falls back to [`donate`].
+#[inline]
+pub fn donate_transfer(to: &Arc<Thread>, transfer: Transfer) -> Result<Option<Transfer>, Transfer> {
+    switch::donate_transfer(to, transfer)
+}
+
+/// Changes the priority of `thread`. A queued thread keeps its place until it runs again,
+/// the current thread gives up the core if a ready thread now has a higher priority.
+pub fn set_priority(thread: &Arc<Thread>, priority: Priority) {
+    thread.set_priority(priority.max(Priority::MIN));
+
+    if Arc::ptr_eq(thread, &current()) {
+        switch::schedule(Reason::Preempt);
+    }
+}
+
+/// Restricts the cores `thread` may run on. The current thread moves right away if it may
+/// no longer run on the executing core, other threads once they are queued again.
+pub fn set_affinity(thread: &Arc<Thread>, affinity: Affinity) {
+    thread.set_affinity(affinity);
+
+    if Arc::ptr_eq(thread, &current()) && !affinity.contains(cores::percpu::index()) {
+        switch::schedule(Reason::Yield);
+    }
+}
+
+/// Ends the current thread. Its stack is freed once no other reference to it is left.
+pub fn exit() -> ! {
+    interrupts::disable();
+
+    // The reference has to be gone before the switch, the stack is abandoned.
+    current().set_state(ThreadState::Exited);
+
+    // SAFETY: Interrupts are disabled.
+    unsafe { switch::reschedule(Reason::Yield) };
+
+    unreachable!("An exited thread was resumed");
+}
+
+/// First code a spawned thread runs, on its own stack.
+extern "C" fn start(entry: usize) -> ! {
+    switch::finish_switch();
+    interrupts::enable();
+
+    // SAFETY: `spawn_with` leaked the entry for this thread only.
+    let entry = unsafe { Box::from_raw(entry as *mut Entry) };
+    entry();
+
+    exit()
+}
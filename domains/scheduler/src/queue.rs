@@ -0,0 +1,112 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::sync::Arc;
+use kstructs::queue::LinkedQueue;
+use threads::{Priority, Thread};
+
+/// Number of priority levels, one per value of [`Priority`].
+pub const PRIORITY_LEVELS: usize = 256;
+
+/// Ready threads of one core, one FIFO per priority.
+///
+/// The FIFOs are linked through the threads, see [`LinkedQueue`], so queuing never allocates. It
+/// happens in interrupt handlers and while switching threads, where the heap lock may be
+/// held by the interrupted thread.
+pub(crate) struct RunQueue {
+    levels: [LinkedQueue<Thread>; PRIORITY_LEVELS],
+    /// Bit `n` is set while level `n` holds a thread.
+    occupied: [u64; PRIORITY_LEVELS / 64],
+    len: usize,
+}
+
+impl RunQueue {
+    pub fn new() -> Self {
+        Self {
+            levels: [const { LinkedQueue::new() }; PRIORITY_LEVELS],
+            occupied: [0; PRIORITY_LEVELS / 64],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Queues `thread` at its current priority, behind or ahead of the threads already
+    /// there. The thread must not be in any run queue.
+    pub fn push(&mut self, thread: Arc<Thread>, front: bool) {
+        let level = thread.priority().get() as usize;
+
+        if front {
+            self.levels[level].push_front(thread);
+        } else {
+            self.levels[level].push_back(thread);
+        }
+
+        self.occupied[level / 64] |= 1 << (level % 64);
+        self.len += 1;
+    }
+
+    /// Returns the priority of the first thread [`RunQueue::pop`] would return.
+    pub fn highest(&self) -> Option<Priority> {
+        self.occupied
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, &word)| word != 0)
+            .map(|(index, word)| {
+                Priority::new((index * 64 + 63 - word.leading_zeros() as usize) as u8)
+            })
+    }
+
+    /// Removes the first thread of the highest priority.
+    pub fn pop(&mut self) -> Option<Arc<Thread>> {
+        let level = self.highest()?.get() as usize;
+
+        self.take(level, |_| true)
+    }
+
+    /// Removes the first thread of the highest priority that may run on the core with the
+    /// index `core`.
+    pub fn steal(&mut self, core: usize) -> Option<Arc<Thread>> {
+        for level in (0..PRIORITY_LEVELS).rev() {
+            if self.occupied[level / 64] & (1 << (level % 64)) == 0 {
+                continue;
+            }
+
+            if let Some(thread) = self.take(level, |thread| thread.affinity().contains(core)) {
+                return Some(thread);
+            }
+        }
+
+        None
+    }
+
+    fn take(&mut self, level: usize, accept: impl Fn(&Thread) -> bool) -> Option<Arc<Thread>> {
+        let thread = self.levels[level].remove(accept)?;
+
+        if self.levels[level].is_empty() {
+            self.occupied[level / 64] &= !(1 << (level % 64));
+        }
+
+        self.len -= 1;
+
+        Some(thread)
+    }
+}
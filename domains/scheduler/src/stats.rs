@@ -0,0 +1,75 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::sync::atomic::Ordering;
+
+use uio::kprintln;
+
+use crate::switch::{self, IDLE};
+
+/// Counters of one core, to debug scheduling latency.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Statistics {
+    /// Thread switches, including those to and from the idle thread.
+    pub context_switches: u64,
+    /// Switches away from running threads for one of higher priority.
+    pub preemptions: u64,
+    /// Threads taken from the run queues of other cores.
+    pub steals: u64,
+    /// Threads waiting in the run queue.
+    pub queued: usize,
+    /// Nanoseconds the idle thread ran, up to its last switch away.
+    pub idle_time: u64,
+}
+
+/// Returns the counters of the core with the index `core`, `None` if the scheduler does not
+/// run on it.
+pub fn statistics(core: usize) -> Option<Statistics> {
+    let scheduler = switch::cores().get(core)?;
+
+    if !scheduler.active.load(Ordering::Acquire) {
+        return None;
+    }
+
+    let idle = IDLE.get_for(core)?.load(Ordering::Relaxed);
+
+    Some(Statistics {
+        context_switches: scheduler.context_switches.load(Ordering::Relaxed),
+        preemptions: scheduler.preemptions.load(Ordering::Relaxed),
+        steals: scheduler.steals.load(Ordering::Relaxed),
+        queued: scheduler.queued.load(Ordering::Relaxed),
+        // SAFETY: Idle threads are never freed.
+        idle_time: unsafe { idle.as_ref() }.map_or(0, |idle| idle.runtime()),
+    })
+}
+
+/// Prints the counters of every core running the scheduler.
+pub fn log_statistics() {
+    for core in 0..switch::cores().len() {
+        if let Some(statistics) = statistics(core) {
+            kprintln!(
+                "Scheduler: Core {}: {} switches, {} preemptions, {} steals, {} queued, {} ms idle",
+                core,
+                statistics.context_switches,
+                statistics.preemptions,
+                statistics.steals,
+                statistics.queued,
+                statistics.idle_time / 1_000_000
+            );
+        }
+    }
+}
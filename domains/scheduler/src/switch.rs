@@ -0,0 +1,531 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use apic::ioapic::DeliveryMode;
+use apic::lapic::IpiDestination;
+use idt::InterruptStackFrame;
+use spin::{Mutex, Once};
+use threads::{address_space, Priority, Thread, ThreadState, Transfer};
+use x86_64::op::interrupts;
+
+use crate::queue::RunQueue;
+
+/// Ticks a thread runs before threads of the same priority get their turn.
+pub const TIMESLICE_TICKS: u32 = 10;
+
+/// Scheduler state of one core, reachable from all cores.
+pub(crate) struct CoreScheduler {
+    pub queue: Mutex<RunQueue>,
+    /// Length of `queue`, read without the lock to balance the load.
+    pub queued: AtomicUsize,
+    /// Priority of the running thread, [`Priority::IDLE`] while the core idles.
+    pub running: AtomicU8,
+    /// Set once the core runs threads.
+    pub active: AtomicBool,
+    pub context_switches: AtomicU64,
+    pub preemptions: AtomicU64,
+    /// Threads taken from the queues of other cores.
+    pub steals: AtomicU64,
+}
+
+impl CoreScheduler {
+    fn new() -> Self {
+        Self {
+            queue: Mutex::new(RunQueue::new()),
+            queued: AtomicUsize::new(0),
+            running: AtomicU8::new(Priority::IDLE.get()),
+            active: AtomicBool::new(false),
+            context_switches: AtomicU64::new(0),
+            preemptions: AtomicU64::new(0),
+            steals: AtomicU64::new(0),
+        }
+    }
+
+    fn push(&self, thread: Arc<Thread>, front: bool) {
+        self.queue.lock().push(thread, front);
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Why the current thread offers its core.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Reason {
+    /// It gives up the rest of its timeslice, or blocked.
+    Yield,
+    /// Its timeslice is used up.
+    Expired,
+    /// A thread of higher priority may have become ready.
+    Preempt,
+}
+
+static CORES: Once<Vec<CoreScheduler>> = Once::new();
+
+cores::percpu! {
+    /// Thread running on the core, holds one reference.
+    static CURRENT: AtomicPtr<Thread> = AtomicPtr::new(ptr::null_mut());
+    /// Thread the core just switched away from, holds one reference until
+    /// [`finish_switch`] runs on the stack of the next thread.
+    static PREVIOUS: AtomicPtr<Thread> = AtomicPtr::new(ptr::null_mut());
+    /// Whether [`finish_switch`] queues the previous thread ahead of its peers.
+    static REQUEUE_FRONT: AtomicBool = AtomicBool::new(false);
+    /// Runs while no other thread is ready, holds one reference.
+    pub(crate) static IDLE: AtomicPtr<Thread> = AtomicPtr::new(ptr::null_mut());
+    /// Clock time of the last switch, the runtime of the current thread starts there.
+    static SWITCHED_AT: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Creates the per core state and hooks the scheduler into the tick and the application
+/// processors.
+pub(crate) fn init() {
+    CORES.call_once(|| {
+        cores::cores()
+            .iter()
+            .map(|_| CoreScheduler::new())
+            .collect()
+    });
+
+    idt::register(apic::RESCHEDULE_VECTOR, reschedule_interrupt)
+        .expect("Reschedule vector already in use");
+    apic::clock::set_tick_handler(tick);
+    cores::set_ap_main(ap_main);
+}
+
+#[inline]
+pub(crate) fn cores() -> &'static [CoreScheduler] {
+    CORES.get().expect("The scheduler is not initialised")
+}
+
+/// Adopts the code running on the executing core as thread `name`, creates the idle thread
+/// of the core and starts scheduling on it.
+pub(crate) fn init_core(name: &'static str) -> Arc<Thread> {
+    let current = Arc::new(Thread::adopt(name, address_space::kernel()));
+    let idle = Thread::new(
+        "idle",
+        Priority::IDLE,
+        address_space::kernel(),
+        idle_loop,
+        0,
+    );
+    let index = cores::percpu::index();
+
+    interrupts::without_interrupts(|| {
+        current.set_running(index);
+        current.set_timeslice(TIMESLICE_TICKS);
+
+        // SAFETY: The thread is kept alive by `CURRENT` until it is switched away from.
+        unsafe { threads::attach(&current) };
+
+        CURRENT
+            .get()
+            .store(Arc::into_raw(current.clone()).cast_mut(), Ordering::Relaxed);
+        IDLE.get()
+            .store(Arc::into_raw(Arc::new(idle)).cast_mut(), Ordering::Relaxed);
+        SWITCHED_AT
+            .get()
+            .store(apic::clock::now(), Ordering::Relaxed);
+
+        let this = &cores()[index];
+        this.running
+            .store(current.priority().get(), Ordering::Relaxed);
+        this.active.store(true, Ordering::Release);
+    });
+
+    current
+}
+
+/// Returns the thread running on the executing core.
+///
+/// # Panics
+/// If the scheduler does not run on the executing core.
+pub(crate) fn current() -> Arc<Thread> {
+    interrupts::without_interrupts(|| {
+        let current = CURRENT.get().load(Ordering::Relaxed);
+        assert!(!current.is_null(), "No thread runs on this core");
+
+        // SAFETY: `CURRENT` holds a reference as long as the thread runs.
+        unsafe {
+            Arc::increment_strong_count(current);
+            Arc::from_raw(current)
+        }
+    })
+}
+
+/// Queues a ready `thread` on the least loaded core it may run on and preempts that core
+/// if the thread has a higher priority than the running one.
+pub(crate) fn enqueue(thread: Arc<Thread>) {
+    let priority = thread.priority();
+
+    interrupts::without_interrupts(|| {
+        let core = select_core(&thread);
+        cores()[core].push(thread, false);
+
+        if priority.get() > cores()[core].running.load(Ordering::Relaxed) {
+            notify(core);
+        }
+    });
+}
+
+/// Picks a core for `thread`: the one with the fewest threads among those the thread may
+/// run on. Ties go to the core the thread ran on last, then to the executing one.
+fn select_core(thread: &Thread) -> usize {
+    let affinity = thread.affinity();
+    let last = thread.last_core();
+    let local = cores::percpu::index();
+
+    cores()
+        .iter()
+        .enumerate()
+        .filter(|(index, core)| affinity.contains(*index) && core.active.load(Ordering::Acquire))
+        .min_by_key(|(index, core)| {
+            let busy = core.running.load(Ordering::Relaxed) > Priority::IDLE.get();
+            let load = core.queued.load(Ordering::Relaxed) + busy as usize;
+
+            (load, *index != last, *index != local)
+        })
+        .map(|(index, _)| index)
+        .or_else(|| (0..cores().len()).find(|&index| affinity.contains(index)))
+        .unwrap_or(local)
+}
+
+/// Asks the core with the index `core` to pick its next thread again.
+fn notify(core: usize) {
+    let apic_id = cores::cores()[core].apic_id();
+
+    apic::local_apic().send_ipi(
+        apic::RESCHEDULE_VECTOR,
+        DeliveryMode::Fixed,
+        IpiDestination::Apic(apic_id),
+    );
+}
+
+/// Offers the core to the ready threads. Returns at once if the current thread keeps
+/// running, otherwise once it is resumed, with what the resuming switch handed over.
+pub(crate) fn schedule(reason: Reason) -> Option<Transfer> {
+    // SAFETY: Interrupts are disabled.
+    interrupts::without_interrupts(|| unsafe { reschedule(reason) })
+}
+
+/// Switches to the highest priority ready thread if the current one has to or may give up
+/// the core for `reason`, to the idle thread if nothing else is ready.
+///
+/// Returns the transfer handed over by the switch that resumed the current thread.
+///
+/// # Safety
+/// Interrupts must be disabled.
+pub(crate) unsafe fn reschedule(reason: Reason) -> Option<Transfer> {
+    let current = CURRENT.get().load(Ordering::Relaxed);
+
+    if current.is_null() {
+        return None;
+    }
+
+    let index = cores::percpu::index();
+    let this = &cores()[index];
+    let current = &*current;
+    let is_idle = ptr::eq(current, IDLE.get().load(Ordering::Relaxed));
+
+    // Woken again before it could switch away.
+    if current.state() == ThreadState::Ready && !is_idle {
+        current.set_state(ThreadState::Running);
+    }
+
+    let running = current.state() == ThreadState::Running && !is_idle;
+    let misplaced = running && !current.affinity().contains(index);
+
+    let next = {
+        let mut queue = this.queue.lock();
+
+        let take = match queue.highest() {
+            None => false,
+            Some(_) if !running || misplaced => true,
+            Some(highest) => match reason {
+                Reason::Preempt => highest > current.priority(),
+                Reason::Yield | Reason::Expired => highest >= current.priority(),
+            },
+        };
+
+        let next = if take { queue.pop() } else { None };
+        this.queued.store(queue.len(), Ordering::Relaxed);
+
+        next
+    };
+
+    let next = match next.or_else(|| (!running || misplaced).then(|| steal(index)).flatten()) {
+        Some(next) => next,
+        None if running && !misplaced => {
+            this.running
+                .store(current.priority().get(), Ordering::Relaxed);
+
+            if reason == Reason::Expired {
+                current.set_timeslice(TIMESLICE_TICKS);
+            }
+
+            return None;
+        }
+        None if is_idle => return None,
+        None => {
+            let idle = IDLE.get().load(Ordering::Relaxed);
+            Arc::increment_strong_count(idle);
+            Arc::from_raw(idle)
+        }
+    };
+
+    if running {
+        current.set_state(ThreadState::Ready);
+
+        if reason == Reason::Preempt {
+            this.preemptions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // A preempted thread keeps the rest of its timeslice and stays first in line, one that
+    // used up or gave up its timeslice queues behind its peers with a new one.
+    REQUEUE_FRONT
+        .get()
+        .store(reason == Reason::Preempt, Ordering::Relaxed);
+
+    if reason != Reason::Preempt {
+        current.set_timeslice(TIMESLICE_TICKS);
+    }
+
+    switch_to(current, next, None)
+}
+
+/// Makes the blocked `thread` ready and queues it, unless it still switches away from its
+/// core, then [`finish_switch`] queues it.
+pub(crate) fn wake(thread: &Arc<Thread>) {
+    if thread.wake() {
+        enqueue(thread.clone());
+    }
+}
+
+/// Switches from the current thread to the blocked `to` right away, bypassing the run
+/// queues. `to` runs on the rest of the current timeslice.
+///
+/// Falls back to waking `to` if it may not run on this core or is still switching away from
+/// another one. A current thread that blocked itself is switched away from either way, one
+/// that is still running is queued behind its peers.
+///
+/// Returns the transfer handed over by the switch that resumed the current thread.
+pub(crate) fn donate(to: &Arc<Thread>) -> Option<Transfer> {
+    interrupts::without_interrupts(|| unsafe {
+        if let Ok(resumed) = switch_directly(to, None) {
+            return resumed;
+        }
+
+        wake(to);
+
+        let current = CURRENT.get().load(Ordering::Relaxed);
+
+        if !current.is_null() && (*current).state() != ThreadState::Running {
+            reschedule(Reason::Yield)
+        } else {
+            None
+        }
+    })
+}
+
+/// Like [`donate`], but hands `transfer` to `to` in registers. Nothing happens if `to` can
+/// not be switched to right away, the transfer is returned to be delivered otherwise.
+pub(crate) fn donate_transfer(
+    to: &Arc<Thread>,
+    transfer: Transfer,
+) -> Result<Option<Transfer>, Transfer> {
+    // SAFETY: Interrupts are disabled.
+    interrupts::without_interrupts(|| unsafe {
+        switch_directly(to, Some(transfer)).map_err(|_| transfer)
+    })
+}
+
+/// Switches to the blocked `to` on the rest of the current timeslice if it may run on this
+/// core and is off all cores. Returns the transfer the current thread was resumed with,
+/// or `Err` without a switch.
+///
+/// # Safety
+/// Interrupts must be disabled.
+unsafe fn switch_directly(
+    to: &Arc<Thread>,
+    transfer: Option<Transfer>,
+) -> Result<Option<Transfer>, ()> {
+    let current = CURRENT.get().load(Ordering::Relaxed);
+    let index = cores::percpu::index();
+
+    if current.is_null() || !to.affinity().contains(index) || !to.claim() {
+        return Err(());
+    }
+
+    let current = &*current;
+
+    if current.state() == ThreadState::Running {
+        current.set_state(ThreadState::Ready);
+    }
+
+    // The donor gets a new timeslice when it runs again.
+    to.set_timeslice(current.timeslice().max(1));
+    current.set_timeslice(0);
+    REQUEUE_FRONT.get().store(false, Ordering::Relaxed);
+
+    Ok(switch_to(current, to.clone(), transfer))
+}
+
+/// Takes a thread from the busiest core whose queue holds one that may run here.
+fn steal(index: usize) -> Option<Arc<Thread>> {
+    let (_, victim) = cores()
+        .iter()
+        .enumerate()
+        .filter(|(other, core)| *other != index && core.queued.load(Ordering::Relaxed) > 0)
+        .max_by_key(|(_, core)| core.queued.load(Ordering::Relaxed))?;
+
+    let thread = {
+        let mut queue = victim.queue.lock();
+        let thread = queue.steal(index);
+        victim.queued.store(queue.len(), Ordering::Relaxed);
+
+        thread
+    }?;
+
+    cores()[index].steals.fetch_add(1, Ordering::Relaxed);
+
+    Some(thread)
+}
+
+/// Switches from `current` to `next`, which has to be off all cores or claimed, handing
+/// `transfer` over in registers. Returns the transfer `current` is resumed with.
+///
+/// # Safety
+/// Interrupts must be disabled and `current` must be the thread running on the executing
+/// core.
+pub(crate) unsafe fn switch_to(
+    current: &Thread,
+    next: Arc<Thread>,
+    transfer: Option<Transfer>,
+) -> Option<Transfer> {
+    let index = cores::percpu::index();
+    let this = &cores()[index];
+
+    next.set_running(index);
+
+    if next.timeslice() == 0 {
+        next.set_timeslice(TIMESLICE_TICKS);
+    }
+
+    this.running.store(next.priority().get(), Ordering::Relaxed);
+    this.context_switches.fetch_add(1, Ordering::Relaxed);
+
+    let now = apic::clock::now();
+    let switched_at = SWITCHED_AT.get().swap(now, Ordering::Relaxed);
+    current.add_runtime(now.saturating_sub(switched_at));
+
+    let next = Arc::into_raw(next);
+
+    // The current thread is only queued by `finish_switch`, once its context is saved and
+    // another core can safely resume it.
+    PREVIOUS
+        .get()
+        .store(ptr::from_ref(current).cast_mut(), Ordering::Relaxed);
+    CURRENT.get().store(next.cast_mut(), Ordering::Relaxed);
+
+    let resumed = threads::switch(current, &*next, transfer);
+
+    finish_switch();
+
+    resumed
+}
+
+/// Hands the thread switched away from back, on the stack of the thread switched to.
+///
+/// Ready threads are queued again, exited ones are freed here unless referenced elsewhere.
+/// Blocked threads are kept alive by whatever they wait for.
+///
+/// Freeing a thread unmaps its stack here, so the locks below that have to be IRQ safe, see
+/// the `Drop` of the kernel stack in the threads domain.
+pub(crate) fn finish_switch() {
+    let previous = PREVIOUS.get().swap(ptr::null_mut(), Ordering::Relaxed);
+
+    if previous.is_null() {
+        return;
+    }
+
+    // SAFETY: `switch_to` moved the reference of `CURRENT` into `PREVIOUS`.
+    let previous = unsafe { Arc::from_raw(previous) };
+    let state = previous.leave_cpu();
+
+    if ptr::eq(Arc::as_ptr(&previous), IDLE.get().load(Ordering::Relaxed)) {
+        return;
+    }
+
+    if state == ThreadState::Ready {
+        let index = cores::percpu::index();
+
+        if previous.affinity().contains(index) {
+            let front = REQUEUE_FRONT.get().load(Ordering::Relaxed);
+            cores()[index].push(previous, front);
+        } else {
+            enqueue(previous);
+        }
+    }
+}
+
+/// Takes a tick off the timeslice of the current thread, registered with the clock.
+fn tick() {
+    let current = CURRENT.get().load(Ordering::Relaxed);
+
+    if current.is_null() || ptr::eq(current, IDLE.get().load(Ordering::Relaxed)) {
+        return;
+    }
+
+    // SAFETY: The tick runs with interrupts disabled and `CURRENT` keeps the thread alive.
+    unsafe {
+        if (*current).consume_tick() {
+            reschedule(Reason::Expired);
+        }
+    }
+}
+
+fn reschedule_interrupt(_vector: u8, _stack_frame: &InterruptStackFrame) {
+    apic::end_of_interrupt();
+
+    // SAFETY: Interrupts stay disabled until the handler returns.
+    unsafe { reschedule(Reason::Preempt) };
+}
+
+/// Halts until a thread becomes ready, here or on a core it can be taken from.
+extern "C" fn idle_loop(_argument: usize) -> ! {
+    finish_switch();
+
+    loop {
+        interrupts::disable();
+
+        // SAFETY: Interrupts are disabled.
+        unsafe { reschedule(Reason::Yield) };
+
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Continues an application processor as thread of the scheduler, registered with
+/// [`cores::set_ap_main`].
+fn ap_main() -> ! {
+    init_core("boot");
+
+    crate::exit()
+}
@@ -0,0 +1,122 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+pub mod cspace;
+pub mod object;
+pub mod rights;
+
+pub use cspace::{CSpace, Slot};
+pub use object::{KernelObject, Object, ObjectType};
+pub use rights::Rights;
+
+use alloc::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapError {
+    /// The slot lies beyond the end of its CSpace.
+    InvalidSlot(Slot),
+    /// The slot holds no capability.
+    EmptySlot(Slot),
+    /// The slot already holds a capability.
+    SlotOccupied(Slot),
+    /// Only endpoint and notification capabilities carry badges.
+    NotBadgeable(ObjectType),
+    /// The badge of a capability can not be changed once set.
+    AlreadyBadged(u64),
+}
+
+/// Reference to a kernel object together with the rights it grants.
+///
+/// The badge is chosen when minting and tells receivers which capability a message was
+/// sent through, 0 means unbadged.
+#[derive(Clone, Debug)]
+pub struct Capability {
+    object: Object,
+    rights: Rights,
+    badge: u64,
+}
+
+impl Capability {
+    /// The original capability to a newly created object.
+    pub fn new<T: KernelObject>(object: Arc<T>, rights: Rights) -> Self {
+        Self {
+            object: Object::new(object),
+            rights,
+            badge: 0,
+        }
+    }
+
+    #[inline]
+    pub fn object(&self) -> &Object {
+        &self.object
+    }
+
+    #[inline]
+    pub fn object_type(&self) -> ObjectType {
+        self.object.object_type()
+    }
+
+    #[inline]
+    pub fn rights(&self) -> Rights {
+        self.rights
+    }
+
+    #[inline]
+    pub fn badge(&self) -> u64 {
+        self.badge
+    }
+
+    /// Returns the object if it is a `T` and the capability grants at least `rights`.
+    pub fn object_as<T: KernelObject>(&self, rights: Rights) -> Option<Arc<T>> {
+        if self.rights.contains(rights) {
+            self.object.downcast()
+        } else {
+            None
+        }
+    }
+
+    /// A capability to the same object with at most `rights`.
+    fn derive(&self, rights: Rights) -> Self {
+        Self {
+            object: self.object.clone(),
+            rights: self.rights & rights,
+            badge: self.badge,
+        }
+    }
+
+    /// Like [`Capability::derive`], also setting the badge of an unbadged endpoint or
+    /// notification capability.
+    fn mint(&self, rights: Rights, badge: u64) -> Result<Self, CapError> {
+        if badge == 0 {
+            return Ok(self.derive(rights));
+        }
+
+        match self.object_type() {
+            ObjectType::Endpoint | ObjectType::Notification => {}
+            other => return Err(CapError::NotBadgeable(other)),
+        }
+
+        if self.badge != 0 {
+            return Err(CapError::AlreadyBadged(self.badge));
+        }
+
+        Ok(Self {
+            badge,
+            ..self.derive(rights)
+        })
+    }
+}
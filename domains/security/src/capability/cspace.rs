@@ -0,0 +1,526 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::op::interrupts;
+
+use super::{CapError, Capability, Rights};
+
+/// Index of a slot in a [`CSpace`].
+pub type Slot = usize;
+
+type NodeId = usize;
+
+/// All CSpaces and the derivation tree spanning them, behind one lock so capabilities can
+/// move between CSpaces atomically.
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// The capability slots of one task.
+///
+/// Every capability is a node in the derivation tree: capabilities copied, derived or
+/// minted from another one are its children, so revoking a capability reaches every copy
+/// made from it in any CSpace. Dropping the CSpace deletes its capabilities.
+pub struct CSpace {
+    id: usize,
+    size: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Address {
+    space: usize,
+    slot: Slot,
+}
+
+struct Entry {
+    capability: Capability,
+    node: NodeId,
+}
+
+/// Position of a capability in the derivation tree.
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    address: Address,
+}
+
+struct Registry {
+    spaces: Vec<Option<Vec<Option<Entry>>>>,
+    nodes: Vec<Option<Node>>,
+    free_spaces: Vec<usize>,
+    free_nodes: Vec<NodeId>,
+}
+
+impl CSpace {
+    /// Creates a CSpace with `size` empty slots.
+    pub fn new(size: usize) -> Self {
+        let id = with_registry(|registry| registry.create_space(size));
+
+        Self { id, size }
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns a copy of the capability in `slot`, for invoking it.
+    pub fn get(&self, slot: Slot) -> Result<Capability, CapError> {
+        with_registry(|registry| {
+            registry
+                .entry(self.address(slot)?)
+                .map(|entry| entry.capability.clone())
+        })
+    }
+
+    /// Returns the first empty slot.
+    pub fn first_empty(&self) -> Option<Slot> {
+        with_registry(|registry| registry.first_empty(self.id))
+    }
+
+    /// Places the original capability to a new object into `slot`, as a root of the
+    /// derivation tree.
+    pub fn insert(&self, slot: Slot, capability: Capability) -> Result<(), CapError> {
+        let address = self.address(slot)?;
+
+        with_registry(|registry| registry.place(address, capability, None))
+    }
+
+    /// Copies the capability in `slot` into `to_slot` of `to`, with the same rights and
+    /// badge.
+    pub fn copy(&self, slot: Slot, to: &CSpace, to_slot: Slot) -> Result<(), CapError> {
+        self.derive(slot, to, to_slot, Rights::ALL)
+    }
+
+    /// Copies the capability in `slot` into `to_slot` of `to`, keeping only the rights also
+    /// in `rights`.
+    pub fn derive(
+        &self,
+        slot: Slot,
+        to: &CSpace,
+        to_slot: Slot,
+        rights: Rights,
+    ) -> Result<(), CapError> {
+        self.mint(slot, to, to_slot, rights, 0)
+    }
+
+    /// Like [`CSpace::derive`], also badging the copy of an unbadged endpoint or
+    /// notification capability. A badge of 0 leaves the badge unchanged.
+    pub fn mint(
+        &self,
+        slot: Slot,
+        to: &CSpace,
+        to_slot: Slot,
+        rights: Rights,
+        badge: u64,
+    ) -> Result<(), CapError> {
+        let from = self.address(slot)?;
+        let to = to.address(to_slot)?;
+
+        with_registry(|registry| registry.mint(from, to, rights, badge))
+    }
+
+    /// Moves the capability in `slot` into `to_slot` of `to`, keeping its place in the
+    /// derivation tree.
+    pub fn move_to(&self, slot: Slot, to: &CSpace, to_slot: Slot) -> Result<(), CapError> {
+        let from = self.address(slot)?;
+        let to = to.address(to_slot)?;
+
+        with_registry(|registry| registry.move_entry(from, to))
+    }
+
+    /// Deletes the capability in `slot`. Capabilities derived from it stay valid and take
+    /// its place in the derivation tree.
+    pub fn delete(&self, slot: Slot) -> Result<(), CapError> {
+        let address = self.address(slot)?;
+
+        // Objects may be freed with the last capability, outside of the lock.
+        let capability = with_registry(|registry| registry.remove(address))?;
+        drop(capability);
+
+        Ok(())
+    }
+
+    /// Deletes every capability derived from the one in `slot`, in all CSpaces. The
+    /// capability itself stays.
+    pub fn revoke(&self, slot: Slot) -> Result<(), CapError> {
+        let address = self.address(slot)?;
+
+        let revoked = with_registry(|registry| registry.revoke(address))?;
+        drop(revoked);
+
+        Ok(())
+    }
+
+    fn address(&self, slot: Slot) -> Result<Address, CapError> {
+        if slot < self.size {
+            Ok(Address {
+                space: self.id,
+                slot,
+            })
+        } else {
+            Err(CapError::InvalidSlot(slot))
+        }
+    }
+}
+
+impl Drop for CSpace {
+    fn drop(&mut self) {
+        let capabilities = with_registry(|registry| registry.destroy_space(self.id, self.size));
+
+        drop(capabilities);
+    }
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            spaces: Vec::new(),
+            nodes: Vec::new(),
+            free_spaces: Vec::new(),
+            free_nodes: Vec::new(),
+        }
+    }
+
+    /// Adds a CSpace with `size` empty slots and returns its ID.
+    fn create_space(&mut self, size: usize) -> usize {
+        let slots = Some((0..size).map(|_| None).collect());
+
+        match self.free_spaces.pop() {
+            Some(id) => {
+                self.spaces[id] = slots;
+                id
+            }
+            None => {
+                self.spaces.push(slots);
+                self.spaces.len() - 1
+            }
+        }
+    }
+
+    /// Removes the capabilities of the CSpace `space` with `size` slots and frees its ID.
+    fn destroy_space(&mut self, space: usize, size: usize) -> Vec<Capability> {
+        let capabilities = (0..size)
+            .filter_map(|slot| self.remove(Address { space, slot }).ok())
+            .collect();
+
+        self.spaces[space] = None;
+        self.free_spaces.push(space);
+
+        capabilities
+    }
+
+    fn first_empty(&self, space: usize) -> Option<Slot> {
+        self.spaces[space]
+            .as_ref()
+            .and_then(|slots| slots.iter().position(Option::is_none))
+    }
+
+    fn slot_mut(&mut self, address: Address) -> &mut Option<Entry> {
+        let slots = self.spaces[address.space]
+            .as_mut()
+            .expect("CSpace already destroyed");
+
+        &mut slots[address.slot]
+    }
+
+    fn entry(&self, address: Address) -> Result<&Entry, CapError> {
+        self.spaces[address.space]
+            .as_ref()
+            .and_then(|slots| slots[address.slot].as_ref())
+            .ok_or(CapError::EmptySlot(address.slot))
+    }
+
+    fn check_empty(&mut self, address: Address) -> Result<(), CapError> {
+        match self.slot_mut(address) {
+            Some(_) => Err(CapError::SlotOccupied(address.slot)),
+            None => Ok(()),
+        }
+    }
+
+    fn node_mut(&mut self, node: NodeId) -> &mut Node {
+        self.nodes[node]
+            .as_mut()
+            .expect("Dangling derivation tree node")
+    }
+
+    /// Stores `capability` at `address` as child of `parent`.
+    fn place(
+        &mut self,
+        address: Address,
+        capability: Capability,
+        parent: Option<NodeId>,
+    ) -> Result<(), CapError> {
+        self.check_empty(address)?;
+
+        let node = Node {
+            parent,
+            children: vec![],
+            address,
+        };
+        let node = match self.free_nodes.pop() {
+            Some(id) => {
+                self.nodes[id] = Some(node);
+                id
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+
+        if let Some(parent) = parent {
+            self.node_mut(parent).children.push(node);
+        }
+
+        *self.slot_mut(address) = Some(Entry { capability, node });
+
+        Ok(())
+    }
+
+    /// Places a copy of the capability at `from` at `to`, as its child.
+    fn mint(
+        &mut self,
+        from: Address,
+        to: Address,
+        rights: Rights,
+        badge: u64,
+    ) -> Result<(), CapError> {
+        let source = self.entry(from)?;
+        let capability = source.capability.mint(rights, badge)?;
+        let parent = source.node;
+
+        self.place(to, capability, Some(parent))
+    }
+
+    /// Moves the capability at `from` to `to`, keeping its node.
+    fn move_entry(&mut self, from: Address, to: Address) -> Result<(), CapError> {
+        self.entry(from)?;
+        self.check_empty(to)?;
+
+        let entry = self.slot_mut(from).take().expect("Checked above");
+        self.node_mut(entry.node).address = to;
+        *self.slot_mut(to) = Some(entry);
+
+        Ok(())
+    }
+
+    /// Takes all descendants of the capability at `address` out of their slots and the
+    /// derivation tree.
+    fn revoke(&mut self, address: Address) -> Result<Vec<Capability>, CapError> {
+        let node = self.entry(address)?.node;
+        let mut revoked = Vec::new();
+        let mut pending = core::mem::take(&mut self.node_mut(node).children);
+
+        while let Some(child) = pending.pop() {
+            let child = self.free_node(child);
+            pending.extend(child.children);
+
+            let entry = self.slot_mut(child.address).take();
+            revoked.extend(entry.map(|entry| entry.capability));
+        }
+
+        Ok(revoked)
+    }
+
+    fn free_node(&mut self, node: NodeId) -> Node {
+        self.free_nodes.push(node);
+        self.nodes[node]
+            .take()
+            .expect("Dangling derivation tree node")
+    }
+
+    /// Takes the capability at `address` out of its slot and the derivation tree, its
+    /// children move up to its parent.
+    fn remove(&mut self, address: Address) -> Result<Capability, CapError> {
+        let entry = self
+            .slot_mut(address)
+            .take()
+            .ok_or(CapError::EmptySlot(address.slot))?;
+        let node = self.free_node(entry.node);
+
+        for &child in &node.children {
+            self.node_mut(child).parent = node.parent;
+        }
+
+        if let Some(parent) = node.parent {
+            let siblings = &mut self.node_mut(parent).children;
+            siblings.retain(|&sibling| sibling != entry.node);
+            siblings.extend(node.children);
+        }
+
+        Ok(entry.capability)
+    }
+}
+
+/// Runs `f` on the registry with interrupts disabled, a preempted holder of the lock would
+/// stall every capability operation.
+fn with_registry<R>(f: impl FnOnce(&mut Registry) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut REGISTRY.lock()))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use super::*;
+    use x86_64::structures::port::PortRange;
+
+    use crate::capability::object::IoPorts;
+
+    fn capability() -> Capability {
+        Capability::new(
+            Arc::new(IoPorts::new(PortRange::new(0x3F8, 8))),
+            Rights::ALL,
+        )
+    }
+
+    fn at(space: usize, slot: Slot) -> Address {
+        Address { space, slot }
+    }
+
+    fn copy(registry: &mut Registry, from: Address, to: Address) {
+        registry.mint(from, to, Rights::ALL, 0).unwrap();
+    }
+
+    fn node(registry: &Registry, address: Address) -> &Node {
+        let id = registry.entry(address).unwrap().node;
+
+        registry.nodes[id].as_ref().unwrap()
+    }
+
+    fn node_id(registry: &Registry, address: Address) -> NodeId {
+        registry.entry(address).unwrap().node
+    }
+
+    #[test]
+    fn remove_moves_children_to_parent() {
+        let mut registry = Registry::new();
+        let space = registry.create_space(4);
+
+        registry.place(at(space, 0), capability(), None).unwrap();
+        copy(&mut registry, at(space, 0), at(space, 1));
+        copy(&mut registry, at(space, 1), at(space, 2));
+        copy(&mut registry, at(space, 1), at(space, 3));
+
+        let root = node_id(&registry, at(space, 0));
+        registry.remove(at(space, 1)).unwrap();
+
+        assert_eq!(node(&registry, at(space, 2)).parent, Some(root));
+        assert_eq!(node(&registry, at(space, 3)).parent, Some(root));
+        let children = [at(space, 2), at(space, 3)].map(|address| node_id(&registry, address));
+        assert_eq!(node(&registry, at(space, 0)).children, children);
+        assert!(matches!(
+            registry.remove(at(space, 1)),
+            Err(CapError::EmptySlot(1))
+        ));
+
+        // Removing a root turns its children into roots.
+        registry.remove(at(space, 0)).unwrap();
+
+        assert_eq!(node(&registry, at(space, 2)).parent, None);
+        assert_eq!(node(&registry, at(space, 3)).parent, None);
+    }
+
+    #[test]
+    fn revoke_reaches_all_descendants() {
+        let mut registry = Registry::new();
+        let first = registry.create_space(2);
+        let second = registry.create_space(3);
+
+        registry.place(at(first, 0), capability(), None).unwrap();
+        copy(&mut registry, at(first, 0), at(second, 0));
+        copy(&mut registry, at(second, 0), at(second, 1));
+        copy(&mut registry, at(second, 1), at(first, 1));
+        registry.place(at(second, 2), capability(), None).unwrap();
+
+        let revoked = registry.revoke(at(first, 0)).unwrap();
+
+        assert_eq!(revoked.len(), 3);
+        assert!(node(&registry, at(first, 0)).children.is_empty());
+        assert!(registry.entry(at(first, 0)).is_ok());
+        assert!(registry.entry(at(first, 1)).is_err());
+        assert!(registry.entry(at(second, 0)).is_err());
+        assert!(registry.entry(at(second, 1)).is_err());
+        assert!(registry.entry(at(second, 2)).is_ok());
+        assert_eq!(registry.free_nodes.len(), 3);
+
+        // Nothing is left to revoke.
+        assert!(registry.revoke(at(first, 0)).unwrap().is_empty());
+        assert!(matches!(
+            registry.revoke(at(first, 1)),
+            Err(CapError::EmptySlot(1))
+        ));
+    }
+
+    #[test]
+    fn move_updates_node_address() {
+        let mut registry = Registry::new();
+        let first = registry.create_space(2);
+        let second = registry.create_space(2);
+
+        registry.place(at(first, 0), capability(), None).unwrap();
+        copy(&mut registry, at(first, 0), at(first, 1));
+        registry.move_entry(at(first, 1), at(second, 1)).unwrap();
+
+        assert!(registry.entry(at(first, 1)).is_err());
+        assert_eq!(node(&registry, at(second, 1)).address, at(second, 1));
+
+        // Revoking has to find the capability at its new address.
+        assert_eq!(registry.revoke(at(first, 0)).unwrap().len(), 1);
+        assert!(registry.entry(at(second, 1)).is_err());
+
+        registry.place(at(second, 0), capability(), None).unwrap();
+
+        assert!(matches!(
+            registry.move_entry(at(first, 0), at(second, 0)),
+            Err(CapError::SlotOccupied(0))
+        ));
+        assert!(matches!(
+            registry.move_entry(at(first, 1), at(second, 1)),
+            Err(CapError::EmptySlot(1))
+        ));
+    }
+
+    #[test]
+    fn ids_are_reused() {
+        let mut registry = Registry::new();
+        let first = registry.create_space(2);
+        let second = registry.create_space(1);
+
+        registry.place(at(first, 0), capability(), None).unwrap();
+        copy(&mut registry, at(first, 0), at(second, 0));
+
+        let root = node_id(&registry, at(first, 0));
+        let capabilities = registry.destroy_space(first, 2);
+
+        assert_eq!(capabilities.len(), 1);
+        assert_eq!(node(&registry, at(second, 0)).parent, None);
+        assert_eq!(registry.create_space(3), first);
+        assert_eq!(registry.first_empty(first), Some(0));
+        assert_eq!(registry.spaces.len(), 2);
+
+        registry.place(at(first, 2), capability(), None).unwrap();
+        let reused = node_id(&registry, at(first, 2));
+
+        assert_eq!(reused, root);
+        assert_eq!(registry.nodes.len(), 2);
+        assert!(registry.free_nodes.is_empty());
+        assert_eq!(node(&registry, at(first, 2)).address, at(first, 2));
+    }
+}
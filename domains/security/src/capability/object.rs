@@ -0,0 +1,151 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::any::Any;
+use core::fmt;
+
+use alloc::sync::Arc;
+use x86_64::structures::memory::PhysicalAddress;
+use x86_64::structures::port::PortRange;
+
+/// Kinds of kernel objects capabilities can refer to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectType {
+    Thread,
+    AddressSpace,
+    Endpoint,
+    Notification,
+    Untyped,
+    IrqHandler,
+    IoPorts,
+}
+
+/// Conversion to [`Any`], implemented for every eligible type so kernel objects can be
+/// downcast.
+pub trait AsAny: Any + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+}
+
+impl<T: Any + Send + Sync> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+/// An object the kernel hands out capabilities to.
+///
+/// Implemented by the domains owning the objects, like threads or IPC endpoints.
+pub trait KernelObject: AsAny {
+    fn object_type(&self) -> ObjectType;
+}
+
+/// Shared reference to a kernel object of any type.
+#[derive(Clone)]
+pub struct Object(Arc<dyn KernelObject>);
+
+impl Object {
+    pub fn new<T: KernelObject>(object: Arc<T>) -> Self {
+        Self(object)
+    }
+
+    #[inline]
+    pub fn object_type(&self) -> ObjectType {
+        self.0.object_type()
+    }
+
+    /// Returns the object if it is a `T`.
+    pub fn downcast<T: KernelObject>(&self) -> Option<Arc<T>> {
+        self.0.clone().into_any().downcast().ok()
+    }
+
+    /// Returns whether both refer to the same object.
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Object")
+            .field(&self.object_type())
+            .field(&Arc::as_ptr(&self.0).cast::<()>())
+            .finish()
+    }
+}
+
+/// A range of physical memory not yet turned into other objects.
+#[derive(Debug)]
+pub struct Untyped {
+    base: PhysicalAddress,
+    size: u64,
+}
+
+impl Untyped {
+    pub fn new(base: PhysicalAddress, size: u64) -> Self {
+        Self { base, size }
+    }
+
+    #[inline]
+    pub fn base(&self) -> PhysicalAddress {
+        self.base
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl KernelObject for Untyped {
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Untyped
+    }
+}
+
+/// A range of I/O ports its holders may access.
+///
+/// The range is the one a TSS [`IoPermissionBitmap`] is opened for when the capability is
+/// delegated to a task.
+///
+/// [`IoPermissionBitmap`]: x86_64::structures::port::IoPermissionBitmap
+#[derive(Debug)]
+pub struct IoPorts {
+    range: PortRange,
+}
+
+impl IoPorts {
+    pub fn new(range: PortRange) -> Self {
+        Self { range }
+    }
+
+    #[inline]
+    pub fn range(&self) -> PortRange {
+        self.range
+    }
+}
+
+impl KernelObject for IoPorts {
+    fn object_type(&self) -> ObjectType {
+        ObjectType::IoPorts
+    }
+}
@@ -0,0 +1,36 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// What a capability allows its holder to do with the object, derived capabilities
+    /// can only have fewer rights.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Rights: u8 {
+        /// Receive from endpoints and notifications, read state.
+        const READ = 1 << 0;
+        /// Send to endpoints, signal notifications, change state.
+        const WRITE = 1 << 1;
+        /// Pass capabilities along in IPC.
+        const GRANT = 1 << 2;
+    }
+}
+
+impl Rights {
+    pub const ALL: Self = Self::all();
+}
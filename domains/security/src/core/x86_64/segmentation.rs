@@ -51,7 +51,7 @@ impl Segment32 for CodeSegment {
         unsafe {
             asm!(
                 "push {sel}",
-                "lea {tmp}, [1f + rip]",
+                "lea {tmp}, [2f + rip]",
                 "push {tmp}",
                 "retfq",
                 "2:",
@@ -67,10 +67,107 @@ pub struct StackSegment;
 
 pub struct DataSegment;
 
+pub struct ExtraSegment;
+
 pub struct FSegment;
 
 pub struct GSegment;
 
+macro_rules! segment_register {
+    ($segment:ident, $register:literal) => {
+        impl Segment32 for $segment {
+            fn get_reg() -> SegmentSelector {
+                let result: u16;
+
+                unsafe {
+                    asm!(
+                        concat!("mov {0:x}, ", $register),
+                        out(reg) result,
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+
+                SegmentSelector(result)
+            }
+
+            /// Loading a selector also loads the base from the descriptor, which clears the
+            /// upper 32 bits. Write the base afterwards.
+            fn set_reg(sel: SegmentSelector) {
+                unsafe {
+                    asm!(
+                        concat!("mov ", $register, ", {0:x}"),
+                        in(reg) sel.0,
+                        options(nostack, preserves_flags)
+                    );
+                }
+            }
+        }
+    };
+}
+
+segment_register!(StackSegment, "ss");
+segment_register!(DataSegment, "ds");
+segment_register!(ExtraSegment, "es");
+segment_register!(FSegment, "fs");
+segment_register!(GSegment, "gs");
+
+impl Segment64 for FSegment {
+    /// IA32_FS_BASE
+    const BASE: Msr = Msr::new(0xC000_0100);
+
+    #[inline]
+    fn read_base() -> VirtualAddress {
+        VirtualAddress::new_truncate(unsafe { Self::BASE.read() })
+    }
+
+    #[inline]
+    fn write_base(base: VirtualAddress) {
+        unsafe { Self::BASE.write(base.as_u64()) }
+    }
+}
+
+impl Segment64 for GSegment {
+    /// IA32_GS_BASE
+    const BASE: Msr = Msr::new(0xC000_0101);
+
+    #[inline]
+    fn read_base() -> VirtualAddress {
+        VirtualAddress::new_truncate(unsafe { Self::BASE.read() })
+    }
+
+    /// The kernel keeps the per-CPU data of the executing core there, only the cores
+    /// domain should write it.
+    #[inline]
+    fn write_base(base: VirtualAddress) {
+        unsafe { Self::BASE.write(base.as_u64()) }
+    }
+}
+
+impl GSegment {
+    /// IA32_KERNEL_GS_BASE, exchanged with the GS base by `swapgs`.
+    pub const KERNEL_BASE: Msr = Msr::new(0xC000_0102);
+
+    #[inline]
+    pub fn read_kernel_base() -> VirtualAddress {
+        VirtualAddress::new_truncate(unsafe { Self::KERNEL_BASE.read() })
+    }
+
+    #[inline]
+    pub fn write_kernel_base(base: VirtualAddress) {
+        unsafe { Self::KERNEL_BASE.write(base.as_u64()) }
+    }
+
+    /// Exchanges the GS base with IA32_KERNEL_GS_BASE.
+    ///
+    /// # Safety
+    /// Must be executed exactly once on every switch between user and kernel mode,
+    /// otherwise the kernel runs with the GS base of user space.
+    #[inline]
+    pub unsafe fn swap() {
+        asm!("swapgs", options(nostack, preserves_flags));
+    }
+}
+
 impl SegmentSelector {
     pub const NULL: Self = Self::new(0, PLevel::Ring0);
 
@@ -195,7 +292,7 @@ impl Descriptor {
     pub unsafe fn tss_segment_unchecked(tss: *const TaskStateSegment) -> Descriptor {
         use self::DescriptorFlags as Flags;
 
-        let ptr = tss as u64;
+        let ptr = VirtualAddress::from_ptr(tss).as_u64();
 
         let mut low = Flags::PRESENT.bits();
         // base
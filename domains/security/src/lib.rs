@@ -14,6 +14,9 @@
  * You should have received a copy of the GNU General Public License
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+extern crate alloc;
+
+pub mod capability;
 pub mod core;
@@ -0,0 +1,71 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::sync::Arc;
+use security::capability::{KernelObject, ObjectType};
+use spin::Once;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::types::paging::frame::PhysFrame;
+
+static KERNEL: Once<Arc<AddressSpace>> = Once::new();
+
+/// Page tables a thread runs in, shared by all threads of a task.
+pub struct AddressSpace {
+    root: PhysFrame,
+    flags: Cr3Flags,
+}
+
+impl AddressSpace {
+    /// The level 4 table the space is loaded from.
+    #[inline]
+    pub fn root(&self) -> PhysFrame {
+        self.root
+    }
+
+    /// Loads the space into CR3, unless it is already active.
+    ///
+    /// # Safety
+    /// The space must map the running code and the current stack.
+    pub unsafe fn activate(&self) {
+        if Cr3::read() != (self.root, self.flags) {
+            Cr3::write(self.root, self.flags);
+        }
+    }
+}
+
+impl KernelObject for AddressSpace {
+    fn object_type(&self) -> ObjectType {
+        ObjectType::AddressSpace
+    }
+}
+
+/// Adopts the page tables set up by the bootloader as the kernel address space.
+pub(crate) fn init() {
+    KERNEL.call_once(|| {
+        let (root, flags) = Cr3::read();
+
+        Arc::new(AddressSpace { root, flags })
+    });
+}
+
+/// The address space of kernel threads.
+///
+/// # Panics
+/// If called before [`crate::init`].
+pub fn kernel() -> Arc<AddressSpace> {
+    KERNEL.get().expect("Threads are not initialised").clone()
+}
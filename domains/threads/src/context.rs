@@ -0,0 +1,159 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::arch::{asm, global_asm};
+use core::mem::size_of;
+
+use x86_64::structures::memory::VirtualAddress;
+
+/// Registers a suspended thread keeps on its own stack, in the order
+/// `threads_switch_context` pops them.
+///
+/// Only the callee saved registers are needed, the switch is an ordinary function call to
+/// the compiler. The FPU and SIMD state is switched separately by [`cores::fpu`].
+#[repr(C)]
+struct SwitchFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    rbx: u64,
+    rbp: u64,
+    rip: u64,
+}
+
+/// Number of registers a switch carries into the thread it resumes, see [`switch`].
+pub const TRANSFER_REGISTERS: usize = 8;
+
+/// Values handed from one thread to the next in registers during a switch.
+pub type Transfer = [u64; TRANSFER_REGISTERS];
+
+/// Register save area of a thread that is not running.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct Context {
+    /// Points to a [`SwitchFrame`] while the thread is suspended.
+    rsp: u64,
+}
+
+impl Context {
+    /// Prepares the stack below `stack_top` so that the first switch to the context calls
+    /// `entry(argument)`.
+    ///
+    /// # Safety
+    /// `stack_top` must be the 16 byte aligned top of a stack that is not in use.
+    pub unsafe fn new(
+        stack_top: VirtualAddress,
+        entry: extern "C" fn(usize) -> !,
+        argument: usize,
+    ) -> Self {
+        // Two empty slots end backtraces and keep the stack aligned for the call in
+        // `threads_entry_trampoline`.
+        let top = stack_top.as_u64() - 2 * size_of::<u64>() as u64;
+        let frame = (top - size_of::<SwitchFrame>() as u64) as *mut SwitchFrame;
+
+        let slots = top as *mut [u64; 2];
+        slots.write([0; 2]);
+
+        frame.write(SwitchFrame {
+            r15: 0,
+            r14: 0,
+            r13: argument as u64,
+            r12: entry as usize as u64,
+            rbx: 0,
+            rbp: 0,
+            rip: threads_entry_trampoline as unsafe extern "C" fn() -> ! as usize as u64,
+        });
+
+        Self { rsp: frame as u64 }
+    }
+}
+
+extern "C" {
+    fn threads_entry_trampoline() -> !;
+}
+
+// Takes the previous context in r12 and the next one in r13. The caller saved registers
+// pass through untouched: rax tells the resumed thread whether the others carry a
+// transfer.
+global_asm!(
+    ".global threads_switch_context",
+    "threads_switch_context:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [r12], rsp",
+    "mov rsp, [r13]",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    "",
+    // Reached through the `ret` above on the first switch to a new context.
+    ".global threads_entry_trampoline",
+    "threads_entry_trampoline:",
+    "mov rdi, r13",
+    "call r12",
+    "ud2",
+);
+
+/// Suspends the executing thread into `previous` and resumes `next`, handing `transfer`
+/// over in registers. Returns once a later switch resumes `previous`, with the transfer
+/// that switch handed over.
+///
+/// Only a thread suspended here receives a transfer, a new thread drops it.
+///
+/// # Safety
+/// Interrupts must be disabled and `next` must hold a suspended context, which nothing
+/// else resumes concurrently.
+#[inline]
+pub unsafe fn switch(
+    previous: *mut Context,
+    next: *const Context,
+    transfer: Option<Transfer>,
+) -> Option<Transfer> {
+    let (present, sent) = match transfer {
+        Some(transfer) => (1u64, transfer),
+        None => (0, [0; TRANSFER_REGISTERS]),
+    };
+    let mut received = [0; TRANSFER_REGISTERS];
+    let resumed: u64;
+
+    asm!(
+        "call threads_switch_context",
+        inout("r12") previous => _,
+        inout("r13") next => _,
+        inout("rax") present => resumed,
+        inout("rdi") sent[0] => received[0],
+        inout("rsi") sent[1] => received[1],
+        inout("rdx") sent[2] => received[2],
+        inout("rcx") sent[3] => received[3],
+        inout("r8") sent[4] => received[4],
+        inout("r9") sent[5] => received[5],
+        inout("r10") sent[6] => received[6],
+        inout("r11") sent[7] => received[7],
+        clobber_abi("C"),
+    );
+
+    (resumed != 0).then_some(received)
+}
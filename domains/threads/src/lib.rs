@@ -0,0 +1,62 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod address_space;
+mod context;
+pub mod thread;
+
+use cores::fpu;
+
+pub use context::{Transfer, TRANSFER_REGISTERS};
+pub use thread::{Affinity, Priority, Thread, ThreadId, ThreadState};
+
+/// Adopts the page tables of the bootloader as the kernel address space.
+///
+/// Has to run after [`cores::init`], every thread gets its own FPU state.
+pub fn init() {
+    address_space::init();
+}
+
+/// Suspends `previous`, which runs on the executing core, and resumes `next` in its
+/// address space and with its FPU state. `transfer` reaches `next` in registers.
+///
+/// Returns once a later switch resumes `previous`, with the transfer of that switch.
+///
+/// # Safety
+/// Interrupts must be disabled. `next` has to be suspended and must not be resumed on
+/// another core concurrently, both threads must stay alive until the switch finished.
+pub unsafe fn switch(
+    previous: &Thread,
+    next: &Thread,
+    transfer: Option<Transfer>,
+) -> Option<Transfer> {
+    next.address_space().activate();
+    fpu::switch_to(next.fpu());
+    context::switch(previous.context(), next.context(), transfer)
+}
+
+/// Attaches the FPU state of `thread`, the first thread of the executing core.
+///
+/// # Safety
+/// Interrupts must be disabled and `thread` must be running on the executing core.
+pub unsafe fn attach(thread: &Thread) {
+    fpu::switch_to(thread.fpu());
+}
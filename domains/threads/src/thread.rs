@@ -0,0 +1,475 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+use alloc::sync::Arc;
+use cores::fpu::FpuState;
+use cores::percpu::MAX_CORES;
+use kstructs::queue::Linked;
+use security::capability::{KernelObject, ObjectType};
+use x86_64::structures::memory::VirtualAddress;
+
+use crate::address_space::AddressSpace;
+use crate::context::Context;
+
+/// Size of the kernel stack of every thread.
+pub const KERNEL_STACK_SIZE: usize = 64 * 1024;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Unique identifier of a thread, never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn next() -> Self {
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    #[inline]
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ThreadState {
+    /// Waits in a run queue.
+    Ready,
+    /// Executes on a core.
+    Running,
+    /// Waits for an event, whoever blocked it makes it ready again.
+    Blocked,
+    /// Finished, the thread is freed once the last reference is dropped.
+    Exited,
+}
+
+/// Set in the state byte while a core runs the thread or still switches away from it.
+const ON_CPU: u8 = 1 << 7;
+
+impl ThreadState {
+    fn from_u8(value: u8) -> Self {
+        match value & !ON_CPU {
+            0 => Self::Ready,
+            1 => Self::Running,
+            2 => Self::Blocked,
+            _ => Self::Exited,
+        }
+    }
+}
+
+/// Scheduling priority, higher values run first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(u8);
+
+impl Priority {
+    /// Only the idle threads run at this priority.
+    pub const IDLE: Self = Self(0);
+    pub const MIN: Self = Self(1);
+    pub const DEFAULT: Self = Self(128);
+    pub const MAX: Self = Self(u8::MAX);
+
+    #[inline]
+    pub const fn new(value: u8) -> Self {
+        Self(value)
+    }
+
+    #[inline]
+    pub const fn get(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Set of cores a thread may run on, one bit per core index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Affinity(u64);
+
+impl Affinity {
+    pub const ALL: Self = Self(u64::MAX);
+
+    /// Only the core with the index `core`.
+    ///
+    /// # Panics
+    /// If `core` is not below [`MAX_CORES`].
+    pub const fn only(core: usize) -> Self {
+        assert!(core < MAX_CORES, "Core index out of range");
+
+        Self(1 << core)
+    }
+
+    #[inline]
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    #[inline]
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Adds the core with the index `core`.
+    #[inline]
+    pub const fn with(self, core: usize) -> Self {
+        Self(self.0 | Self::only(core).0)
+    }
+
+    #[inline]
+    pub const fn contains(&self, core: usize) -> bool {
+        core < MAX_CORES && self.0 & (1 << core) != 0
+    }
+}
+
+impl Default for Affinity {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// A kernel stack owned by a thread, freed together with it.
+struct KernelStack {
+    top: VirtualAddress,
+    size: usize,
+}
+
+impl KernelStack {
+    fn new(size: usize) -> Self {
+        // Page aligned, which satisfies the 16 bytes the System V ABI expects.
+        let top = pmm::mapping::map_stack(size as u64)
+            .unwrap_or_else(|error| panic!("Could not map a kernel stack: {:?}", error));
+
+        Self { top, size }
+    }
+
+    fn top(&self) -> VirtualAddress {
+        self.top
+    }
+}
+
+impl Drop for KernelStack {
+    /// Runs when the scheduler frees an exited thread on the switch path, with interrupts
+    /// disabled and possibly inside the tick. Every lock taken below `unmap_stack`, the mapper
+    /// and the frame allocator, therefore has to be held with interrupts disabled everywhere
+    /// else too, or a core preempted while holding one deadlocks on itself.
+    fn drop(&mut self) {
+        // SAFETY: Mapped in `new` with the same size, the thread is gone.
+        unsafe { pmm::mapping::unmap_stack(self.top, self.size as u64) };
+    }
+}
+
+/// Thread control block.
+pub struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    /// A [`ThreadState`], with [`ON_CPU`] on top.
+    state: AtomicU8,
+    priority: AtomicU8,
+    affinity: AtomicU64,
+    /// Ticks left of the current timeslice.
+    timeslice: AtomicU32,
+    /// Index of the core the thread ran on last.
+    last_core: AtomicU32,
+    /// Nanoseconds spent running, up to the last switch away.
+    runtime: AtomicU64,
+    /// How often the thread was switched to.
+    switches: AtomicU64,
+    /// Next thread in the run queue holding this one, see [`Linked`].
+    queue_link: AtomicPtr<Thread>,
+    /// Only touched by the scheduler, while the thread is switched from or to.
+    context: UnsafeCell<Context>,
+    /// `None` for threads adopted from the boot stack of a core.
+    stack: Option<KernelStack>,
+    address_space: Arc<AddressSpace>,
+    /// Attached to the core while the thread runs, see [`cores::fpu::switch_to`].
+    fpu: UnsafeCell<FpuState>,
+}
+
+// SAFETY: The cells are only accessed by the scheduler with interrupts disabled, on the
+// core the thread is switched on.
+unsafe impl Send for Thread {}
+unsafe impl Sync for Thread {}
+
+// SAFETY: Only the run queue holding the thread touches the link, under its lock.
+unsafe impl Linked for Thread {
+    #[inline]
+    fn link(&self) -> &AtomicPtr<Self> {
+        &self.queue_link
+    }
+}
+
+impl Thread {
+    /// Creates a ready thread with its own stack, which calls `entry(argument)` on its first
+    /// switch.
+    pub fn new(
+        name: &'static str,
+        priority: Priority,
+        address_space: Arc<AddressSpace>,
+        entry: extern "C" fn(usize) -> !,
+        argument: usize,
+    ) -> Self {
+        let stack = KernelStack::new(KERNEL_STACK_SIZE);
+
+        // SAFETY: The stack was just allocated.
+        let context = unsafe { Context::new(stack.top(), entry, argument) };
+
+        Self::with_context(
+            name,
+            ThreadState::Ready as u8,
+            priority,
+            context,
+            Some(stack),
+            address_space,
+        )
+    }
+
+    /// Wraps the code already running on the executing core, its context is saved on the
+    /// first switch away.
+    pub fn adopt(name: &'static str, address_space: Arc<AddressSpace>) -> Self {
+        Self::with_context(
+            name,
+            ThreadState::Running as u8 | ON_CPU,
+            Priority::DEFAULT,
+            Context::default(),
+            None,
+            address_space,
+        )
+    }
+
+    fn with_context(
+        name: &'static str,
+        state: u8,
+        priority: Priority,
+        context: Context,
+        stack: Option<KernelStack>,
+        address_space: Arc<AddressSpace>,
+    ) -> Self {
+        Self {
+            id: ThreadId::next(),
+            name,
+            state: AtomicU8::new(state),
+            priority: AtomicU8::new(priority.get()),
+            affinity: AtomicU64::new(Affinity::ALL.bits()),
+            timeslice: AtomicU32::new(0),
+            last_core: AtomicU32::new(0),
+            runtime: AtomicU64::new(0),
+            switches: AtomicU64::new(0),
+            queue_link: AtomicPtr::new(ptr::null_mut()),
+            context: UnsafeCell::new(context),
+            stack,
+            address_space,
+            fpu: UnsafeCell::new(FpuState::new()),
+        }
+    }
+
+    #[inline]
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    #[inline]
+    pub fn state(&self) -> ThreadState {
+        ThreadState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    #[inline]
+    pub fn priority(&self) -> Priority {
+        Priority(self.priority.load(Ordering::Relaxed))
+    }
+
+    /// Takes effect the next time the thread is queued.
+    #[inline]
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.store(priority.get(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn affinity(&self) -> Affinity {
+        Affinity(self.affinity.load(Ordering::Relaxed))
+    }
+
+    /// Takes effect the next time the thread is queued.
+    #[inline]
+    pub fn set_affinity(&self, affinity: Affinity) {
+        self.affinity.store(affinity.bits(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn address_space(&self) -> &Arc<AddressSpace> {
+        &self.address_space
+    }
+
+    /// Top of the kernel stack, `None` for adopted threads.
+    #[inline]
+    pub fn stack_top(&self) -> Option<VirtualAddress> {
+        self.stack.as_ref().map(KernelStack::top)
+    }
+
+    /// Nanoseconds the thread ran, up to its last switch away.
+    #[inline]
+    pub fn runtime(&self) -> u64 {
+        self.runtime.load(Ordering::Relaxed)
+    }
+
+    /// How often the thread was switched to.
+    #[inline]
+    pub fn switches(&self) -> u64 {
+        self.switches.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn last_core(&self) -> usize {
+        self.last_core.load(Ordering::Relaxed) as usize
+    }
+}
+
+/// State transitions and bookkeeping, for the scheduler.
+impl Thread {
+    /// Changes the state, keeping whether the thread is on a core.
+    pub fn set_state(&self, state: ThreadState) {
+        let _ = self
+            .state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |value| {
+                Some(value & ON_CPU | state as u8)
+            });
+    }
+
+    /// Marks the thread as running on the executing core `core`.
+    pub fn set_running(&self, core: usize) {
+        self.state
+            .store(ThreadState::Running as u8 | ON_CPU, Ordering::Release);
+        self.last_core.store(core as u32, Ordering::Relaxed);
+        self.switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Clears the mark of [`Thread::set_running`] once the context is saved and returns the
+    /// state the thread was left in.
+    pub fn leave_cpu(&self) -> ThreadState {
+        ThreadState::from_u8(self.state.fetch_and(!ON_CPU, Ordering::AcqRel))
+    }
+
+    /// Makes a blocked thread ready.
+    ///
+    /// Returns whether the caller has to queue the thread. A thread still switching away
+    /// from its core is queued by the scheduler once [`Thread::leave_cpu`] ran instead, so
+    /// it is never queued twice or before its context is saved.
+    pub fn wake(&self) -> bool {
+        let mut value = self.state.load(Ordering::Acquire);
+
+        loop {
+            if ThreadState::from_u8(value) != ThreadState::Blocked {
+                return false;
+            }
+
+            match self.state.compare_exchange_weak(
+                value,
+                value & ON_CPU | ThreadState::Ready as u8,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return value & ON_CPU == 0,
+                Err(current) => value = current,
+            }
+        }
+    }
+
+    /// Takes a blocked thread that is off all cores to run it right away, bypassing the
+    /// run queues. Returns false if the thread is not blocked or still on a core.
+    ///
+    /// The caller has to switch to the thread, starting with [`Thread::set_running`].
+    pub fn claim(&self) -> bool {
+        self.state
+            .compare_exchange(
+                ThreadState::Blocked as u8,
+                ThreadState::Running as u8 | ON_CPU,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+    }
+
+    #[inline]
+    pub fn timeslice(&self) -> u32 {
+        self.timeslice.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn set_timeslice(&self, ticks: u32) {
+        self.timeslice.store(ticks, Ordering::Relaxed);
+    }
+
+    /// Takes one tick off the timeslice, returns whether it is used up.
+    pub fn consume_tick(&self) -> bool {
+        let left = self.timeslice.load(Ordering::Relaxed).saturating_sub(1);
+        self.timeslice.store(left, Ordering::Relaxed);
+
+        left == 0
+    }
+
+    #[inline]
+    pub fn add_runtime(&self, nanos: u64) {
+        self.runtime.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn context(&self) -> *mut Context {
+        self.context.get()
+    }
+
+    #[inline]
+    pub(crate) fn fpu(&self) -> *mut FpuState {
+        self.fpu.get()
+    }
+}
+
+impl KernelObject for Thread {
+    fn object_type(&self) -> ObjectType {
+        ObjectType::Thread
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("state", &self.state())
+            .field("priority", &self.priority())
+            .field("affinity", &self.affinity())
+            .finish()
+    }
+}
@@ -15,34 +15,54 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use self::default::WRITER;
+use self::default::{Colors, Surface, WRITER};
 use core::fmt::{Arguments, Write};
-use exception::hcf;
-use limine::{Framebuffer, NonNullPtr};
+use limine::request::FramebufferRequest;
+use x86_64::op::interrupts;
 
 mod default;
 mod font;
 
-static FRAMEBUFFER_REQUEST: limine::request::FramebufferRequest =
-    limine::request::FramebufferRequest::new();
+static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
 
-pub fn init() -> &'static NonNullPtr<Framebuffer> {
-    let framebuffer: &NonNullPtr<Framebuffer>;
+/// Returns the first framebuffer handed over by the bootloader.
+///
+/// Without a framebuffer all output is dropped, the kernel keeps running.
+pub(crate) fn init() -> Option<Surface> {
+    let framebuffer = FRAMEBUFFER_REQUEST.get_response()?.framebuffers().next()?;
 
-    if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response().get() {
-        if framebuffer_response.framebuffer_count < 1 {
-            hcf();
-        }
-
-        // Get the first framebuffer's information.
-        framebuffer = &framebuffer_response.framebuffers()[0];
-    } else {
-        hcf();
-    }
-
-    framebuffer
+    Some(Surface {
+        address: framebuffer.addr() as usize,
+        width: framebuffer.width(),
+        height: framebuffer.height(),
+        pitch: framebuffer.pitch(),
+        bpp: framebuffer.bpp(),
+    })
 }
 
+/// Takes the writer with interrupts disabled, like [`crate::serial::_kprint`].
 pub fn _kprint(args: Arguments<'_>) {
-    let _ = WRITER.lock().write_fmt(args);
+    interrupts::without_interrupts(|| {
+        let _ = WRITER.lock().write_fmt(args);
+    });
+}
+
+/// Prints in red without waiting for the writer lock.
+///
+/// Meant for the panic handler: the panicking code might hold the lock itself, in which case
+/// `_kprint` would never return.
+pub fn _kprint_emergency(args: Arguments<'_>) {
+    let mut writer = match WRITER.try_lock() {
+        Some(writer) => writer,
+        None => {
+            // SAFETY: The owner is either this core, which will never release the lock, or
+            // another core which gets halted. Interleaved output is acceptable here.
+            unsafe { WRITER.force_unlock() };
+            WRITER.lock()
+        }
+    };
+
+    writer.fg.set(Colors::Red);
+    let _ = writer.write_fmt(args);
+    writer.fg.set(Colors::White);
 }
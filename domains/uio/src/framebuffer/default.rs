@@ -17,7 +17,6 @@
 
 use core::fmt::{Arguments, Write};
 use lazy_static::lazy_static;
-use limine::{Framebuffer, NonNullPtr};
 use spin::Mutex;
 
 use crate::framebuffer::font::{FONT, FONT_DIMENSIONS};
@@ -40,8 +39,20 @@ pub struct Pixel {
     b: u8,
 }
 
+/// The parts of the bootloader framebuffer the writer needs.
+///
+/// Limine's `Framebuffer` holds raw pointers and is `!Send`, which would make `WRITER` `!Sync`.
+/// The address is kept as an integer, the mapping it points to lives as long as the kernel.
+pub(crate) struct Surface {
+    pub(crate) address: usize,
+    pub(crate) width: u64,
+    pub(crate) height: u64,
+    pub(crate) pitch: u64,
+    pub(crate) bpp: u16,
+}
+
 pub struct FramebufferWriter {
-    framebuffer: &'static NonNullPtr<Framebuffer>,
+    framebuffer: Option<Surface>,
     row: u64,
     col: u64,
     pub fg: Pixel,
@@ -100,42 +111,39 @@ impl FramebufferWriter {
     }
 
     fn write(&mut self, char: char) {
+        let Some(framebuffer) = &self.framebuffer else {
+            return;
+        };
+
         match char {
             '\n' => self.new_line(),
             '\t' => self.tab(),
             _ => {
+                // The font only covers printable ASCII.
+                let char = if (' '..='\x7f').contains(&char) {
+                    char
+                } else {
+                    '?'
+                };
                 let offset = (char as u8 - 32) as usize * 16;
+                let address = framebuffer.address as *mut u8;
+                let bytes_per_pixel = (framebuffer.bpp / 8) as usize;
+                let pitch = framebuffer.pitch as usize;
 
                 for y in 0..16 {
                     for x in 0..8 {
                         let cx = self.col as usize + (8 - x);
                         let cy = self.row as usize + y;
 
-                        let ptr_offset = (cx * (self.framebuffer.bpp / 8) as usize
-                            + cy * self.framebuffer.pitch as usize)
-                            as usize;
-
-                        if FONT[y + offset as usize] >> x & 1 == 1 {
-                            unsafe {
-                                *(self
-                                    .framebuffer
-                                    .address
-                                    .as_ptr()
-                                    .unwrap()
-                                    .offset(ptr_offset as isize)
-                                    as *mut u32) = self.fg.as_bits();
-                            }
+                        let ptr_offset = cx * bytes_per_pixel + cy * pitch;
+
+                        let color = if FONT[y + offset] >> x & 1 == 1 {
+                            self.fg.as_bits()
                         } else {
-                            unsafe {
-                                *(self
-                                    .framebuffer
-                                    .address
-                                    .as_ptr()
-                                    .unwrap()
-                                    .offset(ptr_offset as isize)
-                                    as *mut u32) = self.bg.as_bits();
-                            }
-                        }
+                            self.bg.as_bits()
+                        };
+
+                        unsafe { *(address.add(ptr_offset) as *mut u32) = color }
                     }
                 }
 
@@ -148,6 +156,8 @@ impl FramebufferWriter {
     fn new_line(&mut self) {
         self.row += FONT_DIMENSIONS.1 as u64;
         self.col = 0;
+
+        self.scroll();
     }
 
     #[inline]
@@ -159,21 +169,42 @@ impl FramebufferWriter {
 
     #[inline]
     fn check_clear_row(&mut self) {
-        if self.col == self.framebuffer.pitch {
-            let _ = self.write_char('\n');
-        } else {
-            self.col += FONT_DIMENSIONS.0 as u64;
+        let Some(framebuffer) = &self.framebuffer else {
+            return;
+        };
+
+        self.col += FONT_DIMENSIONS.0 as u64;
+
+        // A glyph covers the pixels col + 1 ..= col + 8.
+        if self.col + FONT_DIMENSIONS.0 as u64 >= framebuffer.width {
+            self.new_line();
         }
+    }
 
-        if self.row == self.framebuffer.height {
-            let top_row_bytes = self.framebuffer.pitch as usize * FONT_DIMENSIONS.1 as usize;
+    /// Moves everything up by one text row once the cursor left the screen.
+    fn scroll(&mut self) {
+        let Some(framebuffer) = &self.framebuffer else {
+            return;
+        };
 
-            for offset in 0..top_row_bytes {
-                unsafe { *(self.framebuffer.address.as_ptr().unwrap().add(offset) as *mut u32) = 0 }
-            }
+        let row_height = FONT_DIMENSIONS.1 as u64;
 
-            self.row -= 1;
+        if self.row + row_height <= framebuffer.height {
+            return;
         }
+
+        let pitch = framebuffer.pitch as usize;
+        let row_bytes = pitch * row_height as usize;
+        let visible_bytes =
+            pitch * (framebuffer.height - framebuffer.height % row_height) as usize;
+        let address = framebuffer.address as *mut u8;
+
+        unsafe {
+            core::ptr::copy(address.add(row_bytes), address, visible_bytes - row_bytes);
+            core::ptr::write_bytes(address.add(visible_bytes - row_bytes), 0, row_bytes);
+        }
+
+        self.row -= row_height;
     }
 }
 
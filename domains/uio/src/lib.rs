@@ -19,11 +19,14 @@
 #![feature(allow_internal_unstable)]
 #[macro_use]
 pub mod framebuffer;
+pub mod serial;
+
+use core::fmt::Arguments;
 
 #[allow_internal_unstable(print_internals, format_args_nl)]
 #[macro_export]
 macro_rules! kprint {
-    ($($arg:tt)*) => ($crate::framebuffer::_kprint(format_args!($($arg)*)));
+    ($($arg:tt)*) => ($crate::_kprint(format_args!($($arg)*)));
 }
 
 #[allow_internal_unstable(print_internals, format_args_nl)]
@@ -31,6 +34,27 @@ macro_rules! kprint {
 macro_rules! kprintln {
     () => (kprint!("\n"));
     ($($arg:tt)*) => ({
-    $crate::framebuffer::_kprint(format_args_nl!($($arg)*));
+    $crate::_kprint(::core::format_args_nl!($($arg)*));
     })
 }
+
+/// Like `kprintln!`, but does not wait for other writers. Only use it when the system is going down.
+#[allow_internal_unstable(print_internals, format_args_nl)]
+#[macro_export]
+macro_rules! kprintln_emergency {
+    () => ($crate::_kprint_emergency(format_args!("\n")));
+    ($($arg:tt)*) => ({
+    $crate::_kprint_emergency(::core::format_args_nl!($($arg)*));
+    })
+}
+
+/// Writes to every console: the serial port first, it is the one that survives a broken framebuffer.
+pub fn _kprint(args: Arguments<'_>) {
+    serial::_kprint(args);
+    framebuffer::_kprint(args);
+}
+
+pub fn _kprint_emergency(args: Arguments<'_>) {
+    serial::_kprint_emergency(args);
+    framebuffer::_kprint_emergency(args);
+}
@@ -0,0 +1,220 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::fmt::{Arguments, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::op::interrupts;
+use x86_64::structures::port::{Port, PortRange, PortReadOnly, PortWriteOnly};
+
+/// I/O base of the first serial port.
+pub const COM1: u16 = 0x3F8;
+
+/// Baud rate divisor for 38400 baud, the UART clock runs at 115200 Hz.
+const DIVISOR: u16 = 3;
+
+/// Number of registers of a 16550.
+const REGISTER_COUNT: u16 = 8;
+
+const LINE_CONTROL_8N1: u8 = 0x03;
+const LINE_CONTROL_DLAB: u8 = 0x80;
+/// Enable and clear both FIFOs, interrupt at 14 bytes.
+const FIFO_ENABLE: u8 = 0xC7;
+/// DTR, RTS and OUT2.
+const MODEM_CONTROL_READY: u8 = 0x0B;
+/// RTS, OUT1, OUT2 and loopback.
+const MODEM_CONTROL_LOOPBACK: u8 = 0x1E;
+
+/// Interrupt when a byte was received.
+const INTERRUPT_DATA_AVAILABLE: u8 = 0x01;
+
+const LINE_STATUS_DATA_READY: u8 = 0x01;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 0x20;
+
+lazy_static! {
+    pub static ref SERIAL: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(COM1) });
+}
+
+/// Whether COM1 passed the loopback test, for [`try_receive`].
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// A 16550 compatible UART, polled.
+pub struct SerialPort {
+    /// Holds the low byte of the divisor while DLAB is set.
+    data: Port<u8>,
+    /// Holds the high byte of the divisor while DLAB is set.
+    interrupt_enable: Port<u8>,
+    fifo_control: PortWriteOnly<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: PortReadOnly<u8>,
+    present: bool,
+}
+
+impl SerialPort {
+    /// Programs the UART at `base` for 38400 baud 8N1.
+    ///
+    /// If the UART does not pass a loopback test, it is considered absent and all output is
+    /// dropped.
+    ///
+    /// # Safety
+    /// `base` has to be the I/O base of a 16550 compatible UART or unused.
+    pub unsafe fn new(base: u16) -> Self {
+        let registers = PortRange::new(base, REGISTER_COUNT);
+
+        let mut port = Self {
+            data: registers.port(0),
+            interrupt_enable: registers.port(1),
+            fifo_control: registers.port(2),
+            line_control: registers.port(3),
+            modem_control: registers.port(4),
+            line_status: registers.port(5),
+            present: false,
+        };
+
+        port.interrupt_enable.write(0x00);
+        port.line_control.write(LINE_CONTROL_DLAB);
+        port.data.write(DIVISOR as u8);
+        port.interrupt_enable.write((DIVISOR >> 8) as u8);
+        port.line_control.write(LINE_CONTROL_8N1);
+        port.fifo_control.write(FIFO_ENABLE);
+
+        // Whatever is sent in loopback mode has to come back.
+        port.modem_control.write(MODEM_CONTROL_LOOPBACK);
+        port.data.write(0xAE);
+
+        if port.data.read() == 0xAE {
+            port.present = true;
+            port.modem_control.write(MODEM_CONTROL_READY);
+        }
+
+        port
+    }
+
+    #[inline]
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Raises the UART's IRQ (4 for COM1) whenever a byte was received.
+    ///
+    /// # Safety
+    /// A handler for the IRQ has to be installed.
+    pub unsafe fn enable_receive_interrupt(&mut self) {
+        if self.present {
+            self.interrupt_enable.write(INTERRUPT_DATA_AVAILABLE);
+        }
+    }
+
+    /// Sends a byte, waiting until the transmitter can take it.
+    pub fn send(&mut self, byte: u8) {
+        if !self.present {
+            return;
+        }
+
+        unsafe {
+            while self.line_status.read() & LINE_STATUS_TRANSMIT_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+
+            self.data.write(byte);
+        }
+    }
+
+    /// Returns a received byte, if there is one.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if !self.present {
+            return None;
+        }
+
+        unsafe {
+            if self.line_status.read() & LINE_STATUS_DATA_READY == 0 {
+                return None;
+            }
+
+            Some(self.data.read())
+        }
+    }
+}
+
+impl Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            // Terminals expect CRLF.
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+
+            self.send(byte);
+        }
+
+        Ok(())
+    }
+}
+
+/// Programs COM1. Called first thing during boot so that the whole log reaches the serial port.
+pub fn init() {
+    lazy_static::initialize(&SERIAL);
+    PRESENT.store(SERIAL.lock().is_present(), Ordering::Release);
+}
+
+/// Returns a byte received on COM1, if there is one, without taking the [`SERIAL`] lock.
+///
+/// An interrupt handler has to drain the receive FIFO even while another core prints, or
+/// the edge triggered IRQ is not raised again. Only the line status and receive buffer are
+/// read, which does not disturb a sender holding the lock.
+pub fn try_receive() -> Option<u8> {
+    if !PRESENT.load(Ordering::Acquire) {
+        return None;
+    }
+
+    let mut line_status = PortReadOnly::<u8>::new(COM1 + 5);
+    let mut data = PortReadOnly::<u8>::new(COM1);
+
+    // SAFETY: DLAB is only set while `SerialPort::new` programs the divisor, before
+    // `PRESENT` is set.
+    unsafe {
+        if line_status.read() & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+
+        Some(data.read())
+    }
+}
+
+/// Interrupt handlers print too, an interrupt arriving while this core holds the lock would
+/// spin on it forever.
+pub fn _kprint(args: Arguments<'_>) {
+    interrupts::without_interrupts(|| {
+        let _ = SERIAL.lock().write_fmt(args);
+    });
+}
+
+/// Prints without waiting for the serial lock, see [`crate::framebuffer::_kprint_emergency`].
+pub fn _kprint_emergency(args: Arguments<'_>) {
+    let mut serial = match SERIAL.try_lock() {
+        Some(serial) => serial,
+        None => {
+            // SAFETY: See `framebuffer::_kprint_emergency`.
+            unsafe { SERIAL.force_unlock() };
+            SERIAL.lock()
+        }
+    };
+
+    let _ = serial.write_fmt(args);
+}
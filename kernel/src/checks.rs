@@ -0,0 +1,470 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use spin::Mutex;
+use uio::{kprint, kprintln};
+
+use crate::testing::{self, Outcome, Test};
+
+/// The checks run after boot in debug builds.
+pub const ALL: &[Test] = &[
+    Test {
+        name: "breakpoint",
+        run: breakpoint,
+    },
+    Test {
+        name: "page fault",
+        run: page_fault,
+    },
+    Test {
+        name: "clock",
+        run: clock,
+    },
+    Test {
+        name: "timer modes",
+        run: timer_modes,
+    },
+    Test {
+        name: "threads",
+        run: threads,
+    },
+    Test {
+        name: "ipc",
+        run: ipc,
+    },
+    Test {
+        name: "capabilities",
+        run: capabilities,
+    },
+    Test {
+        name: "notifications",
+        run: notifications,
+    },
+];
+
+/// Raises a breakpoint exception, which is reported and has to return.
+fn breakpoint() -> Outcome {
+    x86_64::op::interrupts::int3();
+
+    Ok(())
+}
+
+/// Writes to an unmapped page and lets a page fault handler map it, the write has to resume
+/// and land in the new page. Before it maps the page, the handler captures the report the
+/// default handler prints for a fault nobody resolves.
+fn page_fault() -> Outcome {
+    use x86_64::structures::memory::VirtualAddress;
+    use x86_64::types::paging::page::{Page, Size4KiB};
+    use x86_64::types::paging::table::PageTableFlags;
+    use x86_64::types::paging::PageFaultErrorCode;
+
+    // Right behind the largest heap, nothing else maps it.
+    const PROBE: u64 = heap::HEAP_START + heap::HEAP_MAX_SIZE;
+
+    static FAULTED: AtomicU64 = AtomicU64::new(0);
+    static REPORT: Mutex<String> = Mutex::new(String::new());
+
+    fn map_probe(address: VirtualAddress, error_code: PageFaultErrorCode) -> bool {
+        let page = Page::containing_address(address);
+
+        if page.start_address().as_u64() != PROBE
+            || error_code.contains(PageFaultErrorCode::CAUSED_BY_PROTECTION_VIOLATION)
+            || FAULTED.swap(address.as_u64(), Ordering::Relaxed) != 0
+        {
+            return false;
+        }
+
+        let _ = idt::describe_page_fault(&mut *REPORT.lock(), address, error_code);
+
+        pmm::mapping::map_page(page, PageTableFlags::PRESENT | PageTableFlags::WRITABLE).is_ok()
+    }
+
+    idt::set_page_fault_handler(map_probe);
+
+    let probe = (PROBE + 8) as *mut u64;
+
+    // SAFETY: The page is unmapped, the handler maps it when the write faults.
+    let value = unsafe {
+        probe.write_volatile(0xdead_beef);
+        probe.read_volatile()
+    };
+
+    let faulted = FAULTED.load(Ordering::Relaxed);
+
+    let page = Page::<Size4KiB>::containing_address(VirtualAddress::new(PROBE));
+    if let Ok(frame) = pmm::mapping::unmap(page) {
+        pmm::free_frame(frame);
+    }
+
+    idt::clear_page_fault_handler();
+
+    let report = core::mem::take(&mut *REPORT.lock());
+    kprint!("{}", report);
+
+    let expected = [
+        format!("Faulting address: {:#x}", probe as u64),
+        "Cause: Write in kernel mode caused by a non-present page".into(),
+        "Page table walk".into(),
+    ];
+
+    if faulted != probe as u64 {
+        Err(format!(
+            "Expected a fault at {:#x}, got {:#x}",
+            probe as u64, faulted
+        ))
+    } else if value != 0xdead_beef {
+        Err(format!("Write did not resume, read {:#x}", value))
+    } else if let Some(line) = expected.iter().find(|line| !report.contains(line.as_str())) {
+        Err(format!("Page fault report lacks \"{}\"", line))
+    } else {
+        Ok(())
+    }
+}
+
+/// Counts the ticks during the first second, they have to match the tick frequency.
+fn clock() -> Outcome {
+    use apic::clock::TICK_FREQUENCY;
+
+    static START: AtomicU64 = AtomicU64::new(0);
+    static COUNTED: AtomicU64 = AtomicU64::new(u64::MAX);
+
+    // Runs in the tick interrupt, the check prints.
+    fn record(_id: apic::clock::TimerId) {
+        let ticks = apic::clock::ticks() - START.load(Ordering::Relaxed);
+
+        COUNTED.store(ticks, Ordering::Release);
+    }
+
+    START.store(apic::clock::ticks(), Ordering::Relaxed);
+
+    let deadline = apic::clock::now() + apic::clock::NANOS_PER_SECOND;
+    apic::clock::add_timer(deadline, record)
+        .map_err(|error| format!("Failed to add timer: {:?}", error))?;
+
+    apic::clock::sleep_until(deadline);
+
+    while COUNTED.load(Ordering::Acquire) == u64::MAX {
+        scheduler::yield_now();
+    }
+
+    let counted = COUNTED.load(Ordering::Acquire);
+    kprintln!("Clock: {} ticks in 1 s", counted);
+
+    if counted.abs_diff(TICK_FREQUENCY) > TICK_FREQUENCY / 10 {
+        return Err(format!("Expected {} ticks", TICK_FREQUENCY));
+    }
+
+    Ok(())
+}
+
+/// Borrows the local APIC timer of this core from the tick and arms it on a spare vector,
+/// first in one-shot and then in TSC-deadline mode. Each has to raise the vector once, not
+/// before its deadline. The tick is restarted afterwards.
+fn timer_modes() -> Outcome {
+    use apic::clock::{NANOS_PER_SECOND, TICK_FREQUENCY};
+    use apic::timer;
+    use cores::features::Features;
+    use x86_64::op::rdtsc;
+
+    const DELAY: u64 = 10_000_000;
+
+    static FIRED: AtomicUsize = AtomicUsize::new(0);
+    static FIRED_AT: AtomicU64 = AtomicU64::new(0);
+
+    fn fired(_vector: u8, _stack_frame: &idt::InterruptStackFrame) {
+        FIRED_AT.store(rdtsc(), Ordering::Relaxed);
+        FIRED.fetch_add(1, Ordering::Release);
+        apic::end_of_interrupt();
+    }
+
+    let tsc_frequency = timer::calibration()
+        .ok_or("The APIC timer is not calibrated")?
+        .tsc_frequency;
+    let cycles =
+        |nanos: u64| (nanos as u128 * tsc_frequency as u128 / NANOS_PER_SECOND as u128) as u64;
+
+    // The tick is stopped while the timer is borrowed, so waiting goes by the TSC.
+    let wait = |count: usize, nanos: u64| {
+        let end = rdtsc() + cycles(nanos);
+
+        while FIRED.load(Ordering::Acquire) < count && rdtsc() < end {
+            core::hint::spin_loop();
+        }
+
+        FIRED.load(Ordering::Acquire)
+    };
+
+    let vector = idt::allocate(fired).map_err(|error| format!("No spare vector: {:?}", error))?;
+
+    let one_shot = || -> Outcome {
+        let start = rdtsc();
+        timer::start_one_shot(vector, DELAY)
+            .map_err(|error| format!("One-shot mode: {:?}", error))?;
+
+        // Waits past the first interrupt, a one-shot timer must not fire again. The timer
+        // frequency is calibrated, so it may be off by a few percent.
+        match wait(2, 5 * DELAY) {
+            1 if FIRED_AT.load(Ordering::Relaxed) - start >= cycles(DELAY) * 9 / 10 => Ok(()),
+            1 => Err("One-shot mode fired early".into()),
+            fired => Err(format!("One-shot mode fired {} times", fired)),
+        }
+    };
+
+    let tsc_deadline = || -> Outcome {
+        if !cores::features::current().has(Features::TSC_DEADLINE) {
+            kprintln!("Timer: TSC-deadline mode unsupported, skipped");
+            return Ok(());
+        }
+
+        let deadline = rdtsc() + cycles(DELAY);
+        timer::start_tsc_deadline(vector, deadline)
+            .map_err(|error| format!("TSC-deadline mode: {:?}", error))?;
+
+        match wait(2, 5 * DELAY) {
+            2 if FIRED_AT.load(Ordering::Relaxed) >= deadline => Ok(()),
+            2 => Err("TSC-deadline mode fired before the deadline".into()),
+            fired => Err(format!("TSC-deadline mode fired {} times", fired - 1)),
+        }
+    };
+
+    FIRED.store(0, Ordering::Relaxed);
+
+    let outcome = one_shot().and_then(|()| tsc_deadline());
+
+    timer::stop();
+    let restarted = timer::start_periodic(apic::TIMER_VECTOR, TICK_FREQUENCY);
+    let _ = idt::unregister(vector);
+
+    restarted.map_err(|error| format!("Could not restart the tick: {:?}", error))?;
+
+    outcome
+}
+
+/// Runs two threads on this core which yield to each other, they have to take turns.
+fn threads() -> Outcome {
+    const ROUNDS: usize = 3;
+
+    static TURN: AtomicUsize = AtomicUsize::new(0);
+
+    fn play(parity: usize) -> Outcome {
+        let name = scheduler::current().name();
+
+        for round in 0..ROUNDS {
+            if TURN.fetch_add(1, Ordering::Relaxed) % 2 != parity {
+                return Err(format!("{} ran out of turn in round {}", name, round));
+            }
+
+            kprintln!("{} {}", name, round);
+            scheduler::yield_now();
+        }
+
+        Ok(())
+    }
+
+    let ping = testing::spawn("ping", || play(0));
+    let pong = testing::spawn("pong", || play(1));
+
+    ping.join().and(pong.join())
+}
+
+/// Checks the zero and relative IPC timeouts and measures call round trips to a thread on
+/// this core. The calls fill all message registers, the answers have to carry them back.
+fn ipc() -> Outcome {
+    use ipc::{Endpoint, IpcError, Message, Timeout, MESSAGE_REGISTERS};
+
+    const ROUND_TRIPS: u64 = 1000;
+    const TIMEOUT_NS: u64 = 10_000_000;
+
+    static ECHO: Endpoint = Endpoint::new();
+    static QUIET: Endpoint = Endpoint::new();
+
+    // Answers every message with its label incremented, forever.
+    fn serve() -> Outcome {
+        let mut next = ECHO.receive(Timeout::Infinite);
+
+        while let Ok((message, reply)) = next {
+            let answer = Message::new(message.label() + 1, message.words());
+
+            next = match reply {
+                Some(reply) => ECHO.reply_wait(reply, answer, Timeout::Infinite),
+                None => ECHO.receive(Timeout::Infinite),
+            };
+        }
+
+        Err(format!("Echo server failed: {:?}", next.err()))
+    }
+
+    if !matches!(QUIET.receive(Timeout::Zero), Err(IpcError::Timeout)) {
+        return Err("Zero timeout did not fail at once".into());
+    }
+
+    let start = apic::clock::now();
+    let result = QUIET.receive(Timeout::Relative(TIMEOUT_NS));
+    let waited = apic::clock::now() - start;
+
+    if !matches!(result, Err(IpcError::Timeout)) || waited < TIMEOUT_NS {
+        return Err(format!("Relative timeout ended after {} ns", waited));
+    }
+
+    // Never joined, the server waits for the next call forever.
+    testing::spawn("echo", serve);
+
+    let start = x86_64::op::rdtsc();
+
+    for round in 0..ROUND_TRIPS {
+        let words: [u64; MESSAGE_REGISTERS] =
+            core::array::from_fn(|index| round << 8 | index as u64);
+
+        match ECHO.call(Message::new(round, &words), Timeout::Infinite) {
+            Ok(answer) if answer.label() == round + 1 && answer.words() == words => {}
+            other => return Err(format!("Call {} failed: {:?}", round, other)),
+        }
+    }
+
+    let cycles = x86_64::op::rdtsc() - start;
+
+    kprintln!(
+        "IPC: {} round trips, {} cycles each",
+        ROUND_TRIPS,
+        cycles / ROUND_TRIPS
+    );
+
+    Ok(())
+}
+
+/// Derives capabilities across two CSpaces, rights may only shrink, badges are set once and
+/// revoking has to reach every derived copy.
+fn capabilities() -> Outcome {
+    use security::capability::object::IoPorts;
+    use security::capability::{CSpace, CapError, Capability, Rights};
+    use x86_64::structures::port::PortRange;
+
+    fn exercise(root: &CSpace, task: &CSpace) -> Result<Outcome, CapError> {
+        let ports = Capability::new(
+            Arc::new(IoPorts::new(PortRange::new(0x3F8, 8))),
+            Rights::ALL,
+        );
+        let endpoint = Capability::new(Arc::new(ipc::Endpoint::new()), Rights::ALL);
+
+        root.insert(0, ports)?;
+        root.insert(1, endpoint)?;
+
+        root.derive(0, task, 0, Rights::READ)?;
+        task.copy(0, task, 1)?;
+        root.mint(1, task, 2, Rights::WRITE, 42)?;
+
+        if task.get(1)?.object_as::<IoPorts>(Rights::WRITE).is_some() {
+            return Ok(Err("Derived capability kept the write right".into()));
+        }
+
+        if task.mint(2, task, 3, Rights::ALL, 7) != Err(CapError::AlreadyBadged(42)) {
+            return Ok(Err("Badge changed twice".into()));
+        }
+
+        root.revoke(0)?;
+
+        if task.get(0).is_ok() || task.get(1).is_ok() || task.get(2).is_err() {
+            return Ok(Err("Revoke reached the wrong slots".into()));
+        }
+
+        Ok(Ok(()))
+    }
+
+    exercise(&CSpace::new(4), &CSpace::new(4))
+        .unwrap_or_else(|error| Err(format!("Capability operation failed: {:?}", error)))
+}
+
+/// Checks that signals accumulate in the word, that a bound thread gets a signal while it
+/// waits on an endpoint and that PIT interrupts arrive through an IRQ handler.
+fn notifications() -> Outcome {
+    use ipc::{Endpoint, Event, IrqHandler, Notification, Timeout};
+
+    const TIMEOUT_NS: u64 = 100_000_000;
+    const PIT_FREQUENCY: u64 = 100;
+    const INTERRUPTS: usize = 3;
+
+    static SERVER: Endpoint = Endpoint::new();
+
+    let notification = Arc::new(Notification::new());
+
+    notification.signal(0b01);
+    notification.signal(0b10);
+
+    if notification.poll() != Some(0b11) || notification.poll().is_some() {
+        return Err("Signals were not combined into one word".into());
+    }
+
+    let bound = notification.clone();
+    let driver = testing::spawn("driver", move || {
+        bound
+            .bind(&scheduler::current())
+            .map_err(|error| format!("Failed to bind: {:?}", error))?;
+
+        // A signal that came first is pending in the word and ends the wait at once.
+        let result = match SERVER.wait(Timeout::Relative(TIMEOUT_NS)) {
+            Ok(Event::Signal(0b100)) => Ok(()),
+            Ok(Event::Signal(word)) => Err(format!("Bound wait got {:#b}", word)),
+            Ok(Event::Message(..)) => Err("Bound wait got a message".into()),
+            Err(error) => Err(format!("Bound wait failed: {:?}", error)),
+        };
+
+        bound.unbind();
+        result
+    });
+    let signal = testing::spawn("signal", move || {
+        notification.signal(0b100);
+        Ok(())
+    });
+
+    driver.join()?;
+    signal.join()?;
+
+    let notification = Arc::new(Notification::new());
+    let handler = IrqHandler::new(legacy::pit::IRQ)
+        .map_err(|error| format!("No PIT IRQ handler: {:?}", error))?;
+
+    handler
+        .set_notification(notification.clone(), 1)
+        .map_err(|error| format!("Failed to unmask the PIT: {:?}", error))?;
+    legacy::pit::start_periodic(PIT_FREQUENCY)
+        .map_err(|error| format!("Failed to start the PIT: {:?}", error))?;
+
+    let mut received = 0;
+    while received < INTERRUPTS && notification.wait(Timeout::Relative(TIMEOUT_NS)).is_ok() {
+        received += 1;
+        let _ = handler.ack();
+    }
+
+    legacy::pit::stop();
+    handler.clear_notification();
+
+    if received < INTERRUPTS {
+        return Err(format!(
+            "{} of {} PIT interrupts through GSI {}",
+            received,
+            INTERRUPTS,
+            handler.gsi()
+        ));
+    }
+
+    Ok(())
+}
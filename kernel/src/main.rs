@@ -18,9 +18,24 @@
 #![no_std]
 #![no_main]
 
-use exception::hcf;
+// Provides the panic handler.
+extern crate exception;
 
-use uio::{kprint, kprintln};
+#[cfg(debug_assertions)]
+extern crate alloc;
+
+#[cfg(debug_assertions)]
+mod checks;
+#[cfg(debug_assertions)]
+mod testing;
+
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use uio::kprintln;
+
+#[global_allocator]
+static ALLOCATOR: heap::KernelAllocator = heap::KernelAllocator;
 
 #[no_mangle]
 unsafe extern "C" fn _start() -> ! {
@@ -29,6 +44,8 @@ unsafe extern "C" fn _start() -> ! {
 }
 
 fn _start_x86_64() -> ! {
+    uio::serial::init();
+
     kprintln!("Copyright (C) 2023 Florian Marrero Liestmann\n");
     kprintln!("Booting hadron...");
 
@@ -38,8 +55,108 @@ fn _start_x86_64() -> ! {
     kprintln!("Setting up IDT: ");
     idt::init();
 
+    kprintln!("Setting up PIC: ");
+    legacy::init();
+
+    kprintln!("Setting up PMM: ");
+    pmm::init();
+
+    kprintln!("Setting up heap: ");
+    heap::init();
+
+    kprintln!("Setting up ACPI: ");
+    acpi::init();
+
+    kprintln!("Setting up HPET: ");
+    hpet::init();
+
+    kprintln!("Setting up APIC: ");
+    apic::init();
+
+    kprintln!("Setting up SMP: ");
+    cores::init();
+
+    kprintln!("Setting up scheduler: ");
+    scheduler::init();
+
     #[cfg(debug_assertions)]
-    kprint!("Reached hcf()");
+    {
+        route_serial_input();
+        testing::run(checks::ALL);
+        kprintln!("Reached idle loop, echoing serial input.");
+    }
+
+    // The boot thread is done, this core continues with its idle thread and whatever was
+    // queued on it. The clock ticks on this core, so interrupts have to be on.
+    x86_64::op::interrupts::enable();
+    scheduler::exit()
+}
+
+/// Delivers the COM1 IRQ through the I/O APIC to this core, as an end to end interrupt check.
+#[cfg(debug_assertions)]
+fn route_serial_input() {
+    const COM1_IRQ: u8 = 4;
+
+    let vector = idt::allocate(echo_serial_input).expect("No free vector for COM1");
+    let gsi = match apic::route_irq(COM1_IRQ, vector, apic::local_apic().id()) {
+        Ok(gsi) => gsi,
+        Err(error) => {
+            kprintln!("COM1: Failed to route IRQ {}: {:?}", COM1_IRQ, error);
+            return;
+        }
+    };
+
+    // SAFETY: The handler was just installed.
+    unsafe { uio::serial::SERIAL.lock().enable_receive_interrupt() };
+
+    kprintln!(
+        "COM1: IRQ {} -> GSI {} -> vector {:#x}",
+        COM1_IRQ,
+        gsi,
+        vector
+    );
+}
+
+/// Size of [`ECHO_BYTES`].
+#[cfg(debug_assertions)]
+const ECHO_CAPACITY: usize = 256;
+
+/// Received bytes waiting to be echoed, for when another core holds the serial lock. Only
+/// [`echo_serial_input`] touches the ring, `ECHO_HEAD` and `ECHO_TAIL` count bytes taken
+/// from and put into it.
+#[cfg(debug_assertions)]
+static ECHO_BYTES: [AtomicU8; ECHO_CAPACITY] = [const { AtomicU8::new(0) }; ECHO_CAPACITY];
+#[cfg(debug_assertions)]
+static ECHO_HEAD: AtomicUsize = AtomicUsize::new(0);
+#[cfg(debug_assertions)]
+static ECHO_TAIL: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(debug_assertions)]
+fn echo_serial_input(_vector: u8, _stack_frame: &idt::InterruptStackFrame) {
+    // The FIFO has to be empty before the end of interrupt, the IRQ is edge triggered.
+    let mut tail = ECHO_TAIL.load(Ordering::Relaxed);
+
+    while let Some(byte) = uio::serial::try_receive() {
+        // Input beyond the capacity is dropped.
+        if tail.wrapping_sub(ECHO_HEAD.load(Ordering::Relaxed)) < ECHO_CAPACITY {
+            ECHO_BYTES[tail % ECHO_CAPACITY].store(byte, Ordering::Relaxed);
+            tail = tail.wrapping_add(1);
+        }
+    }
+
+    ECHO_TAIL.store(tail, Ordering::Relaxed);
+
+    // Another core might be printing, never wait for the lock here.
+    if let Some(mut serial) = uio::serial::SERIAL.try_lock() {
+        let mut head = ECHO_HEAD.load(Ordering::Relaxed);
+
+        while head != tail {
+            serial.send(ECHO_BYTES[head % ECHO_CAPACITY].load(Ordering::Relaxed));
+            head = head.wrapping_add(1);
+        }
+
+        ECHO_HEAD.store(head, Ordering::Relaxed);
+    }
 
-    hcf()
+    apic::end_of_interrupt();
 }
@@ -0,0 +1,110 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use spin::Mutex;
+use uio::kprintln;
+use x86_64::structures::port::Port;
+
+/// Result of a check, the error describes what went wrong.
+pub type Outcome = Result<(), String>;
+
+/// A check run by [`run`].
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn() -> Outcome,
+}
+
+/// I/O port of the QEMU `isa-debug-exit` device, see the `test` target of the Makefile.
+const EXIT_PORT: u16 = 0xF4;
+
+/// Values written to [`EXIT_PORT`]. QEMU exits with `(value << 1) | 1`, so they can not
+/// be confused with QEMU's own exit codes.
+#[derive(Clone, Copy)]
+#[repr(u32)]
+enum ExitCode {
+    Success = 0x10,
+    Failure = 0x11,
+}
+
+/// A thread started by [`spawn`], the check waits for it with [`Helper::join`].
+pub struct Helper(Arc<Mutex<Option<Outcome>>>);
+
+impl Helper {
+    /// Yields until the helper has returned, it has to be on this core.
+    pub fn join(self) -> Outcome {
+        loop {
+            if let Some(outcome) = self.0.lock().take() {
+                return outcome;
+            }
+
+            scheduler::yield_now();
+        }
+    }
+}
+
+/// Runs `f` in a thread on this core at the default priority, next to the check.
+pub fn spawn<F>(name: &'static str, f: F) -> Helper
+where
+    F: FnOnce() -> Outcome + Send + 'static,
+{
+    let outcome = Arc::new(Mutex::new(None));
+    let result = outcome.clone();
+
+    scheduler::spawn_with(name, scheduler::Priority::DEFAULT, here(), move || {
+        *result.lock() = Some(f());
+    });
+
+    Helper(outcome)
+}
+
+/// Runs `tests` one after another in a thread on this core and reports the result through
+/// the `isa-debug-exit` device. Without the device the kernel keeps running.
+pub fn run(tests: &'static [Test]) {
+    scheduler::spawn_with("tests", scheduler::Priority::DEFAULT, here(), move || {
+        let mut failed = 0;
+
+        for test in tests {
+            match (test.run)() {
+                Ok(()) => kprintln!("Test {}: ok", test.name),
+                Err(error) => {
+                    kprintln!("Test {}: FAILED: {}", test.name, error);
+                    failed += 1;
+                }
+            }
+        }
+
+        kprintln!("Tests: {} passed, {} failed", tests.len() - failed, failed);
+
+        exit_qemu(if failed == 0 {
+            ExitCode::Success
+        } else {
+            ExitCode::Failure
+        });
+    });
+}
+
+fn here() -> scheduler::Affinity {
+    scheduler::Affinity::only(cores::current().index() as usize)
+}
+
+fn exit_qemu(code: ExitCode) {
+    // SAFETY: Nothing else uses the port, writes are ignored without the device.
+    unsafe { Port::<u32>::new(EXIT_PORT).write(code as u32) };
+}
@@ -0,0 +1,124 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// Function pointer types an [`AtomicFn`] can hold.
+///
+/// # Safety
+/// Implementors have to be function pointers, which are never null and have the size of a
+/// data pointer.
+pub unsafe trait FnPtr: Copy {}
+
+macro_rules! fn_ptr {
+    ($($argument:ident),*) => {
+        // SAFETY: A function pointer.
+        unsafe impl<R, $($argument),*> FnPtr for fn($($argument),*) -> R {}
+    };
+}
+
+fn_ptr!();
+fn_ptr!(A);
+fn_ptr!(A, B);
+fn_ptr!(A, B, C);
+
+/// A function pointer that is registered once and called from any core, for example a hook
+/// an interrupt handler calls into.
+pub struct AtomicFn<F: FnPtr> {
+    function: AtomicPtr<()>,
+    kind: PhantomData<F>,
+}
+
+impl<F: FnPtr> AtomicFn<F> {
+    /// Creates an empty slot.
+    pub const fn new() -> Self {
+        Self {
+            function: AtomicPtr::new(ptr::null_mut()),
+            kind: PhantomData,
+        }
+    }
+
+    /// Registers `function`, replacing the one registered before.
+    pub fn store(&self, function: F) {
+        // SAFETY: `F` is a function pointer, so it fits into a pointer.
+        let function: *mut () = unsafe { mem::transmute_copy(&function) };
+
+        self.function.store(function, Ordering::Release);
+    }
+
+    /// Removes the registered function, the slot is empty again.
+    pub fn clear(&self) {
+        self.function.store(ptr::null_mut(), Ordering::Release);
+    }
+
+    /// Returns the registered function, `None` if there is none yet.
+    pub fn load(&self) -> Option<F> {
+        let function = self.function.load(Ordering::Acquire);
+
+        // SAFETY: Only `store` writes a pointer other than null, always one made from an `F`.
+        (!function.is_null()).then(|| unsafe { mem::transmute_copy(&function) })
+    }
+}
+
+impl<F: FnPtr> Default for AtomicFn<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn double(value: u64) -> u64 {
+        value * 2
+    }
+
+    fn triple(value: u64) -> u64 {
+        value * 3
+    }
+
+    #[test]
+    fn empty_until_stored() {
+        let slot = AtomicFn::<fn(u64) -> u64>::new();
+        assert!(slot.load().is_none());
+
+        slot.store(double);
+        assert_eq!(slot.load().map(|function| function(4)), Some(8));
+    }
+
+    #[test]
+    fn store_replaces() {
+        let slot = AtomicFn::<fn(u64) -> u64>::new();
+
+        slot.store(double);
+        slot.store(triple);
+        assert_eq!(slot.load().map(|function| function(4)), Some(12));
+    }
+
+    #[test]
+    fn clear_empties() {
+        let slot = AtomicFn::<fn(u64) -> u64>::new();
+
+        slot.store(double);
+        slot.clear();
+        assert!(slot.load().is_none());
+    }
+}
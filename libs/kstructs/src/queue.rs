@@ -0,0 +1,259 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use alloc::sync::Arc;
+
+/// Values that carry the link of the [`LinkedQueue`] holding them, so queuing never
+/// allocates.
+///
+/// # Safety
+/// `link` has to return the same pointer for the lifetime of the value, and nothing but
+/// the queue holding the value may touch it.
+pub unsafe trait Linked: Sized {
+    fn link(&self) -> &AtomicPtr<Self>;
+}
+
+/// A FIFO linked through its entries, holding one reference to each.
+///
+/// Every entry is in at most one queue at a time, callers serialise access to the queue.
+pub struct LinkedQueue<T: Linked> {
+    head: *const T,
+    tail: *const T,
+}
+
+// SAFETY: The queued values are `Sync` and only reached through the queue.
+unsafe impl<T: Linked + Send + Sync> Send for LinkedQueue<T> {}
+
+impl<T: Linked> LinkedQueue<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null(),
+            tail: ptr::null(),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Queues `value` behind the values already there. It must not be in any queue.
+    pub fn push_back(&mut self, value: Arc<T>) {
+        let value = Arc::into_raw(value);
+
+        // SAFETY: The queue holds a reference to every value linked into it.
+        unsafe {
+            (*value).link().store(ptr::null_mut(), Ordering::Relaxed);
+
+            match self.tail.as_ref() {
+                Some(tail) => tail.link().store(value.cast_mut(), Ordering::Relaxed),
+                None => self.head = value,
+            }
+        }
+
+        self.tail = value;
+    }
+
+    /// Queues `value` ahead of the values already there. It must not be in any queue.
+    pub fn push_front(&mut self, value: Arc<T>) {
+        let value = Arc::into_raw(value);
+
+        // SAFETY: The reference was just moved into the queue.
+        unsafe {
+            (*value)
+                .link()
+                .store(self.head.cast_mut(), Ordering::Relaxed)
+        };
+
+        if self.tail.is_null() {
+            self.tail = value;
+        }
+
+        self.head = value;
+    }
+
+    /// Removes the first value.
+    #[inline]
+    pub fn pop(&mut self) -> Option<Arc<T>> {
+        self.remove(|_| true)
+    }
+
+    /// Unlinks the first value `accept` returns true for and hands its reference back.
+    pub fn remove(&mut self, accept: impl Fn(&T) -> bool) -> Option<Arc<T>> {
+        let mut previous: *const T = ptr::null();
+        let mut current = self.head;
+
+        // SAFETY: The queue holds a reference to every value linked into it.
+        unsafe {
+            while let Some(value) = current.as_ref() {
+                let next = value.link().load(Ordering::Relaxed);
+
+                if accept(value) {
+                    match previous.as_ref() {
+                        Some(previous) => previous.link().store(next, Ordering::Relaxed),
+                        None => self.head = next,
+                    }
+
+                    if self.tail == current {
+                        self.tail = previous;
+                    }
+
+                    return Some(Arc::from_raw(current));
+                }
+
+                previous = current;
+                current = next;
+            }
+        }
+
+        None
+    }
+}
+
+impl<T: Linked> Default for LinkedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Linked> Drop for LinkedQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec::Vec;
+
+    struct Entry {
+        id: u32,
+        link: AtomicPtr<Entry>,
+    }
+
+    // SAFETY: Only the queue touches `link`.
+    unsafe impl Linked for Entry {
+        fn link(&self) -> &AtomicPtr<Self> {
+            &self.link
+        }
+    }
+
+    fn entry(id: u32) -> Arc<Entry> {
+        Arc::new(Entry {
+            id,
+            link: AtomicPtr::new(ptr::null_mut()),
+        })
+    }
+
+    fn drain(queue: &mut LinkedQueue<Entry>) -> Vec<u32> {
+        let mut ids = Vec::new();
+
+        while let Some(entry) = queue.pop() {
+            ids.push(entry.id);
+        }
+
+        assert!(queue.is_empty());
+
+        ids
+    }
+
+    #[test]
+    fn push_back_and_front_keep_order() {
+        let mut queue = LinkedQueue::new();
+        assert!(queue.pop().is_none());
+
+        queue.push_back(entry(2));
+        queue.push_back(entry(3));
+        queue.push_front(entry(1));
+        queue.push_front(entry(0));
+        queue.push_back(entry(4));
+
+        assert_eq!(drain(&mut queue), [0, 1, 2, 3, 4]);
+
+        // The tail is reset once the queue runs empty.
+        queue.push_front(entry(5));
+        queue.push_back(entry(6));
+        assert_eq!(drain(&mut queue), [5, 6]);
+    }
+
+    #[test]
+    fn remove_middle() {
+        let mut queue = LinkedQueue::new();
+        (0..3).for_each(|id| queue.push_back(entry(id)));
+
+        assert_eq!(
+            queue.remove(|entry| entry.id == 1).map(|entry| entry.id),
+            Some(1)
+        );
+
+        queue.push_back(entry(3));
+        assert_eq!(drain(&mut queue), [0, 2, 3]);
+    }
+
+    #[test]
+    fn remove_tail_moves_the_tail_back() {
+        let mut queue = LinkedQueue::new();
+        (0..3).for_each(|id| queue.push_back(entry(id)));
+
+        assert_eq!(
+            queue.remove(|entry| entry.id == 2).map(|entry| entry.id),
+            Some(2)
+        );
+
+        // Would be lost if the tail still pointed at the removed entry.
+        queue.push_back(entry(3));
+        assert_eq!(drain(&mut queue), [0, 1, 3]);
+    }
+
+    #[test]
+    fn steal_takes_the_first_accepted_entry() {
+        let mut queue = LinkedQueue::new();
+        (0..5).for_each(|id| queue.push_back(entry(id)));
+
+        // Like a core stealing the first thread allowed to run on it.
+        let odd = |entry: &Entry| entry.id % 2 == 1;
+        assert_eq!(queue.remove(odd).map(|entry| entry.id), Some(1));
+        assert_eq!(queue.remove(odd).map(|entry| entry.id), Some(3));
+        assert!(queue.remove(odd).is_none());
+
+        assert_eq!(drain(&mut queue), [0, 2, 4]);
+    }
+
+    #[test]
+    fn references_are_held_until_removed() {
+        let first = entry(0);
+        let second = entry(1);
+
+        let mut queue = LinkedQueue::new();
+        queue.push_back(first.clone());
+        queue.push_back(second.clone());
+        assert_eq!(Arc::strong_count(&first), 2);
+
+        let removed = queue.pop().unwrap();
+        assert!(Arc::ptr_eq(&removed, &first));
+        drop(removed);
+        assert_eq!(Arc::strong_count(&first), 1);
+
+        drop(queue);
+        assert_eq!(Arc::strong_count(&second), 1);
+    }
+}
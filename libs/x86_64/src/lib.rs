@@ -14,7 +14,7 @@
  * You should have received a copy of the GNU General Public License
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 pub mod op;
 pub mod registers;
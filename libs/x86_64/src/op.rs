@@ -15,7 +15,12 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+pub mod fpu;
 pub mod interrupts;
+pub mod port;
+pub mod tlb;
+
+use core::arch::asm;
 
 use crate::registers::Msr;
 
@@ -28,3 +33,16 @@ pub unsafe fn wrmsr(msr: u32, value: u64) {
 pub fn rdmsr(msr: u32) -> u64 {
     unsafe { Msr::new(msr).read() }
 }
+
+/// Reads the time stamp counter.
+#[inline]
+pub fn rdtsc() -> u64 {
+    let high: u32;
+    let low: u32;
+
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+
+    ((high as u64) << 32) | (low as u64)
+}
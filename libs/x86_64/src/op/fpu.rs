@@ -0,0 +1,97 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::arch::asm;
+
+/// Clears CR0.TS, so FPU and SIMD instructions no longer raise #NM.
+#[inline]
+pub fn clts() {
+    unsafe {
+        asm!("clts", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Resets the x87 FPU to its initial state.
+#[inline]
+pub fn fninit() {
+    unsafe {
+        asm!("fninit", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Saves the x87 and SSE state into the 512 byte area at `area`.
+///
+/// # Safety
+/// `area` has to be valid for 512 bytes and 16 byte aligned.
+#[inline]
+pub unsafe fn fxsave(area: *mut u8) {
+    asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags));
+}
+
+/// # Safety
+/// `area` has to be valid for 512 bytes, 16 byte aligned and hold a valid FXSAVE image.
+#[inline]
+pub unsafe fn fxrstor(area: *const u8) {
+    asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags, readonly));
+}
+
+macro_rules! xsave_instruction {
+    ($(#[$attr:meta])* $name:ident, $mnemonic:literal) => {
+        $(#[$attr])*
+        ///
+        /// # Safety
+        /// CR4.OSXSAVE must be set and `area` has to be 64 byte aligned and large enough for
+        /// the components in XCR0 selected by `mask`, as reported by CPUID leaf 0xD.
+        #[inline]
+        pub unsafe fn $name(area: *mut u8, mask: u64) {
+            asm!(
+                concat!($mnemonic, " [{}]"),
+                in(reg) area,
+                in("eax") mask as u32,
+                in("edx") (mask >> 32) as u32,
+                options(nostack, preserves_flags)
+            );
+        }
+    };
+}
+
+xsave_instruction!(
+    /// Saves the state components selected by `mask` into `area`.
+    xsave,
+    "xsave64"
+);
+xsave_instruction!(
+    /// Like [`xsave`], but skips components unmodified since the last restore.
+    xsaveopt,
+    "xsaveopt64"
+);
+xsave_instruction!(
+    /// Like [`xsaveopt`], in the compacted format and including supervisor components.
+    xsaves,
+    "xsaves64"
+);
+xsave_instruction!(
+    /// Restores the state components selected by `mask` from `area`, components missing
+    /// in the image are put into their initial state.
+    xrstor,
+    "xrstor64"
+);
+xsave_instruction!(
+    /// Restores an image written by [`xsaves`].
+    xrstors,
+    "xrstors64"
+);
@@ -17,6 +17,8 @@
 
 use core::arch::asm;
 
+use crate::registers::rflags::{self, RFlags};
+
 pub fn enable() {
     unsafe {
         asm!("sti", options(nomem, nostack));
@@ -28,3 +30,54 @@ pub fn disable() {
         asm!("cli", options(nomem, nostack));
     }
 }
+
+/// Returns whether maskable interrupts are enabled on this core.
+#[inline]
+pub fn are_enabled() -> bool {
+    rflags::read().contains(RFlags::INTERRUPT_FLAG)
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous state afterwards.
+///
+/// Use this around locks that are also taken by interrupt handlers.
+#[inline]
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = are_enabled();
+
+    if enabled {
+        disable();
+    }
+
+    let result = f();
+
+    if enabled {
+        enable();
+    }
+
+    result
+}
+
+/// Halts the core until the next interrupt arrives.
+#[inline]
+pub fn hlt() {
+    unsafe {
+        asm!("hlt", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Enables interrupts and halts. `sti` only takes effect after the next instruction, so no
+/// interrupt can slip in between a check with interrupts disabled and the halt.
+#[inline]
+pub fn enable_and_hlt() {
+    unsafe {
+        asm!("sti; hlt", options(nomem, nostack));
+    }
+}
+
+/// Raises a breakpoint exception.
+#[inline]
+pub fn int3() {
+    unsafe {
+        asm!("int3", options(nomem, nostack));
+    }
+}
@@ -0,0 +1,174 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::arch::asm;
+
+/// Reads a byte from `port`.
+///
+/// # Safety
+/// Reading a port can have side effects on the device behind it.
+#[inline]
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+
+    asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+
+    value
+}
+
+/// Writes a byte to `port`.
+///
+/// # Safety
+/// Writing a port can have arbitrary side effects on the device behind it.
+#[inline]
+pub unsafe fn outb(port: u16, value: u8) {
+    asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+}
+
+/// Reads a word from `port`.
+///
+/// # Safety
+/// See [`inb`].
+#[inline]
+pub unsafe fn inw(port: u16) -> u16 {
+    let value: u16;
+
+    asm!("in ax, dx", out("ax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+
+    value
+}
+
+/// Writes a word to `port`.
+///
+/// # Safety
+/// See [`outb`].
+#[inline]
+pub unsafe fn outw(port: u16, value: u16) {
+    asm!("out dx, ax", in("dx") port, in("ax") value, options(nomem, nostack, preserves_flags));
+}
+
+/// Reads a double word from `port`.
+///
+/// # Safety
+/// See [`inb`].
+#[inline]
+pub unsafe fn inl(port: u16) -> u32 {
+    let value: u32;
+
+    asm!("in eax, dx", out("eax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+
+    value
+}
+
+/// Writes a double word to `port`.
+///
+/// # Safety
+/// See [`outb`].
+#[inline]
+pub unsafe fn outl(port: u16, value: u32) {
+    asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
+}
+
+/// Reads `buffer.len()` bytes from `port` into `buffer`.
+///
+/// # Safety
+/// See [`inb`].
+#[inline]
+pub unsafe fn insb(port: u16, buffer: &mut [u8]) {
+    asm!(
+        "rep insb",
+        in("dx") port,
+        inout("rdi") buffer.as_mut_ptr() => _,
+        inout("rcx") buffer.len() => _,
+        options(nostack, preserves_flags),
+    );
+}
+
+/// Writes all bytes of `buffer` to `port`.
+///
+/// # Safety
+/// See [`outb`].
+#[inline]
+pub unsafe fn outsb(port: u16, buffer: &[u8]) {
+    asm!(
+        "rep outsb",
+        in("dx") port,
+        inout("rsi") buffer.as_ptr() => _,
+        inout("rcx") buffer.len() => _,
+        options(nostack, preserves_flags, readonly),
+    );
+}
+
+/// Reads `buffer.len()` words from `port` into `buffer`.
+///
+/// # Safety
+/// See [`inb`].
+#[inline]
+pub unsafe fn insw(port: u16, buffer: &mut [u16]) {
+    asm!(
+        "rep insw",
+        in("dx") port,
+        inout("rdi") buffer.as_mut_ptr() => _,
+        inout("rcx") buffer.len() => _,
+        options(nostack, preserves_flags),
+    );
+}
+
+/// Writes all words of `buffer` to `port`.
+///
+/// # Safety
+/// See [`outb`].
+#[inline]
+pub unsafe fn outsw(port: u16, buffer: &[u16]) {
+    asm!(
+        "rep outsw",
+        in("dx") port,
+        inout("rsi") buffer.as_ptr() => _,
+        inout("rcx") buffer.len() => _,
+        options(nostack, preserves_flags, readonly),
+    );
+}
+
+/// Reads `buffer.len()` double words from `port` into `buffer`.
+///
+/// # Safety
+/// See [`inb`].
+#[inline]
+pub unsafe fn insl(port: u16, buffer: &mut [u32]) {
+    asm!(
+        "rep insd",
+        in("dx") port,
+        inout("rdi") buffer.as_mut_ptr() => _,
+        inout("rcx") buffer.len() => _,
+        options(nostack, preserves_flags),
+    );
+}
+
+/// Writes all double words of `buffer` to `port`.
+///
+/// # Safety
+/// See [`outb`].
+#[inline]
+pub unsafe fn outsl(port: u16, buffer: &[u32]) {
+    asm!(
+        "rep outsd",
+        in("dx") port,
+        inout("rsi") buffer.as_ptr() => _,
+        inout("rcx") buffer.len() => _,
+        options(nostack, preserves_flags, readonly),
+    );
+}
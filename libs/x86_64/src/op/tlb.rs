@@ -0,0 +1,41 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::arch::asm;
+
+use crate::structures::memory::VirtualAddress;
+
+/// Invalidates the TLB entry for the page containing `address` on the current core.
+#[inline]
+pub fn flush(address: VirtualAddress) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) address.as_u64(), options(nostack, preserves_flags));
+    }
+}
+
+/// Invalidates all non-global TLB entries on the current core by reloading CR3.
+#[inline]
+pub fn flush_all() {
+    unsafe {
+        asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags)
+        );
+    }
+}
@@ -15,7 +15,9 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+pub mod control;
 pub mod rflags;
+pub mod xcontrol;
 
 use core::arch::asm;
 
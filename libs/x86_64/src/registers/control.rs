@@ -0,0 +1,309 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use bitflags::bitflags;
+use core::arch::asm;
+
+use super::Msr;
+use crate::structures::memory::{PhysicalAddress, VirtualAddress};
+use crate::types::paging::frame::PhysFrame;
+
+bitflags! {
+    /// Flags of CR0, controlling the operating mode of the processor.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+    #[repr(transparent)]
+    pub struct Cr0Flags: u64 {
+        /// Enables protected mode.
+        const PROTECTED_MODE_ENABLE = 1;
+        /// Makes WAIT/FWAIT raise #NM when TS is set.
+        const MONITOR_COPROCESSOR = 1 << 1;
+        /// No x87 FPU present, x87 instructions raise #NM.
+        const EMULATE_COPROCESSOR = 1 << 2;
+        /// Set on a task switch. x87, MMX and SSE instructions raise #NM while it is set,
+        /// which allows saving the FPU state lazily.
+        const TASK_SWITCHED = 1 << 3;
+        /// Indicates support of 387 instructions. Always set on modern processors.
+        const EXTENSION_TYPE = 1 << 4;
+        /// Reports x87 errors through #MF instead of the legacy external mechanism.
+        const NUMERIC_ERROR = 1 << 5;
+        /// Write protection of read-only pages applies to ring 0 as well.
+        const WRITE_PROTECT = 1 << 16;
+        /// Enables alignment checking for ring 3 together with RFLAGS.AC.
+        const ALIGNMENT_MASK = 1 << 18;
+        /// Disables write-through caching.
+        const NOT_WRITE_THROUGH = 1 << 29;
+        /// Disables the memory caches.
+        const CACHE_DISABLE = 1 << 30;
+        /// Enables paging.
+        const PAGING = 1 << 31;
+    }
+}
+
+bitflags! {
+    /// Flags of CR4, enabling architectural extensions.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+    #[repr(transparent)]
+    pub struct Cr4Flags: u64 {
+        /// Virtual-8086 mode extensions.
+        const VIRTUAL_8086_MODE_EXTENSIONS = 1;
+        /// Protected-mode virtual interrupts.
+        const PROTECTED_MODE_VIRTUAL_INTERRUPTS = 1 << 1;
+        /// Restricts RDTSC to ring 0.
+        const TIMESTAMP_DISABLE = 1 << 2;
+        /// Debugging extensions.
+        const DEBUGGING_EXTENSIONS = 1 << 3;
+        /// 4 MiB pages in 32-bit paging.
+        const PAGE_SIZE_EXTENSION = 1 << 4;
+        /// Physical address extension. Required for long mode.
+        const PHYSICAL_ADDRESS_EXTENSION = 1 << 5;
+        /// Enables the machine check exception.
+        const MACHINE_CHECK_EXCEPTION = 1 << 6;
+        /// Enables global pages.
+        const PAGE_GLOBAL = 1 << 7;
+        /// Allows RDPMC in all rings.
+        const PERFORMANCE_MONITOR_COUNTER = 1 << 8;
+        /// Enables FXSAVE/FXRSTOR and SSE instructions.
+        const OSFXSR = 1 << 9;
+        /// Enables #XM for unmasked SIMD floating-point exceptions.
+        const OSXMMEXCPT_ENABLE = 1 << 10;
+        /// Makes SGDT, SIDT, SLDT, SMSW and STR raise #GP outside of ring 0.
+        const USER_MODE_INSTRUCTION_PREVENTION = 1 << 11;
+        /// Five-level paging.
+        const L5_PAGING = 1 << 12;
+        /// Enables VMX.
+        const VIRTUAL_MACHINE_EXTENSIONS = 1 << 13;
+        /// Enables SMX.
+        const SAFER_MODE_EXTENSIONS = 1 << 14;
+        /// Enables RDFSBASE, RDGSBASE, WRFSBASE and WRGSBASE.
+        const FSGSBASE = 1 << 16;
+        /// Enables process-context identifiers.
+        const PCID = 1 << 17;
+        /// Enables XSAVE and the extended processor states.
+        const OSXSAVE = 1 << 18;
+        /// Enables the AES key locker instructions.
+        const KEY_LOCKER = 1 << 19;
+        /// Supervisor mode execution prevention: ring 0 can not execute user pages.
+        const SUPERVISOR_MODE_EXECUTION_PROTECTION = 1 << 20;
+        /// Supervisor mode access prevention: ring 0 can not access user pages unless RFLAGS.AC is set.
+        const SUPERVISOR_MODE_ACCESS_PREVENTION = 1 << 21;
+        /// Protection keys for user pages.
+        const PROTECTION_KEY_USER = 1 << 22;
+        /// Control-flow enforcement technology.
+        const CONTROL_FLOW_ENFORCEMENT = 1 << 23;
+        /// Protection keys for supervisor pages.
+        const PROTECTION_KEY_SUPERVISOR = 1 << 24;
+    }
+}
+
+bitflags! {
+    /// Flags of the extended feature enable register (IA32_EFER).
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+    #[repr(transparent)]
+    pub struct EferFlags: u64 {
+        /// Enables SYSCALL and SYSRET.
+        const SYSTEM_CALL_EXTENSIONS = 1;
+        /// Enables long mode.
+        const LONG_MODE_ENABLE = 1 << 8;
+        /// Set by the processor while long mode is active.
+        const LONG_MODE_ACTIVE = 1 << 10;
+        /// Enables the no-execute page flag.
+        const NO_EXECUTE_ENABLE = 1 << 11;
+        /// Enables SVM (AMD).
+        const SECURE_VIRTUAL_MACHINE_ENABLE = 1 << 12;
+        /// Long mode segment limits (AMD).
+        const LONG_MODE_SEGMENT_LIMIT_ENABLE = 1 << 13;
+        /// Fast FXSAVE/FXRSTOR, skipping the XMM registers in ring 0 (AMD).
+        const FAST_FXSAVE_FXRSTOR = 1 << 14;
+        /// Translation cache extension (AMD).
+        const TRANSLATION_CACHE_EXTENSION = 1 << 15;
+    }
+}
+
+bitflags! {
+    /// Flags stored in the low bits of CR3 (when CR4.PCIDE is clear).
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+    #[repr(transparent)]
+    pub struct Cr3Flags: u64 {
+        /// Write-through caching for the level 4 table.
+        const PAGE_LEVEL_WRITE_THROUGH = 1 << 3;
+        /// Disables caching for the level 4 table.
+        const PAGE_LEVEL_CACHE_DISABLE = 1 << 4;
+    }
+}
+
+pub struct Cr0;
+
+/// Holds the linear address that caused the last page fault.
+pub struct Cr2;
+
+/// Holds the physical address of the active level 4 page table.
+pub struct Cr3;
+
+pub struct Cr4;
+
+pub struct Efer;
+
+impl Cr0 {
+    #[inline]
+    pub fn read() -> Cr0Flags {
+        Cr0Flags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        let value: u64;
+
+        unsafe {
+            asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags));
+        }
+
+        value
+    }
+
+    /// Writes `flags`, keeping all reserved bits.
+    ///
+    /// # Safety
+    /// Changing the operating mode, e.g. disabling paging, can break memory safety.
+    #[inline]
+    pub unsafe fn write(flags: Cr0Flags) {
+        let value = (Self::read_raw() & !Cr0Flags::all().bits()) | flags.bits();
+
+        asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+
+    /// # Safety
+    /// See [`Self::write`].
+    #[inline]
+    pub unsafe fn update(f: impl FnOnce(&mut Cr0Flags)) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        Self::write(flags);
+    }
+}
+
+impl Cr2 {
+    #[inline]
+    pub fn read() -> VirtualAddress {
+        let value: u64;
+
+        unsafe {
+            asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags));
+        }
+
+        VirtualAddress::new_truncate(value)
+    }
+}
+
+impl Cr3 {
+    #[inline]
+    pub fn read() -> (PhysFrame, Cr3Flags) {
+        let value: u64;
+
+        unsafe {
+            asm!("mov {}, cr3", out(reg) value, options(nomem, nostack, preserves_flags));
+        }
+
+        (
+            PhysFrame::containing_address(PhysicalAddress::new(value & 0x000f_ffff_ffff_f000)),
+            Cr3Flags::from_bits_truncate(value),
+        )
+    }
+
+    /// # Safety
+    /// The frame must hold a valid level 4 table which maps the currently running code.
+    #[inline]
+    pub unsafe fn write(frame: PhysFrame, flags: Cr3Flags) {
+        let value = frame.start_address().as_u64() | flags.bits();
+
+        asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+impl Cr4 {
+    #[inline]
+    pub fn read() -> Cr4Flags {
+        Cr4Flags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        let value: u64;
+
+        unsafe {
+            asm!("mov {}, cr4", out(reg) value, options(nomem, nostack, preserves_flags));
+        }
+
+        value
+    }
+
+    /// Writes `flags`, keeping all reserved bits.
+    ///
+    /// # Safety
+    /// Enabling features the processor does not support raises #GP, disabling features in use
+    /// (e.g. PAE) can break memory safety.
+    #[inline]
+    pub unsafe fn write(flags: Cr4Flags) {
+        let value = (Self::read_raw() & !Cr4Flags::all().bits()) | flags.bits();
+
+        asm!("mov cr4, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+
+    /// # Safety
+    /// See [`Self::write`].
+    #[inline]
+    pub unsafe fn update(f: impl FnOnce(&mut Cr4Flags)) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        Self::write(flags);
+    }
+}
+
+impl Efer {
+    pub const MSR: Msr = Msr::new(0xC000_0080);
+
+    #[inline]
+    pub fn read() -> EferFlags {
+        EferFlags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        // SAFETY: IA32_EFER exists on every processor supporting long mode.
+        unsafe { Self::MSR.read() }
+    }
+
+    /// Writes `flags`, keeping all reserved bits.
+    ///
+    /// # Safety
+    /// Enabling features the processor does not support raises #GP, clearing long mode bits
+    /// breaks the kernel.
+    #[inline]
+    pub unsafe fn write(flags: EferFlags) {
+        let value = (Self::read_raw() & !EferFlags::all().bits()) | flags.bits();
+
+        Self::MSR.write(value);
+    }
+
+    /// # Safety
+    /// See [`Self::write`].
+    #[inline]
+    pub unsafe fn update(f: impl FnOnce(&mut EferFlags)) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        Self::write(flags);
+    }
+}
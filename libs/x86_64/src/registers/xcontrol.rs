@@ -0,0 +1,104 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use bitflags::bitflags;
+use core::arch::asm;
+
+bitflags! {
+    /// Flags of XCR0, selecting the state components managed by XSAVE.
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+    #[repr(transparent)]
+    pub struct XCr0Flags: u64 {
+        /// x87 FPU state, must always be set.
+        const X87 = 1;
+        /// SSE state, the XMM registers and MXCSR.
+        const SSE = 1 << 1;
+        /// Upper halves of the YMM registers. Requires SSE.
+        const AVX = 1 << 2;
+        /// MPX bound registers.
+        const BNDREG = 1 << 3;
+        /// MPX bound configuration and status.
+        const BNDCSR = 1 << 4;
+        /// AVX-512 opmask registers k0 - k7.
+        const OPMASK = 1 << 5;
+        /// Upper halves of ZMM0 - ZMM15.
+        const ZMM_HI256 = 1 << 6;
+        /// ZMM16 - ZMM31.
+        const HI16_ZMM = 1 << 7;
+        /// Protection key rights register for user pages.
+        const PKRU = 1 << 9;
+        /// AMX tile configuration.
+        const XTILECFG = 1 << 17;
+        /// AMX tile data.
+        const XTILEDATA = 1 << 18;
+    }
+}
+
+/// Extended control register 0, only accessible once CR4.OSXSAVE is set.
+pub struct XCr0;
+
+impl XCr0 {
+    #[inline]
+    pub fn read() -> XCr0Flags {
+        XCr0Flags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        let high: u32;
+        let low: u32;
+
+        unsafe {
+            asm!(
+                "xgetbv",
+                in("ecx") 0,
+                out("eax") low,
+                out("edx") high,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        ((high as u64) << 32) | (low as u64)
+    }
+
+    /// Writes `flags`, keeping all reserved bits.
+    ///
+    /// # Safety
+    /// Components the processor does not support, AVX without SSE and AVX-512 components
+    /// enabled only partially raise #GP. Disabling components in use loses their state.
+    #[inline]
+    pub unsafe fn write(flags: XCr0Flags) {
+        let value = (Self::read_raw() & !XCr0Flags::all().bits()) | flags.bits();
+
+        asm!(
+            "xsetbv",
+            in("ecx") 0,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    /// # Safety
+    /// See [`Self::write`].
+    #[inline]
+    pub unsafe fn update(f: impl FnOnce(&mut XCr0Flags)) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        Self::write(flags);
+    }
+}
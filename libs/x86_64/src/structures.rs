@@ -16,4 +16,5 @@
  */
 
 pub mod memory;
+pub mod port;
 pub mod table;
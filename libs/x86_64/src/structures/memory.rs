@@ -15,20 +15,241 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use core::ops::Add;
+use core::ops::{Add, Sub};
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A canonical 64-bit virtual address.
+///
+/// With four-level paging only the lower 48 bits are translated, bits 48 - 63 have to be
+/// copies of bit 47. Addresses violating this rule are called non-canonical and raise a
+/// general protection fault when used.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct VirtualAddress(u64);
 
+/// A physical address. The architecture limits them to 52 bits.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct PhysicalAddress(u64);
+
+/// The value is not a canonical virtual address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VirtualAddressNotValid(pub u64);
+
+/// The value has bits above bit 51 set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhysicalAddressNotValid(pub u64);
+
 impl VirtualAddress {
-    pub fn new(value: u64) -> Self {
-        Self(value)
+    /// Creates a virtual address.
+    ///
+    /// # Panics
+    /// If `value` is not canonical. See [`Self::try_new`] and [`Self::new_truncate`].
+    #[inline]
+    pub const fn new(value: u64) -> Self {
+        match Self::try_new(value) {
+            Ok(address) => address,
+            Err(_) => panic!("Virtual address is not canonical."),
+        }
+    }
+
+    /// Creates a virtual address if `value` is canonical.
+    #[inline]
+    pub const fn try_new(value: u64) -> Result<Self, VirtualAddressNotValid> {
+        let address = Self::new_truncate(value);
+
+        if address.0 == value {
+            Ok(address)
+        } else {
+            Err(VirtualAddressNotValid(value))
+        }
+    }
+
+    /// Creates a virtual address by sign-extending bit 47 into bits 48 - 63.
+    #[inline]
+    pub const fn new_truncate(value: u64) -> Self {
+        Self(((value << 16) as i64 >> 16) as u64)
+    }
+
+    #[inline]
+    pub const fn zero() -> Self {
+        Self(0)
+    }
+
+    #[inline]
+    pub fn from_ptr<T: ?Sized>(ptr: *const T) -> Self {
+        Self::new(ptr as *const () as u64)
+    }
+
+    #[inline]
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    #[inline]
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    /// Rounds the address up to the next multiple of `align`, which must be a power of two.
+    ///
+    /// # Panics
+    /// If the result overflows or is not canonical, like rounding up the last page of the
+    /// lower half.
+    #[inline]
+    pub const fn align_up(self, align: u64) -> Self {
+        Self::new(align_up(self.0, align))
+    }
+
+    /// Rounds the address down to a multiple of `align`, which must be a power of two.
+    #[inline]
+    pub const fn align_down(self, align: u64) -> Self {
+        Self::new_truncate(align_down(self.0, align))
+    }
+
+    #[inline]
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// Offset of the address inside its 4 KiB page.
+    #[inline]
+    pub const fn page_offset(self) -> u64 {
+        self.0 & 0xfff
     }
 
+    /// Index into the level 1 page table (bits 12 - 20).
     #[inline]
-    pub fn from_ptr<T>(ptr: *const T) -> Self {
-        Self::new(ptr as u64)
+    pub const fn p1_index(self) -> usize {
+        self.page_table_index(1)
+    }
+
+    /// Index into the level 2 page table (bits 21 - 29).
+    #[inline]
+    pub const fn p2_index(self) -> usize {
+        self.page_table_index(2)
+    }
+
+    /// Index into the level 3 page table (bits 30 - 38).
+    #[inline]
+    pub const fn p3_index(self) -> usize {
+        self.page_table_index(3)
+    }
+
+    /// Index into the level 4 page table (bits 39 - 47).
+    #[inline]
+    pub const fn p4_index(self) -> usize {
+        self.page_table_index(4)
+    }
+
+    /// Index into the page table of the given level (1 - 4).
+    #[inline]
+    pub const fn page_table_index(self, level: u8) -> usize {
+        ((self.0 >> (12 + 9 * (level as u64 - 1))) & 0o777) as usize
+    }
+
+    /// Builds the canonical address selected by the given page table indices.
+    #[inline]
+    pub const fn from_page_table_indices(p4: usize, p3: usize, p2: usize, p1: usize) -> Self {
+        Self::new_truncate(
+            (p4 as u64 & 0o777) << 39
+                | (p3 as u64 & 0o777) << 30
+                | (p2 as u64 & 0o777) << 21
+                | (p1 as u64 & 0o777) << 12,
+        )
+    }
+}
+
+impl PhysicalAddress {
+    /// Bits 52 - 63 are reserved.
+    const MASK: u64 = (1 << 52) - 1;
+
+    /// Creates a physical address.
+    ///
+    /// # Panics
+    /// If `value` has bits above bit 51 set. See [`Self::try_new`] and [`Self::new_truncate`].
+    #[inline]
+    pub const fn new(value: u64) -> Self {
+        match Self::try_new(value) {
+            Ok(address) => address,
+            Err(_) => panic!("Physical address exceeds 52 bits."),
+        }
+    }
+
+    /// Creates a physical address if `value` fits into 52 bits.
+    #[inline]
+    pub const fn try_new(value: u64) -> Result<Self, PhysicalAddressNotValid> {
+        if value & !Self::MASK == 0 {
+            Ok(Self(value))
+        } else {
+            Err(PhysicalAddressNotValid(value))
+        }
+    }
+
+    /// Creates a physical address by clearing bits 52 - 63.
+    #[inline]
+    pub const fn new_truncate(value: u64) -> Self {
+        Self(value & Self::MASK)
+    }
+
+    #[inline]
+    pub const fn zero() -> Self {
+        Self(0)
+    }
+
+    #[inline]
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Rounds the address up to the next multiple of `align`, which must be a power of two.
+    #[inline]
+    pub const fn align_up(self, align: u64) -> Self {
+        Self::new(align_up(self.0, align))
+    }
+
+    /// Rounds the address down to a multiple of `align`, which must be a power of two.
+    #[inline]
+    pub const fn align_down(self, align: u64) -> Self {
+        Self(align_down(self.0, align))
+    }
+
+    #[inline]
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// Offset of the address inside its 4 KiB frame.
+    #[inline]
+    pub const fn frame_offset(self) -> u64 {
+        self.0 & 0xfff
+    }
+
+    /// Number of the 4 KiB frame containing the address.
+    #[inline]
+    pub const fn frame_number(self) -> u64 {
+        self.0 >> 12
+    }
+}
+
+#[inline]
+const fn align_down(value: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "Alignment must be a power of two.");
+
+    value & !(align - 1)
+}
+
+#[inline]
+const fn align_up(value: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "Alignment must be a power of two.");
+
+    match value.checked_add(align - 1) {
+        Some(value) => value & !(align - 1),
+        None => panic!("Aligning the address up overflows."),
     }
 }
 
@@ -39,18 +260,24 @@ impl core::fmt::Debug for VirtualAddress {
     }
 }
 
-impl Default for VirtualAddress {
-    fn default() -> Self {
-        Self(0)
+impl core::fmt::Debug for PhysicalAddress {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:#x}", self.0)
     }
 }
 
 impl Add<u64> for VirtualAddress {
     type Output = Self;
 
+    /// # Panics
+    /// If the addition overflows or the result is not canonical.
     #[inline]
     fn add(self, rhs: u64) -> Self::Output {
-        VirtualAddress::new(self.0 + rhs)
+        match self.0.checked_add(rhs) {
+            Some(value) => VirtualAddress::new(value),
+            None => panic!("Virtual address addition overflows."),
+        }
     }
 }
 
@@ -63,3 +290,184 @@ impl Add<usize> for VirtualAddress {
         self + rhs as u64
     }
 }
+
+impl Sub<u64> for VirtualAddress {
+    type Output = Self;
+
+    /// # Panics
+    /// If the subtraction underflows or the result is not canonical.
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        match self.0.checked_sub(rhs) {
+            Some(value) => VirtualAddress::new(value),
+            None => panic!("Virtual address subtraction underflows."),
+        }
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+impl Sub<usize> for VirtualAddress {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: usize) -> Self::Output {
+        self - rhs as u64
+    }
+}
+
+impl Sub<VirtualAddress> for VirtualAddress {
+    type Output = u64;
+
+    /// Distance between two addresses in bytes.
+    #[inline]
+    fn sub(self, rhs: VirtualAddress) -> Self::Output {
+        match self.0.checked_sub(rhs.0) {
+            Some(distance) => distance,
+            None => panic!("Virtual address subtraction underflows."),
+        }
+    }
+}
+
+impl Add<u64> for PhysicalAddress {
+    type Output = Self;
+
+    /// # Panics
+    /// If the result exceeds 52 bits.
+    #[inline]
+    fn add(self, rhs: u64) -> Self::Output {
+        match self.0.checked_add(rhs) {
+            Some(value) => PhysicalAddress::new(value),
+            None => panic!("Physical address addition overflows."),
+        }
+    }
+}
+
+impl Sub<u64> for PhysicalAddress {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        match self.0.checked_sub(rhs) {
+            Some(value) => PhysicalAddress(value),
+            None => panic!("Physical address subtraction underflows."),
+        }
+    }
+}
+
+impl Sub<PhysicalAddress> for PhysicalAddress {
+    type Output = u64;
+
+    /// Distance between two addresses in bytes.
+    #[inline]
+    fn sub(self, rhs: PhysicalAddress) -> Self::Output {
+        match self.0.checked_sub(rhs.0) {
+            Some(distance) => distance,
+            None => panic!("Physical address subtraction underflows."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_addresses_must_be_canonical() {
+        assert_eq!(
+            VirtualAddress::try_new(0x0000_7fff_ffff_ffff).map(VirtualAddress::as_u64),
+            Ok(0x0000_7fff_ffff_ffff)
+        );
+        assert_eq!(
+            VirtualAddress::try_new(0xffff_8000_0000_0000).map(VirtualAddress::as_u64),
+            Ok(0xffff_8000_0000_0000)
+        );
+        assert_eq!(
+            VirtualAddress::try_new(0x0000_8000_0000_0000),
+            Err(VirtualAddressNotValid(0x0000_8000_0000_0000))
+        );
+        assert_eq!(
+            VirtualAddress::try_new(0xffff_7fff_ffff_ffff),
+            Err(VirtualAddressNotValid(0xffff_7fff_ffff_ffff))
+        );
+    }
+
+    #[test]
+    fn new_truncate_sign_extends_bit_47() {
+        assert_eq!(
+            VirtualAddress::new_truncate(0x0000_8000_0000_1000).as_u64(),
+            0xffff_8000_0000_1000
+        );
+        assert_eq!(
+            VirtualAddress::new_truncate(0x1234_7fff_ffff_f000).as_u64(),
+            0x0000_7fff_ffff_f000
+        );
+        assert_eq!(
+            PhysicalAddress::new_truncate(0xfff0_0000_1234_5000).as_u64(),
+            0x0000_0000_1234_5000
+        );
+        assert!(PhysicalAddress::try_new(1 << 52).is_err());
+    }
+
+    #[test]
+    fn align_helpers() {
+        let address = VirtualAddress::new(0xffff_8000_0000_1234);
+
+        assert_eq!(address.align_down(0x1000).as_u64(), 0xffff_8000_0000_1000);
+        assert_eq!(address.align_up(0x1000).as_u64(), 0xffff_8000_0000_2000);
+        assert_eq!(address.align_up(1), address);
+        assert!(address.align_down(0x1000).is_aligned(0x1000));
+        assert!(!address.is_aligned(0x1000));
+
+        let physical = PhysicalAddress::new(0x1234);
+        assert_eq!(physical.align_up(0x1000).as_u64(), 0x2000);
+        assert_eq!(physical.align_down(0x1000).as_u64(), 0x1000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn align_up_past_the_lower_half_panics() {
+        VirtualAddress::new(0x0000_7fff_ffff_f001).align_up(0x1000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn align_up_needs_a_power_of_two() {
+        VirtualAddress::new(0x1234).align_up(0x1800);
+    }
+
+    #[test]
+    fn sub() {
+        let address = VirtualAddress::new(0x5000);
+
+        assert_eq!((address - 0x1000u64).as_u64(), 0x4000);
+        assert_eq!(address - VirtualAddress::new(0x1000), 0x4000);
+        assert_eq!(
+            PhysicalAddress::new(0x5000) - PhysicalAddress::new(0x1000),
+            0x4000
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn sub_underflow_panics() {
+        let _ = VirtualAddress::new(0x1000) - VirtualAddress::new(0x2000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn sub_into_the_hole_panics() {
+        let _ = VirtualAddress::new(0xffff_8000_0000_0000) - 1u64;
+    }
+
+    #[test]
+    fn page_table_indices() {
+        let address = VirtualAddress::from_page_table_indices(0o777, 0o1, 0o2, 0o3) + 0x123u64;
+
+        assert_eq!(address.as_u64(), 0xffff_ff80_4040_3123);
+        assert_eq!(address.p4_index(), 0o777);
+        assert_eq!(address.p3_index(), 0o1);
+        assert_eq!(address.p2_index(), 0o2);
+        assert_eq!(address.p1_index(), 0o3);
+        assert_eq!(address.page_offset(), 0x123);
+    }
+}
@@ -0,0 +1,436 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::op::port;
+
+/// A value that can be read from an I/O port.
+pub trait PortRead: Sized {
+    /// # Safety
+    /// Reading a port can have side effects on the device behind it.
+    unsafe fn read_from_port(port: u16) -> Self;
+
+    /// Reads `buffer.len()` values with a single string instruction.
+    ///
+    /// # Safety
+    /// See [`PortRead::read_from_port`].
+    unsafe fn read_string_from_port(port: u16, buffer: &mut [Self]);
+}
+
+/// A value that can be written to an I/O port.
+pub trait PortWrite: Sized {
+    /// # Safety
+    /// Writing a port can have arbitrary side effects on the device behind it.
+    unsafe fn write_to_port(port: u16, value: Self);
+
+    /// Writes all values of `buffer` with a single string instruction.
+    ///
+    /// # Safety
+    /// See [`PortWrite::write_to_port`].
+    unsafe fn write_string_to_port(port: u16, buffer: &[Self]);
+}
+
+macro_rules! port_access {
+    ($t:ty, $in:ident, $out:ident, $ins:ident, $outs:ident) => {
+        impl PortRead for $t {
+            #[inline]
+            unsafe fn read_from_port(port: u16) -> Self {
+                port::$in(port)
+            }
+
+            #[inline]
+            unsafe fn read_string_from_port(port: u16, buffer: &mut [Self]) {
+                port::$ins(port, buffer)
+            }
+        }
+
+        impl PortWrite for $t {
+            #[inline]
+            unsafe fn write_to_port(port: u16, value: Self) {
+                port::$out(port, value)
+            }
+
+            #[inline]
+            unsafe fn write_string_to_port(port: u16, buffer: &[Self]) {
+                port::$outs(port, buffer)
+            }
+        }
+    };
+}
+
+port_access!(u8, inb, outb, insb, outsb);
+port_access!(u16, inw, outw, insw, outsw);
+port_access!(u32, inl, outl, insl, outsl);
+
+/// Marks what a [`PortGeneric`] may be used for.
+pub trait PortAccess {
+    const DEBUG_NAME: &'static str;
+}
+
+pub trait PortReadAccess: PortAccess {}
+
+pub trait PortWriteAccess: PortAccess {}
+
+#[derive(Debug)]
+pub struct ReadOnlyAccess;
+
+#[derive(Debug)]
+pub struct WriteOnlyAccess;
+
+#[derive(Debug)]
+pub struct ReadWriteAccess;
+
+impl PortAccess for ReadOnlyAccess {
+    const DEBUG_NAME: &'static str = "ReadOnly";
+}
+
+impl PortAccess for WriteOnlyAccess {
+    const DEBUG_NAME: &'static str = "WriteOnly";
+}
+
+impl PortAccess for ReadWriteAccess {
+    const DEBUG_NAME: &'static str = "ReadWrite";
+}
+
+impl PortReadAccess for ReadOnlyAccess {}
+impl PortReadAccess for ReadWriteAccess {}
+impl PortWriteAccess for WriteOnlyAccess {}
+impl PortWriteAccess for ReadWriteAccess {}
+
+/// An I/O port transferring values of type `T`, restricted by `A`.
+pub struct PortGeneric<T, A> {
+    port: u16,
+    phantom: PhantomData<(T, A)>,
+}
+
+pub type Port<T> = PortGeneric<T, ReadWriteAccess>;
+
+pub type PortReadOnly<T> = PortGeneric<T, ReadOnlyAccess>;
+
+pub type PortWriteOnly<T> = PortGeneric<T, WriteOnlyAccess>;
+
+impl<T, A> PortGeneric<T, A> {
+    #[inline]
+    pub const fn new(port: u16) -> Self {
+        Self {
+            port,
+            phantom: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub const fn number(&self) -> u16 {
+        self.port
+    }
+}
+
+impl<T: PortRead, A: PortReadAccess> PortGeneric<T, A> {
+    /// # Safety
+    /// Reading a port can have side effects on the device behind it.
+    #[inline]
+    pub unsafe fn read(&mut self) -> T {
+        T::read_from_port(self.port)
+    }
+
+    /// Fills `buffer` with values read from the port (`ins`).
+    ///
+    /// # Safety
+    /// See [`Self::read`].
+    #[inline]
+    pub unsafe fn read_string(&mut self, buffer: &mut [T]) {
+        T::read_string_from_port(self.port, buffer)
+    }
+}
+
+impl<T: PortWrite, A: PortWriteAccess> PortGeneric<T, A> {
+    /// # Safety
+    /// Writing a port can have arbitrary side effects on the device behind it.
+    #[inline]
+    pub unsafe fn write(&mut self, value: T) {
+        T::write_to_port(self.port, value)
+    }
+
+    /// Writes every value of `buffer` to the port (`outs`).
+    ///
+    /// # Safety
+    /// See [`Self::write`].
+    #[inline]
+    pub unsafe fn write_string(&mut self, buffer: &[T]) {
+        T::write_string_to_port(self.port, buffer)
+    }
+}
+
+impl<T, A: PortAccess> fmt::Debug for PortGeneric<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PortGeneric")
+            .field("port", &self.port)
+            .field("size", &core::mem::size_of::<T>())
+            .field("access", &A::DEBUG_NAME)
+            .finish()
+    }
+}
+
+impl<T, A> Clone for PortGeneric<T, A> {
+    fn clone(&self) -> Self {
+        Self::new(self.port)
+    }
+}
+
+impl<T, A> PartialEq for PortGeneric<T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.port == other.port
+    }
+}
+
+impl<T, A> Eq for PortGeneric<T, A> {}
+
+/// A contiguous block of I/O ports, e.g. the registers of one device.
+///
+/// Ports are handed out relative to the base, so a driver only ever touches the range it was
+/// given. The same range can be granted to user space through an [`IoPermissionBitmap`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortRange {
+    base: u16,
+    count: u16,
+}
+
+impl PortRange {
+    /// # Panics
+    /// If the range does not fit into the 16-bit I/O address space.
+    #[inline]
+    pub const fn new(base: u16, count: u16) -> Self {
+        assert!(
+            base as u32 + count as u32 <= 0x1_0000,
+            "Port range exceeds the I/O address space"
+        );
+
+        Self { base, count }
+    }
+
+    #[inline]
+    pub const fn base(&self) -> u16 {
+        self.base
+    }
+
+    #[inline]
+    pub const fn count(&self) -> u16 {
+        self.count
+    }
+
+    #[inline]
+    pub const fn contains(&self, port: u16) -> bool {
+        port >= self.base && ((port - self.base) as u32) < self.count as u32
+    }
+
+    /// Returns the port at `offset` from the base.
+    ///
+    /// # Panics
+    /// If a `T` at `offset` would not lie inside the range.
+    #[inline]
+    pub fn port<T, A>(&self, offset: u16) -> PortGeneric<T, A> {
+        assert!(
+            offset as usize + core::mem::size_of::<T>() <= self.count as usize,
+            "Port offset {:#x} outside of {:?}",
+            offset,
+            self
+        );
+
+        PortGeneric::new(self.base + offset)
+    }
+
+    /// Iterates over the port numbers of the range.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = u16> {
+        (self.base as u32..self.base as u32 + self.count as u32).map(|port| port as u16)
+    }
+}
+
+/// Size of the I/O permission bitmap in bytes, one bit per port.
+const IO_BITMAP_BYTES: usize = 0x1_0000 / 8;
+
+/// The I/O permission bitmap of a task state segment.
+///
+/// A set bit denies access to the port, a cleared bit allows it for code running with a CPL
+/// above the IOPL. The bitmap has to be placed directly after the TSS, with `iomap_base` set
+/// to its offset from the TSS and the TSS descriptor limit covering the trailing byte.
+#[repr(C)]
+#[derive(Clone)]
+pub struct IoPermissionBitmap {
+    bits: [u8; IO_BITMAP_BYTES],
+    /// The processor may read one byte past the bitmap, it has to be all ones.
+    terminator: u8,
+}
+
+impl IoPermissionBitmap {
+    /// Creates a bitmap denying access to every port.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            bits: [0xFF; IO_BITMAP_BYTES],
+            terminator: 0xFF,
+        }
+    }
+
+    /// Allows access to every port in `range`.
+    pub fn allow(&mut self, range: PortRange) {
+        for port in range.iter() {
+            self.bits[port as usize / 8] &= !(1 << (port % 8));
+        }
+    }
+
+    /// Denies access to every port in `range`.
+    pub fn deny(&mut self, range: PortRange) {
+        for port in range.iter() {
+            self.bits[port as usize / 8] |= 1 << (port % 8);
+        }
+    }
+
+    #[inline]
+    pub fn is_allowed(&self, port: u16) -> bool {
+        self.bits[port as usize / 8] & (1 << (port % 8)) == 0
+    }
+
+    /// Returns true if every port in `range` is accessible.
+    #[inline]
+    pub fn allows(&self, range: PortRange) -> bool {
+        range.iter().all(|port| self.is_allowed(port))
+    }
+}
+
+impl Default for IoPermissionBitmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for IoPermissionBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let allowed = (0..=u16::MAX).filter(|&port| self.is_allowed(port)).count();
+
+        f.debug_struct("IoPermissionBitmap")
+            .field("allowed_ports", &allowed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_bounds() {
+        let last = PortRange::new(0xFFFF, 1);
+        assert!(last.contains(0xFFFF));
+        assert!(!last.contains(0xFFFE));
+        assert_eq!(last.iter().collect::<Vec<_>>(), [0xFFFF]);
+
+        let all = PortRange::new(0, 0xFFFF);
+        assert!(all.contains(0xFFFE));
+        assert!(!all.contains(0xFFFF));
+        assert_eq!(all.iter().count(), 0xFFFF);
+
+        let empty = PortRange::new(0x3F8, 0);
+        assert!(!empty.contains(0x3F8));
+        assert_eq!(empty.iter().count(), 0);
+
+        // An empty range may start right after the last port.
+        assert_eq!(PortRange::new(0xFFFF, 0).iter().count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the I/O address space")]
+    fn range_past_the_last_port() {
+        PortRange::new(0xFFFF, 2);
+    }
+
+    #[test]
+    fn ports_relative_to_the_base() {
+        let range = PortRange::new(0x3F8, 8);
+
+        assert_eq!(range.port::<u8, ReadWriteAccess>(5).number(), 0x3FD);
+        assert_eq!(range.port::<u32, ReadOnlyAccess>(4).number(), 0x3FC);
+        assert_eq!(
+            PortRange::new(0xFFFF, 1)
+                .port::<u8, ReadWriteAccess>(0)
+                .number(),
+            0xFFFF
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of")]
+    fn port_wider_than_the_rest_of_the_range() {
+        PortRange::new(0x3F8, 8).port::<u16, ReadWriteAccess>(7);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of")]
+    fn port_of_an_empty_range() {
+        PortRange::new(0x3F8, 0).port::<u8, ReadWriteAccess>(0);
+    }
+
+    #[test]
+    fn bitmap_denies_by_default() {
+        let bitmap = IoPermissionBitmap::new();
+
+        assert!(!bitmap.is_allowed(0));
+        assert!(!bitmap.is_allowed(0xFFFF));
+        assert_eq!(bitmap.terminator, 0xFF);
+        // Nothing to deny in an empty range.
+        assert!(bitmap.allows(PortRange::new(0x60, 0)));
+    }
+
+    #[test]
+    fn bitmap_allow_and_deny_overlap() {
+        let mut bitmap = IoPermissionBitmap::new();
+
+        // Crosses a byte boundary on both ends.
+        bitmap.allow(PortRange::new(0x3F6, 12));
+        assert!(bitmap.allows(PortRange::new(0x3F6, 12)));
+        assert!(!bitmap.is_allowed(0x3F5));
+        assert!(!bitmap.is_allowed(0x402));
+
+        // Denying the middle keeps both ends.
+        bitmap.deny(PortRange::new(0x3F8, 8));
+        assert!(bitmap.allows(PortRange::new(0x3F6, 2)));
+        assert!(bitmap.allows(PortRange::new(0x400, 2)));
+        assert!(!bitmap.is_allowed(0x3F8));
+        assert!(!bitmap.is_allowed(0x3FF));
+        assert!(!bitmap.allows(PortRange::new(0x3F6, 12)));
+
+        // Allowing an overlapping range only adds ports.
+        bitmap.allow(PortRange::new(0x3FC, 2));
+        assert!(bitmap.allows(PortRange::new(0x3FC, 2)));
+        assert!(!bitmap.is_allowed(0x3FB));
+        assert!(!bitmap.is_allowed(0x3FE));
+
+        bitmap.allow(PortRange::new(0xFFFF, 1));
+        assert!(bitmap.is_allowed(0xFFFF));
+        assert_eq!(bitmap.terminator, 0xFF);
+
+        bitmap.deny(PortRange::new(0, 0xFFFF));
+        bitmap.deny(PortRange::new(0xFFFF, 1));
+        assert_eq!(
+            (0..=u16::MAX)
+                .filter(|&port| bitmap.is_allowed(port))
+                .count(),
+            0
+        );
+    }
+}
@@ -15,6 +15,11 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+pub mod frame;
+pub mod mapper;
+pub mod page;
+pub mod table;
+
 use bitflags::bitflags;
 
 bitflags! {
@@ -22,6 +27,7 @@ bitflags! {
     // Intel® 64 and IA-32 Architectures Software Developer’s Manual Combined Volumes: 1, 2A, 2B, 2C, 2D, 3A, 3B, 3C, 3D, and 4
     // Page 3240 - 3241
     #[repr(transparent)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct PageFaultErrorCode: u64 {
         /// P flag (bit 0)
         /// This flag is 0 if there is no translation for the linear address because the P flag was 0 in one of the paging-
@@ -0,0 +1,84 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use super::page::{PageSize, Size4KiB};
+use crate::structures::memory::PhysicalAddress;
+
+/// A frame of physical memory.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(C)]
+pub struct PhysFrame<S: PageSize = Size4KiB> {
+    start: PhysicalAddress,
+    size: PhantomData<S>,
+}
+
+impl<S: PageSize> PhysFrame<S> {
+    pub const SIZE: u64 = S::SIZE;
+
+    /// Returns the frame starting at `address`, or `None` if it is not aligned to the frame size.
+    #[inline]
+    pub fn from_start_address(address: PhysicalAddress) -> Option<Self> {
+        if !address.is_aligned(S::SIZE) {
+            return None;
+        }
+
+        Some(Self::containing_address(address))
+    }
+
+    /// Returns the frame that contains `address`.
+    #[inline]
+    pub const fn containing_address(address: PhysicalAddress) -> Self {
+        Self {
+            start: address.align_down(S::SIZE),
+            size: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub const fn start_address(self) -> PhysicalAddress {
+        self.start
+    }
+
+    #[inline]
+    pub const fn size(self) -> u64 {
+        S::SIZE
+    }
+}
+
+impl<S: PageSize> fmt::Debug for PhysFrame<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PhysFrame[{}]({:#x})", S::NAME, self.start.as_u64())
+    }
+}
+
+/// Hands out physical frames, e.g. for new page tables.
+///
+/// # Safety
+/// Implementations must only return frames which are unused and not handed out twice.
+pub unsafe trait FrameAllocator<S: PageSize = Size4KiB> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>>;
+}
+
+/// Takes back frames which were handed out by a [`FrameAllocator`].
+pub trait FrameDeallocator<S: PageSize = Size4KiB> {
+    /// # Safety
+    /// The frame must be unused, i.e. no longer mapped anywhere.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>);
+}
@@ -0,0 +1,640 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use super::frame::{FrameAllocator, PhysFrame};
+use super::page::{Page, PageSize};
+use super::table::{PageTable, PageTableEntry, PageTableFlags};
+use crate::op::tlb;
+use crate::registers::control::Cr3;
+use crate::structures::memory::{PhysicalAddress, VirtualAddress};
+
+/// Maps, unmaps and translates pages of a four-level page table hierarchy.
+///
+/// Page tables are accessed through a linear mapping of physical memory at `offset`,
+/// e.g. the higher half direct map set up by the bootloader. With an offset of 0 the
+/// mapper works on tables which live in ordinary memory, which is handy for tests.
+pub struct Mapper<'a> {
+    level_4_table: &'a mut PageTable,
+    offset: u64,
+}
+
+/// The outcome of a page table walk for a single address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranslateResult {
+    /// The address is mapped by a page of `size` bytes starting at `frame`.
+    Mapped {
+        frame: PhysicalAddress,
+        offset: u64,
+        size: u64,
+        flags: PageTableFlags,
+    },
+    /// One of the entries on the way was not present.
+    NotMapped,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapToError<S: PageSize> {
+    /// The frame allocator could not provide a frame for a new page table.
+    FrameAllocationFailed,
+    /// A table on the way is replaced by a huge page.
+    ParentEntryHugePage,
+    /// The page is already mapped to the given frame.
+    PageAlreadyMapped(PhysFrame<S>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnmapError {
+    /// A table on the way is replaced by a huge page.
+    ParentEntryHugePage,
+    /// The page is not mapped with the requested size.
+    PageNotMapped,
+}
+
+/// A TLB entry that has to be flushed after the page tables were changed.
+#[derive(Debug)]
+#[must_use = "The TLB has to be flushed for the change to take effect."]
+pub struct MapperFlush<S: PageSize>(Page<S>);
+
+impl<S: PageSize> MapperFlush<S> {
+    /// Invalidates the TLB entry of the page on the current core.
+    #[inline]
+    pub fn flush(self) {
+        tlb::flush(self.0.start_address());
+    }
+
+    /// Skips the flush, e.g. because the address space is not active or will be reloaded.
+    #[inline]
+    pub fn ignore(self) {}
+}
+
+enum WalkError {
+    NotMapped,
+    HugePage,
+}
+
+impl<'a> Mapper<'a> {
+    /// # Safety
+    /// `level_4_table` must be a valid level 4 table and all physical memory referenced by the
+    /// hierarchy must be mapped at `offset`.
+    #[inline]
+    pub unsafe fn new(level_4_table: &'a mut PageTable, offset: u64) -> Self {
+        Self {
+            level_4_table,
+            offset,
+        }
+    }
+
+    /// Returns a mapper for the page tables loaded in CR3.
+    ///
+    /// # Safety
+    /// All physical memory must be mapped at `offset` and only one mapper for the active
+    /// tables may exist at a time.
+    #[inline]
+    pub unsafe fn active(offset: u64) -> Mapper<'static> {
+        let (frame, _) = Cr3::read();
+        let table = &mut *VirtualAddress::new(frame.start_address().as_u64() + offset).as_mut_ptr();
+
+        Mapper::new(table, offset)
+    }
+
+    #[inline]
+    pub fn level_4_table(&mut self) -> &mut PageTable {
+        self.level_4_table
+    }
+
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Maps `page` to `frame`, creating missing page tables with frames from `allocator`.
+    ///
+    /// # Safety
+    /// Mapping a frame twice or remapping memory that is in use breaks memory safety.
+    pub unsafe fn map_to<S, A>(
+        &mut self,
+        page: Page<S>,
+        frame: PhysFrame<S>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlush<S>, MapToError<S>>
+    where
+        S: PageSize,
+        A: FrameAllocator + ?Sized,
+    {
+        let address = page.start_address();
+        let parent_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+
+        let mut table: *mut PageTable = self.level_4_table;
+
+        for level in (S::LEVEL + 1..=4).rev() {
+            let entry = &mut (&mut *table)[address.page_table_index(level)];
+
+            if entry.is_unused() {
+                let frame = allocator
+                    .allocate_frame()
+                    .ok_or(MapToError::FrameAllocationFailed)?;
+
+                entry.set_frame(frame, parent_flags);
+                (&mut *self.table_at(frame.start_address())).zero();
+            } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                return Err(MapToError::ParentEntryHugePage);
+            } else if !entry.flags().contains(parent_flags) {
+                entry.set_flags(entry.flags() | parent_flags);
+            }
+
+            table = self.table_at(entry.address());
+        }
+
+        let entry = &mut (&mut *table)[address.page_table_index(S::LEVEL)];
+
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(
+                PhysFrame::containing_address(entry.address()),
+            ));
+        }
+
+        entry.set_frame(
+            frame,
+            flags | PageTableFlags::PRESENT | Self::huge_flag::<S>(),
+        );
+
+        Ok(MapperFlush(page))
+    }
+
+    /// Removes the mapping of `page` and returns the frame it was mapped to.
+    ///
+    /// Page tables which become empty are not freed.
+    pub fn unmap<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+    ) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError> {
+        let entry = self.leaf_entry(page).map_err(|error| match error {
+            WalkError::NotMapped => UnmapError::PageNotMapped,
+            WalkError::HugePage => UnmapError::ParentEntryHugePage,
+        })?;
+
+        let frame = PhysFrame::containing_address(entry.address());
+        entry.set_unused();
+
+        Ok((frame, MapperFlush(page)))
+    }
+
+    /// Replaces the flags of an already mapped page.
+    ///
+    /// # Safety
+    /// Changing the flags of memory that is in use can break memory safety.
+    pub unsafe fn update_flags<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> Result<MapperFlush<S>, UnmapError> {
+        let entry = self.leaf_entry(page).map_err(|error| match error {
+            WalkError::NotMapped => UnmapError::PageNotMapped,
+            WalkError::HugePage => UnmapError::ParentEntryHugePage,
+        })?;
+
+        entry.set_flags(flags | PageTableFlags::PRESENT | Self::huge_flag::<S>());
+
+        Ok(MapperFlush(page))
+    }
+
+    /// Walks the page tables for `address`.
+    pub fn translate(&self, address: VirtualAddress) -> TranslateResult {
+        let mut table: *const PageTable = &*self.level_4_table;
+
+        for level in (1..=4).rev() {
+            // SAFETY: `table` is either the level 4 table or was reached through present entries.
+            let entry = unsafe { &(&*table)[address.page_table_index(level)] };
+            let flags = entry.flags();
+
+            if !flags.contains(PageTableFlags::PRESENT) {
+                return TranslateResult::NotMapped;
+            }
+
+            if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+                let size = 1 << (12 + 9 * (level as u64 - 1));
+
+                return TranslateResult::Mapped {
+                    frame: entry.address().align_down(size),
+                    offset: address.as_u64() & (size - 1),
+                    size,
+                    flags,
+                };
+            }
+
+            table = self.table_at(entry.address());
+        }
+
+        TranslateResult::NotMapped
+    }
+
+    /// Visits every entry used to translate `address`, from level 4 down to the entry that
+    /// is either not present or maps the page.
+    #[inline]
+    pub fn walk(&self, address: VirtualAddress, visit: impl FnMut(u8, usize, PageTableEntry)) {
+        // SAFETY: The tables of a mapper are reachable through its offset.
+        unsafe { walk(self.level_4_table, self.offset, address, visit) }
+    }
+
+    /// Returns the physical address `address` is mapped to.
+    #[inline]
+    pub fn translate_address(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        match self.translate(address) {
+            TranslateResult::Mapped { frame, offset, .. } => Some(frame + offset),
+            TranslateResult::NotMapped => None,
+        }
+    }
+
+    fn leaf_entry<S: PageSize>(&mut self, page: Page<S>) -> Result<&mut PageTableEntry, WalkError> {
+        let address = page.start_address();
+        let mut table: *mut PageTable = self.level_4_table;
+
+        for level in (S::LEVEL + 1..=4).rev() {
+            // SAFETY: `table` is either the level 4 table or was reached through present entries.
+            let entry = unsafe { &(&*table)[address.page_table_index(level)] };
+
+            if !entry.flags().contains(PageTableFlags::PRESENT) {
+                return Err(WalkError::NotMapped);
+            }
+
+            if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                return Err(WalkError::HugePage);
+            }
+
+            table = self.table_at(entry.address());
+        }
+
+        // SAFETY: See above.
+        let entry = unsafe { &mut (&mut *table)[address.page_table_index(S::LEVEL)] };
+        let flags = entry.flags();
+
+        // In a level 1 entry the bit selects the PAT entry, every entry there maps a page.
+        if !flags.contains(PageTableFlags::PRESENT)
+            || (S::LEVEL > 1 && !flags.contains(PageTableFlags::HUGE_PAGE))
+        {
+            return Err(WalkError::NotMapped);
+        }
+
+        Ok(entry)
+    }
+
+    #[inline]
+    fn table_at(&self, address: PhysicalAddress) -> *mut PageTable {
+        VirtualAddress::new(address.as_u64() + self.offset).as_mut_ptr()
+    }
+
+    #[inline]
+    fn huge_flag<S: PageSize>() -> PageTableFlags {
+        if S::LEVEL > 1 {
+            PageTableFlags::HUGE_PAGE
+        } else {
+            PageTableFlags::empty()
+        }
+    }
+}
+
+/// Visits every entry used to translate `address`, starting at `level_4_table`.
+///
+/// The callback receives the level, the index into the table and the entry. The walk stops at
+/// the first entry that is not present or maps a page. Unlike [`Mapper`] this only needs shared
+/// access to the tables, so it can be used to inspect the active hierarchy from an exception
+/// handler while a mapper may be in use.
+///
+/// # Safety
+/// All page tables reachable from `level_4_table` must be mapped at `offset`.
+pub unsafe fn walk(
+    level_4_table: &PageTable,
+    offset: u64,
+    address: VirtualAddress,
+    mut visit: impl FnMut(u8, usize, PageTableEntry),
+) {
+    let mut table: *const PageTable = level_4_table;
+
+    for level in (1..=4).rev() {
+        let index = address.page_table_index(level);
+        let entry = (&*table)[index];
+        let flags = entry.flags();
+
+        visit(level, index, entry);
+
+        if level == 1
+            || !flags.contains(PageTableFlags::PRESENT)
+            || flags.contains(PageTableFlags::HUGE_PAGE)
+        {
+            return;
+        }
+
+        // A corrupted entry must not turn the walk into a #GP.
+        match VirtualAddress::try_new(entry.address().as_u64() + offset) {
+            Ok(next) => table = next.as_ptr(),
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::types::paging::page::{Size2MiB, Size4KiB};
+
+    /// Hands out heap allocated tables, whose addresses double as physical addresses for a
+    /// mapper with offset 0.
+    #[derive(Default)]
+    struct HeapFrames {
+        tables: Vec<Box<PageTable>>,
+        limit: Option<usize>,
+    }
+
+    unsafe impl FrameAllocator for HeapFrames {
+        fn allocate_frame(&mut self) -> Option<PhysFrame> {
+            if self.limit.is_some_and(|limit| self.tables.len() >= limit) {
+                return None;
+            }
+
+            let table = Box::new(PageTable::new());
+            let address = PhysicalAddress::new(&*table as *const PageTable as u64);
+            self.tables.push(table);
+
+            PhysFrame::from_start_address(address)
+        }
+    }
+
+    const ADDRESS: u64 = 0x0000_1234_5678_9000;
+    const FRAME: u64 = 0x1234_5000;
+
+    fn page<S: PageSize>(address: u64) -> Page<S> {
+        Page::containing_address(VirtualAddress::new(address))
+    }
+
+    fn frame<S: PageSize>(address: u64) -> PhysFrame<S> {
+        PhysFrame::containing_address(PhysicalAddress::new(address))
+    }
+
+    fn walked(mapper: &Mapper, address: u64) -> Vec<(u8, usize, PageTableFlags)> {
+        let mut entries = Vec::new();
+        mapper.walk(VirtualAddress::new(address), |level, index, entry| {
+            entries.push((level, index, entry.flags()))
+        });
+
+        entries
+    }
+
+    #[test]
+    fn map_to_creates_tables_and_translates() {
+        let mut level_4 = Box::new(PageTable::new());
+        let mut frames = HeapFrames::default();
+        let mut mapper = unsafe { Mapper::new(&mut level_4, 0) };
+
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        unsafe { mapper.map_to(page::<Size4KiB>(ADDRESS), frame(FRAME), flags, &mut frames) }
+            .unwrap()
+            .ignore();
+
+        assert_eq!(frames.tables.len(), 3);
+        assert_eq!(
+            mapper.translate(VirtualAddress::new(ADDRESS + 0x123)),
+            TranslateResult::Mapped {
+                frame: PhysicalAddress::new(FRAME),
+                offset: 0x123,
+                size: Size4KiB::SIZE,
+                flags: flags | PageTableFlags::PRESENT,
+            }
+        );
+        assert_eq!(
+            mapper.translate_address(VirtualAddress::new(ADDRESS + 8)),
+            Some(PhysicalAddress::new(FRAME + 8))
+        );
+        assert_eq!(
+            mapper.translate(VirtualAddress::new(ADDRESS + Size4KiB::SIZE)),
+            TranslateResult::NotMapped
+        );
+
+        // The neighbouring page shares all tables.
+        let next = page::<Size4KiB>(ADDRESS + Size4KiB::SIZE);
+        unsafe { mapper.map_to(next, frame(FRAME), flags, &mut frames) }
+            .unwrap()
+            .ignore();
+        assert_eq!(frames.tables.len(), 3);
+    }
+
+    #[test]
+    fn map_to_rejects_mapped_pages_and_reports_allocation_failure() {
+        let mut level_4 = Box::new(PageTable::new());
+        let mut frames = HeapFrames::default();
+        let mut mapper = unsafe { Mapper::new(&mut level_4, 0) };
+        let flags = PageTableFlags::WRITABLE;
+
+        unsafe { mapper.map_to(page::<Size4KiB>(ADDRESS), frame(FRAME), flags, &mut frames) }
+            .unwrap()
+            .ignore();
+
+        let again = unsafe {
+            mapper.map_to(
+                page::<Size4KiB>(ADDRESS),
+                frame(FRAME + Size4KiB::SIZE),
+                flags,
+                &mut frames,
+            )
+        };
+        assert_eq!(
+            again.err(),
+            Some(MapToError::PageAlreadyMapped(frame(FRAME)))
+        );
+
+        let mut exhausted = HeapFrames {
+            limit: Some(0),
+            ..HeapFrames::default()
+        };
+        let elsewhere = page::<Size4KiB>(0x0000_7000_0000_0000);
+        let failed = unsafe { mapper.map_to(elsewhere, frame(FRAME), flags, &mut exhausted) };
+        assert_eq!(failed.err(), Some(MapToError::FrameAllocationFailed));
+    }
+
+    #[test]
+    fn huge_pages_block_smaller_mappings() {
+        let mut level_4 = Box::new(PageTable::new());
+        let mut frames = HeapFrames::default();
+        let mut mapper = unsafe { Mapper::new(&mut level_4, 0) };
+        let flags = PageTableFlags::WRITABLE;
+        let base = ADDRESS & !(Size2MiB::SIZE - 1);
+
+        unsafe {
+            mapper.map_to(
+                page::<Size2MiB>(base),
+                frame(0x4000_0000),
+                flags,
+                &mut frames,
+            )
+        }
+        .unwrap()
+        .ignore();
+
+        assert_eq!(frames.tables.len(), 2);
+        assert_eq!(
+            mapper.translate(VirtualAddress::new(ADDRESS)),
+            TranslateResult::Mapped {
+                frame: PhysicalAddress::new(0x4000_0000),
+                offset: ADDRESS - base,
+                size: Size2MiB::SIZE,
+                flags: flags | PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE,
+            }
+        );
+
+        let small =
+            unsafe { mapper.map_to(page::<Size4KiB>(ADDRESS), frame(FRAME), flags, &mut frames) };
+        assert_eq!(small.err(), Some(MapToError::ParentEntryHugePage));
+        assert_eq!(
+            mapper.unmap(page::<Size4KiB>(ADDRESS)).err(),
+            Some(UnmapError::ParentEntryHugePage)
+        );
+
+        let (unmapped, flush) = mapper.unmap(page::<Size2MiB>(base)).unwrap();
+        flush.ignore();
+        assert_eq!(unmapped, frame(0x4000_0000));
+    }
+
+    #[test]
+    fn unmap_returns_the_frame_once() {
+        let mut level_4 = Box::new(PageTable::new());
+        let mut frames = HeapFrames::default();
+        let mut mapper = unsafe { Mapper::new(&mut level_4, 0) };
+
+        assert_eq!(
+            mapper.unmap(page::<Size4KiB>(ADDRESS)).err(),
+            Some(UnmapError::PageNotMapped)
+        );
+
+        let flags = PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page::<Size4KiB>(ADDRESS), frame(FRAME), flags, &mut frames) }
+            .unwrap()
+            .ignore();
+
+        let (unmapped, flush) = mapper.unmap(page::<Size4KiB>(ADDRESS)).unwrap();
+        flush.ignore();
+
+        assert_eq!(unmapped, frame(FRAME));
+        assert_eq!(
+            mapper.translate(VirtualAddress::new(ADDRESS)),
+            TranslateResult::NotMapped
+        );
+        assert_eq!(
+            mapper.unmap(page::<Size4KiB>(ADDRESS)).err(),
+            Some(UnmapError::PageNotMapped)
+        );
+
+        // The tables stay and are reused.
+        unsafe { mapper.map_to(page::<Size4KiB>(ADDRESS), frame(FRAME), flags, &mut frames) }
+            .unwrap()
+            .ignore();
+        assert_eq!(frames.tables.len(), 3);
+    }
+
+    #[test]
+    fn update_flags_replaces_the_leaf_flags() {
+        let mut level_4 = Box::new(PageTable::new());
+        let mut frames = HeapFrames::default();
+        let mut mapper = unsafe { Mapper::new(&mut level_4, 0) };
+        let page = page::<Size4KiB>(ADDRESS);
+
+        assert_eq!(
+            unsafe { mapper.update_flags(page, PageTableFlags::empty()) }.err(),
+            Some(UnmapError::PageNotMapped)
+        );
+
+        unsafe { mapper.map_to(page, frame(FRAME), PageTableFlags::WRITABLE, &mut frames) }
+            .unwrap()
+            .ignore();
+        unsafe { mapper.update_flags(page, PageTableFlags::NO_EXECUTE) }
+            .unwrap()
+            .ignore();
+
+        let TranslateResult::Mapped { flags, frame, .. } = mapper.translate(page.start_address())
+        else {
+            panic!("Page got unmapped");
+        };
+
+        assert_eq!(flags, PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE);
+        assert_eq!(frame, PhysicalAddress::new(FRAME));
+    }
+
+    #[test]
+    fn pat_bit_does_not_hide_small_pages() {
+        let mut level_4 = Box::new(PageTable::new());
+        let mut frames = HeapFrames::default();
+        let mut mapper = unsafe { Mapper::new(&mut level_4, 0) };
+        let page = page::<Size4KiB>(ADDRESS);
+
+        // Bit 7 of a level 1 entry is PAT.
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::HUGE_PAGE;
+        unsafe { mapper.map_to(page, frame(FRAME), flags, &mut frames) }
+            .unwrap()
+            .ignore();
+
+        unsafe { mapper.update_flags(page, flags | PageTableFlags::NO_EXECUTE) }
+            .unwrap()
+            .ignore();
+
+        let (unmapped, flush) = mapper.unmap(page).unwrap();
+        flush.ignore();
+        assert_eq!(unmapped, frame(FRAME));
+    }
+
+    #[test]
+    fn walk_visits_every_level_down_to_the_leaf() {
+        let mut level_4 = Box::new(PageTable::new());
+        let mut frames = HeapFrames::default();
+        let mut mapper = unsafe { Mapper::new(&mut level_4, 0) };
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+        let empty = walked(&mapper, ADDRESS);
+        assert_eq!(empty, [(4, 0x24, PageTableFlags::empty())]);
+
+        unsafe { mapper.map_to(page::<Size4KiB>(ADDRESS), frame(FRAME), flags, &mut frames) }
+            .unwrap()
+            .ignore();
+
+        let address = VirtualAddress::new(ADDRESS);
+        let entries = walked(&mapper, ADDRESS);
+        let levels: Vec<_> = entries
+            .iter()
+            .map(|&(level, index, _)| (level, index))
+            .collect();
+        let expected: Vec<_> = (1..=4)
+            .rev()
+            .map(|level| (level, address.page_table_index(level)))
+            .collect();
+
+        assert_eq!(levels, expected);
+
+        // User access has to be allowed on every level.
+        for (_, _, entry) in entries {
+            assert!(entry.contains(flags | PageTableFlags::PRESENT));
+        }
+
+        // The walk ends at the first entry that is not present.
+        let neighbour = ADDRESS + Size2MiB::SIZE;
+        let partial = walked(&mapper, neighbour);
+        assert_eq!(partial.len(), 3);
+        assert_eq!(partial[2].2, PageTableFlags::empty());
+    }
+}
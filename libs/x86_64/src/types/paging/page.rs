@@ -0,0 +1,113 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::structures::memory::VirtualAddress;
+
+/// A page size supported by four-level paging.
+pub trait PageSize: Copy + Eq + Ord + fmt::Debug {
+    /// Size of the page in bytes.
+    const SIZE: u64;
+    /// Level of the page table whose entries map pages of this size.
+    const LEVEL: u8;
+    const NAME: &'static str;
+}
+
+/// 4 KiB page, mapped by a level 1 entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Size4KiB {}
+
+/// 2 MiB page, mapped by a level 2 entry with the huge page flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Size2MiB {}
+
+/// 1 GiB page, mapped by a level 3 entry with the huge page flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Size1GiB {}
+
+impl PageSize for Size4KiB {
+    const SIZE: u64 = 4096;
+    const LEVEL: u8 = 1;
+    const NAME: &'static str = "4KiB";
+}
+
+impl PageSize for Size2MiB {
+    const SIZE: u64 = Size4KiB::SIZE * 512;
+    const LEVEL: u8 = 2;
+    const NAME: &'static str = "2MiB";
+}
+
+impl PageSize for Size1GiB {
+    const SIZE: u64 = Size2MiB::SIZE * 512;
+    const LEVEL: u8 = 3;
+    const NAME: &'static str = "1GiB";
+}
+
+/// A page of virtual memory.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(C)]
+pub struct Page<S: PageSize = Size4KiB> {
+    start: VirtualAddress,
+    size: PhantomData<S>,
+}
+
+impl<S: PageSize> Page<S> {
+    pub const SIZE: u64 = S::SIZE;
+
+    /// Returns the page starting at `address`, or `None` if it is not aligned to the page size.
+    #[inline]
+    pub fn from_start_address(address: VirtualAddress) -> Option<Self> {
+        if !address.is_aligned(S::SIZE) {
+            return None;
+        }
+
+        Some(Self::containing_address(address))
+    }
+
+    /// Returns the page that contains `address`.
+    #[inline]
+    pub fn containing_address(address: VirtualAddress) -> Self {
+        Self {
+            start: address.align_down(S::SIZE),
+            size: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn start_address(self) -> VirtualAddress {
+        self.start
+    }
+
+    #[inline]
+    pub const fn size(self) -> u64 {
+        S::SIZE
+    }
+
+    /// Returns the page following this one.
+    #[inline]
+    pub fn next(self) -> Self {
+        Self::containing_address(self.start + S::SIZE)
+    }
+}
+
+impl<S: PageSize> fmt::Debug for Page<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Page[{}]({:#x})", S::NAME, self.start.as_u64())
+    }
+}
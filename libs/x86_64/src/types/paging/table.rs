@@ -0,0 +1,211 @@
+/*
+ * This file is part of the hadron distribution (https://github.com/fxttr/hadron).
+ * Copyright (c) 2023 Florian Marrero Liestmann.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, version 3.
+ *
+ * This program is distributed in the hope that it will be useful, but
+ * WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+ * General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use bitflags::bitflags;
+use core::fmt;
+use core::ops::{Index, IndexMut};
+
+use super::frame::PhysFrame;
+use super::page::PageSize;
+use crate::structures::memory::PhysicalAddress;
+
+/// Number of entries in a page table of any level.
+pub const ENTRY_COUNT: usize = 512;
+
+bitflags! {
+    // See: https://www.intel.com/content/www/us/en/developer/articles/technical/intel-sdm.html
+    // Intel® 64 and IA-32 Architectures Software Developer’s Manual, Volume 3A
+    // Table 4-15 to 4-20 (Formats of the paging-structure entries with 4-level paging)
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+    #[repr(transparent)]
+    pub struct PageTableFlags: u64 {
+        /// The entry is valid and may be used for translation.
+        const PRESENT = 1;
+        /// Writes are allowed. Must be set in every level for the page to be writable.
+        const WRITABLE = 1 << 1;
+        /// Ring 3 may access the page. Must be set in every level for user accesses.
+        const USER_ACCESSIBLE = 1 << 2;
+        /// Write-through caching instead of write-back.
+        const WRITE_THROUGH = 1 << 3;
+        /// Disables caching for the page.
+        const NO_CACHE = 1 << 4;
+        /// Set by the CPU when the entry is used for a translation.
+        const ACCESSED = 1 << 5;
+        /// Set by the CPU on a write to the mapped page. Only valid in leaf entries.
+        const DIRTY = 1 << 6;
+        /// Maps a 2 MiB page in a level 2 entry or a 1 GiB page in a level 3 entry. In a
+        /// level 1 entry the bit is PAT instead.
+        const HUGE_PAGE = 1 << 7;
+        /// The translation is kept in the TLB on an address space switch. Requires CR4.PGE.
+        const GLOBAL = 1 << 8;
+        /// Ignored by the CPU, free for use by the kernel.
+        const AVAILABLE_9 = 1 << 9;
+        /// Ignored by the CPU, free for use by the kernel.
+        const AVAILABLE_10 = 1 << 10;
+        /// Ignored by the CPU, free for use by the kernel.
+        const AVAILABLE_11 = 1 << 11;
+        /// Instruction fetches are not allowed. Requires EFER.NXE.
+        const NO_EXECUTE = 1 << 63;
+    }
+}
+
+/// A 64-bit entry of a page table.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    /// Bits 12 - 51 hold the physical address of the frame or the next table.
+    const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    #[inline]
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    #[inline]
+    pub const fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    #[inline]
+    pub fn set_unused(&mut self) {
+        self.0 = 0;
+    }
+
+    #[inline]
+    pub const fn flags(&self) -> PageTableFlags {
+        PageTableFlags::from_bits_truncate(self.0)
+    }
+
+    #[inline]
+    pub const fn address(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.0 & Self::ADDRESS_MASK)
+    }
+
+    /// Returns the 4 KiB frame this entry points to.
+    pub fn frame(&self) -> Result<PhysFrame, FrameError> {
+        if !self.flags().contains(PageTableFlags::PRESENT) {
+            Err(FrameError::FrameNotPresent)
+        } else if self.flags().contains(PageTableFlags::HUGE_PAGE) {
+            Err(FrameError::HugeFrame)
+        } else {
+            Ok(PhysFrame::containing_address(self.address()))
+        }
+    }
+
+    #[inline]
+    pub fn set_address(&mut self, address: PhysicalAddress, flags: PageTableFlags) {
+        assert!(
+            address.as_u64() & !Self::ADDRESS_MASK == 0,
+            "Page table entries need a 4 KiB aligned address below 2^52."
+        );
+
+        self.0 = address.as_u64() | flags.bits();
+    }
+
+    #[inline]
+    pub fn set_frame<S: PageSize>(&mut self, frame: PhysFrame<S>, flags: PageTableFlags) {
+        self.set_address(frame.start_address(), flags)
+    }
+
+    #[inline]
+    pub fn set_flags(&mut self, flags: PageTableFlags) {
+        self.0 = self.address().as_u64() | flags.bits();
+    }
+}
+
+impl Default for PageTableEntry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for PageTableEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("PageTableEntry");
+        s.field("address", &self.address());
+        s.field("flags", &self.flags());
+        s.finish()
+    }
+}
+
+/// Reasons why [`PageTableEntry::frame`] can not return a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The entry does not have the present flag set.
+    FrameNotPresent,
+    /// The entry maps a 2 MiB or 1 GiB page instead of pointing to a table.
+    HugeFrame,
+}
+
+/// A page table of any level. Occupies exactly one 4 KiB frame.
+#[derive(Clone)]
+#[repr(C)]
+#[repr(align(4096))]
+pub struct PageTable {
+    entries: [PageTableEntry; ENTRY_COUNT],
+}
+
+impl PageTable {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            entries: [PageTableEntry::new(); ENTRY_COUNT],
+        }
+    }
+
+    /// Marks every entry as unused.
+    #[inline]
+    pub fn zero(&mut self) {
+        for entry in self.iter_mut() {
+            entry.set_unused();
+        }
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &PageTableEntry> {
+        self.entries.iter()
+    }
+
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut PageTableEntry> {
+        self.entries.iter_mut()
+    }
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.entries[index]
+    }
+}